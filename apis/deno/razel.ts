@@ -168,6 +168,12 @@ export namespace Razel {
         NoRemoteCache = 'razel:no-remote-cache',
         // disable sandbox and also cache - for commands with unspecified input/output files
         NoSandbox = 'razel:no-sandbox',
+        // capture stderr together with stdout like `2>&1` - not supported on Windows
+        MergeStderr = 'razel:merge-stderr',
+        // pass the GNU Make jobserver of `razel exec --jobserver` to the command - not supported on Windows
+        Jobserver = 'razel:jobserver',
+        // don't remove existing output files before executing the command - requires NoSandbox
+        PreserveOutputs = 'razel:preserve-outputs',
     }
 }
 
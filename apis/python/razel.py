@@ -29,6 +29,12 @@ class Razel:
         """don't use remote cache"""
         NO_SANDBOX = 'razel:no-sandbox'
         """disable sandbox and also cache - for commands with unspecified input/output files"""
+        MERGE_STDERR = 'razel:merge-stderr'
+        """capture stderr together with stdout like `2>&1` - not supported on Windows"""
+        JOBSERVER = 'razel:jobserver'
+        """pass the GNU Make jobserver of `razel exec --jobserver` to the command - not supported on Windows"""
+        PRESERVE_OUTPUTS = 'razel:preserve-outputs'
+        """don't remove existing output files before executing the command - requires NO_SANDBOX"""
 
     def __init__(self, workspace_dir: str) -> None:
         workspace_dir = str(workspace_dir)
@@ -1,4 +1,8 @@
 // build script to generate code from bazel remote execution protobuf files
+// and to embed build metadata shown by `razel version`
+
+use std::path::Path;
+use std::process::Command;
 
 fn main() {
     let files =
@@ -9,7 +13,38 @@ fn main() {
     let config = prost_build::Config::new();
     tonic_build::configure()
         .build_client(true)
-        .build_server(false)
+        .build_server(true)
+        // the server is only used by tests against a fake remote cache
+        .server_mod_attribute(".", "#[cfg(test)]")
         .compile_protos_with_config(config, &files, &["src/bazel_remote_exec/proto"])
         .unwrap();
+    set_build_metadata_env();
+}
+
+fn set_build_metadata_env() {
+    for x in [".git/HEAD", ".git/refs"] {
+        if Path::new(x).exists() {
+            println!("cargo:rerun-if-changed={x}");
+        }
+    }
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|x| x.status.success())
+        .and_then(|x| String::from_utf8(x.stdout).ok())
+        .map_or_else(|| "unknown".to_string(), |x| x.trim().to_string());
+    let features = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|x| x.to_lowercase().replace('_', "-"))
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    println!("cargo:rustc-env=RAZEL_GIT_COMMIT={git_commit}");
+    println!(
+        "cargo:rustc-env=RAZEL_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+    println!("cargo:rustc-env=RAZEL_FEATURES={features}");
 }
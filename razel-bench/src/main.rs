@@ -1,6 +1,6 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use razel_bench::types::{Bench, CacheState, BENCHES_OUT_DIR};
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use razel_bench::types::{Bench, CacheState, Comparison, BENCHES_OUT_DIR};
 use serde_json::Value;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -15,7 +15,10 @@ const REMOTE_CACHE_STATUS_PORT: u16 = 8081;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommands>,
     /// razel binary to benchmark
     #[clap(short, long, default_value = "razel")]
     bin: PathBuf,
@@ -33,6 +36,20 @@ struct Cli {
     runs: usize,
 }
 
+#[derive(Subcommand)]
+enum CliCommands {
+    /// Compare bench summaries of a candidate razel binary against a baseline
+    Compare {
+        /// glob pattern of baseline summary files, e.g. "benches/bench.main.*.summary.json"
+        baseline: String,
+        /// glob pattern of candidate summary files
+        candidate: String,
+        /// flag changes of the mean duration above this ratio
+        #[clap(short, long, default_value = "0.05")]
+        threshold: f32,
+    },
+}
+
 #[derive(Clone)]
 struct Config {
     title: String,
@@ -160,10 +177,43 @@ fn exec(program: &str, args: &Vec<String>) {
     assert!(status.success(), "{status:?}");
 }
 
+fn read_benches(pattern: &str) -> Result<Vec<Bench>> {
+    let benches = glob::glob(pattern)
+        .with_context(|| format!("invalid pattern: {pattern}"))?
+        .map(|x| Bench::from_path(x?))
+        .collect::<Result<Vec<_>>>()?;
+    if benches.is_empty() {
+        bail!("no bench summaries found: {pattern}");
+    }
+    Ok(benches)
+}
+
+fn compare(baseline: &str, candidate: &str, threshold: f32) -> Result<()> {
+    let comparison = Comparison::new(
+        &read_benches(baseline)?,
+        &read_benches(candidate)?,
+        threshold,
+    );
+    comparison.print();
+    let regressions = comparison.regressions();
+    if regressions != 0 {
+        bail!("{regressions} regressions above {}%", threshold * 100.0);
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let cli = Cli::parse();
+    if let Some(CliCommands::Compare {
+        baseline,
+        candidate,
+        threshold,
+    }) = &cli.command
+    {
+        return compare(baseline, candidate, *threshold);
+    }
     fs::create_dir_all(BENCHES_OUT_DIR)?;
     let file_abs = cli.file.canonicalize().unwrap();
     let workspace_dir = file_abs.parent().unwrap();
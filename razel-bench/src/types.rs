@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use razel::metadata::LogFile;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs;
 use std::fs::File;
@@ -50,7 +51,7 @@ impl Bench {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[allow(clippy::enum_variant_names)]
 pub enum CacheState {
     /// Local execution from scratch
@@ -71,3 +72,286 @@ impl CacheState {
         }
     }
 }
+
+/// Mean and standard deviation of the durations of repeated runs [s]
+#[derive(Debug, PartialEq)]
+pub struct DurationStats {
+    pub runs: usize,
+    pub mean: f32,
+    /// sample standard deviation, 0 for a single run
+    pub std_dev: f32,
+}
+
+impl DurationStats {
+    pub fn new(durations: &[f32]) -> Self {
+        let runs = durations.len();
+        let mean = durations.iter().sum::<f32>() / runs as f32;
+        let std_dev = if runs > 1 {
+            let sum_sq = durations.iter().map(|x| (x - mean).powi(2)).sum::<f32>();
+            (sum_sq / (runs - 1) as f32).sqrt()
+        } else {
+            0.0
+        };
+        Self {
+            runs,
+            mean,
+            std_dev,
+        }
+    }
+
+    /// Welch's t-test with 95% confidence, None if there are less than 2 runs
+    fn differs_significantly(&self, other: &Self) -> Option<bool> {
+        if self.runs < 2 || other.runs < 2 {
+            return None;
+        }
+        let var_a = self.std_dev.powi(2) / self.runs as f32;
+        let var_b = other.std_dev.powi(2) / other.runs as f32;
+        let diff = (self.mean - other.mean).abs();
+        if var_a + var_b == 0.0 {
+            return Some(diff > 0.0);
+        }
+        let t = diff / (var_a + var_b).sqrt();
+        let df = (var_a + var_b).powi(2)
+            / (var_a.powi(2) / (self.runs - 1) as f32 + var_b.powi(2) / (other.runs - 1) as f32);
+        Some(t > t_critical_95(df))
+    }
+}
+
+/// Two-sided critical value of Student's t-distribution for 95% confidence, df is rounded down
+fn t_critical_95(df: f32) -> f32 {
+    const TABLE: [(f32, f32); 15] = [
+        (1.0, 12.706),
+        (2.0, 4.303),
+        (3.0, 3.182),
+        (4.0, 2.776),
+        (5.0, 2.571),
+        (6.0, 2.447),
+        (7.0, 2.365),
+        (8.0, 2.306),
+        (9.0, 2.262),
+        (10.0, 2.228),
+        (15.0, 2.131),
+        (20.0, 2.086),
+        (30.0, 2.042),
+        (60.0, 2.000),
+        (120.0, 1.980),
+    ];
+    TABLE
+        .iter()
+        .rev()
+        .find(|(x, _)| df >= *x)
+        .unwrap_or(&TABLE[0])
+        .1
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChangeStatus {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+/// Comparison of the runs of a bench with the same title and cache state
+#[derive(Debug)]
+pub struct BenchComparison {
+    pub title: String,
+    pub cache_state: CacheState,
+    pub baseline: DurationStats,
+    pub candidate: DurationStats,
+    /// baseline duration / candidate duration, > 1 if the candidate is faster
+    pub speedup: f32,
+    /// None if there are not enough runs to tell
+    pub significant: Option<bool>,
+    pub status: ChangeStatus,
+}
+
+/// Compares benches of a baseline and a candidate razel binary
+#[derive(Debug)]
+pub struct Comparison {
+    pub benches: Vec<BenchComparison>,
+    /// title and cache state of benches which are missing in the candidate
+    pub only_in_baseline: Vec<(String, CacheState)>,
+    /// title and cache state of benches which are missing in the baseline
+    pub only_in_candidate: Vec<(String, CacheState)>,
+}
+
+impl Comparison {
+    /// Flags changes of the mean duration above the threshold (e.g. 0.05 for 5%), unless they are
+    /// not significant
+    pub fn new(baseline: &[Bench], candidate: &[Bench], threshold: f32) -> Self {
+        let baseline = Self::group(baseline);
+        let mut candidate = Self::group(candidate);
+        let mut benches = vec![];
+        let mut only_in_baseline = vec![];
+        for (key, baseline_durations) in baseline {
+            let Some(candidate_durations) = candidate.remove(&key) else {
+                only_in_baseline.push(key);
+                continue;
+            };
+            let baseline = DurationStats::new(&baseline_durations);
+            let candidate = DurationStats::new(&candidate_durations);
+            let speedup = baseline.mean / candidate.mean;
+            let significant = baseline.differs_significantly(&candidate);
+            let status = if significant == Some(false) {
+                ChangeStatus::Unchanged
+            } else if candidate.mean > baseline.mean * (1.0 + threshold) {
+                ChangeStatus::Regression
+            } else if candidate.mean < baseline.mean * (1.0 - threshold) {
+                ChangeStatus::Improvement
+            } else {
+                ChangeStatus::Unchanged
+            };
+            let (title, cache_state) = key;
+            benches.push(BenchComparison {
+                title,
+                cache_state,
+                baseline,
+                candidate,
+                speedup,
+                significant,
+                status,
+            });
+        }
+        Self {
+            benches,
+            only_in_baseline,
+            only_in_candidate: candidate.into_keys().collect(),
+        }
+    }
+
+    /// Durations of runs grouped by title and cache state
+    fn group(benches: &[Bench]) -> BTreeMap<(String, CacheState), Vec<f32>> {
+        let mut groups: BTreeMap<_, Vec<f32>> = BTreeMap::new();
+        for bench in benches {
+            groups
+                .entry((bench.title.clone(), bench.cache_state))
+                .or_default()
+                .push(bench.duration);
+        }
+        groups
+    }
+
+    pub fn regressions(&self) -> usize {
+        self.benches
+            .iter()
+            .filter(|x| x.status == ChangeStatus::Regression)
+            .count()
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{:<20} {:<20} {:>16} {:>16} {:>8} {:>12}  status",
+            "title", "cache state", "baseline [s]", "candidate [s]", "speedup", "significant"
+        );
+        for x in &self.benches {
+            println!(
+                "{:<20} {:<20} {:>16} {:>16} {:>7.2}x {:>12}  {:?}",
+                x.title,
+                format!("{:?}", x.cache_state),
+                format!("{:.2} ± {:.2}", x.baseline.mean, x.baseline.std_dev),
+                format!("{:.2} ± {:.2}", x.candidate.mean, x.candidate.std_dev),
+                x.speedup,
+                match x.significant {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "too few runs",
+                },
+                x.status,
+            );
+        }
+        for (title, cache_state) in &self.only_in_baseline {
+            println!("missing in candidate: {title} {cache_state:?}");
+        }
+        for (title, cache_state) in &self.only_in_candidate {
+            println!("missing in baseline: {title} {cache_state:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn benches(title: &str, cache_state: CacheState, durations: &[f32]) -> Vec<Bench> {
+        durations
+            .iter()
+            .enumerate()
+            .map(|(i, duration)| Bench {
+                id: format!("{title}.{cache_state:?}.{i}"),
+                path: Default::default(),
+                title: title.into(),
+                cache_state,
+                timestamp: i as u128,
+                duration: *duration,
+                remote_cache_stats_before: None,
+                remote_cache_stats_after: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compare() {
+        let baseline = [
+            benches("a", CacheState::LocalCold, &[10.0, 10.2, 9.8]),
+            benches("a", CacheState::LocalWarm, &[1.0, 1.1, 0.9]),
+            benches("b", CacheState::LocalCold, &[5.0, 6.0, 4.0]),
+            benches("c", CacheState::LocalWarm, &[1.0, 1.0, 1.0]),
+            benches("d", CacheState::LocalCold, &[2.0]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        let candidate = [
+            benches("a", CacheState::LocalCold, &[12.0, 12.1, 11.9]),
+            benches("a", CacheState::LocalWarm, &[0.5, 0.55, 0.45]),
+            benches("a", CacheState::LocalColdRemoteCold, &[3.0, 3.0, 3.0]),
+            benches("b", CacheState::LocalCold, &[5.2, 4.0, 6.5]),
+            benches("d", CacheState::LocalCold, &[3.0]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        let comparison = Comparison::new(&baseline, &candidate, 0.05);
+        let status = comparison
+            .benches
+            .iter()
+            .map(|x| (x.title.as_str(), x.cache_state, x.significant, x.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            status,
+            [
+                (
+                    "a",
+                    CacheState::LocalCold,
+                    Some(true),
+                    ChangeStatus::Regression
+                ),
+                (
+                    "a",
+                    CacheState::LocalWarm,
+                    Some(true),
+                    ChangeStatus::Improvement
+                ),
+                (
+                    "b",
+                    CacheState::LocalCold,
+                    Some(false),
+                    ChangeStatus::Unchanged
+                ),
+                ("d", CacheState::LocalCold, None, ChangeStatus::Regression),
+            ]
+        );
+        assert_eq!(comparison.regressions(), 2);
+        assert_eq!(
+            comparison.only_in_baseline,
+            [("c".to_string(), CacheState::LocalWarm)]
+        );
+        assert_eq!(
+            comparison.only_in_candidate,
+            [("a".to_string(), CacheState::LocalColdRemoteCold)]
+        );
+        let stats = &comparison.benches[0].baseline;
+        assert!((stats.mean - 10.0).abs() < 1e-5);
+        assert!((stats.std_dev - 0.2).abs() < 1e-5);
+    }
+}
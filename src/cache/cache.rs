@@ -1,11 +1,15 @@
 use crate::bazel_remote_exec::{ActionResult, OutputFile};
-use crate::cache::{BlobDigest, GrpcRemoteCache, LocalCache, MessageDigest};
+use crate::cache::{
+    BlobDigest, FlushedUploads, GrpcRemoteCache, LocalCache, MessageDigest, RateLimiter,
+};
+use crate::config::{LinkType, REMOTE_CACHE_CONNECT_TIMEOUT};
 use crate::CacheHit;
 use anyhow::{bail, Context, Error};
 use itertools::Itertools;
 use log::info;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
@@ -18,6 +22,15 @@ pub struct Cache {
     remote_cache: Option<GrpcRemoteCache>,
     /// Only cache commands with: output size / exec time < threshold [kilobyte / s]
     remote_cache_threshold: Option<u32>,
+    /// Don't use remote cache for commands with larger outputs [byte]
+    remote_cache_max_size: Option<u64>,
+    /// bandwidth limits for remote cache uploads/downloads [byte / s], 0 means unlimited
+    remote_cache_upload_limit: u64,
+    remote_cache_download_limit: u64,
+    /// number of remote cache uploads to queue before pushing waits
+    remote_cache_max_inflight_uploads: usize,
+    /// bearer token sent to the remote cache
+    remote_cache_auth_token: Option<String>,
     cas_states: Arc<Mutex<HashMap<String, CacheState>>>,
 }
 
@@ -33,6 +46,11 @@ impl Cache {
             local_cache,
             remote_cache: None,
             remote_cache_threshold: None,
+            remote_cache_max_size: None,
+            remote_cache_upload_limit: 0,
+            remote_cache_download_limit: 0,
+            remote_cache_max_inflight_uploads: 1000,
+            remote_cache_auth_token: None,
             cas_states: Arc::new(Mutex::new(Default::default())),
         })
     }
@@ -45,6 +63,32 @@ impl Cache {
         self.local_cache.cas_path(digest)
     }
 
+    /// Set bandwidth limits [byte / s] to be used by connect_remote_cache(), 0 means unlimited
+    pub fn set_remote_cache_bandwidth_limits(&mut self, upload: u64, download: u64) {
+        self.remote_cache_upload_limit = upload;
+        self.remote_cache_download_limit = download;
+    }
+
+    /// Set how output files are linked from the local cache into out_dir
+    pub fn set_out_dir_link_type(&mut self, link_type: LinkType) {
+        self.local_cache.out_dir_link_type = link_type;
+    }
+
+    /// Drop write permissions of output files linked or copied into out_dir
+    pub fn set_out_dir_readonly(&mut self, readonly: bool) {
+        self.local_cache.out_dir_readonly = readonly;
+    }
+
+    /// Set the number of uploads to be queued by connect_remote_cache()
+    pub fn set_remote_cache_max_inflight_uploads(&mut self, max_inflight_uploads: usize) {
+        self.remote_cache_max_inflight_uploads = max_inflight_uploads;
+    }
+
+    /// Set the bearer token to be used by connect_remote_cache()
+    pub fn set_remote_cache_auth_token(&mut self, token: Option<String>) {
+        self.remote_cache_auth_token = token;
+    }
+
     /// Use the first available remote cache. Ignore connection failures because remote caching is optional.
     pub async fn connect_remote_cache(
         &mut self,
@@ -59,15 +103,30 @@ impl Cache {
                     "remote cache should be an URI, e.g. grpc://localhost:9092[/instance_name]",
                 )?;
             match uri.scheme_str() {
-                Some("grpc") => match GrpcRemoteCache::new(uri, &self.local_cache.dir).await {
-                    Ok(x) => {
+                Some("grpc") => match tokio::time::timeout(
+                    REMOTE_CACHE_CONNECT_TIMEOUT,
+                    GrpcRemoteCache::new(
+                        uri,
+                        &self.local_cache.dir,
+                        RateLimiter::new(self.remote_cache_upload_limit),
+                        RateLimiter::new(self.remote_cache_download_limit),
+                        self.remote_cache_max_inflight_uploads,
+                        self.remote_cache_auth_token.as_deref(),
+                    ),
+                )
+                .await
+                {
+                    Ok(Ok(x)) => {
                         self.remote_cache = Some(x);
                         self.remote_cache_threshold = remote_cache_threshold;
                         info!("connected to remote cache: {url}");
                         return Ok(true);
                     }
-                    _ => {
-                        info!("failed to connect to remote cache: {url}");
+                    Ok(Err(e)) => {
+                        info!("failed to connect to remote cache: {url}: {e}");
+                    }
+                    Err(_) => {
+                        info!("failed to connect to remote cache: {url}: timeout");
                     }
                 },
                 _ => bail!("only grpc remote caches are supported: {url}"),
@@ -76,45 +135,82 @@ impl Cache {
         Ok(false)
     }
 
+    /// Export cached action results and their output files into an archive, see LocalCache
+    pub async fn export_archive(
+        &self,
+        action_digests: &[MessageDigest],
+        archive: &Path,
+    ) -> Result<usize, Error> {
+        self.local_cache
+            .export_archive(action_digests, archive)
+            .await
+    }
+
+    /// Import action results and output files from an archive into the local cache
+    pub async fn import_archive(&self, archive: &Path) -> Result<usize, Error> {
+        self.local_cache.import_archive(archive).await
+    }
+
+    /// Returns the cached action result, otherwise why the remote cache missed if it was queried
     pub async fn get_action_result(
         &mut self,
         digest: &MessageDigest,
         use_remote_cache: bool,
-    ) -> Option<(ActionResult, CacheHit)> {
+    ) -> Result<(ActionResult, CacheHit), Option<RemoteCacheMiss>> {
         let remote_cache = self.remote_cache.as_ref().filter(|_| use_remote_cache);
         let (action_result, mut cache_hit) =
             if let Some(x) = self.local_cache.get_action_result(digest).await {
                 (x, CacheHit::Local)
             } else if let Some(remote_cache) = remote_cache {
-                let x = remote_cache.get_action_result(digest.clone()).await?;
-                self.local_cache.push_action_result(digest, &x).await.ok()?;
+                let x = remote_cache
+                    .get_action_result(digest.clone())
+                    .await
+                    .ok_or(Some(RemoteCacheMiss::NoActionResult))?;
+                self.local_cache
+                    .push_action_result(digest, &x)
+                    .await
+                    .map_err(|_| None)?;
                 (x, CacheHit::Remote)
             } else {
-                return None;
+                return Err(None);
             };
         if action_result.output_files.is_empty() {
-            return Some((action_result, cache_hit));
+            return Ok((action_result, cache_hit));
         }
         let to_download = self.get_files_to_download(&action_result).await;
         if to_download.is_empty() {
-            return Some((action_result, cache_hit));
+            return Ok((action_result, cache_hit));
         }
-        let remote_cache = self.remote_cache.as_ref().filter(|_| use_remote_cache)?;
+        let remote_cache = self
+            .remote_cache
+            .as_ref()
+            .filter(|_| use_remote_cache)
+            .ok_or(None)?;
         if self.is_output_size_above_remote_cache_threshold(&action_result) {
-            return None;
+            return Err(Some(RemoteCacheMiss::AboveThreshold));
+        }
+        if remote_cache.is_above_max_batch_size(&to_download) {
+            return Err(Some(RemoteCacheMiss::AboveMaxBatchSize));
         }
         let downloaded = remote_cache
             .download_and_store_blobs(&to_download)
             .await
-            .ok()?;
-        if downloaded.is_empty() {
-            return None;
+            .map_err(|_| None)?;
+        self.move_downloaded_files_to_cas(&downloaded)
+            .await
+            .map_err(|_| None)?;
+        if downloaded.len() != to_download.len() {
+            let missing = to_download
+                .iter()
+                .filter(|x| !downloaded.iter().any(|(d, _)| Some(d) == x.digest.as_ref()))
+                .map(|x| x.path.clone())
+                .collect();
+            return Err(Some(RemoteCacheMiss::MissingBlobs(missing)));
         }
-        self.move_downloaded_files_to_cas(&downloaded).await.ok()?;
         if cache_hit == CacheHit::Local {
             cache_hit = CacheHit::Mixed;
         }
-        (downloaded.len() == to_download.len()).then_some((action_result, cache_hit))
+        Ok((action_result, cache_hit))
     }
 
     async fn move_downloaded_files_to_cas(
@@ -169,12 +265,16 @@ impl Cache {
         let files = self
             .prepare_files_to_push(action_result, sandbox_dir)
             .await?;
-        let mut remote_cache = self.remote_cache.as_ref().filter(|_| use_remote_cache);
+        let mut remote_cache = self.remote_cache.as_ref().filter(|_| {
+            use_remote_cache && !self.is_output_size_above_remote_cache_max_size(action_result)
+        });
         self.local_cache
             .push_action_result(message_digest, action_result)
             .await?;
         if let Some(remote_cache) = remote_cache {
-            remote_cache.push_action_result(message_digest.clone(), action_result.clone());
+            remote_cache
+                .push_action_result(message_digest.clone(), action_result.clone())
+                .await;
         }
         if self.is_output_size_above_remote_cache_threshold(action_result) {
             // just skip uploading to cas, ac upload is still useful, e.g. files might already be cached
@@ -233,7 +333,7 @@ impl Cache {
         }
         if cas_state.is_upload_needed() {
             if let Some(remote_cache) = remote_cache {
-                remote_cache.push_blob(file.digest, file.cas_path);
+                remote_cache.push_blob(file.digest, file.cas_path).await;
                 *cas_state = CacheState::LocallyCreatedAndUploaded;
             }
         }
@@ -244,25 +344,47 @@ impl Cache {
     pub async fn link_output_files_into_out_dir(
         &self,
         output_files: &Vec<OutputFile>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<usize, anyhow::Error> {
         self.local_cache
             .link_output_files_into_out_dir(output_files, &self.out_dir)
             .await
     }
 
     fn is_output_size_above_remote_cache_threshold(&self, action_result: &ActionResult) -> bool {
-        let Some(threshold) = self.remote_cache_threshold else {
-            return false;
-        };
-        let Some(exec_duration) = action_result
-            .execution_metadata
-            .as_ref()
-            .and_then(|x| x.virtual_execution_duration.as_ref())
-            .map(|x| Duration::new(x.seconds as u64, x.nanos as u32).as_secs_f32())
-        else {
-            return false;
-        };
-        Self::get_output_size(action_result) as f32 / exec_duration > threshold as f32 * 1000.0
+        !RemoteCacheDecision::for_action_result(self.remote_cache_threshold, action_result).upload()
+    }
+
+    fn is_output_size_above_remote_cache_max_size(&self, action_result: &ActionResult) -> bool {
+        self.remote_cache_max_size
+            .is_some_and(|x| Self::get_output_size(action_result) > x)
+    }
+
+    /// Waits for pending remote cache uploads, see GrpcRemoteCache::flush()
+    pub async fn flush_remote_cache(&self, timeout: Duration) -> Option<FlushedUploads> {
+        Some(self.remote_cache.as_ref()?.flush(timeout).await)
+    }
+
+    pub fn has_remote_cache(&self) -> bool {
+        self.remote_cache.is_some()
+    }
+
+    /// Remote cache threshold [kilobyte / s] used for new and cloned caches
+    pub fn remote_cache_threshold(&self) -> Option<u32> {
+        self.remote_cache_threshold
+    }
+
+    /// Override the remote cache threshold, e.g. for a single command
+    pub fn set_remote_cache_threshold(&mut self, threshold: Option<u32>) {
+        self.remote_cache_threshold = threshold;
+    }
+
+    /// Max size of inputs/outputs [byte] of commands to use the remote cache for
+    pub fn remote_cache_max_size(&self) -> Option<u64> {
+        self.remote_cache_max_size
+    }
+
+    pub fn set_remote_cache_max_size(&mut self, max_size: Option<u64>) {
+        self.remote_cache_max_size = max_size;
     }
 
     fn get_output_size(action_result: &ActionResult) -> u64 {
@@ -286,6 +408,100 @@ impl Cache {
     }
 }
 
+/// Whether output files of an action are worth being uploaded to/downloaded from the remote cache
+#[derive(Debug, PartialEq)]
+pub enum RemoteCacheDecision {
+    NoThreshold,
+    /// exec duration is unknown, e.g. for cache hits
+    NoExecDuration,
+    /// output size / exec time [kilobyte / s] is below the threshold
+    BelowThreshold {
+        rate: f32,
+        threshold: u32,
+    },
+    /// output size / exec time [kilobyte / s] is above the threshold
+    AboveThreshold {
+        rate: f32,
+        threshold: u32,
+    },
+}
+
+impl RemoteCacheDecision {
+    pub fn new(threshold: Option<u32>, output_size: u64, exec_duration: Option<Duration>) -> Self {
+        let Some(threshold) = threshold else {
+            return Self::NoThreshold;
+        };
+        let Some(exec_duration) = exec_duration else {
+            return Self::NoExecDuration;
+        };
+        let rate = output_size as f32 / 1000.0 / exec_duration.as_secs_f32();
+        if rate > threshold as f32 {
+            Self::AboveThreshold { rate, threshold }
+        } else {
+            Self::BelowThreshold { rate, threshold }
+        }
+    }
+
+    /// Uses the output size and exec duration stored in the action result like Cache::push()
+    pub fn for_action_result(threshold: Option<u32>, action_result: &ActionResult) -> Self {
+        let exec_duration = action_result
+            .execution_metadata
+            .as_ref()
+            .and_then(|x| x.virtual_execution_duration.as_ref())
+            .map(|x| Duration::new(x.seconds as u64, x.nanos as u32));
+        Self::new(
+            threshold,
+            Cache::get_output_size(action_result),
+            exec_duration,
+        )
+    }
+
+    pub fn upload(&self) -> bool {
+        !matches!(self, Self::AboveThreshold { .. })
+    }
+}
+
+impl fmt::Display for RemoteCacheDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoThreshold => write!(f, "no threshold set"),
+            Self::NoExecDuration => write!(f, "exec duration unknown"),
+            Self::BelowThreshold { rate, threshold } => {
+                write!(f, "{rate:.1} kB/s <= threshold {threshold} kB/s")
+            }
+            Self::AboveThreshold { rate, threshold } => {
+                write!(f, "{rate:.1} kB/s > threshold {threshold} kB/s")
+            }
+        }
+    }
+}
+
+/// Why an action result could not be taken from the remote cache, see `--explain-remote-miss`
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemoteCacheMiss {
+    /// the action cache has no entry for the action digest
+    NoActionResult,
+    /// the action result was found, but output files are missing in the CAS, e.g. evicted
+    MissingBlobs(Vec<String>),
+    /// output files are not downloaded because of `--remote-cache-threshold`
+    AboveThreshold,
+    /// output files are not downloaded because they exceed the max batch size of the server
+    AboveMaxBatchSize,
+}
+
+impl fmt::Display for RemoteCacheMiss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoActionResult => write!(f, "no action result"),
+            Self::MissingBlobs(x) => {
+                write!(f, "action result found, output files missing in CAS: {x:?}")
+            }
+            Self::AboveThreshold => write!(f, "output files above remote cache threshold"),
+            Self::AboveMaxBatchSize => write!(f, "output files above max batch size"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum CacheState {
     New,
@@ -313,3 +529,58 @@ struct PushFileData {
     out_path: PathBuf,
     cas_path: PathBuf,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_cache_decision() {
+        let threshold = Some(100);
+        let exec_duration = Some(Duration::from_secs(2));
+        // 50 kB/s: cheap to transfer compared to executing again
+        let worthwhile = RemoteCacheDecision::new(threshold, 100_000, exec_duration);
+        assert_eq!(
+            worthwhile,
+            RemoteCacheDecision::BelowThreshold {
+                rate: 50.0,
+                threshold: 100
+            }
+        );
+        assert!(worthwhile.upload());
+        // 500 kB/s: faster to execute again than to transfer
+        let not_worthwhile = RemoteCacheDecision::new(threshold, 1_000_000, exec_duration);
+        assert_eq!(
+            not_worthwhile,
+            RemoteCacheDecision::AboveThreshold {
+                rate: 500.0,
+                threshold: 100
+            }
+        );
+        assert!(!not_worthwhile.upload());
+        assert!(RemoteCacheDecision::new(None, 1_000_000, exec_duration).upload());
+        assert!(RemoteCacheDecision::new(threshold, 1_000_000, None).upload());
+    }
+
+    #[test]
+    fn remote_cache_max_size() {
+        let dir = crate::new_tmp_dir!();
+        let mut cache = Cache::new(dir.join("cache"), "razel-out".into()).unwrap();
+        let action_result = |size_bytes| ActionResult {
+            output_files: vec![OutputFile {
+                path: "out.txt".into(),
+                digest: Some(BlobDigest {
+                    hash: Default::default(),
+                    size_bytes,
+                }),
+                ..Default::default()
+            }],
+            stdout_raw: vec![0; 10],
+            ..Default::default()
+        };
+        assert!(!cache.is_output_size_above_remote_cache_max_size(&action_result(1_000_000)));
+        cache.set_remote_cache_max_size(Some(1000));
+        assert!(!cache.is_output_size_above_remote_cache_max_size(&action_result(990)));
+        assert!(cache.is_output_size_above_remote_cache_max_size(&action_result(991)));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::bazel_remote_exec::{ActionResult, Digest};
+use crate::cache::{LocalCache, MessageDigest};
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use itertools::Itertools;
+use prost::Message;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+const AC_DIR: &str = "ac";
+const CAS_DIR: &str = "cas";
+
+impl LocalCache {
+    /// Writes the action results of the given actions and their output files into a tar.gz archive.
+    ///
+    /// Actions which are not cached or have missing output files are skipped.
+    /// Returns the number of exported actions.
+    pub async fn export_archive(
+        &self,
+        action_digests: &[MessageDigest],
+        archive: &Path,
+    ) -> Result<usize, anyhow::Error> {
+        let file = File::create(archive)
+            .with_context(|| format!("failed to create cache archive {archive:?}"))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let mut exported_blobs = HashSet::new();
+        let mut exported_actions = 0;
+        for digest in action_digests.iter().unique_by(|x| &x.hash) {
+            let Some(action_result) = self.get_action_result(digest).await else {
+                continue;
+            };
+            let mut blobs = vec![];
+            for file in &action_result.output_files {
+                let digest = file.digest.as_ref().unwrap();
+                if !self.is_blob_cached(digest).await {
+                    blobs.clear();
+                    break;
+                }
+                blobs.push(digest);
+            }
+            if blobs.len() != action_result.output_files.len() {
+                continue;
+            }
+            builder.append_path_with_name(
+                self.ac_path(digest),
+                Path::new(AC_DIR).join(&digest.hash),
+            )?;
+            for digest in blobs {
+                if exported_blobs.insert(digest.hash.clone()) {
+                    builder.append_path_with_name(
+                        self.cas_path(digest),
+                        Path::new(CAS_DIR).join(&digest.hash),
+                    )?;
+                }
+            }
+            exported_actions += 1;
+        }
+        builder
+            .into_inner()?
+            .finish()
+            .with_context(|| format!("failed to write cache archive {archive:?}"))?;
+        Ok(exported_actions)
+    }
+
+    /// Imports action results and output files from an archive created by export_archive().
+    ///
+    /// Output files are only imported if their digest matches, action results only if all their
+    /// output files are cached. Returns the number of imported actions.
+    pub async fn import_archive(&self, archive: &Path) -> Result<usize, anyhow::Error> {
+        let tmp_dir = self.dir.join("import").join(std::process::id().to_string());
+        let result = self.import_archive_via_dir(archive, &tmp_dir).await;
+        std::fs::remove_dir_all(&tmp_dir).ok();
+        result.with_context(|| format!("failed to import cache archive {archive:?}"))
+    }
+
+    async fn import_archive_via_dir(
+        &self,
+        archive: &Path,
+        tmp_dir: &Path,
+    ) -> Result<usize, anyhow::Error> {
+        let (ac_hashes, cas_hashes) = Self::unpack_archive(archive, tmp_dir)?;
+        for hash in cas_hashes {
+            let src = tmp_dir.join(CAS_DIR).join(&hash);
+            let digest = Digest::for_path(&src).await?;
+            if digest.hash != hash {
+                bail!("digest of output file does not match: {hash}");
+            }
+            self.prepare_file_to_move(&src).await?;
+            self.move_file_into_cache(&src, &digest).await?;
+        }
+        let mut imported_actions = 0;
+        for hash in ac_hashes {
+            let path = tmp_dir.join(AC_DIR).join(&hash);
+            let action_result = ActionResult::decode(std::fs::read(&path)?.as_slice())
+                .with_context(|| format!("invalid action result: {hash}"))?;
+            for file in &action_result.output_files {
+                let digest = file.digest.as_ref().unwrap();
+                if !self.is_blob_cached(digest).await {
+                    bail!("output file {:?} of action {hash} missing", file.path);
+                }
+            }
+            let digest = MessageDigest {
+                hash,
+                ..Default::default()
+            };
+            self.push_action_result(&digest, &action_result).await?;
+            imported_actions += 1;
+        }
+        Ok(imported_actions)
+    }
+
+    /// Unpacks the archive into the dir and returns the hashes of the AC and CAS entries.
+    ///
+    /// Only regular files are accepted, links could point outside of the dir.
+    fn unpack_archive(
+        archive: &Path,
+        dir: &Path,
+    ) -> Result<(Vec<String>, Vec<String>), anyhow::Error> {
+        std::fs::create_dir_all(dir.join(AC_DIR))?;
+        std::fs::create_dir_all(dir.join(CAS_DIR))?;
+        let file = File::open(archive)?;
+        let mut tar = tar::Archive::new(GzDecoder::new(BufReader::new(file)));
+        let mut ac_hashes = vec![];
+        let mut cas_hashes = vec![];
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let path: PathBuf = entry.path()?.into();
+            if !entry.header().entry_type().is_file() {
+                bail!("unexpected entry type: {path:?}");
+            }
+            let (kind, hash) = match path.components().collect_vec().as_slice() {
+                [Component::Normal(kind), Component::Normal(hash)] => (
+                    kind.to_str().unwrap_or_default(),
+                    hash.to_str().unwrap_or_default().to_string(),
+                ),
+                _ => bail!("unexpected entry: {path:?}"),
+            };
+            if hash.len() != 64 || !hash.chars().all(|x| x.is_ascii_hexdigit()) {
+                bail!("unexpected entry: {path:?}");
+            }
+            match kind {
+                AC_DIR => ac_hashes.push(hash.clone()),
+                CAS_DIR => cas_hashes.push(hash.clone()),
+                _ => bail!("unexpected entry: {path:?}"),
+            }
+            entry.unpack(dir.join(kind).join(hash))?;
+        }
+        Ok((ac_hashes, cas_hashes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_remote_exec::OutputFile;
+    use crate::new_tmp_dir;
+
+    #[tokio::test]
+    async fn export_and_import_archive() {
+        let dir = new_tmp_dir!();
+        let src_cache = LocalCache::new(dir.join("src-cache")).unwrap();
+        let output = dir.join_and_write_file("output.txt", "some content");
+        let output_digest = Digest::for_path(&output).await.unwrap();
+        src_cache.prepare_file_to_move(&output).await.unwrap();
+        src_cache
+            .move_file_into_cache(&output, &output_digest)
+            .await
+            .unwrap();
+        let action_digest = Digest::for_string(&"action".to_string());
+        let action_result = ActionResult {
+            output_files: vec![OutputFile {
+                path: "output.txt".into(),
+                digest: Some(output_digest.clone()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        src_cache
+            .push_action_result(&action_digest, &action_result)
+            .await
+            .unwrap();
+        let not_cached = Digest::for_string(&"not-cached".to_string());
+        let archive = dir.join("cache.tar.gz");
+        assert_eq!(
+            src_cache
+                .export_archive(&[action_digest.clone(), not_cached], &archive)
+                .await
+                .unwrap(),
+            1
+        );
+        let dst_cache = LocalCache::new(dir.join("dst-cache")).unwrap();
+        assert_eq!(dst_cache.import_archive(&archive).await.unwrap(), 1);
+        assert_eq!(
+            dst_cache.get_action_result(&action_digest).await,
+            Some(action_result)
+        );
+        assert!(dst_cache.is_blob_cached(&output_digest).await);
+        assert!(!dir
+            .join("dst-cache/import")
+            .read_dir()
+            .unwrap()
+            .any(|_| true));
+    }
+
+    #[tokio::test]
+    async fn import_archive_with_modified_blob() {
+        let dir = new_tmp_dir!();
+        let hash = Digest::for_string(&"expected".to_string()).hash;
+        let archive = dir.join("cache.tar.gz");
+        {
+            let file = File::create(&archive).unwrap();
+            let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(8);
+            header.set_mode(0o444);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("cas/{hash}"), "modified".as_bytes())
+                .unwrap();
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+        let cache = LocalCache::new(dir.join("cache")).unwrap();
+        let error = cache.import_archive(&archive).await.unwrap_err();
+        assert!(
+            format!("{error:#}").contains(&format!("digest of output file does not match: {hash}"))
+        );
+        assert!(!cache
+            .cas_path(&Digest {
+                hash,
+                size_bytes: 8
+            })
+            .exists());
+    }
+
+    #[tokio::test]
+    async fn import_archive_with_link() {
+        let dir = new_tmp_dir!();
+        let hash = Digest::for_string(&"expected".to_string()).hash;
+        for entry_type in [tar::EntryType::Symlink, tar::EntryType::Link] {
+            let archive = dir.join("cache.tar.gz");
+            {
+                let file = File::create(&archive).unwrap();
+                let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(entry_type);
+                header.set_size(0);
+                builder
+                    .append_link(&mut header, format!("cas/{hash}"), "/etc/passwd")
+                    .unwrap();
+                builder.into_inner().unwrap().finish().unwrap();
+            }
+            let cache = LocalCache::new(dir.join("cache")).unwrap();
+            let error = cache.import_archive(&archive).await.unwrap_err();
+            assert!(
+                format!("{error:#}").contains("unexpected entry type"),
+                "{error:#}"
+            );
+        }
+    }
+}
@@ -1,21 +1,29 @@
 use crate::bazel_remote_exec;
 use crate::bazel_remote_exec::Digest;
 use anyhow::Context;
+use prost::bytes::buf::UninitSlice;
+use prost::bytes::BufMut;
 use sha2::Sha256;
 use std::fmt::Debug;
 use std::path::Path;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 pub type MessageDigest = Digest;
 pub type BlobDigest = Digest;
 
 impl Digest {
     pub async fn for_file(file: File) -> Result<BlobDigest, anyhow::Error> {
+        Self::for_reader(file).await
+    }
+
+    /// Hash the content of a reader without reading it into memory
+    pub async fn for_reader<R: AsyncRead + Unpin>(
+        mut reader: R,
+    ) -> Result<BlobDigest, anyhow::Error> {
         use sha2::Digest;
-        let mut reader = BufReader::new(file);
         let mut hasher = Sha256::new();
-        let mut buffer = [0; 1024];
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
         let mut len = 0;
         loop {
             let count = reader.read(&mut buffer).await?;
@@ -31,6 +39,26 @@ impl Digest {
         })
     }
 
+    /// Blocking variant of [Self::for_reader]
+    pub fn for_std_reader(reader: &mut dyn std::io::Read) -> Result<BlobDigest, anyhow::Error> {
+        use sha2::Digest;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
+        let mut len = 0;
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buffer[..count]);
+            len += count;
+        }
+        Ok(bazel_remote_exec::Digest {
+            hash: Self::hex(&hasher.finalize()),
+            size_bytes: len as i64,
+        })
+    }
+
     pub async fn for_path(path: impl AsRef<Path> + Debug) -> Result<BlobDigest, anyhow::Error> {
         let file = File::open(&path)
             .await
@@ -48,8 +76,11 @@ impl Digest {
         }
     }
 
+    /// Hash the encoded message without encoding it into a buffer first
     pub fn for_message<T: prost::Message>(msg: &T) -> MessageDigest {
-        Self::for_bytes(message_to_pb_buf(msg))
+        let mut writer = HashWriter::new();
+        msg.encode(&mut writer).unwrap();
+        writer.finalize()
     }
 
     pub fn for_string(text: &String) -> MessageDigest {
@@ -61,6 +92,64 @@ impl Digest {
     }
 }
 
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Feeds bytes written by the prost encoder into the hasher via a fixed size buffer
+struct HashWriter {
+    hasher: Sha256,
+    buffer: Vec<u8>,
+    filled: usize,
+    len: usize,
+}
+
+impl HashWriter {
+    fn new() -> Self {
+        use sha2::Digest;
+        Self {
+            hasher: Sha256::new(),
+            buffer: vec![0; HASH_BUFFER_SIZE],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        use sha2::Digest;
+        self.hasher.update(&self.buffer[..self.filled]);
+        self.filled = 0;
+    }
+
+    fn finalize(mut self) -> MessageDigest {
+        use sha2::Digest;
+        self.flush();
+        bazel_remote_exec::Digest {
+            hash: bazel_remote_exec::Digest::hex(&self.hasher.finalize()),
+            size_bytes: self.len as i64,
+        }
+    }
+}
+
+// SAFETY: chunk_mut() returns the unfilled part of the initialized buffer,
+// advance_mut() is only called with counts within that part.
+unsafe impl BufMut for HashWriter {
+    fn remaining_mut(&self) -> usize {
+        isize::MAX as usize - self.len
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        assert!(self.filled + cnt <= self.buffer.len());
+        self.filled += cnt;
+        self.len += cnt;
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        if self.filled == self.buffer.len() {
+            self.flush();
+        }
+        UninitSlice::new(&mut self.buffer[self.filled..])
+    }
+}
+
 pub fn message_to_pb_buf<T: prost::Message>(msg: &T) -> Vec<u8> {
     let mut vec = Vec::with_capacity(msg.encoded_len());
     msg.encode(&mut vec).unwrap();
@@ -72,6 +161,7 @@ mod tests {
     use sha2::Digest;
 
     use super::*;
+    use serial_test::serial;
 
     fn digest_file_sha256_simple(path: impl AsRef<Path>) -> Result<super::Digest, anyhow::Error> {
         let bytes = std::fs::read(path)?;
@@ -117,6 +207,76 @@ mod tests {
         assert_eq!(act, exp);
     }
 
+    #[tokio::test]
+    async fn digest_for_reader_equals_for_file() {
+        let path = "Cargo.lock";
+        let for_file = super::Digest::for_file(File::open(path).await.unwrap())
+            .await
+            .unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        let for_reader = super::Digest::for_reader(bytes.as_slice()).await.unwrap();
+        assert_eq!(for_reader, for_file);
+    }
+
+    /// The input is streamed from a generator, therefore it's never in memory.
+    ///
+    /// serial because the peak memory is measured for the whole test process
+    #[tokio::test]
+    #[serial]
+    async fn digest_for_large_reader() {
+        let len = 64 * 1024 * 1024 + 1;
+        let reader = tokio::io::repeat(b'x').take(len);
+        #[cfg(target_os = "linux")]
+        let peak_before = crate::test_utils::peak_memory_kb();
+        let act = super::Digest::for_reader(reader).await.unwrap();
+        #[cfg(target_os = "linux")]
+        {
+            let increase = crate::test_utils::peak_memory_kb() - peak_before;
+            assert!(
+                increase < 16 * 1024,
+                "peak memory increased by {increase} kB"
+            );
+        }
+        let mut hasher = Sha256::new();
+        let chunk = [b'x'; 1024];
+        for _ in 0..len / 1024 {
+            hasher.update(chunk);
+        }
+        hasher.update(b"x");
+        assert_eq!(
+            act,
+            super::Digest {
+                hash: super::Digest::hex(&hasher.finalize()),
+                size_bytes: len as i64,
+            }
+        );
+    }
+
+    #[test]
+    fn digest_for_message_equals_for_bytes() {
+        use crate::bazel_remote_exec::{ActionResult, OutputFile};
+        let small = ActionResult {
+            exit_code: 1,
+            ..Default::default()
+        };
+        // larger than the buffer of HashWriter
+        let large = ActionResult {
+            output_files: vec![OutputFile {
+                path: "a".into(),
+                contents: vec![1; 3 * HASH_BUFFER_SIZE + 7],
+                ..Default::default()
+            }],
+            stdout_raw: vec![2; HASH_BUFFER_SIZE],
+            ..Default::default()
+        };
+        for msg in [ActionResult::default(), small, large] {
+            assert_eq!(
+                super::Digest::for_message(&msg),
+                super::Digest::for_bytes(message_to_pb_buf(&msg))
+            );
+        }
+    }
+
     #[test]
     fn digest_for_string() {
         assert_eq!(
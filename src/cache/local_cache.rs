@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Context};
 use log::warn;
@@ -7,15 +9,32 @@ use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
 use crate::bazel_remote_exec::{ActionResult, Digest, OutputFile};
-use crate::cache::{message_to_pb_buf, BlobDigest, MessageDigest};
+use crate::cache::{message_to_pb_buf, BlobDigest, MessageDigest, OutDirIndex};
 use crate::config::LinkType;
-use crate::{force_remove_file, set_file_readonly, write_gitignore};
+use crate::{force_remove_file, set_file_readonly, set_file_unix_mode, write_gitignore, FileLock};
+
+/// Locked by running razel processes, see [FileLock]
+pub const LOCK_FILE_NAME: &str = "lock";
+
+/// key: blob hash and Unix mode
+type OutDirCopies = HashMap<(String, Option<u32>), PathBuf>;
 
 #[derive(Clone)]
 pub struct LocalCache {
     pub dir: PathBuf,
     ac_dir: PathBuf,
     cas_dir: PathBuf,
+    /// how output files are linked into out_dir
+    pub out_dir_link_type: LinkType,
+    /// drop write permissions of output files in out_dir, also of copies
+    pub out_dir_readonly: bool,
+    /// first copy of each blob and mode in out_dir, identical readonly output files are hardlinked
+    /// to it
+    out_dir_copies: Arc<Mutex<OutDirCopies>>,
+    /// loaded on first use
+    out_dir_index: Arc<Mutex<Option<Arc<OutDirIndex>>>>,
+    /// shared lock on the cache dir to block `razel clean` while in use
+    _lock: Arc<FileLock>,
 }
 
 impl LocalCache {
@@ -24,11 +43,19 @@ impl LocalCache {
         let cas_dir = dir.join("cas");
         std::fs::create_dir_all(&ac_dir)?;
         std::fs::create_dir_all(&cas_dir)?;
+        // absolute path allows comparing symlink targets in out_dir
+        let cas_dir = std::fs::canonicalize(&cas_dir)?;
         write_gitignore(&dir);
+        let lock = FileLock::shared(&dir.join(LOCK_FILE_NAME))?;
         Ok(Self {
             dir,
             ac_dir,
             cas_dir,
+            out_dir_link_type: crate::config::OUT_DIR_LINK_TYPE,
+            out_dir_readonly: false,
+            out_dir_copies: Default::default(),
+            out_dir_index: Default::default(),
+            _lock: Arc::new(lock),
         })
     }
 
@@ -36,8 +63,12 @@ impl LocalCache {
         self.cas_dir.join(&digest.hash)
     }
 
+    pub fn ac_path(&self, digest: &MessageDigest) -> PathBuf {
+        self.ac_dir.join(&digest.hash)
+    }
+
     pub async fn get_action_result(&self, digest: &MessageDigest) -> Option<ActionResult> {
-        let path = self.ac_dir.join(&digest.hash);
+        let path = self.ac_path(digest);
         match Self::try_read_pb_file(&path).await {
             Ok(x) => x,
             Err(x) => {
@@ -53,7 +84,7 @@ impl LocalCache {
         digest: &MessageDigest,
         result: &ActionResult,
     ) -> Result<(), anyhow::Error> {
-        let path = self.ac_dir.join(&digest.hash);
+        let path = self.ac_path(digest);
         Self::write_pb_file(&path, result)
             .await
             .with_context(|| format!("push_action_result(): {path:?}"))
@@ -110,22 +141,175 @@ impl LocalCache {
         Ok(dst)
     }
 
+    /// Links output files from the cache into out_dir and returns the number of created links.
+    ///
+    /// Files in out_dir which are already linked to the correct cache file are kept, the index of
+    /// materialized files avoids checking them again, see OutDirIndex.
+    /// Files with a Unix mode different from the cache file are copied to restore the mode, write
+    /// permissions are ignored when linking because cache files are readonly.
+    /// With out_dir_readonly, write permissions are dropped and files are always linked.
     pub async fn link_output_files_into_out_dir(
         &self,
         output_files: &Vec<OutputFile>,
         out_dir: &Path,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<usize, anyhow::Error> {
+        let index = self.out_dir_index(out_dir);
+        let mut linked = 0;
         for file in output_files {
-            let cas_path = self.cas_path(file.digest.as_ref().unwrap());
+            let digest = file.digest.as_ref().unwrap();
+            let cas_path = self.cas_path(digest);
             let out_path = out_dir.join(&file.path);
-            match crate::config::OUT_DIR_LINK_TYPE {
-                LinkType::Hardlink => crate::force_hardlink(&cas_path, &out_path).await?,
+            let mode = file
+                .node_properties
+                .as_ref()
+                .and_then(|x| x.unix_mode)
+                .map(|x| if self.out_dir_readonly { x & !0o222 } else { x });
+            let link_type = match mode {
+                Some(x) if !Self::has_readonly_mode(&cas_path, x).await => LinkType::Copy,
+                _ => self.out_dir_link_type,
+            };
+            if index
+                .is_materialized(&file.path, &out_path, digest, mode, link_type)
+                .await
+            {
+                continue;
+            }
+            if Self::is_linked(link_type, &cas_path, &out_path).await {
+                index
+                    .insert(&file.path, &out_path, digest, mode, link_type)
+                    .await;
+                continue;
+            }
+            match link_type {
+                LinkType::Hardlink => match crate::force_hardlink(&cas_path, &out_path).await {
+                    Err(e) if crate::is_cross_device_error(&e) => {
+                        self.copy_into_out_dir(digest, mode, &cas_path, &out_path)
+                            .await?
+                    }
+                    x => x?,
+                },
                 LinkType::Symlink => crate::force_symlink(&cas_path, &out_path).await?,
+                LinkType::Copy => {
+                    self.copy_into_out_dir(digest, mode, &cas_path, &out_path)
+                        .await?
+                }
             }
+            index
+                .insert(&file.path, &out_path, digest, mode, link_type)
+                .await;
+            linked += 1;
+        }
+        Ok(linked)
+    }
+
+    fn out_dir_index(&self, out_dir: &Path) -> Arc<OutDirIndex> {
+        let mut index = self.out_dir_index.lock().unwrap();
+        match index.as_ref().filter(|x| x.is_for_out_dir(out_dir)) {
+            Some(x) => x.clone(),
+            None => index
+                .insert(Arc::new(OutDirIndex::open(&self.dir, out_dir)))
+                .clone(),
+        }
+    }
+
+    /// Copies a blob into out_dir and sets its mode, but hardlinks to an existing readonly copy of
+    /// the same blob to not store identical output files multiple times.
+    ///
+    /// Writable copies are never shared to keep them independent.
+    async fn copy_into_out_dir(
+        &self,
+        digest: &Digest,
+        mode: Option<u32>,
+        cas_path: &PathBuf,
+        out_path: &PathBuf,
+    ) -> Result<(), anyhow::Error> {
+        let key = (digest.hash.clone(), mode);
+        let existing = self.out_dir_copies.lock().unwrap().get(&key).cloned();
+        if let Some(existing) = existing.filter(|x| x != out_path) {
+            if Self::is_unmodified_readonly_copy(&existing, digest).await
+                && crate::force_hardlink(&existing, out_path).await.is_ok()
+            {
+                return Ok(());
+            }
+        }
+        crate::force_copy(cas_path, out_path).await?;
+        if let Some(mode) = mode {
+            set_file_unix_mode(out_path, mode)
+                .await
+                .with_context(|| format!("set_file_unix_mode() {out_path:?}"))?;
+        }
+        // also without mode, e.g. for results of older razel versions
+        if self.out_dir_readonly {
+            set_file_readonly(out_path)
+                .await
+                .with_context(|| format!("set_file_readonly() {out_path:?}"))?;
+        }
+        // modifying a writable copy must not change other output files
+        if tokio::fs::metadata(out_path)
+            .await
+            .is_ok_and(|x| x.permissions().readonly())
+        {
+            self.out_dir_copies
+                .lock()
+                .unwrap()
+                .insert(key, out_path.clone());
         }
         Ok(())
     }
 
+    /// The existing copy might have been removed or made writable and modified in the meantime
+    async fn is_unmodified_readonly_copy(path: &Path, digest: &Digest) -> bool {
+        match tokio::fs::metadata(path).await {
+            Ok(x) if x.permissions().readonly() && x.len() == digest.size_bytes as u64 => {}
+            _ => return false,
+        }
+        BlobDigest::for_path(path)
+            .await
+            .is_ok_and(|x| x.hash == digest.hash)
+    }
+
+    /// Copies are always recreated to not compare the content
+    async fn is_linked(link_type: LinkType, cas_path: &Path, out_path: &Path) -> bool {
+        match link_type {
+            LinkType::Hardlink => Self::is_same_file(cas_path, out_path).await,
+            LinkType::Symlink => tokio::fs::read_link(out_path)
+                .await
+                .is_ok_and(|x| x == cas_path),
+            LinkType::Copy => false,
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    async fn is_same_file(cas_path: &Path, out_path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (
+            tokio::fs::metadata(cas_path).await,
+            tokio::fs::symlink_metadata(out_path).await,
+        ) {
+            (Ok(cas), Ok(out)) => cas.dev() == out.dev() && cas.ino() == out.ino(),
+            _ => false,
+        }
+    }
+
+    /// Checks if a link to the readonly cache file has the mode ignoring write permissions
+    #[cfg(target_family = "unix")]
+    async fn has_readonly_mode(cas_path: &Path, mode: u32) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::metadata(cas_path)
+            .await
+            .is_ok_and(|x| x.permissions().mode() & 0o777 == mode & 0o555)
+    }
+
+    #[cfg(target_family = "windows")]
+    async fn has_readonly_mode(_cas_path: &Path, _mode: u32) -> bool {
+        true
+    }
+
+    #[cfg(target_family = "windows")]
+    async fn is_same_file(_cas_path: &Path, _out_path: &Path) -> bool {
+        false
+    }
+
     async fn try_read_pb_file<T: prost::Message + Default>(
         path: &PathBuf,
     ) -> Result<Option<T>, anyhow::Error> {
@@ -155,6 +339,7 @@ impl LocalCache {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bazel_remote_exec::NodeProperties;
     use crate::new_tmp_dir;
     use std::time::Duration;
 
@@ -176,4 +361,197 @@ mod tests {
         let dst_mtime = dst.metadata().unwrap().modified().unwrap();
         assert_eq!(dst_mtime, src_mtime);
     }
+
+    #[tokio::test]
+    async fn link_output_files_into_out_dir() {
+        for (link_type, exp_linked) in [
+            (LinkType::Symlink, [1, 0, 1]),
+            (LinkType::Hardlink, [1, 0, 1]),
+            (LinkType::Copy, [1, 0, 1]),
+        ] {
+            let dir = new_tmp_dir!();
+            let mut cache = LocalCache::new(dir.join("cache")).unwrap();
+            cache.out_dir_link_type = link_type;
+            let out_dir = dir.join("out");
+            let src = dir.join_and_write_file("out/output.txt", "some content");
+            let digest = Digest::for_path(&src).await.unwrap();
+            cache.prepare_file_to_move(&src).await.unwrap();
+            cache.move_file_into_cache(&src, &digest).await.unwrap();
+            let cas_path = cache.cas_path(&digest);
+            let output_files = vec![OutputFile {
+                path: "output.txt".into(),
+                digest: Some(digest),
+                ..Default::default()
+            }];
+            let mut linked = vec![];
+            for tamper in [false, false, true] {
+                if tamper {
+                    force_remove_file(&src).await.unwrap();
+                    std::fs::write(&src, "tampered").unwrap();
+                }
+                linked.push(
+                    cache
+                        .link_output_files_into_out_dir(&output_files, &out_dir)
+                        .await
+                        .unwrap(),
+                );
+            }
+            // no re-link on warm run without changes, but tampered file must be replaced
+            assert_eq!(linked, exp_linked, "{link_type:?}");
+            assert_eq!(std::fs::read_to_string(&src).unwrap(), "some content");
+            let is_symlink = std::fs::symlink_metadata(&src).unwrap().is_symlink();
+            assert_eq!(is_symlink, link_type == LinkType::Symlink);
+            #[cfg(target_family = "unix")]
+            assert_eq!(
+                LocalCache::is_same_file(&cas_path, &src).await,
+                link_type == LinkType::Hardlink
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn out_dir_readonly() {
+        for (out_dir_readonly, unix_mode) in
+            [(false, Some(0o644)), (true, Some(0o644)), (true, None)]
+        {
+            let dir = new_tmp_dir!();
+            let mut cache = LocalCache::new(dir.join("cache")).unwrap();
+            cache.out_dir_link_type = LinkType::Copy;
+            cache.out_dir_readonly = out_dir_readonly;
+            let out_dir = dir.join("out");
+            let src = dir.join_and_write_file("out/output.txt", "some content");
+            let digest = Digest::for_path(&src).await.unwrap();
+            cache.prepare_file_to_move(&src).await.unwrap();
+            cache.move_file_into_cache(&src, &digest).await.unwrap();
+            let output_files = vec![OutputFile {
+                path: "output.txt".into(),
+                digest: Some(digest.clone()),
+                node_properties: unix_mode.map(|x| NodeProperties {
+                    unix_mode: Some(x),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }];
+            for _ in 0..2 {
+                // edited file must be replaced, also if it was readonly before
+                force_remove_file(&src).await.unwrap();
+                std::fs::write(&src, "edited").unwrap();
+                cache
+                    .link_output_files_into_out_dir(&output_files, &out_dir)
+                    .await
+                    .unwrap();
+                assert_eq!(std::fs::read_to_string(&src).unwrap(), "some content");
+                let readonly = std::fs::metadata(&src).unwrap().permissions().readonly();
+                assert_eq!(readonly, out_dir_readonly);
+                // permissions are not checked for root
+                #[cfg(target_family = "unix")]
+                if out_dir_readonly && unsafe { libc::geteuid() } != 0 {
+                    assert!(std::fs::write(&src, "edited").is_err());
+                }
+            }
+            assert!(cache.is_blob_cached(&digest).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn out_dir_index() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let new_cache = || {
+            let mut cache = LocalCache::new(cache_dir.clone()).unwrap();
+            cache.out_dir_link_type = LinkType::Copy;
+            cache
+        };
+        let cache = new_cache();
+        let out_dir = dir.join("out");
+        let src = dir.join_and_write_file("out/output.txt", "some content");
+        let digest = Digest::for_path(&src).await.unwrap();
+        cache.prepare_file_to_move(&src).await.unwrap();
+        cache.move_file_into_cache(&src, &digest).await.unwrap();
+        let output_files = vec![OutputFile {
+            path: "output.txt".into(),
+            digest: Some(digest),
+            ..Default::default()
+        }];
+        let link = |cache: LocalCache| {
+            let output_files = output_files.clone();
+            let out_dir = out_dir.clone();
+            async move {
+                cache
+                    .link_output_files_into_out_dir(&output_files, &out_dir)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(link(cache.clone()).await, 1);
+        assert_eq!(link(cache).await, 0);
+        // index is loaded from the cache dir
+        assert_eq!(link(new_cache()).await, 0);
+        // modified in place with same size and inode
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        crate::drop_readonly_flag(&src, std::fs::metadata(&src).unwrap())
+            .await
+            .unwrap();
+        std::fs::write(&src, "SOME CONTENT").unwrap();
+        assert_eq!(link(new_cache()).await, 1);
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "some content");
+        assert_eq!(link(new_cache()).await, 0);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn identical_output_files_share_inode() {
+        use std::os::unix::fs::MetadataExt;
+        for link_type in [LinkType::Hardlink, LinkType::Copy] {
+            let dir = new_tmp_dir!();
+            let mut cache = LocalCache::new(dir.join("cache")).unwrap();
+            cache.out_dir_link_type = link_type;
+            let out_dir = dir.join("out");
+            let src = dir.join_and_write_file("src.txt", "same content");
+            let digest = Digest::for_path(&src).await.unwrap();
+            cache.prepare_file_to_move(&src).await.unwrap();
+            cache.move_file_into_cache(&src, &digest).await.unwrap();
+            let output_files = ["a.txt", "b/b.txt"].map(|x| OutputFile {
+                path: x.into(),
+                digest: Some(digest.clone()),
+                ..Default::default()
+            });
+            // linked by separate commands
+            for file in &output_files {
+                cache
+                    .link_output_files_into_out_dir(&vec![file.clone()], &out_dir)
+                    .await
+                    .unwrap();
+            }
+            let [a, b] = output_files
+                .clone()
+                .map(|x| std::fs::metadata(out_dir.join(x.path)).unwrap());
+            assert_eq!(a.ino(), b.ino(), "{link_type:?}");
+            assert_eq!(a.nlink(), if link_type == LinkType::Copy { 2 } else { 3 });
+            if link_type == LinkType::Copy {
+                // a modified copy with the same size and mode is not shared anymore
+                let a = out_dir.join(&output_files[0].path);
+                crate::drop_readonly_flag(&a, std::fs::metadata(&a).unwrap())
+                    .await
+                    .unwrap();
+                std::fs::write(&a, "SAME CONTENT").unwrap();
+                set_file_readonly(&a).await.unwrap();
+                let c = OutputFile {
+                    path: "c.txt".into(),
+                    digest: Some(digest.clone()),
+                    ..Default::default()
+                };
+                cache
+                    .link_output_files_into_out_dir(&vec![c.clone()], &out_dir)
+                    .await
+                    .unwrap();
+                let c = out_dir.join(c.path);
+                assert_eq!(std::fs::read_to_string(&c).unwrap(), "same content");
+                assert_ne!(
+                    std::fs::metadata(&c).unwrap().ino(),
+                    std::fs::metadata(&a).unwrap().ino()
+                );
+            }
+        }
+    }
 }
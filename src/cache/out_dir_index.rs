@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::bazel_remote_exec::Digest;
+use crate::config::LinkType;
+
+/// Persisted index of the files materialized from the local cache into out_dir.
+///
+/// Allows skipping re-links of unchanged files without comparing them to the cache file.
+/// Entries are appended to a jsonl file in the cache dir, later lines overwrite earlier ones.
+/// Files modified in out_dir are detected by their metadata and are then checked as without index.
+pub struct OutDirIndex {
+    out_dir: PathBuf,
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    /// opened on first append
+    file: Option<File>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct Entry {
+    /// relative to out_dir
+    path: PathBuf,
+    hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+    link_type: LinkType,
+    #[serde(flatten)]
+    stat: Stat,
+}
+
+/// Metadata of the file in out_dir, not following symlinks
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct Stat {
+    len: u64,
+    /// [ns] since UNIX_EPOCH
+    mtime: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ino: Option<u64>,
+}
+
+impl Stat {
+    async fn new(path: &Path) -> Option<Self> {
+        let metadata = tokio::fs::symlink_metadata(path).await.ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        #[cfg(target_family = "unix")]
+        let ino = Some(std::os::unix::fs::MetadataExt::ino(&metadata));
+        #[cfg(not(target_family = "unix"))]
+        let ino = None;
+        Some(Self {
+            len: metadata.len(),
+            mtime: mtime.as_nanos() as u64,
+            ino,
+        })
+    }
+}
+
+impl OutDirIndex {
+    /// Loads the index of out_dir from the cache dir, a missing or broken index is ignored
+    pub fn open(cache_dir: &Path, out_dir: &Path) -> Self {
+        let out_dir = std::env::current_dir()
+            .map(|x| x.join(out_dir))
+            .unwrap_or_else(|_| out_dir.to_path_buf());
+        let name = Digest::for_string(&out_dir.to_string_lossy().to_string()).hash;
+        let path = cache_dir.join("out-dir-index").join(name + ".jsonl");
+        let mut state = State::default();
+        let lines = Self::load(&path, &mut state.entries);
+        if lines > 2 * state.entries.len() + 1000 {
+            Self::compact(&path, &state.entries);
+        }
+        Self {
+            out_dir,
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn is_for_out_dir(&self, out_dir: &Path) -> bool {
+        std::env::current_dir().is_ok_and(|x| x.join(out_dir) == self.out_dir)
+    }
+
+    /// Checks if the file was materialized with the same digest, mode and link type and was not
+    /// modified since
+    pub async fn is_materialized(
+        &self,
+        file_path: &str,
+        out_path: &Path,
+        digest: &Digest,
+        mode: Option<u32>,
+        link_type: LinkType,
+    ) -> bool {
+        let entry = self
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .get(Path::new(file_path))
+            .cloned();
+        let Some(entry) = entry else {
+            return false;
+        };
+        entry.hash == digest.hash
+            && entry.mode == mode
+            && entry.link_type == link_type
+            && Stat::new(out_path).await.is_some_and(|x| x == entry.stat)
+    }
+
+    /// Records a materialized file, to be called after linking or copying it
+    pub async fn insert(
+        &self,
+        file_path: &str,
+        out_path: &Path,
+        digest: &Digest,
+        mode: Option<u32>,
+        link_type: LinkType,
+    ) {
+        let Some(stat) = Stat::new(out_path).await else {
+            return;
+        };
+        let entry = Entry {
+            path: file_path.into(),
+            hash: digest.hash.clone(),
+            mode,
+            link_type,
+            stat,
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.entries.get(&entry.path) == Some(&entry) {
+            return;
+        }
+        if let Err(e) = self.append(&mut state.file, &entry) {
+            warn!("failed to update {:?}: {e}", self.path);
+        }
+        state.entries.insert(entry.path.clone(), entry);
+    }
+
+    /// Returns the number of lines
+    fn load(path: &Path, entries: &mut HashMap<PathBuf, Entry>) -> usize {
+        let Ok(file) = File::open(path) else {
+            return 0;
+        };
+        let mut lines = 0;
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            lines += 1;
+            // an incomplete last line might be left if razel was killed
+            if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+                entries.insert(entry.path.clone(), entry);
+            }
+        }
+        lines
+    }
+
+    /// Rewrites the file without overwritten entries
+    fn compact(path: &Path, entries: &HashMap<PathBuf, Entry>) {
+        let tmp = path.with_extension("jsonl.tmp");
+        let result = (|| -> std::io::Result<()> {
+            let mut file = File::create(&tmp)?;
+            for entry in entries.values() {
+                writeln!(file, "{}", serde_json::to_string(entry)?)?;
+            }
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            warn!("failed to compact {path:?}: {e}");
+        }
+    }
+
+    fn append(&self, file: &mut Option<File>, entry: &Entry) -> std::io::Result<()> {
+        if file.is_none() {
+            std::fs::create_dir_all(self.path.parent().unwrap())?;
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        // a single write to not interleave lines of concurrent razel processes
+        let line = serde_json::to_string(entry)? + "\n";
+        file.as_mut().unwrap().write_all(line.as_bytes())
+    }
+}
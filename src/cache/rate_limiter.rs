@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Token bucket to limit the bandwidth of concurrent transfers, e.g. remote cache uploads
+///
+/// Transfers bigger than the bucket are allowed by going into debt, therefore waiting never blocks
+/// other transfers from reserving their share.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Option<Arc<Mutex<Bucket>>>,
+}
+
+struct Bucket {
+    /// bytes per second
+    rate: f64,
+    /// max tokens to allow bursts of up to one second
+    capacity: f64,
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter for the given bytes per second, 0 means unlimited
+    pub fn new(bytes_per_sec: u64) -> Self {
+        if bytes_per_sec == 0 {
+            return Self { bucket: None };
+        }
+        let rate = bytes_per_sec as f64;
+        Self {
+            bucket: Some(Arc::new(Mutex::new(Bucket {
+                rate,
+                capacity: rate,
+                tokens: rate,
+                last_update: Instant::now(),
+            }))),
+        }
+    }
+
+    /// Waits until the given number of bytes may be transferred
+    pub async fn acquire(&self, bytes: u64) {
+        let Some(bucket) = &self.bucket else {
+            return;
+        };
+        let wait = bucket.lock().unwrap().reserve(bytes as f64, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl Bucket {
+    /// Takes the tokens and returns the time to wait until they would have been available
+    fn reserve(&mut self, bytes: f64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_update = now;
+        self.tokens -= bytes;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unlimited() {
+        let start = Instant::now();
+        RateLimiter::new(0).acquire(u64::MAX).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    /// 5 concurrent transfers of 100 kB with 200 kB/s: 200 kB burst, 300 kB need 1.5 s
+    #[test]
+    fn concurrent_transfers() {
+        let now = Instant::now();
+        let mut bucket = Bucket {
+            rate: 200_000.0,
+            capacity: 200_000.0,
+            tokens: 200_000.0,
+            last_update: now,
+        };
+        let waits = (0..5)
+            .map(|_| bucket.reserve(100_000.0, now).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(waits, [0, 0, 500, 1000, 1500]);
+        // tokens are refilled over time
+        let waits = bucket.reserve(100_000.0, now + Duration::from_secs(2));
+        assert_eq!(waits, Duration::ZERO);
+    }
+}
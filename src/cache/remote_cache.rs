@@ -6,51 +6,137 @@ use crate::bazel_remote_exec::{
     BatchUpdateBlobsRequest, Digest, GetActionResultRequest, GetCapabilitiesRequest, OutputFile,
     ServerCapabilities, UpdateActionResultRequest,
 };
-use crate::cache::{BlobDigest, MessageDigest};
+use crate::cache::{BlobDigest, MessageDigest, RateLimiter};
 use crate::make_file_executable;
 use anyhow::{anyhow, bail, Context};
 use log::warn;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{mpsc, Notify};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
 use tonic::transport::{Channel, Uri};
-use tonic::Code;
+use tonic::{Code, Status};
+
+type AuthChannel = InterceptedService<Channel, AuthInterceptor>;
+
+/// Adds the `authorization` header to all requests if a token is set
+#[derive(Clone)]
+struct AuthInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl AuthInterceptor {
+    fn new(token: Option<&str>) -> anyhow::Result<Self> {
+        let authorization = token
+            .map(|x| format!("Bearer {x}").parse())
+            .transpose()
+            .context("remote cache auth token contains invalid characters")?;
+        Ok(Self { authorization })
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if let Some(x) = &self.authorization {
+            request.metadata_mut().insert("authorization", x.clone());
+        }
+        Ok(request)
+    }
+}
 
 // TODO add Zstd compression for blobs
 #[derive(Clone)]
 pub struct GrpcRemoteCache {
     instance_name: String,
     download_dir: PathBuf,
-    ac_client: ActionCacheClient<Channel>,
-    cas_client: ContentAddressableStorageClient<Channel>,
+    ac_client: ActionCacheClient<AuthChannel>,
+    cas_client: ContentAddressableStorageClient<AuthChannel>,
     max_batch_blob_size: i64,
-    ac_upload_tx: UnboundedSender<(MessageDigest, ActionResult)>,
-    cas_upload_tx: UnboundedSender<(BlobDigest, PathBuf)>,
+    ac_upload_tx: Sender<(MessageDigest, ActionResult)>,
+    cas_upload_tx: Sender<(BlobDigest, PathBuf)>,
+    uploads: Arc<Uploads>,
+    download_limiter: RateLimiter,
+}
+
+/// Counts uploads which are queued or in flight to wait for them before exiting
+#[derive(Default)]
+struct Uploads {
+    pending: AtomicUsize,
+    uploaded: AtomicUsize,
+    failed: AtomicUsize,
+    finished: Notify,
+}
+
+impl Uploads {
+    fn finish(&self, success: bool) {
+        if success {
+            self.uploaded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        self.finished.notify_waiters();
+    }
+
+    /// Drop queued uploads after the upload task stopped on an error
+    fn drop_queued<T>(&self, rx: &mut Receiver<T>) {
+        rx.close();
+        while rx.try_recv().is_ok() {
+            self.finish(false);
+        }
+    }
+}
+
+/// Result of GrpcRemoteCache::flush()
+#[derive(Debug, PartialEq)]
+pub struct FlushedUploads {
+    /// uploads finished while flushing
+    pub flushed: usize,
+    /// uploads which failed or did not finish before the timeout
+    pub dropped: usize,
 }
 
 impl GrpcRemoteCache {
-    pub async fn new(uri: Uri, dir: &Path) -> anyhow::Result<Self> {
-        let instance_name = uri
-            .path()
-            .strip_prefix('/')
-            .unwrap_or(uri.path())
-            .to_string();
-        let uri_wo_instance_name = Uri::builder()
-            .scheme("grpc")
-            .authority(uri.authority().unwrap().clone())
-            .path_and_query("")
-            .build()
-            .unwrap();
+    /// At most `max_inflight_uploads` AC and CAS uploads each are queued, pushing more waits.
+    ///
+    /// `auth_token` is sent as bearer token with each request.
+    pub async fn new(
+        uri: Uri,
+        dir: &Path,
+        upload_limiter: RateLimiter,
+        download_limiter: RateLimiter,
+        max_inflight_uploads: usize,
+        auth_token: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let (uri_wo_instance_name, instance_name) = Self::split_instance_name(&uri)?;
+        let auth = AuthInterceptor::new(auth_token)?;
         let download_dir = dir.join("download").join(std::process::id().to_string());
         std::fs::create_dir_all(&download_dir)?;
         let channel = Channel::builder(uri_wo_instance_name).connect().await?;
+        let channel = InterceptedService::new(channel, auth);
         let ac_client = ActionCacheClient::new(channel.clone());
         let cas_client = ContentAddressableStorageClient::new(channel.clone());
-        let (ac_upload_tx, ac_upload_rx) = mpsc::unbounded_channel();
-        let (cas_upload_tx, cas_upload_rx) = mpsc::unbounded_channel();
-        Self::spawn_ac_upload(instance_name.clone(), ac_client.clone(), ac_upload_rx);
-        Self::spawn_cas_upload(instance_name.clone(), cas_client.clone(), cas_upload_rx);
+        let (ac_upload_tx, ac_upload_rx) = mpsc::channel(max_inflight_uploads.max(1));
+        let (cas_upload_tx, cas_upload_rx) = mpsc::channel(max_inflight_uploads.max(1));
+        let uploads = Arc::new(Uploads::default());
+        Self::spawn_ac_upload(
+            instance_name.clone(),
+            ac_client.clone(),
+            ac_upload_rx,
+            uploads.clone(),
+        );
+        Self::spawn_cas_upload(
+            instance_name.clone(),
+            cas_client.clone(),
+            cas_upload_rx,
+            upload_limiter,
+            uploads.clone(),
+        );
         let mut client = Self {
             instance_name,
             download_dir,
@@ -59,16 +145,33 @@ impl GrpcRemoteCache {
             max_batch_blob_size: 0,
             ac_upload_tx,
             cas_upload_tx,
+            uploads,
+            download_limiter,
         };
         client.check_capabilities(channel.clone()).await?;
         Ok(client)
     }
 
-    async fn check_capabilities(&mut self, channel: Channel) -> anyhow::Result<()> {
+    /// Splits e.g. `grpc://host:port/projects/p/instances/i` into the URI of the server and the
+    /// instance name `projects/p/instances/i`, which may contain slashes.
+    fn split_instance_name(uri: &Uri) -> anyhow::Result<(Uri, String)> {
+        let authority = uri
+            .authority()
+            .ok_or_else(|| anyhow!("host missing in remote cache URI: {uri}"))?;
+        let instance_name = uri.path().trim_matches('/').to_string();
+        let uri_wo_instance_name = Uri::builder()
+            .scheme("grpc")
+            .authority(authority.clone())
+            .path_and_query("")
+            .build()?;
+        Ok((uri_wo_instance_name, instance_name))
+    }
+
+    async fn check_capabilities(&mut self, channel: AuthChannel) -> anyhow::Result<()> {
         let mut client = CapabilitiesClient::new(channel);
         let capabilities: ServerCapabilities = client
             .get_capabilities(tonic::Request::new(GetCapabilitiesRequest {
-                ..Default::default()
+                instance_name: self.instance_name.clone(),
             }))
             .await?
             .into_inner();
@@ -120,8 +223,9 @@ impl GrpcRemoteCache {
 
     fn spawn_ac_upload(
         instance_name: String,
-        mut client: ActionCacheClient<Channel>,
-        mut rx: UnboundedReceiver<(MessageDigest, ActionResult)>,
+        mut client: ActionCacheClient<AuthChannel>,
+        mut rx: Receiver<(MessageDigest, ActionResult)>,
+        uploads: Arc<Uploads>,
     ) {
         tokio::spawn(async move {
             while let Some((action_digest, action_result)) = rx.recv().await {
@@ -134,8 +238,9 @@ impl GrpcRemoteCache {
                     }))
                     .await
                 {
-                    Ok(_) => {}
+                    Ok(_) => uploads.finish(true),
                     Err(x) => {
+                        uploads.finish(false);
                         if x.code() != Code::Ok {
                             warn!("Remote cache error in update_action_result(): {:?}", x);
                             break;
@@ -143,6 +248,7 @@ impl GrpcRemoteCache {
                     }
                 }
             }
+            uploads.drop_queued(&mut rx);
         });
     }
 
@@ -150,11 +256,14 @@ impl GrpcRemoteCache {
     /// TODO upload multiple files at once, until max_batch_total_size_bytes
     fn spawn_cas_upload(
         instance_name: String,
-        mut client: ContentAddressableStorageClient<Channel>,
-        mut rx: UnboundedReceiver<(BlobDigest, PathBuf)>,
+        mut client: ContentAddressableStorageClient<AuthChannel>,
+        mut rx: Receiver<(BlobDigest, PathBuf)>,
+        limiter: RateLimiter,
+        uploads: Arc<Uploads>,
     ) {
         tokio::spawn(async move {
             while let Some((digest, path)) = rx.recv().await {
+                limiter.acquire(digest.size_bytes as u64).await;
                 let data = tokio::fs::read(&path)
                     .await
                     .with_context(|| format!("Read file from local cache: {:?}", path))
@@ -170,8 +279,9 @@ impl GrpcRemoteCache {
                     }))
                     .await
                 {
-                    Ok(_) => {}
+                    Ok(_) => uploads.finish(true),
                     Err(x) => {
+                        uploads.finish(false);
                         if x.code() != Code::Ok {
                             warn!("Remote cache error in batch_update_blobs(): {:?}", x);
                             break;
@@ -179,6 +289,7 @@ impl GrpcRemoteCache {
                     }
                 }
             }
+            uploads.drop_queued(&mut rx);
         });
     }
 
@@ -205,11 +316,18 @@ impl GrpcRemoteCache {
         }
     }
 
-    pub fn push_action_result(&self, digest: MessageDigest, result: ActionResult) {
-        self.ac_upload_tx.send((digest, result)).ok();
+    /// Queues the upload, waits if max_inflight_uploads are already queued
+    pub async fn push_action_result(&self, digest: MessageDigest, result: ActionResult) {
+        self.uploads.pending.fetch_add(1, Ordering::Relaxed);
+        if self.ac_upload_tx.send((digest, result)).await.is_err() {
+            self.uploads.finish(false);
+        }
     }
 
     pub async fn get_blob(&self, digest: BlobDigest) -> Option<Vec<u8>> {
+        self.download_limiter
+            .acquire(digest.size_bytes as u64)
+            .await;
         match self
             .cas_client
             .clone()
@@ -228,28 +346,35 @@ impl GrpcRemoteCache {
         }
     }
 
+    /// Returns true if the files can not be downloaded with a single batch request
+    ///
+    /// TODO split into multiple requests
+    pub fn is_above_max_batch_size(&self, files: &[&OutputFile]) -> bool {
+        files
+            .iter()
+            .map(|x| x.digest.as_ref().unwrap().size_bytes)
+            .sum::<i64>()
+            > self.max_batch_blob_size
+    }
+
     /// TODO replace asserts with proper error handling
     pub async fn download_and_store_blobs(
         &self,
         files: &[&OutputFile],
     ) -> anyhow::Result<Vec<(BlobDigest, PathBuf)>> {
         assert!(!files.is_empty());
-        if files
-            .iter()
-            .any(|x| x.digest.as_ref().unwrap().size_bytes > self.max_batch_blob_size)
-        {
+        if self.is_above_max_batch_size(files) {
             // command has to be executed locally, therefore no need to download any files
             return Ok(vec![]);
         }
-        if files
-            .iter()
-            .map(|x| x.digest.as_ref().unwrap().size_bytes)
-            .sum::<i64>()
-            > self.max_batch_blob_size
-        {
-            // TODO split into multiple requests
-            return Ok(vec![]);
-        }
+        self.download_limiter
+            .acquire(
+                files
+                    .iter()
+                    .map(|x| x.digest.as_ref().unwrap().size_bytes as u64)
+                    .sum(),
+            )
+            .await;
         let mut downloaded = Vec::with_capacity(files.len());
         match self
             .cas_client
@@ -317,11 +442,37 @@ impl GrpcRemoteCache {
     }
 
     /// Blob is read from local cache only at upload to avoid keeping too many big files in memory.
-    pub fn push_blob(&self, digest: BlobDigest, path: PathBuf) {
+    pub async fn push_blob(&self, digest: BlobDigest, path: PathBuf) {
         if digest.size_bytes > self.max_batch_blob_size {
             return;
         }
-        self.cas_upload_tx.send((digest, path)).ok();
+        self.uploads.pending.fetch_add(1, Ordering::Relaxed);
+        if self.cas_upload_tx.send((digest, path)).await.is_err() {
+            self.uploads.finish(false);
+        }
+    }
+
+    /// Waits up to the timeout for queued and in flight uploads to finish
+    pub async fn flush(&self, timeout: Duration) -> FlushedUploads {
+        let uploaded = self.uploads.uploaded.load(Ordering::Relaxed);
+        let failed = self.uploads.failed.load(Ordering::Relaxed);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let finished = self.uploads.finished.notified();
+            tokio::pin!(finished);
+            finished.as_mut().enable();
+            if self.uploads.pending.load(Ordering::Relaxed) == 0 {
+                break;
+            }
+            if tokio::time::timeout_at(deadline, finished).await.is_err() {
+                break;
+            }
+        }
+        FlushedUploads {
+            flushed: self.uploads.uploaded.load(Ordering::Relaxed) - uploaded,
+            dropped: self.uploads.failed.load(Ordering::Relaxed) - failed
+                + self.uploads.pending.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -345,6 +496,36 @@ mod tests {
     const INSTANCE_NAME: &str = "main";
     const CACHE_URL: &str = "grpc://localhost:9092";
 
+    #[test]
+    fn split_instance_name() {
+        for (uri, exp_uri, exp_instance_name) in [
+            ("grpc://localhost:9092", "grpc://localhost:9092/", ""),
+            ("grpc://localhost:9092/", "grpc://localhost:9092/", ""),
+            (
+                "grpc://localhost:9092/main",
+                "grpc://localhost:9092/",
+                "main",
+            ),
+            (
+                "grpc://cache.example.com/projects/p/instances/i",
+                "grpc://cache.example.com/",
+                "projects/p/instances/i",
+            ),
+            (
+                "grpc://user@localhost:9092/projects/p/instances/i/",
+                "grpc://user@localhost:9092/",
+                "projects/p/instances/i",
+            ),
+        ] {
+            let (act_uri, act_instance_name) =
+                GrpcRemoteCache::split_instance_name(&uri.parse().unwrap()).unwrap();
+            assert_eq!(act_uri.to_string(), exp_uri, "{uri}");
+            assert_eq!(act_uri.path(), "/", "{uri}");
+            assert_eq!(act_instance_name, exp_instance_name, "{uri}");
+        }
+        assert!(GrpcRemoteCache::split_instance_name(&"/main".parse().unwrap()).is_err());
+    }
+
     #[tokio::test]
     async fn grpc_server_capabilities() {
         let mut client = CapabilitiesClient::connect(CACHE_URL).await.unwrap();
@@ -1,15 +1,22 @@
-use anyhow::bail;
-use clap::{Args, Parser, Subcommand};
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+use crate::config::{read_config_files, ConfigFile, LinkType};
 use crate::executors::HttpRemoteExecConfig;
 use crate::metadata::Tag;
 use crate::razel_jsonl::parse_jsonl_file;
-use crate::tasks::DownloadFileTask;
-use crate::{parse_batch_file, parse_command, tasks, CommandBuilder, FileType, Razel};
+use crate::tasks::{DownloadFileTask, HashTask};
+use crate::{
+    parse_batch_file, parse_command, tasks, CleanTargets, CommandBuilder, FileType,
+    HttpClientConfig, LogFormat, Razel, VersionInfo,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -17,12 +24,24 @@ use crate::{parse_batch_file, parse_command, tasks, CommandBuilder, FileType, Ra
 struct Cli {
     #[clap(subcommand)]
     command: CliCommands,
+    /// Format of razel log messages, does not affect output of commands
+    #[clap(
+        long,
+        global = true,
+        env = "RAZEL_LOG_FORMAT",
+        value_enum,
+        default_value_t
+    )]
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand, Debug)]
 enum CliCommands {
     /// Execute a custom command
     Command {
+        /// Attach the command to a pseudo-terminal for interactive use, disables capturing and caching
+        #[clap(long, visible_alias = "tty")]
+        interactive: bool,
         #[clap(last = true, required = true)]
         command: Vec<String>,
     },
@@ -31,13 +50,16 @@ enum CliCommands {
     Task(CliTasks),
     /// Execute commands from a razel.jsonl or batch file
     #[clap(visible_alias = "build", visible_alias = "test")]
-    Exec(Exec),
+    Exec(Box<Exec>),
     /// List commands from a razel.jsonl or batch file
     #[clap(visible_alias = "ls", visible_alias = "show-only")]
     ListCommands {
-        /// File with commands to list
+        /// File with commands to list, can be repeated to merge files of one workspace dir
         #[clap(short, long, default_value = "razel.jsonl")]
-        file: String,
+        file: Vec<String>,
+        /// Output format
+        #[clap(long, value_enum, default_value_t)]
+        format: ListFormat,
         #[clap(flatten)]
         filter_args: FilterArgs,
     },
@@ -50,24 +72,102 @@ enum CliCommands {
         #[clap(required = true)]
         files: Vec<String>,
     },
+    /// Export or import the local cache
+    #[clap(subcommand)]
+    Cache(CacheCommand),
+    /// Write config, dependency graph, action digests, cache state, critical path and the last
+    /// log into a json file to attach to bug reports, without executing commands
+    Diagnose {
+        /// File with commands, can be repeated to merge files of one workspace dir
+        #[clap(short, long, default_value = "razel.jsonl")]
+        file: Vec<String>,
+        /// Json file to write
+        #[clap(short, long, default_value = "razel-diagnose.json")]
+        out: PathBuf,
+        /// Local cache directory
+        #[clap(long, env = "RAZEL_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+    },
+    /// Print a digest over the action digests of all commands to key CI caches on, without
+    /// executing commands
+    Fingerprint {
+        /// File with commands, can be repeated to merge files of one workspace dir
+        #[clap(short, long, default_value = "razel.jsonl")]
+        file: Vec<String>,
+        /// Mix this string into the cache keys like with `razel exec --cache-salt`
+        #[clap(long, env = "RAZEL_CACHE_SALT")]
+        cache_salt: Option<String>,
+        #[clap(flatten)]
+        filter_args: FilterArgs,
+    },
+    /// Remove razel-out (default) and/or parts of the local cache
+    Clean {
+        /// Remove razel-out and the output base
+        #[clap(long)]
+        out: bool,
+        /// Remove cached results of the local cache
+        #[clap(long)]
+        cache: bool,
+        /// Remove sandbox dirs left by killed razel processes
+        #[clap(long)]
+        sandbox: bool,
+        /// Remove razel-out, cached results and sandbox dirs
+        #[clap(long)]
+        all: bool,
+        /// Local cache directory (use --info to show default value)
+        #[clap(long, env = "RAZEL_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+        /// Directory with output files instead of razel-out
+        #[clap(long, env = "RAZEL_OUTPUT_BASE")]
+        output_base: Option<PathBuf>,
+    },
     /// Subcommands for Razel system management
     #[clap(subcommand)]
     System(SystemCommand),
+    /// Show the version and build metadata
+    Version {
+        /// Print version, git commit, target, cargo features and digest function as json
+        #[clap(long)]
+        json: bool,
+    },
     // TODO add Debug subcommand
     // TODO add upgrade subcommand
 }
 
 #[derive(Args, Debug)]
 struct Exec {
-    /// File with commands to execute
+    /// File with commands to execute, can be repeated to merge files of one workspace dir
     #[clap(short, long, default_value = "razel.jsonl")]
-    file: String,
+    file: Vec<String>,
     #[clap(flatten)]
     run_args: RunArgs,
     #[clap(flatten)]
     filter_args: FilterArgs,
 }
 
+/// Format for listing commands
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ListFormat {
+    /// Name and command line, or one output file path per line with --list-outputs
+    #[default]
+    Text,
+    /// One json object per line: name, executable, args, env, inputs, outputs, deps, tags
+    /// (only name and outputs with --list-outputs)
+    Json,
+}
+
+/// How the razel executable is included in the cache key of tasks and WASI modules
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum SelfDigestMode {
+    /// Digest of the razel binary, any rebuild invalidates the cache
+    #[default]
+    Binary,
+    /// Razel version, cached results are reused after rebuilds with changed task implementations
+    Version,
+    /// Not included, only for tasks known to be independent of the razel version
+    None,
+}
+
 #[derive(Args, Debug)]
 pub struct RunArgs {
     /// No execution, just show info about configuration, cache, ...
@@ -76,27 +176,217 @@ pub struct RunArgs {
     /// No execution, just list commands
     #[clap(short, long, visible_alias = "ls")]
     pub no_execution: bool,
+    /// Output format for listing commands with --no-execution
+    #[clap(long, value_enum, default_value_t, requires = "no_execution")]
+    pub format: ListFormat,
+    /// List paths of output files instead of commands with --no-execution
+    #[clap(long, requires = "no_execution")]
+    pub list_outputs: bool,
+    /// Print a shell script running the commands without sandbox and cache with --no-execution
+    #[clap(long, requires = "no_execution", conflicts_with = "list_outputs")]
+    pub print_commands: bool,
     /// Do not stop on first failure
     #[clap(short, long, visible_alias = "keep-running")]
     pub keep_going: bool,
     /// Show verbose output
     #[clap(short, long)]
     pub verbose: bool,
+    /// Only show failed commands and the final summary, e.g. for CI logs
+    #[clap(short = 'q', long, visible_alias = "quiet", conflicts_with = "verbose")]
+    pub summary_only: bool,
     /// Prefix of tags to group the report
     #[clap(long, default_value = "group")]
     pub group_by_tag: String,
+    /// Number of commands to run in parallel, defaults to the number of CPUs
+    #[clap(short, long, env = "RAZEL_JOBS")]
+    pub jobs: Option<usize>,
+    /// Capacity of a resource for commands tagged with razel:resource:<name>[:<count>], e.g. gpu=1
+    #[clap(long, value_parser = parse_resource)]
+    pub resource: Vec<(String, usize)>,
     /// Local cache directory (use --info to show default value)
     #[clap(long, env = "RAZEL_CACHE_DIR")]
     pub cache_dir: Option<PathBuf>,
+    /// Directory to write output files to instead of razel-out, which becomes a link to it
+    #[clap(long, env = "RAZEL_OUTPUT_BASE")]
+    pub output_base: Option<PathBuf>,
     /// Comma seperated list of remote cache URLs
     #[clap(long, env = "RAZEL_REMOTE_CACHE", value_delimiter = ',')]
     pub remote_cache: Vec<String>,
     /// Only cache commands with: output size / exec time < threshold [kilobyte / s]
     #[clap(long, env = "RAZEL_REMOTE_CACHE_THRESHOLD")]
     pub remote_cache_threshold: Option<u32>,
+    /// Limit bandwidth of remote cache uploads [byte / s], 0 means unlimited
+    #[clap(long, env = "RAZEL_REMOTE_CACHE_UPLOAD_LIMIT", default_value_t = 0)]
+    pub remote_cache_upload_limit: u64,
+    /// Limit bandwidth of remote cache downloads [byte / s], 0 means unlimited
+    #[clap(long, env = "RAZEL_REMOTE_CACHE_DOWNLOAD_LIMIT", default_value_t = 0)]
+    pub remote_cache_download_limit: u64,
+    /// Number of remote cache uploads to queue before commands wait for uploading
+    #[clap(long, env = "RAZEL_MAX_INFLIGHT_UPLOADS", default_value_t = 1000)]
+    pub max_inflight_uploads: usize,
+    /// Don't use remote cache for commands with inputs or outputs larger than this [byte]
+    #[clap(
+        long = "no-remote-cache-for-large-inputs",
+        env = "RAZEL_NO_REMOTE_CACHE_FOR_LARGE_INPUTS",
+        value_name = "MAX_SIZE"
+    )]
+    pub remote_cache_max_size: Option<u64>,
+    /// File containing a token to authenticate at the remote cache, sent as bearer token
+    #[clap(long, env = "RAZEL_REMOTE_CACHE_AUTH_TOKEN_FILE")]
+    pub remote_cache_auth_token_file: Option<PathBuf>,
+    /// Query the remote cache for all commands concurrently before execution to fill the local cache
+    #[clap(long, env = "RAZEL_SEED_FROM_REMOTE")]
+    pub seed_from_remote: bool,
+    /// Fail if no remote cache is available instead of running without it
+    #[clap(long, env = "RAZEL_REQUIRE_REMOTE_CACHE")]
+    pub require_remote_cache: bool,
     /// Http remote execution configuration
     #[clap(long, env = "RAZEL_HTTP_REMOTE_EXEC")]
     pub http_remote_exec: Option<HttpRemoteExecConfig>,
+    /// Proxy for DownloadFile tasks and http remote exec, defaults to HTTP_PROXY and HTTPS_PROXY
+    #[clap(long, env = "RAZEL_HTTP_PROXY")]
+    pub http_proxy: Option<String>,
+    /// Comma-separated list of hosts to not use the proxy for, defaults to NO_PROXY
+    #[clap(long, env = "RAZEL_HTTP_NO_PROXY")]
+    pub http_no_proxy: Option<String>,
+    /// Timeout for connecting to http servers [seconds]
+    #[clap(long, env = "RAZEL_HTTP_CONNECT_TIMEOUT")]
+    pub http_connect_timeout: Option<u64>,
+    /// Timeout for reading responses of http servers [seconds]
+    #[clap(long, env = "RAZEL_HTTP_READ_TIMEOUT")]
+    pub http_read_timeout: Option<u64>,
+    /// Comma-separated list of PEM files with additional CA certificates for https
+    #[clap(long, env = "RAZEL_HTTP_CA_CERT", value_delimiter = ',')]
+    pub http_ca_cert: Vec<PathBuf>,
+    /// Execute non-cached commands twice and fail if outputs differ
+    ///
+    /// Commands without sandbox, e.g. tagged with razel:no-sandbox, are executed only once and listed in a warning
+    #[clap(long)]
+    pub check_determinism: bool,
+    /// No execution, just link output files of cached commands into razel-out
+    #[clap(long)]
+    pub materialize_only: bool,
+    /// Warn about all inputs which are outputs of other commands
+    #[clap(long)]
+    pub warn_implicit_deps: bool,
+    /// Print the reason for each command which was not executed
+    #[clap(long)]
+    pub explain_skips: bool,
+    /// Print path, type and digest of each input file of a command after the run
+    #[clap(long, value_name = "TARGET")]
+    pub explain_inputs: Option<String>,
+    /// Log for commands if the remote cache was hit or why it missed, e.g. missing blobs in CAS
+    #[clap(long)]
+    pub explain_remote_miss: bool,
+    /// Append each finished command to log.json immediately, to keep it if razel is killed
+    #[clap(long)]
+    pub incremental_log: bool,
+    /// Sample the memory of the razel process and write it to razel-metadata/memory_profile.json [Linux only]
+    #[clap(long)]
+    pub profile_memory: bool,
+    /// Copy log.json, report.json and measurements.csv into razel-metadata/runs and keep this number of previous runs
+    #[clap(long, env = "RAZEL_KEEP_METADATA_RUNS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub keep_metadata_runs: Option<u64>,
+    /// Set PATH of commands to the directories of declared executables and inputs only
+    #[clap(long)]
+    pub hermetic_path: bool,
+    /// Comma-separated list of directories to add to the hermetic PATH, e.g. /bin
+    #[clap(long, value_delimiter = ',', requires = "hermetic_path")]
+    pub hermetic_path_allow: Vec<String>,
+    /// Fail sandboxed commands which create files that are neither inputs nor declared outputs
+    #[clap(long)]
+    pub strict_outputs: bool,
+    /// Comma-separated list of regex patterns for files to allow with --strict-outputs, e.g. ^tmp
+    #[clap(long, value_delimiter = ',', requires = "strict_outputs")]
+    pub strict_outputs_ignore: Vec<String>,
+    /// Let commands without sandbox write outputs to temporary files which are renamed after success
+    #[clap(long)]
+    pub atomic_outputs: bool,
+    /// Trace files read by commands and warn about undeclared inputs, disables reading the cache [Linux only]
+    #[clap(long)]
+    pub trace_inputs: bool,
+    /// Only allow sandboxed commands to read declared inputs and system dirs and to write the sandbox [Linux only]
+    #[clap(long)]
+    pub strict_sandbox: bool,
+    /// Set RAZEL_ACTION_DIGEST of commands to their cache key, e.g. to record it in outputs
+    #[clap(long)]
+    pub action_digest_env: bool,
+    /// Kill sandboxed commands whose output files exceed this total size [byte]
+    ///
+    /// Best-effort: the size is checked periodically, commands might write more between checks.
+    #[clap(long, env = "RAZEL_MAX_COMMAND_OUTPUT_SIZE")]
+    pub max_command_output_size: Option<u64>,
+    /// Start commands tagged with razel:phase:<n> after all commands of lower phases succeeded
+    #[clap(long)]
+    pub phased: bool,
+    /// Abort the run after this time, kill running commands and don't start others [seconds]
+    #[clap(long, env = "RAZEL_DEADLINE")]
+    pub deadline: Option<u64>,
+    /// Write the names of commands in the order they were started to this file
+    #[clap(long)]
+    pub record_schedule: Option<PathBuf>,
+    /// Start ready commands in the order of a file written with --record-schedule
+    #[clap(long)]
+    pub replay_schedule: Option<PathBuf>,
+    /// Mix this string into the cache keys to not share cache entries, e.g. with other toolchains
+    #[clap(long, env = "RAZEL_CACHE_SALT")]
+    pub cache_salt: Option<String>,
+    /// How output files are linked from the local cache into the output directory [default: hardlink]
+    #[clap(long, value_enum, env = "RAZEL_LINK_TYPE")]
+    pub link_type: Option<LinkType>,
+    /// Make output files in razel-out readonly, also copies, to not modify cache files by editing them
+    #[clap(long)]
+    pub link_cas_readonly: bool,
+    /// Host a GNU Make jobserver for commands tagged with razel:jobserver [Unix only]
+    #[clap(long)]
+    pub jobserver: bool,
+    /// Interval of status updates [ms], default: 200 on a terminal, 20000 otherwise
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval: Option<u64>,
+    /// Keep compiled WASI modules in the local cache to not compile them again in later runs
+    #[clap(long)]
+    pub prewarm_wasi: bool,
+    /// Raise the open files limit at most to this value instead of the hard limit [Unix only]
+    #[clap(long, env = "RAZEL_MAX_OPEN_FILES")]
+    pub max_open_files: Option<u64>,
+    /// How the razel executable is included in the cache key of tasks and WASI modules
+    #[clap(long, value_enum, default_value_t)]
+    pub self_digest_mode: SelfDigestMode,
+    /// Rewrite golden files of ensure-golden tasks instead of failing
+    #[clap(long)]
+    pub update_goldens: bool,
+    /// Fail before execution if digests of input files differ from this manifest
+    #[clap(long)]
+    pub input_manifest: Option<PathBuf>,
+    /// Write digests of input files to this manifest
+    #[clap(long)]
+    pub export_input_manifest: Option<PathBuf>,
+    /// Add headers included by C/C++ compile commands to their inputs, found by the preprocessor
+    #[clap(long)]
+    pub discover_includes: bool,
+    /// Fail if digests of output files differ from razel.lock or are missing in it
+    #[clap(long)]
+    pub verify_outputs: bool,
+    /// Write digests of output files to razel.lock
+    #[clap(long, conflicts_with = "verify_outputs")]
+    pub update_lock: bool,
+    /// Args set from config files and the file they were read from
+    #[clap(skip)]
+    pub config_sources: Vec<(String, PathBuf)>,
+}
+
+impl RunArgs {
+    fn http_client_config(&self) -> HttpClientConfig {
+        HttpClientConfig {
+            http_proxy: self.http_proxy.clone(),
+            https_proxy: self.http_proxy.clone(),
+            no_proxy: self.http_no_proxy.clone(),
+            connect_timeout: self.http_connect_timeout.map(Duration::from_secs),
+            read_timeout: self.http_read_timeout.map(Duration::from_secs),
+            ca_certs: self.http_ca_cert.clone(),
+        }
+        .with_env_proxy()
+    }
 }
 
 impl Default for RunArgs {
@@ -104,13 +394,102 @@ impl Default for RunArgs {
         Self {
             info: false,
             no_execution: false,
+            list_outputs: false,
+            print_commands: false,
+            format: Default::default(),
             keep_going: false,
             verbose: true,
+            summary_only: false,
             group_by_tag: "group".to_string(),
+            jobs: None,
+            resource: vec![],
             cache_dir: None,
+            output_base: None,
             remote_cache: vec![],
             remote_cache_threshold: None,
+            remote_cache_upload_limit: 0,
+            remote_cache_download_limit: 0,
+            max_inflight_uploads: 1000,
+            remote_cache_max_size: None,
+            remote_cache_auth_token_file: None,
+            seed_from_remote: false,
+            require_remote_cache: false,
+            action_digest_env: false,
             http_remote_exec: None,
+            http_proxy: None,
+            http_no_proxy: None,
+            http_connect_timeout: None,
+            http_read_timeout: None,
+            http_ca_cert: vec![],
+            check_determinism: false,
+            materialize_only: false,
+            warn_implicit_deps: false,
+            explain_skips: false,
+            explain_inputs: None,
+            explain_remote_miss: false,
+            incremental_log: false,
+            profile_memory: false,
+            keep_metadata_runs: None,
+            atomic_outputs: false,
+            trace_inputs: false,
+            strict_sandbox: false,
+            max_command_output_size: None,
+            phased: false,
+            deadline: None,
+            record_schedule: None,
+            replay_schedule: None,
+            cache_salt: None,
+            link_type: None,
+            link_cas_readonly: false,
+            jobserver: false,
+            stats_interval: None,
+            prewarm_wasi: false,
+            max_open_files: None,
+            hermetic_path: false,
+            hermetic_path_allow: vec![],
+            strict_outputs: false,
+            self_digest_mode: Default::default(),
+            strict_outputs_ignore: vec![],
+            update_goldens: false,
+            input_manifest: None,
+            export_input_manifest: None,
+            discover_includes: false,
+            verify_outputs: false,
+            update_lock: false,
+            config_sources: vec![],
+        }
+    }
+}
+
+impl RunArgs {
+    /// Set args which are neither set on command line nor by environment variables from config files.
+    ///
+    /// The files are expected to be ordered by precedence.
+    pub fn apply_config_files(&mut self, files: Vec<(PathBuf, ConfigFile)>) {
+        for (path, config) in files {
+            let mut set = |key: &str| self.config_sources.push((key.into(), path.clone()));
+            if self.cache_dir.is_none() && config.cache_dir.is_some() {
+                self.cache_dir = config.cache_dir;
+                set("cache-dir");
+            }
+            if let (true, Some(x)) = (self.remote_cache.is_empty(), config.remote_cache) {
+                self.remote_cache = x;
+                set("remote-cache");
+            }
+            if self.remote_cache_threshold.is_none() && config.remote_cache_threshold.is_some() {
+                self.remote_cache_threshold = config.remote_cache_threshold;
+                set("remote-cache-threshold");
+            }
+            if self.remote_cache_auth_token_file.is_none()
+                && config.remote_cache_auth_token_file.is_some()
+            {
+                self.remote_cache_auth_token_file = config.remote_cache_auth_token_file;
+                set("remote-cache-auth-token-file");
+            }
+            if self.jobs.is_none() && config.jobs.is_some() {
+                self.jobs = config.jobs;
+                set("jobs");
+            }
         }
     }
 }
@@ -131,6 +510,29 @@ pub struct FilterArgs {
     //pub filter_tags: Vec<String>,
 }
 
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Export cached results of the commands of a razel.jsonl or batch file into a tar.gz archive
+    Export {
+        /// Archive to create
+        archive: PathBuf,
+        /// File with commands to export
+        #[clap(short, long, default_value = "razel.jsonl")]
+        file: String,
+        /// Local cache directory (use --info to show default value)
+        #[clap(long, env = "RAZEL_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+    },
+    /// Import an archive created by `razel cache export` into the local cache
+    Import {
+        /// Archive to import
+        archive: PathBuf,
+        /// Local cache directory (use --info to show default value)
+        #[clap(long, env = "RAZEL_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum SystemCommand {
     /// Check remote cache availability
@@ -149,14 +551,24 @@ enum CliTasks {
     CsvConcat(CsvConcatTask),
     /// Filter a csv file - keeping only the specified cols
     CsvFilter(CsvFilterTask),
+    /// Concatenate multiple files of any type
+    Concat(ConcatTask),
+    /// Merge multiple LCOV coverage files
+    CoverageMerge(CoverageMergeTask),
     /// Write a text file
     WriteFile(WriteFileTask),
+    /// Write SHA-256 digests of files in the format of sha256sum
+    Hash(HashTaskBuilder),
     /// Download a file
     DownloadFile(DownloadFileTaskBuilder),
+    /// Extract files from a tar, tar.gz, tar.xz or zip archive
+    ExtractArchive(ExtractArchiveTask),
     /// Ensure that two files are equal
     EnsureEqual(EnsureEqualTask),
     /// Ensure that two files are not equal
     EnsureNotEqual(EnsureNotEqualTask),
+    /// Ensure that a file equals a golden file, use `exec --update-goldens` to rewrite it
+    EnsureGolden(EnsureGoldenTask),
     /// Post a HTTP multipart form for remote execution
     HttpRemoteExec(HttpRemoteExecTask),
 }
@@ -174,10 +586,15 @@ impl CliTasks {
             CliTasks::CaptureRegex(x) => x.build(&mut builder, razel),
             CliTasks::CsvConcat(x) => x.build(&mut builder, razel),
             CliTasks::CsvFilter(x) => x.build(&mut builder, razel),
+            CliTasks::Concat(x) => x.build(&mut builder, razel),
+            CliTasks::CoverageMerge(x) => x.build(&mut builder, razel),
             CliTasks::WriteFile(x) => x.build(&mut builder, razel),
+            CliTasks::Hash(x) => x.build(&mut builder, razel),
             CliTasks::DownloadFile(x) => x.build(&mut builder, razel),
+            CliTasks::ExtractArchive(x) => x.build(&mut builder, razel),
             CliTasks::EnsureEqual(x) => x.build(&mut builder, razel),
             CliTasks::EnsureNotEqual(x) => x.build(&mut builder, razel),
+            CliTasks::EnsureGolden(x) => x.build(&mut builder, razel),
             CliTasks::HttpRemoteExec(x) => x.build(&mut builder, razel),
         }?;
         razel.push(builder)?;
@@ -217,14 +634,18 @@ struct CsvConcatTask {
     input: Vec<String>,
     /// Concatenated file to create
     output: String,
+    /// Encoding of input files without BOM, the output is always UTF-8
+    #[clap(long, default_value = "utf-8")]
+    encoding: String,
 }
 
 impl TaskBuilder for CsvConcatTask {
     fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
         let inputs = builder.inputs(&self.input, razel)?;
         let output = builder.output(&self.output, FileType::OutputFile, razel)?;
+        let encoding = tasks::csv_encoding(&self.encoding)?;
         builder.blocking_task_executor(Arc::new(move || {
-            tasks::csv_concat(inputs.clone(), output.clone())
+            tasks::csv_concat(inputs.clone(), output.clone(), encoding)
         }));
         Ok(())
     }
@@ -239,14 +660,88 @@ struct CsvFilterTask {
     /// Col names to keep - all other cols are dropped
     #[clap(short, long = "col", num_args = 0..)]
     cols: Vec<String>,
+    /// Encoding of the input file if it has no BOM, the output is always UTF-8
+    #[clap(long, default_value = "utf-8")]
+    encoding: String,
 }
 
 impl TaskBuilder for CsvFilterTask {
     fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
         let input = builder.input(&self.input, razel)?;
         let output = builder.output(&self.output, FileType::OutputFile, razel)?;
+        let encoding = tasks::csv_encoding(&self.encoding)?;
         builder.blocking_task_executor(Arc::new(move || {
-            tasks::csv_filter(input.clone(), output.clone(), self.cols.clone())
+            tasks::csv_filter(input.clone(), output.clone(), self.cols.clone(), encoding)
+        }));
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct ConcatTask {
+    /// Input files
+    #[clap(required = true)]
+    input: Vec<String>,
+    /// Concatenated file to create
+    output: String,
+    /// String to write between files
+    #[clap(long, default_value = "")]
+    separator: String,
+    /// String to write before the first file
+    #[clap(long, default_value = "")]
+    prefix: String,
+    /// String to write after the last file
+    #[clap(long, default_value = "")]
+    suffix: String,
+    /// Append a newline to files not ending with one, e.g. to not join their last and first lines
+    #[clap(long)]
+    ensure_newline: bool,
+}
+
+impl TaskBuilder for ConcatTask {
+    fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
+        let inputs = builder.inputs(&self.input, razel)?;
+        let output = builder.output(&self.output, FileType::OutputFile, razel)?;
+        builder.blocking_task_executor(Arc::new(move || {
+            tasks::concat(
+                inputs.clone(),
+                output.clone(),
+                &self.separator,
+                &self.prefix,
+                &self.suffix,
+                self.ensure_newline,
+            )
+        }));
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct CoverageMergeTask {
+    /// Input LCOV files
+    #[clap(required = true)]
+    input: Vec<String>,
+    /// Merged LCOV file to create
+    output: String,
+    /// Prefix to remove from source file paths, to merge inputs of different source roots
+    #[clap(long, num_args = 0..)]
+    strip_prefix: Vec<String>,
+    /// Skip malformed records with a warning instead of failing
+    #[clap(long)]
+    skip_malformed: bool,
+}
+
+impl TaskBuilder for CoverageMergeTask {
+    fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
+        let inputs = builder.inputs(&self.input, razel)?;
+        let output = builder.output(&self.output, FileType::OutputFile, razel)?;
+        builder.blocking_task_executor(Arc::new(move || {
+            tasks::coverage_merge(
+                inputs.clone(),
+                output.clone(),
+                self.strip_prefix.clone(),
+                self.skip_malformed,
+            )
         }));
         Ok(())
     }
@@ -270,6 +765,24 @@ impl TaskBuilder for WriteFileTask {
     }
 }
 
+#[derive(Args, Debug)]
+struct HashTaskBuilder {
+    /// Files to hash
+    #[clap(required = true)]
+    input: Vec<String>,
+    /// File to write the digests to
+    output: String,
+}
+
+impl TaskBuilder for HashTaskBuilder {
+    fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
+        let inputs = builder.inputs(&self.input, razel)?;
+        let output = builder.output(&self.output, FileType::OutputFile, razel)?;
+        builder.async_task_executor(HashTask { inputs, output });
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 struct DownloadFileTaskBuilder {
     #[clap(short, long)]
@@ -292,11 +805,51 @@ impl TaskBuilder for DownloadFileTaskBuilder {
             url: self.url,
             output,
             executable: self.executable,
+            client: razel.http_client(),
         });
         Ok(())
     }
 }
 
+#[derive(Args, Debug)]
+struct ExtractArchiveTask {
+    /// Archive to extract: tar, tar.gz, tar.xz or zip
+    archive: String,
+    /// Directory to extract the files into
+    output_dir: String,
+    /// Files to extract, relative to the archive root - only those are extracted and cached
+    #[clap(required = true)]
+    files: Vec<String>,
+}
+
+impl TaskBuilder for ExtractArchiveTask {
+    fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
+        let archive = builder.input(&self.archive, razel)?;
+        let mut output_dir = None;
+        for file in &self.files {
+            let output = builder.output(
+                &Path::new(&self.output_dir)
+                    .join(file)
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+                FileType::OutputFile,
+                razel,
+            )?;
+            output_dir = output
+                .ancestors()
+                .nth(Path::new(file).components().count())
+                .map(|x| x.to_path_buf());
+        }
+        let output_dir = output_dir.unwrap();
+        let files = self.files.iter().map(PathBuf::from).collect::<Vec<_>>();
+        builder.blocking_task_executor(Arc::new(move || {
+            tasks::extract_archive(archive.clone(), output_dir.clone(), files.clone())
+        }));
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 struct EnsureEqualTask {
     file1: String,
@@ -331,6 +884,39 @@ impl TaskBuilder for EnsureNotEqualTask {
     }
 }
 
+#[derive(Args, Debug)]
+struct EnsureGoldenTask {
+    /// File to check, e.g. stdout of a command
+    actual: String,
+    /// File with the expected content
+    golden: String,
+    /// Ignore differences between CRLF and LF line endings
+    #[clap(long)]
+    normalize_line_endings: bool,
+}
+
+impl TaskBuilder for EnsureGoldenTask {
+    fn build(self, builder: &mut CommandBuilder, razel: &mut Razel) -> Result<(), anyhow::Error> {
+        let actual = builder.input(&self.actual, razel)?;
+        let golden = builder.input(&self.golden, razel)?;
+        let normalize_line_endings = self.normalize_line_endings;
+        let update = razel.update_goldens;
+        if update {
+            // the result depends on the update flag which is not part of the action
+            builder.tag(Tag::NoCache);
+        }
+        builder.blocking_task_executor(Arc::new(move || {
+            tasks::ensure_golden(
+                actual.clone(),
+                golden.clone(),
+                normalize_line_endings,
+                update,
+            )
+        }));
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 struct HttpRemoteExecTask {
     /// url for HTTP multipart form POST
@@ -355,7 +941,7 @@ impl TaskBuilder for HttpRemoteExecTask {
             let file = builder.input(&self.files[i], razel)?;
             files.push((name, file));
         }
-        builder.http_remote_executor(state, self.url, files);
+        builder.http_remote_executor(state, self.url, files, razel.http_client());
         Ok(())
     }
 }
@@ -366,27 +952,100 @@ pub async fn parse_cli(
 ) -> Result<Option<RunArgs>, anyhow::Error> {
     let cli = Cli::parse_from(args.iter());
     Ok(match cli.command {
-        CliCommands::Command { command } => {
+        CliCommands::Command {
+            interactive,
+            command,
+        } => {
+            let run_args = apply_config(razel, Default::default())?;
+            razel.interactive = interactive;
             parse_command(razel, command)?;
-            Some(Default::default())
+            Some(run_args)
         }
         CliCommands::Task(task) => {
+            let run_args = apply_config(razel, Default::default())?;
             task.build_command(razel, "task".to_string(), args, vec![])?;
-            Some(Default::default())
+            Some(run_args)
         }
         CliCommands::Exec(exec) => {
-            if let Some(x) = &exec.run_args.http_remote_exec {
+            set_workspace_dir_of_files(razel, &exec.file)?;
+            let run_args = apply_config(razel, exec.run_args)?;
+            razel.set_http_client_config(&run_args.http_client_config())?;
+            if let Some(x) = &run_args.http_remote_exec {
                 razel.set_http_remote_exec_config(x);
             }
-            apply_file(razel, &exec.file)?;
+            if let Some(x) = &run_args.output_base {
+                razel.set_output_base(x)?;
+            }
+            razel.check_determinism = run_args.check_determinism;
+            razel.materialize_only = run_args.materialize_only;
+            razel.remote_cache_upload_limit = run_args.remote_cache_upload_limit;
+            razel.remote_cache_download_limit = run_args.remote_cache_download_limit;
+            razel.max_inflight_uploads = run_args.max_inflight_uploads;
+            razel.remote_cache_max_size = run_args.remote_cache_max_size;
+            razel.remote_cache_auth_token_file = run_args.remote_cache_auth_token_file.clone();
+            razel.seed_from_remote = run_args.seed_from_remote;
+            razel.require_remote_cache = run_args.require_remote_cache;
+            razel.explain_skips = run_args.explain_skips;
+            razel.explain_remote_miss = run_args.explain_remote_miss;
+            razel.incremental_log = run_args.incremental_log;
+            razel.profile_memory = run_args.profile_memory;
+            razel.keep_metadata_runs = run_args.keep_metadata_runs.map(|x| x as usize);
+            razel.atomic_outputs = run_args.atomic_outputs;
+            if run_args.trace_inputs {
+                razel.set_trace_inputs();
+            }
+            if run_args.strict_sandbox {
+                razel.set_strict_sandbox();
+            }
+            razel.max_command_output_size = run_args.max_command_output_size;
+            razel.action_digest_env = run_args.action_digest_env;
+            razel.phased = run_args.phased;
+            razel.deadline = run_args.deadline.map(Duration::from_secs);
+            razel.record_schedule = run_args.record_schedule.clone();
+            razel.replay_schedule = run_args.replay_schedule.clone();
+            razel.cache_salt = run_args.cache_salt.clone();
+            if let Some(x) = run_args.link_type {
+                razel.link_type = x;
+            }
+            razel.link_cas_readonly = run_args.link_cas_readonly;
+            razel.jobserver = run_args.jobserver;
+            razel.prewarm_wasi = run_args.prewarm_wasi;
+            razel.stats_interval = run_args.stats_interval.map(Duration::from_millis);
+            razel.max_open_files = run_args.max_open_files;
+            razel.summary_only = run_args.summary_only;
+            razel.update_goldens = run_args.update_goldens;
+            razel.self_digest_mode = run_args.self_digest_mode;
+            razel.input_manifest = run_args.input_manifest.clone();
+            razel.export_input_manifest = run_args.export_input_manifest.clone();
+            razel.discover_includes = run_args.discover_includes;
+            razel.verify_outputs = run_args.verify_outputs;
+            razel.update_lock = run_args.update_lock;
+            razel.hermetic_path = run_args
+                .hermetic_path
+                .then(|| run_args.hermetic_path_allow.clone());
+            if run_args.strict_outputs {
+                razel.set_strict_outputs(&run_args.strict_outputs_ignore)?;
+            }
+            apply_files(razel, &exec.file)?;
             apply_filter(razel, &exec.filter_args)?;
-            Some(exec.run_args)
+            if let Some(x) = &run_args.explain_inputs {
+                razel.set_explain_inputs(x)?;
+            }
+            if run_args.warn_implicit_deps {
+                razel.warn_implicit_deps();
+            }
+            Some(run_args)
         }
-        CliCommands::ListCommands { file, filter_args } => {
-            apply_file(razel, &file)?;
+        CliCommands::ListCommands {
+            file,
+            format,
+            filter_args,
+        } => {
+            apply_files(razel, &file)?;
             apply_filter(razel, &filter_args)?;
             Some(RunArgs {
                 no_execution: true,
+                format,
                 ..Default::default()
             })
         }
@@ -394,12 +1053,108 @@ pub async fn parse_cli(
             import(razel, &output, files)?;
             None
         }
+        CliCommands::Cache(c) => {
+            match c {
+                CacheCommand::Export {
+                    archive,
+                    file,
+                    cache_dir,
+                } => {
+                    set_workspace_dir_of_files(razel, std::slice::from_ref(&file))?;
+                    let run_args = apply_config(
+                        razel,
+                        RunArgs {
+                            cache_dir,
+                            ..Default::default()
+                        },
+                    )?;
+                    apply_file(razel, &file)?;
+                    razel.export_cache(&archive, run_args.cache_dir).await?;
+                }
+                CacheCommand::Import { archive, cache_dir } => {
+                    let run_args = apply_config(
+                        razel,
+                        RunArgs {
+                            cache_dir,
+                            ..Default::default()
+                        },
+                    )?;
+                    razel.import_cache(&archive, run_args.cache_dir).await?;
+                }
+            }
+            None
+        }
+        CliCommands::Diagnose {
+            file,
+            out,
+            cache_dir,
+        } => {
+            set_workspace_dir_of_files(razel, &file)?;
+            let run_args = apply_config(
+                razel,
+                RunArgs {
+                    cache_dir,
+                    ..Default::default()
+                },
+            )?;
+            apply_files(razel, &file)?;
+            razel
+                .diagnose(&out, run_args.cache_dir, &run_args.config_sources)
+                .await?;
+            None
+        }
+        CliCommands::Fingerprint {
+            file,
+            cache_salt,
+            filter_args,
+        } => {
+            apply_files(razel, &file)?;
+            apply_filter(razel, &filter_args)?;
+            razel.cache_salt = cache_salt;
+            println!("{}", razel.fingerprint().await?.hash);
+            None
+        }
+        CliCommands::Clean {
+            out,
+            cache,
+            sandbox,
+            all,
+            cache_dir,
+            output_base,
+        } => {
+            let run_args = apply_config(
+                razel,
+                RunArgs {
+                    cache_dir,
+                    ..Default::default()
+                },
+            )?;
+            if let Some(x) = &output_base {
+                razel.set_output_base(x)?;
+            }
+            let targets = CleanTargets {
+                out: out || all || !(cache || sandbox),
+                cache: cache || all,
+                sandbox: sandbox || all,
+            };
+            razel.clean_dirs(targets, run_args.cache_dir)?;
+            None
+        }
         CliCommands::System(s) => {
             match s {
                 SystemCommand::CheckRemoteCache { urls } => razel.check_remote_cache(urls).await?,
             }
             None
         }
+        CliCommands::Version { json } => {
+            let info = VersionInfo::new();
+            if json {
+                println!("{}", serde_json::to_string(&info)?);
+            } else {
+                println!("{info}");
+            }
+            None
+        }
     })
 }
 
@@ -411,7 +1166,13 @@ pub fn parse_cli_within_file(
 ) -> Result<(), anyhow::Error> {
     let cli = Cli::try_parse_from(args.iter())?;
     match cli.command {
-        CliCommands::Command { command } => {
+        CliCommands::Command {
+            interactive,
+            command,
+        } => {
+            if interactive {
+                bail!("interactive commands are not allowed within files");
+            }
             parse_command(razel, command)?;
         }
         CliCommands::Task(task) => {
@@ -422,6 +1183,40 @@ pub fn parse_cli_within_file(
     Ok(())
 }
 
+/// The workspace dir is needed to read the project config file before loading the files
+fn set_workspace_dir_of_files(razel: &mut Razel, files: &[String]) -> Result<(), anyhow::Error> {
+    if let Some(dir) = files.first().and_then(|x| Path::new(x).parent()) {
+        razel.set_workspace_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Set unset run args from config files and apply the ones affecting the Razel instance
+fn apply_config(razel: &mut Razel, mut run_args: RunArgs) -> Result<RunArgs, anyhow::Error> {
+    run_args.apply_config_files(read_config_files(razel.workspace_dir())?);
+    if let Some(jobs) = run_args.jobs {
+        razel.set_worker_threads(jobs)?;
+    }
+    for (name, capacity) in &run_args.resource {
+        razel.set_resource_capacity(name, *capacity)?;
+    }
+    Ok(run_args)
+}
+
+/// Parses `<name>=<capacity>` of --resource
+fn parse_resource(x: &str) -> Result<(String, usize), String> {
+    let (name, capacity) = x
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty() && !name.contains(':'))
+        .ok_or_else(|| format!("expected <name>=<capacity>: {x}"))?;
+    let capacity = capacity
+        .parse()
+        .ok()
+        .filter(|x| *x > 0)
+        .ok_or_else(|| format!("failed to parse resource capacity: {capacity}"))?;
+    Ok((name.into(), capacity))
+}
+
 fn apply_file(razel: &mut Razel, file: &String) -> Result<(), anyhow::Error> {
     match Path::new(file).extension().and_then(OsStr::to_str) {
         Some("jsonl") => parse_jsonl_file(razel, file),
@@ -429,9 +1224,53 @@ fn apply_file(razel: &mut Razel, file: &String) -> Result<(), anyhow::Error> {
     }
 }
 
+/// Loads commands from multiple files into one graph.
+///
+/// The files must be in the same directory, which is used as workspace dir. Deps on commands of
+/// files loaded later are resolved after all files are loaded.
+fn apply_files(razel: &mut Razel, files: &[String]) -> Result<(), anyhow::Error> {
+    let [first, others @ ..] = files else {
+        bail!("no file given");
+    };
+    if others.is_empty() {
+        return apply_file(razel, first);
+    }
+    let dir = canonical_parent_dir(first)?;
+    for other in others {
+        if canonical_parent_dir(other)? != dir {
+            bail!(
+                "files must be in the same directory to share the workspace dir: {first}, {other}"
+            );
+        }
+    }
+    razel.defer_deps();
+    let mut sources: HashMap<String, &String> = HashMap::new();
+    for file in files {
+        let known = sources.len();
+        apply_file(razel, file)?;
+        let names = razel.command_names().skip(known).cloned().collect_vec();
+        for name in names {
+            if let Some(other) = sources.insert(name.clone(), file) {
+                bail!("duplicate command name {name:?} in {other} and {file}");
+            }
+        }
+    }
+    razel.resolve_deferred_deps()
+}
+
+/// Directory of a file comparable to others, e.g. for `a.jsonl` and `./b.jsonl`
+fn canonical_parent_dir(file: &str) -> Result<PathBuf, anyhow::Error> {
+    let dir = Path::new(file)
+        .parent()
+        .filter(|x| !x.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    dir.canonicalize()
+        .with_context(|| format!("directory of {file}"))
+}
+
 fn apply_filter(razel: &mut Razel, filter: &FilterArgs) -> Result<(), anyhow::Error> {
     if !filter.targets.is_empty() {
-        razel.filter_targets(&filter.targets);
+        razel.filter_targets(&filter.targets)?;
     } else if !filter.filter_regex.is_empty() {
         razel.filter_targets_regex(&filter.filter_regex)?;
     } else if !filter.filter_regex_all.is_empty() {
@@ -446,3 +1285,204 @@ fn import(razel: &mut Razel, output: &Path, files: Vec<String>) -> Result<(), an
     }
     razel.write_jsonl(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use itertools::chain;
+    use serial_test::serial;
+    use std::env;
+
+    fn exec_run_args(args: &[&str]) -> RunArgs {
+        let args = chain!([crate::config::EXECUTABLE, "exec"], args.iter().copied());
+        match Cli::try_parse_from(args).unwrap().command {
+            CliCommands::Exec(x) => x.run_args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn resource_arg() {
+        let run_args = exec_run_args(&["--resource", "gpu=1", "--resource", "db=4"]);
+        assert_eq!(
+            run_args.resource,
+            [("gpu".to_string(), 1), ("db".to_string(), 4)]
+        );
+        for arg in ["gpu", "gpu=0", "=1", "gpu:0=1"] {
+            let args = [crate::config::EXECUTABLE, "exec", "--resource", arg];
+            assert!(Cli::try_parse_from(args).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn config_precedence() {
+        let dir = new_tmp_dir!();
+        let project = dir.join_and_write_file(
+            "razel.toml",
+            "jobs = 3\nremote-cache = [\"grpc://project:9092\"]\n",
+        );
+        let user = dir.join_and_write_file(
+            "user/config.toml",
+            "jobs = 2\nremote-cache = [\"grpc://user:9092\"]\nremote-cache-threshold = 100\n",
+        );
+        let files = vec![
+            (
+                project.clone(),
+                ConfigFile::read(&project).unwrap().unwrap(),
+            ),
+            (user.clone(), ConfigFile::read(&user).unwrap().unwrap()),
+        ];
+        env::remove_var("RAZEL_JOBS");
+        env::remove_var("RAZEL_REMOTE_CACHE");
+        env::remove_var("RAZEL_REMOTE_CACHE_THRESHOLD");
+        // project config file overrides user config file
+        let mut run_args = exec_run_args(&[]);
+        run_args.apply_config_files(files.clone());
+        assert_eq!(run_args.jobs, Some(3));
+        assert_eq!(run_args.remote_cache, vec!["grpc://project:9092"]);
+        assert_eq!(run_args.remote_cache_threshold, Some(100));
+        assert_eq!(
+            run_args.config_sources,
+            vec![
+                ("remote-cache".into(), project.clone()),
+                ("jobs".into(), project.clone()),
+                ("remote-cache-threshold".into(), user.clone()),
+            ]
+        );
+        // environment variable overrides config files
+        env::set_var("RAZEL_JOBS", "5");
+        let mut run_args = exec_run_args(&[]);
+        run_args.apply_config_files(files.clone());
+        assert_eq!(run_args.jobs, Some(5));
+        // command line overrides environment variable
+        let mut run_args = exec_run_args(&["--jobs", "7"]);
+        run_args.apply_config_files(files);
+        assert_eq!(run_args.jobs, Some(7));
+        env::remove_var("RAZEL_JOBS");
+    }
+
+    fn append_command(name: &str, deps: &[&str], file: &Path) -> String {
+        serde_json::json!({
+            "name": name,
+            "executable": "sh",
+            "args": ["-c", format!("echo {name} >> {}", file.to_str().unwrap())],
+            "deps": deps,
+            "tags": ["razel:no-sandbox"],
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn multiple_files() {
+        let dir = new_tmp_dir!();
+        let order = std::fs::canonicalize(dir.dir()).unwrap().join("order.txt");
+        let a = dir.join_and_write_file("a.jsonl", &append_command("a", &["b"], &order));
+        let b = dir.join_and_write_file("b.jsonl", &append_command("b", &[], &order));
+        let files = [a, b].map(|x| x.to_str().unwrap().to_string());
+        let mut razel = Razel::new();
+        razel.read_cache = false;
+        apply_files(&mut razel, &files).unwrap();
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        assert_eq!(std::fs::read_to_string(order).unwrap(), "b\na\n");
+    }
+
+    #[test]
+    fn multiple_files_errors() {
+        let dir = new_tmp_dir!();
+        let order = dir.join("order.txt");
+        let a = dir.join_and_write_file("a.jsonl", &append_command("a", &["b"], &order));
+        let b = dir.join_and_write_file("b.jsonl", &append_command("b", &["a"], &order));
+        let a2 = dir.join_and_write_file("a2.jsonl", &append_command("a", &[], &order));
+        let c = dir.join_and_write_file("c.jsonl", &append_command("c", &["x"], &order));
+        let other = dir.join_and_write_file("sub/b.jsonl", &append_command("b", &[], &order));
+        let apply = |files: &[&PathBuf]| {
+            let files = files.iter().map(|x| x.to_str().unwrap().to_string());
+            apply_files(&mut Razel::new(), &files.collect_vec())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(apply(&[&a, &b]), "circular dependency: a -> b -> a");
+        let error = apply(&[&a, &a2]);
+        assert!(error.starts_with("duplicate command name \"a\""), "{error}");
+        assert!(
+            error.contains("a.jsonl and") && error.ends_with("a2.jsonl"),
+            "{error}"
+        );
+        assert_eq!(apply(&[&a2, &c]), "unknown command for dep of c: x");
+        assert!(apply(&[&a, &other]).starts_with("files must be in the same directory"));
+        let dot_b = dir.dir().join(".").join("b.jsonl");
+        assert_eq!(apply(&[&a, &dot_b]), "circular dependency: a -> b -> a");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn project_config_file_is_read_from_workspace_dir() {
+        let dir = new_tmp_dir!();
+        dir.join_and_write_file(
+            "razel.toml",
+            "jobs = 3\nremote-cache-auth-token-file = \"token.txt\"\n",
+        );
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            &serde_json::json!({"name": "a", "executable": "true", "args": []}).to_string(),
+        );
+        env::remove_var("RAZEL_JOBS");
+        env::remove_var("RAZEL_REMOTE_CACHE_AUTH_TOKEN_FILE");
+        let args = [
+            crate::config::EXECUTABLE,
+            "exec",
+            "-f",
+            jsonl.to_str().unwrap(),
+        ];
+        let mut razel = Razel::new();
+        let run_args = parse_cli(args.map(|x| x.to_string()).to_vec(), &mut razel)
+            .await
+            .unwrap()
+            .unwrap();
+        let workspace_dir = razel.workspace_dir();
+        let config = workspace_dir.join("razel.toml");
+        assert!(config.is_file());
+        assert_eq!(run_args.jobs, Some(3));
+        assert_eq!(
+            razel.remote_cache_auth_token_file,
+            Some(workspace_dir.join("token.txt"))
+        );
+        assert_eq!(
+            run_args.config_sources,
+            vec![
+                ("remote-cache-auth-token-file".into(), config.clone()),
+                ("jobs".into(), config),
+            ]
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    #[serial]
+    async fn interactive_command() {
+        for (flag, succeeded) in [(None, 0), (Some("--interactive"), 1)] {
+            let args = chain!(
+                [crate::config::EXECUTABLE, "command"],
+                flag,
+                ["--", "sh", "-c", "test -t 0 && test -t 1 && test -t 2"]
+            );
+            let mut razel = Razel::new();
+            parse_cli(args.map(|x| x.to_string()).collect(), &mut razel)
+                .await
+                .unwrap();
+            let stats = razel
+                .run(false, true, "", None, vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, succeeded, "{flag:?}");
+            assert_eq!(stats.cache_hits, 0);
+        }
+    }
+}
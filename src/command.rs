@@ -1,15 +1,18 @@
-use anyhow::{anyhow, Context};
+use anyhow::bail;
+use reqwest::Client;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use url::Url;
 
+use crate::cache::BlobDigest;
 use crate::executors::{
     AsyncTask, AsyncTaskExecutor, BlockingTaskExecutor, CustomCommandExecutor, Executor,
     HttpRemoteExecDomain, HttpRemoteExecutor, TaskFn, WasiExecutor,
 };
 use crate::metadata::Tag;
-use crate::{ArenaId, FileId, FileType, Razel, ScheduleState};
+use crate::tasks::{normalize_entry_path, split_archive_entry};
+use crate::{ArenaId, FileId, FileType, OutputGlob, Razel, ScheduleState};
 
 pub struct Command {
     pub id: CommandId,
@@ -18,8 +21,20 @@ pub struct Command {
     pub executables: Vec<FileId>,
     /// input files excluding <Self::executables>
     pub inputs: Vec<FileId>,
+    /// input files which are available in the sandbox but not part of the action digest
+    pub config_inputs: Vec<FileId>,
+    /// files inside of archive inputs, extracted into the sandbox before execution
+    pub archive_entries: Vec<ArchiveEntry>,
+    /// archives in <Self::inputs> only used for <Self::archive_entries>, replaced by the entries
+    /// in the action digest
+    pub entry_only_archives: Vec<FileId>,
     pub outputs: Vec<FileId>,
-    /// dependencies on other commands in addition to input files
+    /// patterns of additional output files collected from the sandbox after execution
+    pub output_globs: Vec<OutputGlob>,
+    /// outputs also copied to `razel-out/artifacts/<name>/` after success, see Razel::set_artifacts()
+    pub artifacts: Vec<FileId>,
+    /// dependencies on other commands in addition to input files, only used for scheduling and
+    /// not part of the action digest
     pub deps: Vec<CommandId>,
     pub executor: Executor,
     pub tags: Vec<Tag>,
@@ -34,15 +49,32 @@ pub struct Command {
 
 pub type CommandId = ArenaId<Command>;
 
+/// Input like `data.tar#dir/file.csv`: a file inside of an archive input
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    pub archive: FileId,
+    /// path inside of the archive
+    pub entry: PathBuf,
+    /// path of the extracted file in the sandbox: archive path, `#` and entry path
+    pub path: PathBuf,
+    /// digest of the entry content, set before computing the action digest
+    pub digest: Option<BlobDigest>,
+}
+
 pub struct CommandBuilder {
     name: String,
     args_with_out_paths: Vec<String>,
     executables: Vec<FileId>,
     inputs: Vec<FileId>,
+    config_inputs: Vec<FileId>,
+    archive_entries: Vec<ArchiveEntry>,
     outputs: Vec<FileId>,
+    output_globs: Vec<OutputGlob>,
     stdout_file: Option<PathBuf>,
     stderr_file: Option<PathBuf>,
     deps: Vec<CommandId>,
+    /// names of deps which are not yet known, see Razel::defer_deps()
+    deferred_deps: Vec<String>,
     executor: Option<Executor>,
     tags: Vec<Tag>,
 }
@@ -54,15 +86,25 @@ impl CommandBuilder {
             args_with_out_paths: args,
             executables: vec![],
             inputs: vec![],
+            config_inputs: vec![],
+            archive_entries: vec![],
             outputs: vec![],
+            output_globs: vec![],
             stdout_file: None,
             stderr_file: None,
             deps: vec![],
+            deferred_deps: vec![],
             executor: None,
             tags,
         }
     }
 
+    pub fn tag(&mut self, tag: Tag) {
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+    }
+
     fn map_out_path(&mut self, original: &String, mapped: &str) {
         self.args_with_out_paths.iter_mut().for_each(|x| {
             if x == original {
@@ -72,6 +114,12 @@ impl CommandBuilder {
     }
 
     pub fn input(&mut self, path: &String, razel: &mut Razel) -> Result<PathBuf, anyhow::Error> {
+        if let Some((archive, entry)) = split_archive_entry(path) {
+            if razel.is_existing_file(path) {
+                bail!("input {path:?} is ambiguous: file and entry of archive {archive:?}");
+            }
+            return self.archive_entry(path, archive, entry, razel);
+        }
         razel.input_file(path.clone()).map(|file| {
             self.map_out_path(path, file.path.to_str().unwrap());
             self.inputs.push(file.id);
@@ -85,12 +133,54 @@ impl CommandBuilder {
         razel: &mut Razel,
     ) -> Result<Vec<PathBuf>, anyhow::Error> {
         self.inputs.reserve(paths.len());
+        paths.iter().map(|path| self.input(path, razel)).collect()
+    }
+
+    /// File inside of an archive input, e.g. `data.tar#dir/file.csv`
+    ///
+    /// The archive is the declared input, the entry is extracted into the sandbox before execution.
+    fn archive_entry(
+        &mut self,
+        path: &String,
+        archive: &str,
+        entry: &str,
+        razel: &mut Razel,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let entry = normalize_entry_path(Path::new(entry))?;
+        let file = razel.input_file(archive.into())?;
+        let sandbox_path = PathBuf::from(format!(
+            "{}#{}",
+            file.path.to_str().unwrap(),
+            entry.to_str().unwrap()
+        ));
+        self.map_out_path(path, sandbox_path.to_str().unwrap());
+        if !self.archive_entries.iter().any(|x| x.path == sandbox_path) {
+            self.archive_entries.push(ArchiveEntry {
+                archive: file.id,
+                entry,
+                path: sandbox_path.clone(),
+                digest: None,
+            });
+        }
+        Ok(sandbox_path)
+    }
+
+    /// Input files which do not change the outputs, e.g. logging config
+    ///
+    /// They are linked into the sandbox but not used for the cache key - changing them does not
+    /// invalidate cached results.
+    pub fn config_inputs(
+        &mut self,
+        paths: &[String],
+        razel: &mut Razel,
+    ) -> Result<Vec<PathBuf>, anyhow::Error> {
+        self.config_inputs.reserve(paths.len());
         paths
             .iter()
             .map(|path| {
                 let file = razel.input_file(path.clone())?;
                 self.map_out_path(path, file.path.to_str().unwrap());
-                self.inputs.push(file.id);
+                self.config_inputs.push(file.id);
                 Ok(file.path.clone())
             })
             .collect()
@@ -126,6 +216,27 @@ impl CommandBuilder {
             .collect()
     }
 
+    /// Output files which are not known before execution, the base dir is mapped like outputs
+    pub fn output_globs(
+        &mut self,
+        globs: &[OutputGlob],
+        razel: &Razel,
+    ) -> Result<(), anyhow::Error> {
+        self.output_globs.reserve(globs.len());
+        for glob in globs {
+            let resolved = razel.output_glob(glob)?;
+            let base_dir = glob.base_dir();
+            if !base_dir.as_os_str().is_empty() {
+                self.map_out_path(
+                    &base_dir.to_str().unwrap().to_string(),
+                    resolved.base_dir().to_str().unwrap(),
+                );
+            }
+            self.output_globs.push(resolved);
+        }
+        Ok(())
+    }
+
     pub fn stdout(&mut self, path: &String, razel: &mut Razel) -> Result<(), anyhow::Error> {
         let file = razel.output_file(path, FileType::OutputFile)?;
         self.outputs.push(file.id);
@@ -141,19 +252,32 @@ impl CommandBuilder {
     }
 
     pub fn dep(&mut self, command_name: &String, razel: &mut Razel) -> Result<(), anyhow::Error> {
-        let command_id = razel
-            .get_command_by_name(command_name)
-            .with_context(|| anyhow!("unknown command for dep: {command_name}"))?;
-        self.deps.push(command_id.id);
+        match razel.get_command_by_name(command_name) {
+            Some(command) => self.deps.push(command.id),
+            None if razel.is_deferring_deps() => self.deferred_deps.push(command_name.clone()),
+            None => bail!("unknown command for dep: {command_name}"),
+        }
         Ok(())
     }
 
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn take_deferred_deps(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.deferred_deps)
+    }
+
     pub fn custom_command_executor(
         &mut self,
         executable: String,
         env: HashMap<String, String>,
         razel: &mut Razel,
     ) -> Result<(), anyhow::Error> {
+        let merge_stderr = self.tags.contains(&Tag::MergeStderr);
+        if merge_stderr && self.stderr_file.is_some() {
+            bail!("stderr file can not be combined with razel:merge-stderr tag");
+        }
         let file = razel.executable(executable)?;
         self.executables.push(file.id);
         self.executor = Some(Executor::CustomCommand(CustomCommandExecutor {
@@ -169,6 +293,28 @@ impl CommandBuilder {
                     None
                 }
             }),
+            deadline: None,
+            umask: self.tags.iter().find_map(|t| {
+                if let Tag::Umask(x) = t {
+                    Some(*x)
+                } else {
+                    None
+                }
+            }),
+            merge_stderr,
+            io_nice: self.tags.iter().find_map(|t| {
+                if let Tag::IoNice(c, l) = t {
+                    Some((*c, *l))
+                } else {
+                    None
+                }
+            }),
+            trace_inputs: false,
+            strict_sandbox_inputs: None,
+            interactive: false,
+            max_output_size: None,
+            #[cfg(target_family = "unix")]
+            jobserver: None,
         }));
         Ok(())
     }
@@ -180,7 +326,7 @@ impl CommandBuilder {
         razel: &mut Razel,
     ) -> Result<(), anyhow::Error> {
         let mut read_dirs = vec![];
-        for id in &self.inputs {
+        for id in self.inputs.iter().chain(&self.config_inputs) {
             let dir = razel.get_file_path(*id).parent().unwrap().to_path_buf();
             if !read_dirs.contains(&dir) {
                 read_dirs.push(dir);
@@ -225,22 +371,36 @@ impl CommandBuilder {
         state: Option<Arc<HttpRemoteExecDomain>>,
         url: Url,
         files: Vec<(String, PathBuf)>,
+        client: Client,
     ) {
         self.executor = Some(Executor::HttpRemote(HttpRemoteExecutor {
             args: self.args_with_out_paths.clone(),
             state,
             url,
             files,
+            client,
         }));
     }
 
-    pub fn build(self, id: CommandId) -> Command {
+    pub fn build(mut self, id: CommandId) -> Command {
+        let mut entry_only_archives = vec![];
+        for entry in &self.archive_entries {
+            if !self.inputs.contains(&entry.archive) {
+                self.inputs.push(entry.archive);
+                entry_only_archives.push(entry.archive);
+            }
+        }
         Command {
             id,
             name: self.name,
             executables: self.executables,
             inputs: self.inputs,
+            config_inputs: self.config_inputs,
+            archive_entries: self.archive_entries,
+            entry_only_archives,
             outputs: self.outputs,
+            output_globs: self.output_globs,
+            artifacts: vec![],
             deps: self.deps,
             executor: self.executor.unwrap(),
             tags: self.tags,
@@ -1,36 +1,161 @@
 use anyhow::Context;
+use clap::ValueEnum;
 use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// How files are linked, e.g. from the local cache into the output directory
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum LinkType {
+    /// falls back to copying if the files are on different devices
     Hardlink,
     Symlink,
+    Copy,
 }
 
 /// The max number of args to show in command lines, or show all if not set.
 pub static UI_COMMAND_ARGS_LIMIT: Option<usize> = Some(100);
 pub static UI_UPDATE_INTERVAL_TTY: f32 = 0.2;
 pub static UI_UPDATE_INTERVAL_NON_TTY: f32 = 20.0;
+/// Number of status lines when finishing commands if not writing to a terminal, e.g. for CI logs
+pub static UI_PROGRESS_LINES_NON_TTY: usize = 10;
 pub static EXECUTABLE: &str = "razel";
 pub static OUT_DIR: &str = "razel-out";
+/// Name of the file marking an output base created by razel, other dirs are not removed
+pub static OUTPUT_BASE_MARKER: &str = ".razel-output-base";
+/// Name of a project-local cache directory, preferred over the user cache directory
+pub static LOCAL_CACHE_DIR: &str = ".razel-cache";
+/// Files/directories marking the root of a project when searching for LOCAL_CACHE_DIR
+pub static PROJECT_ROOT_MARKERS: [&str; 2] = [".git", "razel.jsonl"];
+/// Name of the project config file within the workspace dir
+pub static PROJECT_CONFIG_FILE: &str = "razel.toml";
+/// Name of the file with digests of output files within the workspace dir, see --verify-outputs
+pub static LOCK_FILE: &str = "razel.lock";
+/// Name of the config file within the user config dir, e.g. ~/.config/razel/config.toml
+pub static USER_CONFIG_FILE: &str = "config.toml";
 /// The prefix for using a param/response file as command args
 pub static RESPONSE_FILE_PREFIX: &str = "@";
+/// Name of the param/response file within the sandbox
+pub static RESPONSE_FILE_NAME: &str = "params";
+/// Host directories commands may read and execute files from with --strict-sandbox
+pub static STRICT_SANDBOX_SYSTEM_DIRS: [&str; 6] =
+    ["/bin", "/lib", "/lib32", "/lib64", "/sbin", "/usr"];
+/// Max time to connect to a remote cache and query its capabilities, it's disabled for the run otherwise
+pub static REMOTE_CACHE_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Max time to wait for pending remote cache uploads at the end of a run
+pub static REMOTE_CACHE_FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval to check the size of files written by commands, see --max-command-output-size
+pub static OUTPUT_SIZE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Interval to sample the memory of the razel process, see --profile-memory
+pub static MEMORY_PROFILE_INTERVAL: Duration = Duration::from_millis(100);
+/// Env var set to the action digest of a command, see --action-digest-env
+pub static ACTION_DIGEST_ENV: &str = "RAZEL_ACTION_DIGEST";
 pub static SANDBOX_LINK_TYPE: LinkType = LinkType::Symlink;
-pub static OUT_DIR_LINK_TYPE: LinkType = LinkType::Symlink;
+pub static OUT_DIR_LINK_TYPE: LinkType = LinkType::Hardlink;
 
-pub fn select_cache_dir(workspace_dir: &Path) -> Result<PathBuf, anyhow::Error> {
+/// Defaults for run args read from a config file.
+///
+/// Precedence: command line, environment variables, project config file, user config file, built-in defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    /// relative paths are resolved against the directory of the config file
+    pub cache_dir: Option<PathBuf>,
+    pub remote_cache: Option<Vec<String>>,
+    pub remote_cache_threshold: Option<u32>,
+    /// relative paths are resolved against the directory of the config file
+    pub remote_cache_auth_token_file: Option<PathBuf>,
+    pub jobs: Option<usize>,
+}
+
+impl ConfigFile {
+    /// Returns None if the file does not exist
+    pub fn read(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read config file {path:?}"))
+            }
+        };
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("failed to parse config file {path:?}"))?;
+        if let Some(dir) = path.parent() {
+            config.cache_dir = config.cache_dir.map(|x| dir.join(x));
+            config.remote_cache_auth_token_file =
+                config.remote_cache_auth_token_file.map(|x| dir.join(x));
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Returns the paths of the project and user config file, ordered by precedence
+pub fn config_file_paths(workspace_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![workspace_dir.join(PROJECT_CONFIG_FILE)];
+    if let Some(x) = ProjectDirs::from("de", "reu-dev", EXECUTABLE) {
+        paths.push(x.config_dir().join(USER_CONFIG_FILE));
+    }
+    paths
+}
+
+/// Reads existing config files, ordered by precedence
+pub fn read_config_files(
+    workspace_dir: &Path,
+) -> Result<Vec<(PathBuf, ConfigFile)>, anyhow::Error> {
+    let mut files = vec![];
+    for path in config_file_paths(workspace_dir) {
+        if let Some(x) = ConfigFile::read(&path)? {
+            files.push((path, x));
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the cache directory and the reason for selecting it.
+///
+/// Precedence: explicitly set dir (--cache-dir/RAZEL_CACHE_DIR/config file), existing project-local dir, user cache dir.
+pub fn select_cache_dir(
+    cache_dir: Option<PathBuf>,
+    workspace_dir: &Path,
+) -> Result<(PathBuf, &'static str), anyhow::Error> {
+    if let Some(x) = cache_dir {
+        return Ok((x, "set by --cache-dir, RAZEL_CACHE_DIR or config file"));
+    }
+    if let Some(x) = find_local_cache_dir(workspace_dir) {
+        return Ok((x, "project-local"));
+    }
     let project_dirs = ProjectDirs::from("de", "reu-dev", EXECUTABLE).unwrap();
     let home_cache: PathBuf = project_dirs.cache_dir().into();
     std::fs::create_dir_all(&home_cache)?;
     let home_cache_device = device_of_dir(&home_cache)?;
     let workspace_device = device_of_dir(workspace_dir)?;
     Ok(if home_cache_device == workspace_device {
-        home_cache
+        (home_cache, "user cache dir")
     } else {
-        workspace_dir.join(".razel-cache")
+        (
+            workspace_dir.join(LOCAL_CACHE_DIR),
+            "workspace is on another device than user cache dir",
+        )
     })
 }
 
+/// Searches for LOCAL_CACHE_DIR from the workspace dir up to the project root
+fn find_local_cache_dir(workspace_dir: &Path) -> Option<PathBuf> {
+    for dir in workspace_dir.ancestors() {
+        let cache_dir = dir.join(LOCAL_CACHE_DIR);
+        if cache_dir.is_dir() {
+            return Some(cache_dir);
+        }
+        if PROJECT_ROOT_MARKERS.iter().any(|x| dir.join(x).exists()) {
+            break;
+        }
+    }
+    None
+}
+
 /// The returned directory contains hostname and process id to avoid conflicts with concurrent razel processes
 pub fn select_sandbox_dir(cache_dir: &Path) -> Result<PathBuf, anyhow::Error> {
     Ok(cache_dir
@@ -65,13 +190,13 @@ fn device_of_dir(dir: &Path) -> Result<String, anyhow::Error> {
 mod tests {
     use super::*;
     use crate::test_utils::TempDir;
-    use crate::unique_test_name;
+    use crate::{new_tmp_dir, unique_test_name};
     use directories::UserDirs;
     use std::env;
 
     fn check_cache_dir(workspace_dir: &PathBuf) {
         println!("workspace_dir: {workspace_dir:?}");
-        let cache_dir = select_cache_dir(workspace_dir).unwrap();
+        let (cache_dir, _) = select_cache_dir(None, workspace_dir).unwrap();
         println!("cache_dir:     {cache_dir:?}");
         assert!(cache_dir.is_absolute());
         assert_eq!(
@@ -94,4 +219,92 @@ mod tests {
             TempDir::with_dir(env::temp_dir().join(format!(".tmp-{}", unique_test_name!())));
         check_cache_dir(workspace.dir());
     }
+
+    #[test]
+    fn project_local_cache_dir() {
+        let dir = new_tmp_dir!();
+        let project = std::fs::canonicalize(dir.dir()).unwrap();
+        std::fs::create_dir(project.join(".git")).unwrap();
+        let workspace = project.join("a/b");
+        std::fs::create_dir_all(&workspace).unwrap();
+        // no local cache dir
+        assert_ne!(
+            select_cache_dir(None, &workspace).unwrap().1,
+            "project-local"
+        );
+        // local cache dir at project root
+        std::fs::create_dir(project.join(LOCAL_CACHE_DIR)).unwrap();
+        assert_eq!(
+            select_cache_dir(None, &workspace).unwrap(),
+            (project.join(LOCAL_CACHE_DIR), "project-local")
+        );
+        // local cache dir within workspace is preferred
+        std::fs::create_dir(workspace.join(LOCAL_CACHE_DIR)).unwrap();
+        assert_eq!(
+            select_cache_dir(None, &workspace).unwrap(),
+            (workspace.join(LOCAL_CACHE_DIR), "project-local")
+        );
+        // explicitly set dir overrides local cache dir
+        let explicit = project.join("explicit-cache");
+        assert_eq!(
+            select_cache_dir(Some(explicit.clone()), &workspace)
+                .unwrap()
+                .0,
+            explicit
+        );
+    }
+
+    #[test]
+    fn project_local_cache_dir_above_marker() {
+        let dir = new_tmp_dir!();
+        let dir = std::fs::canonicalize(dir.dir()).unwrap();
+        std::fs::create_dir(dir.join(LOCAL_CACHE_DIR)).unwrap();
+        let project = dir.join("project");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(project.join("razel.jsonl"), "").unwrap();
+        // search stops at marker
+        assert_eq!(find_local_cache_dir(&project), None);
+        std::fs::remove_file(project.join("razel.jsonl")).unwrap();
+        assert_eq!(
+            find_local_cache_dir(&project),
+            Some(dir.join(LOCAL_CACHE_DIR))
+        );
+    }
+
+    #[test]
+    fn read_config_file() {
+        let dir = new_tmp_dir!();
+        let path = dir.join_and_write_file(
+            "razel.toml",
+            "remote-cache = [\"grpc://localhost:9092\"]\ncache-dir = \"cache\"\njobs = 2\n\
+            remote-cache-auth-token-file = \"token.txt\"\n",
+        );
+        assert_eq!(
+            ConfigFile::read(&path).unwrap().unwrap(),
+            ConfigFile {
+                cache_dir: Some(dir.join("cache")),
+                remote_cache: Some(vec!["grpc://localhost:9092".into()]),
+                remote_cache_threshold: None,
+                remote_cache_auth_token_file: Some(dir.join("token.txt")),
+                jobs: Some(2),
+            }
+        );
+        assert_eq!(ConfigFile::read(&dir.join("missing.toml")).unwrap(), None);
+    }
+
+    #[test]
+    fn read_malformed_config_file() {
+        let dir = new_tmp_dir!();
+        for (name, content, exp_error) in [
+            ("syntax.toml", "jobs = ", "line 1, column 8"),
+            ("type.toml", "jobs = \"many\"", "invalid type"),
+            ("unknown.toml", "job = 2", "unknown field `job`"),
+        ] {
+            let path = dir.join_and_write_file(name, content);
+            let error = format!("{:#}", ConfigFile::read(&path).unwrap_err());
+            println!("{error}");
+            assert!(error.contains(name));
+            assert!(error.contains(exp_error));
+        }
+    }
 }
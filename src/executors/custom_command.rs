@@ -1,13 +1,20 @@
-use crate::config::RESPONSE_FILE_PREFIX;
+use crate::config::{OUTPUT_SIZE_CHECK_INTERVAL, RESPONSE_FILE_NAME, RESPONSE_FILE_PREFIX};
+use crate::metadata::IoNiceClass;
 use crate::CGroup;
 use anyhow::anyhow;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::{ExitStatus, Stdio};
+#[cfg(target_family = "unix")]
+use std::sync::Arc;
 use std::time::Instant;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::executors::{ExecutionResult, ExecutionStatus};
+use crate::executors::{ExecutionResult, ExecutionStatus, ProcessGroup};
+#[cfg(target_family = "unix")]
+use crate::executors::{Jobserver, Pty};
+#[cfg(target_os = "linux")]
+use crate::executors::{Landlock, TracedChild};
 
 #[derive(Clone, Default)]
 pub struct CustomCommandExecutor {
@@ -17,6 +24,91 @@ pub struct CustomCommandExecutor {
     pub stdout_file: Option<PathBuf>,
     pub stderr_file: Option<PathBuf>,
     pub timeout: Option<u16>,
+    /// kill the command at this point in time, see razel:deadline tag
+    pub deadline: Option<Instant>,
+    pub umask: Option<u32>,
+    /// capture stderr together with stdout, stderr_file must not be set
+    pub merge_stderr: bool,
+    /// IO scheduling class and level, only supported on Linux
+    pub io_nice: Option<(IoNiceClass, u8)>,
+    /// record files opened for reading, only supported on Linux
+    pub trace_inputs: bool,
+    /// restrict reading to these files and system dirs and writing to the sandbox dir using
+    /// Landlock, only supported on Linux
+    pub strict_sandbox_inputs: Option<Vec<PathBuf>>,
+    /// attach the command to a pseudo-terminal forwarding the terminal of razel, output is not
+    /// captured, only supported on Unix
+    pub interactive: bool,
+    /// kill the command if the files in the sandbox dir exceed this total size [byte], best-effort
+    /// because it's polled, see [Self::watch_output_size]
+    pub max_output_size: Option<u64>,
+    /// pass the pipe of the jobserver to the command, only supported on Unix
+    #[cfg(target_family = "unix")]
+    pub jobserver: Option<Arc<Jobserver>>,
+}
+
+/// Spawned command, optionally traced to record the files it reads
+enum Child {
+    Process(tokio::process::Child),
+    #[cfg(target_os = "linux")]
+    Traced(TracedChild),
+}
+
+impl Child {
+    fn id(&self) -> Option<u32> {
+        match self {
+            Child::Process(x) => x.id(),
+            #[cfg(target_os = "linux")]
+            Child::Traced(x) => Some(x.id()),
+        }
+    }
+
+    async fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        match self {
+            Child::Process(x) => x.wait().await,
+            #[cfg(target_os = "linux")]
+            Child::Traced(x) => x.wait().await,
+        }
+    }
+
+    async fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            Child::Process(x) => x.kill().await,
+            #[cfg(target_os = "linux")]
+            Child::Traced(x) => x.kill().await,
+        }
+    }
+
+    fn take_output_pipes(
+        &mut self,
+    ) -> (
+        Option<tokio::process::ChildStdout>,
+        Option<tokio::process::ChildStderr>,
+    ) {
+        match self {
+            Child::Process(x) => (x.stdout.take(), x.stderr.take()),
+            #[cfg(target_os = "linux")]
+            Child::Traced(x) => x.take_output_pipes(),
+        }
+    }
+
+    fn take_read_files(&mut self) -> Vec<PathBuf> {
+        match self {
+            Child::Process(_) => vec![],
+            #[cfg(target_os = "linux")]
+            Child::Traced(x) => x.take_read_files(),
+        }
+    }
+}
+
+async fn read_to_end<R: AsyncReadExt + Unpin>(
+    reader: Option<R>,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    if let Some(mut reader) = reader {
+        reader.read_to_end(buf).await?;
+    }
+    Ok(())
 }
 
 impl CustomCommandExecutor {
@@ -37,16 +129,37 @@ impl CustomCommandExecutor {
         };
         let cwd = sandbox_dir_option.unwrap_or_else(|| ".".into());
         let execution_start = Instant::now();
-        let child = match tokio::process::Command::new(&self.executable)
+        let mut command = std::process::Command::new(&self.executable);
+        command
             .env_clear()
             .envs(&self.env)
             .args(response_file_args.as_ref().unwrap_or(&self.args))
-            .current_dir(&cwd)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-        {
+            .current_dir(&cwd);
+        let forward_pty = match self.set_interactive(&mut command) {
+            Ok(x) => x,
+            Err(e) => {
+                result.status = ExecutionStatus::FailedToStart;
+                result.error = Some(e.into());
+                return result;
+            }
+        };
+        if forward_pty.is_none() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            ProcessGroup::configure(&mut command);
+        }
+        self.set_umask(&mut command);
+        self.set_io_nice(&mut command);
+        self.set_merge_stderr(&mut command);
+        #[cfg(target_family = "unix")]
+        if let Some(x) = &self.jobserver {
+            x.configure(&mut command);
+        }
+        if let Err(e) = self.set_strict_sandbox(&mut command, &cwd) {
+            result.status = ExecutionStatus::FailedToStart;
+            result.error = Some(e);
+            return result;
+        }
+        let child = match self.spawn(command) {
             Ok(child) => child,
             Err(e) => {
                 result.status = ExecutionStatus::FailedToStart;
@@ -54,16 +167,30 @@ impl CustomCommandExecutor {
                 return result;
             }
         };
-        if let Some(cgroup) = cgroup {
-            cgroup.add_task("memory", child.id().unwrap()).ok();
+        let forwarding = forward_pty.map(tokio::spawn);
+        let command_cgroup = cgroup.and_then(|x| Self::add_to_cgroup(&x, child.id().unwrap()));
+        let process_group = ProcessGroup::new(child.id());
+        let (exec_result, killed_status, read_files) = self
+            .wait_with_limits(child, process_group.as_ref(), &cwd)
+            .await;
+        if let Some(x) = forwarding {
+            x.await.ok();
+        }
+        if let Some(x) = process_group.filter(|_| killed_status.is_none()) {
+            x.finish();
         }
-        let (exec_result, timed_out) = self.wait_with_timeout(child).await;
         match exec_result {
             Ok(output) => {
                 if output.status.success() {
                     result.status = ExecutionStatus::Success;
-                } else if timed_out {
-                    result.status = ExecutionStatus::Timeout;
+                } else if let Some(status) = killed_status {
+                    result.status = status;
+                    if status == ExecutionStatus::OutputSizeExceeded {
+                        result.error = Some(anyhow!(
+                            "output size budget of {} bytes exceeded",
+                            self.max_output_size.unwrap()
+                        ));
+                    }
                 } else {
                     (result.status, result.signal, result.error) =
                         Self::evaluate_status(output.status);
@@ -81,30 +208,308 @@ impl CustomCommandExecutor {
             }
         }
         result.exec_duration = Some(execution_start.elapsed());
+        result.read_files = Self::paths_relative_to_cwd(read_files, &cwd);
+        if let Some(x) = command_cgroup {
+            result.peak_memory = x.peak_memory().ok();
+            // fails if descendants of the command are still running
+            x.remove("memory").ok();
+        }
         self.write_redirect_files(&cwd, &mut result).await;
         result
     }
 
-    async fn wait_with_timeout(
+    /// Adds the process to a sub group of the razel cgroup to measure its peak memory usage.
+    ///
+    /// Falls back to adding it directly to the razel cgroup if the sub group can't be used.
+    fn add_to_cgroup(cgroup: &CGroup, pid: u32) -> Option<CGroup> {
+        match cgroup.create_child("memory", &pid.to_string()) {
+            Ok(child) if child.add_task("memory", pid).is_ok() => return Some(child),
+            Ok(child) => {
+                child.remove("memory").ok();
+            }
+            Err(_) => {}
+        }
+        cgroup.add_task("memory", pid).ok();
+        None
+    }
+
+    #[cfg(target_family = "unix")]
+    fn set_umask(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        if let Some(umask) = self.umask {
+            // SAFETY: umask() is async-signal-safe and can not fail
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(umask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    fn set_umask(&self, _command: &mut std::process::Command) {
+        if self.umask.is_some() {
+            log::warn!(
+                "umask is not supported on Windows, ignoring it: {}",
+                self.executable
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_io_nice(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        if let Some((class, level)) = self.io_nice {
+            let class = match class {
+                IoNiceClass::Realtime => 1,
+                IoNiceClass::BestEffort => 2,
+                IoNiceClass::Idle => 3,
+            };
+            let ioprio = class << IOPRIO_CLASS_SHIFT | libc::c_int::from(level);
+            // SAFETY: syscall() is async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    // only a performance hint, e.g. realtime class fails without CAP_SYS_ADMIN
+                    libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio);
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_io_nice(&self, _command: &mut std::process::Command) {
+        if self.io_nice.is_some() {
+            log::warn!(
+                "ionice is only supported on Linux, ignoring it: {}",
+                self.executable
+            );
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    fn set_merge_stderr(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        if self.merge_stderr {
+            command.stderr(Stdio::null());
+            // SAFETY: dup2() is async-signal-safe
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::dup2(1, 2) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    fn set_merge_stderr(&self, _command: &mut std::process::Command) {
+        if self.merge_stderr {
+            log::warn!(
+                "merging stderr into stdout is not supported on Windows, ignoring it: {}",
+                self.executable
+            );
+        }
+    }
+
+    /// Returns the future forwarding the terminal, to be spawned after the command.
+    ///
+    /// The command creates a new session instead of a process group, its id is the same.
+    #[cfg(target_family = "unix")]
+    fn set_interactive(
         &self,
-        mut child: tokio::process::Child,
-    ) -> (std::io::Result<std::process::Output>, bool) {
-        let timed_out = if let Some(timeout_s) = self.timeout {
-            let sleep = tokio::time::sleep(std::time::Duration::from_secs(timeout_s.into()));
-            tokio::pin!(sleep);
-            tokio::select! {
-                _ = child.wait() => {
-                    false
-                }
-                _ = &mut sleep => {
-                    let _ = child.kill().await;
-                    true
+        command: &mut std::process::Command,
+    ) -> std::io::Result<Option<impl std::future::Future<Output = ()>>> {
+        if !self.interactive {
+            return Ok(None);
+        }
+        Ok(Some(Pty::configure(command)?.forward()))
+    }
+
+    #[cfg(target_family = "windows")]
+    fn set_interactive(
+        &self,
+        _command: &mut std::process::Command,
+    ) -> std::io::Result<Option<std::future::Ready<()>>> {
+        if self.interactive {
+            log::warn!(
+                "interactive commands are not supported on Windows, ignoring it: {}",
+                self.executable
+            );
+        }
+        Ok(None)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_strict_sandbox(
+        &self,
+        command: &mut std::process::Command,
+        sandbox_dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(inputs) = &self.strict_sandbox_inputs {
+            Landlock::new(inputs, sandbox_dir)?.configure(command);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_strict_sandbox(
+        &self,
+        _command: &mut std::process::Command,
+        _sandbox_dir: &Path,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn spawn(&self, command: std::process::Command) -> std::io::Result<Child> {
+        #[cfg(target_os = "linux")]
+        if self.trace_inputs {
+            return TracedChild::spawn(command).map(Child::Traced);
+        }
+        tokio::process::Command::from(command)
+            .kill_on_drop(true)
+            .spawn()
+            .map(Child::Process)
+    }
+
+    /// Waits for the command while reading stdout/stderr, otherwise the command would block on
+    /// full pipes.
+    ///
+    /// On timeout or when exceeding the output size budget, the process group is killed to not
+    /// wait for descendants holding stdout/stderr
+    async fn wait_with_limits(
+        &self,
+        mut child: Child,
+        process_group: Option<&ProcessGroup>,
+        cwd: &Path,
+    ) -> (
+        std::io::Result<std::process::Output>,
+        Option<ExecutionStatus>,
+        Vec<PathBuf>,
+    ) {
+        let (stdout_pipe, stderr_pipe) = child.take_output_pipes();
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let wait = async {
+            let killed_status = if self.timeout.is_some()
+                || self.deadline.is_some()
+                || self.max_output_size.is_some()
+            {
+                let limit = async {
+                    tokio::select! {
+                        _ = self.sleep_until_timeout() => ExecutionStatus::Timeout,
+                        _ = self.watch_output_size(cwd) => ExecutionStatus::OutputSizeExceeded,
+                    }
+                };
+                tokio::select! {
+                    _ = child.wait() => {
+                        None
+                    }
+                    status = limit => {
+                        if let Some(x) = process_group {
+                            x.kill();
+                        }
+                        let _ = child.kill().await;
+                        Some(status)
+                    }
                 }
+            } else {
+                None
+            };
+            (child.wait().await, killed_status)
+        };
+        let ((status, killed_status), stdout_result, stderr_result) = tokio::join!(
+            wait,
+            read_to_end(stdout_pipe, &mut stdout),
+            read_to_end(stderr_pipe, &mut stderr)
+        );
+        let output = status.and_then(|status| {
+            stdout_result?;
+            stderr_result?;
+            Ok(std::process::Output {
+                status,
+                stdout,
+                stderr,
+            })
+        });
+        (output, killed_status, child.take_read_files())
+    }
+
+    /// Sleeps until the timeout or the deadline, never returns if neither is set
+    async fn sleep_until_timeout(&self) {
+        let timeout = self
+            .timeout
+            .map(|x| Instant::now() + std::time::Duration::from_secs(x.into()));
+        match timeout.into_iter().chain(self.deadline).min() {
+            Some(x) => tokio::time::sleep_until(x.into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Returns once the files in the dir exceed max_output_size, never returns if it's not set.
+    ///
+    /// Polling is used because limiting the size using a tmpfs or disk quota requires privileges.
+    /// Therefore the limit is best-effort: the command can write more than max_output_size within
+    /// OUTPUT_SIZE_CHECK_INTERVAL before it's killed.
+    async fn watch_output_size(&self, dir: &Path) {
+        let Some(max_output_size) = self.max_output_size else {
+            return std::future::pending().await;
+        };
+        let mut interval = tokio::time::interval(OUTPUT_SIZE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let dir = dir.to_path_buf();
+            let size = tokio::task::spawn_blocking(move || dir_size(&dir))
+                .await
+                .unwrap_or_default();
+            if size > max_output_size {
+                return;
             }
-        } else {
-            false
+        }
+    }
+
+    /// Makes traced paths within the working dir of the command relative, e.g. to the sandbox dir
+    fn paths_relative_to_cwd(paths: Vec<PathBuf>, cwd: &Path) -> Vec<PathBuf> {
+        if paths.is_empty() {
+            return paths;
+        }
+        let Ok(cwd) = std::fs::canonicalize(cwd) else {
+            return paths;
         };
-        (child.wait_with_output().await, timed_out)
+        paths
+            .into_iter()
+            .map(|x| x.strip_prefix(&cwd).map(|x| x.to_path_buf()).unwrap_or(x))
+            .collect()
+    }
+
+    /// Let the command write an output file to another path, e.g. to rename it after execution.
+    ///
+    /// Only args equal to the path and stdout/stderr files are redirected.
+    /// Returns if the path was found.
+    pub fn redirect_output_file(&mut self, path: &Path, other: &Path) -> bool {
+        let mut found = false;
+        for arg in self
+            .args
+            .iter_mut()
+            .filter(|x| Path::new(x.as_str()) == path)
+        {
+            *arg = other.to_str().unwrap().into();
+            found = true;
+        }
+        for file in [&mut self.stdout_file, &mut self.stderr_file]
+            .into_iter()
+            .flatten()
+            .filter(|x| *x == path)
+        {
+            *file = other.to_path_buf();
+            found = true;
+        }
+        found
     }
 
     pub fn args_with_executable(&self) -> Vec<String> {
@@ -134,6 +539,7 @@ impl CustomCommandExecutor {
                     .flatten(),
             )
             .cloned()
+            .chain(self.merge_stderr.then(|| "2>&1".to_string()))
             .collect()
     }
 
@@ -200,18 +606,17 @@ impl CustomCommandExecutor {
         if !self.is_response_file_needed() {
             return Ok(None);
         }
-        let file_name = "params";
         let path = sandbox_dir
             .as_ref()
             .ok_or_else(|| anyhow!("Sandbox is required for response file!"))?
-            .join(file_name);
+            .join(RESPONSE_FILE_NAME);
         let mut file = tokio::fs::File::create(path).await?;
         file.write_all(self.args.join("\n").as_bytes()).await?;
         file.sync_all().await?;
-        Ok(Some(RESPONSE_FILE_PREFIX.to_string() + file_name))
+        Ok(Some(RESPONSE_FILE_PREFIX.to_string() + RESPONSE_FILE_NAME))
     }
 
-    fn is_response_file_needed(&self) -> bool {
+    pub fn is_response_file_needed(&self) -> bool {
         /* those limits are taken from test_arg_max()
          * TODO replace hardcoded limits with running that check before executing commands */
         let (max_len, terminator_len) = if cfg!(windows) {
@@ -267,29 +672,38 @@ impl CustomCommandExecutor {
     }
 }
 
+/// Total size of regular files within a dir, symlinks e.g. to inputs are not followed
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(x) if x.is_dir() => dir_size(&entry.path()),
+            Ok(x) if x.is_file() => x.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::executors::{CustomCommandExecutor, ExecutionStatus};
     use crate::metadata::Tag;
-    use crate::Razel;
+    use crate::{CustomCommandSpec, Razel};
     use std::path::Path;
 
     #[tokio::test]
     async fn exec_ok() {
         let mut razel = Razel::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-E".into(), "true".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![],
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "true".into()],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec(Path::new("."), None, None).await;
@@ -304,16 +718,8 @@ mod tests {
         let mut razel = Razel::new();
         let command = razel
             .push_custom_command(
-                "test".into(),
-                "./examples/data/a.csv".into(), // file exists but is not executable
-                vec![],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![],
+                CustomCommandSpec { name: "test".into(), executable: "./examples/data/a.csv".into(), args: // file exists but is not executable
+                vec![], ..Default::default() },
             )
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
@@ -328,18 +734,12 @@ mod tests {
     async fn exec_failed_to_run() {
         let mut razel = Razel::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-E".into(), "false".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![],
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "false".into()],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec(Path::new("."), None, None).await;
@@ -353,18 +753,12 @@ mod tests {
     async fn exec_stdout() {
         let mut razel = Razel::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-h".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![],
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-h".into()],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec(Path::new("."), None, None).await;
@@ -380,18 +774,12 @@ mod tests {
     async fn exec_stderr() {
         let mut razel = Razel::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-E".into(), "hopefully-not-existing-command".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![],
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "hopefully-not-existing-command".into()],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec(Path::new("."), None, None).await;
@@ -407,18 +795,13 @@ mod tests {
     async fn exec_timeout() {
         let mut razel = Razel::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-E".into(), "sleep".into(), "3".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-                vec![],
-                vec![Tag::Timeout(1)],
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "sleep".into(), "3".into()],
+                tags: vec![Tag::Timeout(1)],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec(Path::new("."), None, None).await;
@@ -427,21 +810,235 @@ mod tests {
         assert_ne!(result.exit_code, Some(0));
     }
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exec_timeout_kills_descendants() {
+        let dir = crate::new_tmp_dir!();
+        let pid_file = dir.join("grandchild.pid");
+        let mut razel = Razel::new();
+        let command = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    format!("sleep 30 & echo $! > {}; sleep 30", pid_file.display()),
+                ],
+                tags: vec![Tag::Timeout(1)],
+                ..Default::default()
+            })
+            .map(|id| razel.get_command(id).unwrap())
+            .unwrap();
+        let start = std::time::Instant::now();
+        let result = command.executor.exec(Path::new("."), None, None).await;
+        assert_eq!(result.status, ExecutionStatus::Timeout);
+        // stdout is not kept open by the grandchild
+        assert!(start.elapsed().as_secs() < 10);
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // the grandchild is killed, but might not yet be reaped
+        let is_running = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+            .map(|x| !x.contains(") Z "))
+            .unwrap_or_default();
+        assert!(!is_running);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exec_output_size_exceeded() {
+        let dir = crate::new_tmp_dir!();
+        let executor = CustomCommandExecutor {
+            executable: "sh".into(),
+            args: vec![
+                "-c".into(),
+                "while true; do head -c 100000 /dev/zero >> out.bin; done".into(),
+            ],
+            max_output_size: Some(1_000_000),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let result = executor.exec(Some(dir.dir().into()), None).await;
+        assert_eq!(result.status, ExecutionStatus::OutputSizeExceeded);
+        assert_eq!(
+            result.error.unwrap().to_string(),
+            "output size budget of 1000000 bytes exceeded"
+        );
+        assert!(start.elapsed().as_secs() < 10);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn exec_large_stdout_with_limits() {
+        let dir = crate::new_tmp_dir!();
+        let executor = CustomCommandExecutor {
+            executable: "sh".into(),
+            args: vec!["-c".into(), "head -c 1000000 /dev/zero".into()],
+            timeout: Some(60),
+            max_output_size: Some(10_000_000),
+            ..Default::default()
+        };
+        // pipes are drained while waiting, otherwise the command blocks until the timeout
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(30),
+            executor.exec(Some(dir.dir().into()), None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.status, ExecutionStatus::Success);
+        assert_eq!(result.stdout.len(), 1_000_000);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exec_peak_memory() {
+        let Ok(Some(cgroup)) = crate::create_cgroup() else {
+            println!("cgroup not available, skipping test");
+            return;
+        };
+        // tests are in target/<profile>/deps, razel-self-test is built by the workspace
+        let self_test = std::env::current_exe()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .with_file_name("razel-self-test");
+        let memory = 100 * 1024 * 1024;
+        let executor = CustomCommandExecutor {
+            executable: self_test.to_str().unwrap().into(),
+            args: vec!["--memory".into(), memory.to_string()],
+            ..Default::default()
+        };
+        let result = executor.exec(None, Some(cgroup)).await;
+        assert!(result.success(), "{:?}", result.error);
+        let peak_memory = result.peak_memory.unwrap();
+        assert!(peak_memory > memory * 9 / 10, "{peak_memory}");
+        assert!(peak_memory < memory * 2, "{peak_memory}");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn exec_umask() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = crate::new_tmp_dir!();
+        let file = dir.join("umask.txt");
+        let mut razel = Razel::new();
+        let command = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "touch".into(), file.to_str().unwrap().into()],
+                tags: vec![Tag::Umask(0o077)],
+                ..Default::default()
+            })
+            .map(|id| razel.get_command(id).unwrap())
+            .unwrap();
+        let result = command.executor.exec(Path::new("."), None, None).await;
+        assert!(result.success());
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn exec_io_nice() {
+        use crate::metadata::IoNiceClass;
+        if which::which("ionice").is_err() {
+            println!("ionice not available, skipping test");
+            return;
+        }
+        for (tag, expected) in [
+            (
+                Tag::IoNice(IoNiceClass::BestEffort, 7),
+                "best-effort: prio 7",
+            ),
+            (Tag::IoNice(IoNiceClass::Idle, 0), "idle"),
+        ] {
+            let mut razel = Razel::new();
+            let command = razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "test".into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "ionice -p $$".into()],
+                    tags: vec![tag],
+                    ..Default::default()
+                })
+                .map(|id| razel.get_command(id).unwrap())
+                .unwrap();
+            let result = command.executor.exec(Path::new("."), None, None).await;
+            assert!(result.success());
+            assert_eq!(String::from_utf8_lossy(&result.stdout).trim(), expected);
+        }
+    }
+
+    #[test]
+    fn redirect_output_file() {
+        let mut executor = CustomCommandExecutor {
+            executable: "cmake".into(),
+            args: vec![
+                "-E".into(),
+                "copy".into(),
+                "a.txt".into(),
+                "out/b.txt".into(),
+            ],
+            stdout_file: Some("out/stdout.txt".into()),
+            ..Default::default()
+        };
+        assert!(executor.redirect_output_file(Path::new("out/b.txt"), Path::new("out/.b.txt")));
+        assert!(executor
+            .redirect_output_file(Path::new("out/stdout.txt"), Path::new("out/.stdout.txt")));
+        assert!(!executor.redirect_output_file(Path::new("out/c.txt"), Path::new("out/.c.txt")));
+        assert_eq!(executor.args, ["-E", "copy", "a.txt", "out/.b.txt"]);
+        assert_eq!(executor.stdout_file, Some("out/.stdout.txt".into()));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn exec_merge_stderr() {
+        let mut razel = Razel::new();
+        let command = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "echo out1; echo err1 >&2; echo out2; echo err2 >&2".into(),
+                ],
+                tags: vec![Tag::MergeStderr],
+                ..Default::default()
+            })
+            .map(|id| razel.get_command(id).unwrap())
+            .unwrap();
+        let result = command.executor.exec(Path::new("."), None, None).await;
+        assert!(result.success());
+        assert_eq!(
+            String::from_utf8_lossy(&result.stdout),
+            "out1\nerr1\nout2\nerr2\n"
+        );
+        assert!(result.stderr.is_empty());
+    }
+
+    #[test]
+    fn merge_stderr_with_stderr_file() {
+        let mut razel = Razel::new();
+        let result = razel.push_custom_command(CustomCommandSpec {
+            name: "test".into(),
+            executable: "sh".into(),
+            stderr: Some("stderr.txt".into()),
+            tags: vec![Tag::MergeStderr],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+    }
+
     /* TODO
     #[tokio::test]
     async fn exec_kill() {
         let mut razel = Scheduler::new();
         let command = razel
-            .push_custom_command(
-                "test".into(),
-                "cmake".into(),
-                vec!["-E".into(), "sleep".into(), "10".into()],
-                Default::default(),
-                vec![],
-                vec![],
-                None,
-                None,
-            )
+            .push_custom_command(CustomCommandSpec {
+                name: "test".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "sleep".into(), "10".into()],
+                ..Default::default()
+            })
             .map(|id| razel.get_command(id).unwrap())
             .unwrap();
         let result = command.executor.exec().await;
@@ -1,7 +1,9 @@
-use crate::CacheHit;
+use crate::cache::RemoteCacheMiss;
+use crate::{CacheHit, TransientSandboxError};
 use anyhow::{anyhow, Error};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::PathBuf;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -18,6 +20,12 @@ pub struct ExecutionResult {
     pub exec_duration: Option<Duration>,
     /// actual duration of processing the command/task - including caching and overheads
     pub total_duration: Option<Duration>,
+    /// peak memory usage of the command [byte], only measured on Linux
+    pub peak_memory: Option<u64>,
+    /// files opened for reading by the command, only recorded with CustomCommandExecutor::trace_inputs
+    pub read_files: Vec<PathBuf>,
+    /// why the remote cache was queried but not used, only set for executed actions
+    pub remote_cache_miss: Option<RemoteCacheMiss>,
 }
 
 impl ExecutionResult {
@@ -47,6 +55,29 @@ impl ExecutionResult {
         self.status == ExecutionStatus::Crashed && self.signal == Some(9)
     }
 
+    /// System errors not caused by IO for a single command, e.g. creating its sandbox, should
+    /// stop starting further commands
+    pub fn is_fatal_system_error(&self) -> bool {
+        self.status == ExecutionStatus::SystemError && !self.is_caused_by_io_error()
+    }
+
+    /// System error caused by IO for a single command, only fails that command
+    pub fn is_target_local_system_error(&self) -> bool {
+        self.status == ExecutionStatus::SystemError && self.is_caused_by_io_error()
+    }
+
+    /// Target-local system error which is worth retrying, see [TransientSandboxError]
+    pub fn is_transient_system_error(&self) -> bool {
+        self.is_target_local_system_error()
+            && self.error.as_ref().is_some_and(TransientSandboxError::is)
+    }
+
+    fn is_caused_by_io_error(&self) -> bool {
+        self.error
+            .as_ref()
+            .is_some_and(|x| x.chain().any(|x| x.is::<std::io::Error>()))
+    }
+
     pub fn improve_error_message(&mut self) {
         let Ok(stderr) = std::str::from_utf8(&self.stderr).map(|x| x.lines()) else {
             return;
@@ -121,7 +152,11 @@ pub enum ExecutionStatus {
     /// core dumped or terminated by signal
     Crashed,
     Timeout,
+    /// killed because its output files exceeded the budget, see `--max-command-output-size`
+    OutputSizeExceeded,
     Success,
+    /// outputs differ between two executions, see `--check-determinism`
+    Nondeterministic,
     /// not command related error, e.g. cache, sandbox
     SystemError,
 }
@@ -75,7 +75,7 @@ impl Executor {
         match self {
             Executor::CustomCommand(x) => x.stdout_file.as_ref(),
             Executor::Wasi(x) => x.stdout_file.as_ref(),
-            _ => unreachable!(),
+            _ => None,
         }
     }
 
@@ -83,7 +83,7 @@ impl Executor {
         match self {
             Executor::CustomCommand(x) => x.stderr_file.as_ref(),
             Executor::Wasi(x) => x.stderr_file.as_ref(),
-            _ => unreachable!(),
+            _ => None,
         }
     }
 
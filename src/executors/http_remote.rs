@@ -1,5 +1,5 @@
 use crate::executors::{ExecutionResult, ExecutionStatus};
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use itertools::Itertools;
 use log::warn;
 use reqwest::{multipart, Client, Url};
@@ -11,6 +11,7 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tokio::fs;
+use tokio::io::AsyncReadExt;
 
 type Domain = String;
 type Host = String;
@@ -33,7 +34,7 @@ pub struct HttpRemoteExecState {
 }
 
 impl HttpRemoteExecState {
-    pub fn new(config: &HttpRemoteExecConfig) -> Self {
+    pub fn new(config: &HttpRemoteExecConfig, client: &Client) -> Self {
         let domains = config
             .0
             .iter()
@@ -49,7 +50,7 @@ impl HttpRemoteExecState {
                         HttpRemoteExecHost {
                             host,
                             port,
-                            client: Default::default(),
+                            client: client.clone(),
                             available_slots,
                             used_slots: Default::default(),
                             is_ok: AtomicBool::new(true),
@@ -115,6 +116,8 @@ pub struct HttpRemoteExecutor {
     pub state: Option<Arc<HttpRemoteExecDomain>>,
     pub url: Url,
     pub files: Vec<(String, PathBuf)>,
+    /// used if no hosts are configured for the domain
+    pub client: Client,
 }
 
 impl HttpRemoteExecutor {
@@ -122,7 +125,7 @@ impl HttpRemoteExecutor {
         let result = if let Some(domain) = &self.state {
             self.exec_on_some_host_of_domain(domain).await
         } else {
-            self.request(&Default::default(), self.url.clone()).await
+            self.request(&self.client, self.url.clone()).await
         };
         result.unwrap_or_else(|error| ExecutionResult {
             status: ExecutionStatus::SystemError,
@@ -179,17 +182,41 @@ impl HttpRemoteExecutor {
     async fn build_form(&self) -> Result<multipart::Form, anyhow::Error> {
         let mut form = multipart::Form::new();
         for (name, path) in &self.files {
-            let bytes = fs::read(path).await?;
-            let part = multipart::Part::bytes(bytes).file_name(name.clone());
+            let file = fs::File::open(path)
+                .await
+                .with_context(|| format!("{path:?}"))?;
+            let len = file.metadata().await?.len();
+            let part = multipart::Part::stream_with_length(Self::file_body(file), len)
+                .file_name(name.clone());
             form = form.part(name.clone(), part);
         }
         Ok(form)
     }
 
+    /// Reads the file in chunks while sending to not keep large files in memory
+    fn file_body(file: fs::File) -> reqwest::Body {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let stream = futures_util::stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let len = file.read(&mut chunk).await?;
+            if len == 0 {
+                return Ok::<_, std::io::Error>(None);
+            }
+            chunk.truncate(len);
+            Ok(Some((chunk, file)))
+        });
+        reqwest::Body::wrap_stream(stream)
+    }
+
     async fn request(&self, client: &Client, url: Url) -> anyhow::Result<ExecutionResult> {
         let execution_start = Instant::now();
         let form = self.build_form().await?;
-        let response = client.post(url).multipart(form).send().await?;
+        let response = client
+            .post(url.clone())
+            .multipart(form)
+            .send()
+            .await
+            .with_context(|| format!("failed to post form to {url}"))?;
         let status = response.status();
         let text = response.text().await?;
         Ok(ExecutionResult {
@@ -205,3 +232,103 @@ impl HttpRemoteExecutor {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use serial_test::serial;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// Accepts a single request and responds with the number of received body bytes
+    async fn serve_once(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; 64 * 1024];
+        let mut request = vec![];
+        let header_end = loop {
+            let len = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..len]);
+            if let Some(x) = request.windows(4).position(|x| x == b"\r\n\r\n") {
+                break x + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+        let content_length: usize = headers
+            .lines()
+            .find_map(|x| x.strip_prefix("content-length: "))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let mut received = request.len() - header_end;
+        while received < content_length {
+            received += stream.read(&mut buf).await.unwrap();
+        }
+        let body = received.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+    }
+
+    /// serial because the peak memory is measured for the whole test process
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[serial]
+    async fn post_large_file() {
+        const FILE_SIZE: usize = 128 * 1024 * 1024;
+        let dir = new_tmp_dir!();
+        let path = dir.join("large.bin");
+        std::fs::File::create(&path)
+            .unwrap()
+            .set_len(FILE_SIZE as u64)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let server = tokio::spawn(serve_once(listener));
+        let executor = HttpRemoteExecutor {
+            args: vec![],
+            state: None,
+            url,
+            files: vec![("large".into(), path)],
+            client: Default::default(),
+        };
+        let peak_before = crate::test_utils::peak_memory_kb();
+        let result = executor.exec().await;
+        let peak_after = crate::test_utils::peak_memory_kb();
+        server.await.unwrap();
+        assert!(result.success(), "{:?}", result.error);
+        let received: usize = String::from_utf8(result.stdout).unwrap().parse().unwrap();
+        assert!(received > FILE_SIZE);
+        assert!(
+            peak_after - peak_before < 32 * 1024,
+            "peak memory increased by {} kB",
+            peak_after - peak_before
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        drop(listener);
+        let dir = new_tmp_dir!();
+        let path = dir.join_and_write_file("file.txt", "content");
+        let executor = HttpRemoteExecutor {
+            args: vec![],
+            state: None,
+            url,
+            files: vec![("file".into(), path)],
+            client: Default::default(),
+        };
+        let result = executor.exec().await;
+        assert_eq!(result.status, ExecutionStatus::SystemError);
+        assert!(result
+            .error
+            .unwrap()
+            .to_string()
+            .starts_with("failed to post form to"));
+    }
+}
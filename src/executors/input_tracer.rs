@@ -0,0 +1,357 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+
+#[cfg(target_env = "gnu")]
+type PtraceRequest = libc::c_uint;
+#[cfg(not(target_env = "gnu"))]
+type PtraceRequest = libc::c_int;
+
+/// not yet defined in libc for all targets
+const PTRACE_GET_SYSCALL_INFO: PtraceRequest = 0x420e;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+
+/// Entry variant of `struct ptrace_syscall_info`
+#[repr(C)]
+#[derive(Default)]
+struct SyscallEntryInfo {
+    op: u8,
+    pad: [u8; 3],
+    arch: u32,
+    instruction_pointer: u64,
+    stack_pointer: u64,
+    nr: u64,
+    args: [u64; 6],
+}
+
+type TraceResult = (io::Result<ExitStatus>, Vec<PathBuf>);
+
+/// Child process traced with ptrace to record the files it opens for reading.
+///
+/// The command and all its descendants are traced until the command exits. Only the paths passed
+/// to open syscalls are recorded, they might not exist.
+pub struct TracedChild {
+    pid: u32,
+    stdout: Option<tokio::process::ChildStdout>,
+    stderr: Option<tokio::process::ChildStderr>,
+    result_rx: oneshot::Receiver<TraceResult>,
+    result: Option<TraceResult>,
+}
+
+impl TracedChild {
+    /// Spawns the command in a new thread which traces it.
+    ///
+    /// ptrace requires the tracer to be the thread which spawned the process.
+    pub fn spawn(mut command: std::process::Command) -> io::Result<Self> {
+        // SAFETY: ptrace(PTRACE_TRACEME) is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                if ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let (spawned_tx, spawned_rx) = std::sync::mpsc::channel();
+        let (result_tx, result_rx) = oneshot::channel();
+        std::thread::spawn(move || match command.spawn() {
+            Ok(mut child) => {
+                let pid = child.id();
+                spawned_tx
+                    .send(Ok((pid, child.stdout.take(), child.stderr.take())))
+                    .ok();
+                result_tx.send(trace(pid as libc::pid_t)).ok();
+            }
+            Err(e) => {
+                spawned_tx.send(Err(e)).ok();
+            }
+        });
+        let (pid, stdout, stderr) = spawned_rx
+            .recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "input tracer thread stopped"))??;
+        Ok(Self {
+            pid,
+            stdout: stdout
+                .map(tokio::process::ChildStdout::from_std)
+                .transpose()?,
+            stderr: stderr
+                .map(tokio::process::ChildStderr::from_std)
+                .transpose()?,
+            result_rx,
+            result: None,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        if self.result.is_none() {
+            self.result = Some((&mut self.result_rx).await.unwrap_or_else(|_| {
+                let error = io::Error::new(io::ErrorKind::Other, "input tracer failed");
+                (Err(error), vec![])
+            }));
+        }
+        match &self.result.as_ref().unwrap().0 {
+            Ok(x) => Ok(*x),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+
+    pub async fn kill(&mut self) -> io::Result<()> {
+        // SAFETY: kill() has no memory safety requirements
+        if unsafe { libc::kill(self.pid as libc::pid_t, libc::SIGKILL) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.wait().await.map(|_| ())
+    }
+
+    pub fn take_output_pipes(
+        &mut self,
+    ) -> (
+        Option<tokio::process::ChildStdout>,
+        Option<tokio::process::ChildStderr>,
+    ) {
+        (self.stdout.take(), self.stderr.take())
+    }
+
+    /// Paths of files read by the command, empty until it has exited
+    pub fn take_read_files(&mut self) -> Vec<PathBuf> {
+        self.result.take().map(|x| x.1).unwrap_or_default()
+    }
+
+    /// Waits for the command to exit and returns its output and the paths of files it read
+    pub async fn wait_with_output(mut self) -> (io::Result<Output>, Vec<PathBuf>) {
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let (stdout_pipe, stderr_pipe) = self.take_output_pipes();
+        let (status, stdout_result, stderr_result) = tokio::join!(
+            self.wait(),
+            read_to_end(stdout_pipe, &mut stdout),
+            read_to_end(stderr_pipe, &mut stderr)
+        );
+        let read_files = self.take_read_files();
+        let output = status.and_then(|status| {
+            stdout_result?;
+            stderr_result?;
+            Ok(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        });
+        (output, read_files)
+    }
+}
+
+async fn read_to_end<R: AsyncReadExt + Unpin>(
+    reader: Option<R>,
+    buf: &mut Vec<u8>,
+) -> io::Result<()> {
+    if let Some(mut reader) = reader {
+        reader.read_to_end(buf).await?;
+    }
+    Ok(())
+}
+
+/// Traces the process and its descendants until the process exits
+fn trace(pid: libc::pid_t) -> TraceResult {
+    let mut status = 0;
+    // the process stops with SIGTRAP after execve() because of PTRACE_TRACEME
+    // SAFETY: waitpid() and ptrace() have no memory safety requirements for these args
+    unsafe {
+        if libc::waitpid(pid, &mut status, libc::__WALL) == -1 {
+            return (Err(io::Error::last_os_error()), vec![]);
+        }
+        let options = libc::PTRACE_O_TRACESYSGOOD
+            | libc::PTRACE_O_TRACEFORK
+            | libc::PTRACE_O_TRACEVFORK
+            | libc::PTRACE_O_TRACECLONE
+            | libc::PTRACE_O_TRACEEXEC;
+        ptrace(libc::PTRACE_SETOPTIONS, pid, 0, options as usize);
+        ptrace(libc::PTRACE_SYSCALL, pid, 0, 0);
+    }
+    let mut tracees = HashSet::from([pid]);
+    let mut read_files = BTreeSet::new();
+    loop {
+        // __WNOTHREAD to not reap children spawned by other threads
+        // SAFETY: waitpid() has no memory safety requirements
+        let tracee = unsafe { libc::waitpid(-1, &mut status, libc::__WALL | libc::__WNOTHREAD) };
+        if tracee == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return (Err(error), read_files.into_iter().collect());
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            tracees.remove(&tracee);
+            if tracee == pid {
+                // remaining descendants are detached when this thread exits
+                let status = ExitStatus::from_raw(status);
+                return (Ok(status), read_files.into_iter().collect());
+            }
+            continue;
+        }
+        let is_new_tracee = tracees.insert(tracee);
+        let signal = libc::WSTOPSIG(status);
+        let mut inject_signal = 0;
+        if signal == libc::SIGTRAP | 0x80 {
+            if let Some(path) = opened_path_for_reading(tracee) {
+                read_files.insert(path);
+            }
+        } else if is_new_tracee && signal == libc::SIGSTOP {
+            // initial stop of a new descendant
+        } else if signal != libc::SIGTRAP || status >> 16 == 0 {
+            // deliver signals which are not caused by ptrace
+            inject_signal = signal;
+        }
+        // SAFETY: ptrace() has no memory safety requirements for these args
+        unsafe {
+            ptrace(libc::PTRACE_SYSCALL, tracee, 0, inject_signal as usize);
+        }
+    }
+}
+
+/// Returns the path if the tracee is entering a syscall to open a file for reading
+fn opened_path_for_reading(tracee: libc::pid_t) -> Option<PathBuf> {
+    let mut info = SyscallEntryInfo::default();
+    // SAFETY: the kernel writes at most size_of::<SyscallEntryInfo>() bytes into info
+    let len = unsafe {
+        ptrace(
+            PTRACE_GET_SYSCALL_INFO,
+            tracee,
+            std::mem::size_of::<SyscallEntryInfo>(),
+            &mut info as *mut SyscallEntryInfo as usize,
+        )
+    };
+    if len <= 0 || info.op != PTRACE_SYSCALL_INFO_ENTRY {
+        return None;
+    }
+    let nr = info.nr as libc::c_long;
+    let args = info.args;
+    let (dirfd, path_addr, flags) = match nr {
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open => (libc::AT_FDCWD, args[0], args[1]),
+        libc::SYS_openat => (args[0] as i32, args[1], args[2]),
+        // flags are the first member of struct open_how
+        libc::SYS_openat2 => (
+            args[0] as i32,
+            args[1],
+            u64::from_ne_bytes(read_memory(tracee, args[2], 8)?.try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    let flags = flags as libc::c_int;
+    if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_PATH != 0 {
+        return None;
+    }
+    let path = PathBuf::from(read_string(tracee, path_addr)?);
+    let path = if path.is_absolute() {
+        path
+    } else if dirfd == libc::AT_FDCWD {
+        std::fs::read_link(format!("/proc/{tracee}/cwd"))
+            .ok()?
+            .join(path)
+    } else {
+        std::fs::read_link(format!("/proc/{tracee}/fd/{dirfd}"))
+            .ok()?
+            .join(path)
+    };
+    Some(normalize(&path))
+}
+
+/// Wrapper to pass addr and data as pointers
+unsafe fn ptrace(
+    request: PtraceRequest,
+    pid: libc::pid_t,
+    addr: usize,
+    data: usize,
+) -> libc::c_long {
+    libc::ptrace(
+        request,
+        pid,
+        addr as *mut libc::c_void,
+        data as *mut libc::c_void,
+    )
+}
+
+fn read_memory(tracee: libc::pid_t, addr: u64, len: usize) -> Option<Vec<u8>> {
+    let file = std::fs::File::open(format!("/proc/{tracee}/mem")).ok()?;
+    let mut buf = vec![0; len];
+    file.read_exact_at(&mut buf, addr).ok()?;
+    Some(buf)
+}
+
+/// Reads a null-terminated string page by page to not read beyond mapped memory
+fn read_string(tracee: libc::pid_t, addr: u64) -> Option<String> {
+    const PAGE_SIZE: u64 = 4096;
+    let file = std::fs::File::open(format!("/proc/{tracee}/mem")).ok()?;
+    let mut string = vec![];
+    let mut addr = addr;
+    while string.len() < libc::PATH_MAX as usize {
+        let mut buf = vec![0; (PAGE_SIZE - addr % PAGE_SIZE) as usize];
+        let len = file.read_at(&mut buf, addr).ok()?;
+        if len == 0 {
+            return None;
+        }
+        if let Some(end) = buf[..len].iter().position(|x| *x == 0) {
+            string.extend_from_slice(&buf[..end]);
+            return String::from_utf8(string).ok();
+        }
+        string.extend_from_slice(&buf[..len]);
+        addr += len as u64;
+    }
+    None
+}
+
+/// Removes `.` and `..` components without resolving symlinks
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn trace_sh() {
+        let dir = crate::new_tmp_dir!();
+        dir.join_and_write_file("input.txt", "input");
+        let cwd = std::fs::canonicalize(dir.dir()).unwrap();
+        let mut command = std::process::Command::new("sh");
+        command
+            .args(["-c", "cat ./input.txt > output.txt; echo ok; exit 3"])
+            .current_dir(&cwd)
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let child = TracedChild::spawn(command).unwrap();
+        let (output, read_files) = child.wait_with_output().await;
+        let output = output.unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"ok\n");
+        assert!(read_files.contains(&cwd.join("input.txt")));
+        assert!(!read_files.contains(&cwd.join("output.txt")));
+    }
+
+    #[test]
+    fn normalize_path() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+
+/// GNU Make jobserver hosted by razel, see `--jobserver`.
+///
+/// Commands tagged with `razel:jobserver` inherit both ends of a pipe filled with tokens and get
+/// `MAKEFLAGS` pointing to it, e.g. to let sub-makes share the jobs of razel. A command has an
+/// implicit token and reads further ones from the pipe to start more jobs, which are written back
+/// when the jobs finished. Tokens of killed commands are not restored.
+///
+/// Tokens taken from the pipe reserve slots of the scheduler, see [Self::tokens_in_use].
+pub struct Jobserver {
+    read: OwnedFd,
+    write: OwnedFd,
+    tokens: usize,
+}
+
+impl Jobserver {
+    pub fn new(tokens: usize) -> io::Result<Self> {
+        let mut fds = [-1; 2];
+        // SAFETY: the pointer is valid for the duration of the call
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe() returned new fds which are owned from now on
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        for fd in [&read, &write] {
+            // only inherited by commands using the jobserver, see configure()
+            Self::set_cloexec(fd.as_raw_fd(), true)?;
+        }
+        File::from(write.try_clone()?).write_all(&vec![b'+'; tokens])?;
+        Ok(Self {
+            read,
+            write,
+            tokens,
+        })
+    }
+
+    /// Returns the number of tokens taken from the pipe by commands
+    pub fn tokens_in_use(&self) -> usize {
+        let mut available: libc::c_int = 0;
+        // SAFETY: the pointer is valid for the duration of the call
+        if unsafe { libc::ioctl(self.read.as_raw_fd(), libc::FIONREAD, &mut available) } == -1 {
+            return 0;
+        }
+        self.tokens.saturating_sub(available as usize)
+    }
+
+    /// Passes the pipe to the command and sets `MAKEFLAGS`
+    pub fn configure(&self, command: &mut std::process::Command) {
+        let (read, write) = (self.read.as_raw_fd(), self.write.as_raw_fd());
+        command.env("MAKEFLAGS", format!("-j --jobserver-auth={read},{write}"));
+        // SAFETY: fcntl() is async-signal-safe
+        unsafe {
+            command.pre_exec(move || {
+                Self::set_cloexec(read, false)?;
+                Self::set_cloexec(write, false)
+            });
+        }
+    }
+
+    fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+        let flags = if cloexec { libc::FD_CLOEXEC } else { 0 };
+        // SAFETY: fcntl() has no memory safety requirements
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executors::CustomCommandExecutor;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn command_reads_token() {
+        let jobserver = Arc::new(Jobserver::new(2).unwrap());
+        // take a token, print it and return it like make
+        let script = r#"
+            auth=${MAKEFLAGS#*--jobserver-auth=}
+            read_fd=${auth%,*}
+            write_fd=${auth#*,}
+            token=$(dd if=/dev/fd/$read_fd bs=1 count=1 2>/dev/null)
+            printf '%s' "$token"
+            printf '%s' "$token" > /dev/fd/$write_fd
+        "#;
+        let executor = CustomCommandExecutor {
+            executable: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            jobserver: Some(jobserver.clone()),
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            let mut result = executor.exec(None, None).await;
+            result.assert_success();
+            assert_eq!(result.stdout, b"+");
+            assert_eq!(jobserver.tokens_in_use(), 0);
+        }
+        // a command keeping a token
+        let executor = CustomCommandExecutor {
+            executable: "sh".into(),
+            args: vec![
+                "-c".into(),
+                r#"auth=${MAKEFLAGS#*--jobserver-auth=}
+                dd if=/dev/fd/${auth%,*} bs=1 count=1 2>/dev/null"#
+                    .into(),
+            ],
+            jobserver: Some(jobserver.clone()),
+            ..Default::default()
+        };
+        executor.exec(None, None).await.assert_success();
+        assert_eq!(jobserver.tokens_in_use(), 1);
+        // commands without jobserver don't inherit the pipe
+        let executor = CustomCommandExecutor {
+            executable: "sh".into(),
+            args: vec![
+                "-c".into(),
+                format!("test -e /dev/fd/{}", jobserver.read.as_raw_fd()),
+            ],
+            ..Default::default()
+        };
+        let result = executor.exec(None, None).await;
+        assert!(!result.success());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::config;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+/// all rights of ABI version 1, including creating and removing files
+const ACCESS_FS_ABI_1: u64 = (1 << 13) - 1;
+/// linking or renaming files between directories, added in ABI version 2
+const ACCESS_FS_REFER: u64 = 1 << 13;
+/// truncating files, added in ABI version 3
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Restricts the filesystem access of a command using Landlock.
+///
+/// The command may read and execute its inputs and the system dirs, and may only write within its
+/// sandbox dir. Paths are opened before spawning the command, the restriction is applied to the
+/// child process only.
+pub struct Landlock {
+    handled_access: u64,
+    rules: Vec<(OwnedFd, u64)>,
+}
+
+impl Landlock {
+    /// Returns the Landlock ABI version or None if not supported by the kernel
+    pub fn abi_version() -> Option<u32> {
+        // SAFETY: no memory is accessed when querying the version
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        (version > 0).then_some(version as u32)
+    }
+
+    pub fn new(inputs: &[PathBuf], sandbox_dir: &Path) -> io::Result<Self> {
+        let abi_version = Self::abi_version()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "Landlock not supported"))?;
+        let mut handled_access = ACCESS_FS_ABI_1;
+        if abi_version >= 2 {
+            handled_access |= ACCESS_FS_REFER;
+        }
+        if abi_version >= 3 {
+            handled_access |= ACCESS_FS_TRUNCATE;
+        }
+        let file_access = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE;
+        let dir_access = file_access | ACCESS_FS_READ_DIR;
+        let mut rules = vec![(open_path(sandbox_dir)?, handled_access)];
+        for dir in config::STRICT_SANDBOX_SYSTEM_DIRS {
+            if let Ok(fd) = open_path(Path::new(dir)) {
+                rules.push((fd, dir_access));
+            }
+        }
+        if let Ok(fd) = open_path(Path::new("/dev")) {
+            let access = ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_DIR;
+            rules.push((fd, access | (handled_access & ACCESS_FS_TRUNCATE)));
+        }
+        if let Ok(fd) = open_path(Path::new("/proc")) {
+            rules.push((fd, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR));
+        }
+        for input in inputs {
+            let fd = open_path(input)?;
+            let access = if input.is_dir() {
+                dir_access
+            } else {
+                file_access
+            };
+            rules.push((fd, access));
+        }
+        Ok(Self {
+            handled_access,
+            rules,
+        })
+    }
+
+    /// Let the command restrict itself before exec
+    pub fn configure(self, command: &mut std::process::Command) {
+        // SAFETY: only async-signal-safe syscalls are used, no memory is allocated
+        unsafe {
+            command.pre_exec(move || self.restrict_self());
+        }
+    }
+
+    fn restrict_self(&self) -> io::Result<()> {
+        let ruleset_attr = RulesetAttr {
+            handled_access_fs: self.handled_access,
+        };
+        // SAFETY: the attr structs are valid for the duration of the syscalls
+        unsafe {
+            let ruleset_fd = libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &ruleset_attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            );
+            if ruleset_fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let ruleset_fd = ruleset_fd as libc::c_int;
+            for (fd, access) in &self.rules {
+                let path_beneath = PathBeneathAttr {
+                    allowed_access: *access,
+                    parent_fd: fd.as_raw_fd(),
+                };
+                if libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset_fd,
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &path_beneath as *const PathBeneathAttr,
+                    0,
+                ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::close(ruleset_fd);
+        }
+        Ok(())
+    }
+}
+
+fn open_path(path: &Path) -> io::Result<OwnedFd> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{path:?}: {e}")))?;
+    Ok(file.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use std::process::Stdio;
+
+    fn cat(landlock: Landlock, cwd: &Path, file: &Path) -> std::process::Output {
+        let mut command = std::process::Command::new("cat");
+        command
+            .arg(file)
+            .current_dir(cwd)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        landlock.configure(&mut command);
+        command.output().unwrap()
+    }
+
+    #[test]
+    fn restrict_reads() {
+        if Landlock::abi_version().is_none() {
+            println!("Landlock not supported, skipping test");
+            return;
+        }
+        let dir = new_tmp_dir!();
+        let sandbox_dir = dir.join("sandbox");
+        std::fs::create_dir_all(&sandbox_dir).unwrap();
+        let input = std::fs::canonicalize(dir.join_and_write_file("input.txt", "input")).unwrap();
+        let other = std::fs::canonicalize(dir.join_and_write_file("other.txt", "other")).unwrap();
+        let landlock = || Landlock::new(std::slice::from_ref(&input), &sandbox_dir).unwrap();
+        let output = cat(landlock(), &sandbox_dir, &input);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"input");
+        assert!(!cat(landlock(), &sandbox_dir, &other).status.success());
+        assert!(!cat(landlock(), &sandbox_dir, Path::new("/etc/hostname"))
+            .status
+            .success());
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+
+/// Process groups of running commands, killed on Ctrl+C
+static RUNNING_PROCESS_GROUPS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// Process group of a command to kill its descendants as well.
+///
+/// The group is killed when dropped before `finish()` was called, e.g. on timeout or if the
+/// execution is aborted. Only supported on Unix, on Windows just the direct child is killed.
+pub struct ProcessGroup {
+    pgid: i32,
+    finished: bool,
+}
+
+impl ProcessGroup {
+    /// Let the command create a new process group with its pid as id
+    #[cfg(target_family = "unix")]
+    pub fn configure(command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    #[cfg(target_family = "windows")]
+    pub fn configure(_command: &mut std::process::Command) {}
+
+    /// Expects the id of a child process configured with configure()
+    pub fn new(child_id: Option<u32>) -> Option<Self> {
+        let pgid = child_id? as i32;
+        RUNNING_PROCESS_GROUPS.lock().unwrap().push(pgid);
+        Some(Self {
+            pgid,
+            finished: false,
+        })
+    }
+
+    pub fn kill(&self) {
+        kill_process_group(self.pgid);
+    }
+
+    /// The command finished normally, descendants which are still running are kept
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.kill();
+        }
+        RUNNING_PROCESS_GROUPS
+            .lock()
+            .unwrap()
+            .retain(|x| *x != self.pgid);
+    }
+}
+
+/// Kill the process groups of all running commands.
+///
+/// Commands run in their own process group and therefore don't receive SIGINT from the terminal.
+pub fn kill_running_process_groups() {
+    for pgid in RUNNING_PROCESS_GROUPS.lock().unwrap().iter() {
+        kill_process_group(*pgid);
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn kill_process_group(pgid: i32) {
+    // SAFETY: killpg() has no memory safety requirements, errors are ignored
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+}
+
+#[cfg(target_family = "windows")]
+fn kill_process_group(_pgid: i32) {}
@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+/// Settings of the terminal of razel while it is switched to raw mode, restored on exit
+static SAVED_TERMINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// Pseudo-terminal to run an interactive command attached to the terminal of razel.
+///
+/// The command becomes a session leader with the pty as controlling terminal. Stdin is forwarded
+/// in raw mode to let the command handle line editing and Ctrl+C, its output is not captured.
+pub struct Pty {
+    master: File,
+}
+
+impl Pty {
+    /// Opens a pty and lets the command use it as stdin, stdout and stderr.
+    ///
+    /// The command must not be configured to create a process group, it creates a new session.
+    pub fn configure(command: &mut std::process::Command) -> io::Result<Self> {
+        let (mut master, mut slave) = (-1, -1);
+        let window_size = window_size();
+        // SAFETY: the pointers are valid for the duration of the call
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                window_size.as_ref().map_or(std::ptr::null(), |x| x),
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty() returned new fds which are owned from now on
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // SAFETY: fcntl() has no memory safety requirements
+        if unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: setsid() and ioctl() are async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(Self { master })
+    }
+
+    /// Forwards stdin to the command and its output to stdout until the pty is closed.
+    ///
+    /// Must be called after spawning the command, because the pty is closed once no process
+    /// uses it anymore.
+    pub async fn forward(self) {
+        let raw_terminal = RawTerminal::enable();
+        let stdin_forwarding = self.master.try_clone().and_then(|input| {
+            let (stop_receiver, stop_sender) = pipe()?;
+            let thread = std::thread::spawn(move || forward_stdin(input, stop_receiver));
+            Ok((thread, stop_sender))
+        });
+        let mut output = self.master;
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = [0; 4096];
+            let mut stdout = io::stdout();
+            // fails with EIO after the command and its descendants closed the pty
+            while let Ok(len @ 1..) = output.read(&mut buffer) {
+                if stdout.write_all(&buffer[..len]).is_err() || stdout.flush().is_err() {
+                    break;
+                }
+            }
+        })
+        .await
+        .ok();
+        if let Ok((thread, stop_sender)) = stdin_forwarding {
+            // closing the pipe wakes up the thread waiting for stdin
+            drop(stop_sender);
+            tokio::task::spawn_blocking(move || thread.join())
+                .await
+                .ok();
+        }
+        drop(raw_terminal);
+    }
+}
+
+/// Forwards stdin to the pty until stdin is closed or `stop` becomes readable.
+///
+/// Reads directly from the fd, because the buffer of `io::stdin()` would hide data from poll().
+fn forward_stdin(mut input: File, stop: File) {
+    let mut fds = [
+        libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stop.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    let mut buffer = [0u8; 1024];
+    loop {
+        // SAFETY: fds is valid for the duration of the call
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        if fds[1].revents != 0 || fds[0].revents & libc::POLLNVAL != 0 {
+            break;
+        }
+        if fds[0].revents == 0 {
+            continue;
+        }
+        // SAFETY: buffer is valid for writes of its length
+        let len =
+            unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if len <= 0 || input.write_all(&buffer[..len as usize]).is_err() {
+            break;
+        }
+    }
+}
+
+/// Returns the read and the write end of a new pipe, both closed on exec
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [-1; 2];
+    // SAFETY: fds is valid for the duration of the call
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe() returned new fds which are owned from now on
+    let (receiver, sender) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for fd in [receiver.as_raw_fd(), sender.as_raw_fd()] {
+        // SAFETY: fcntl() has no memory safety requirements
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((receiver, sender))
+}
+
+/// Restores the terminal settings if razel exits while an interactive command is running
+pub fn restore_terminal() {
+    if let Some(termios) = SAVED_TERMINAL.lock().unwrap().take() {
+        // SAFETY: termios was filled by tcgetattr()
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+        }
+    }
+}
+
+/// Switches the terminal of razel to raw mode while it exists
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enable() -> Option<Self> {
+        // SAFETY: termios is filled by tcgetattr() before it is used
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return None;
+            }
+            *SAVED_TERMINAL.lock().unwrap() = Some(termios);
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+        }
+        Some(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+fn window_size() -> Option<libc::winsize> {
+    // SAFETY: winsize is filled by ioctl() before it is used
+    unsafe {
+        let mut size = std::mem::zeroed::<libc::winsize>();
+        (libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) == 0).then_some(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_tty_command() -> std::process::Command {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "test -t 0 && test -t 1 && test -t 2"]);
+        command
+    }
+
+    #[tokio::test]
+    async fn tty() {
+        assert!(!is_tty_command()
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+            .success());
+        let mut command = is_tty_command();
+        let pty = Pty::configure(&mut command).unwrap();
+        let mut child = tokio::process::Command::from(command).spawn().unwrap();
+        let forwarding = tokio::spawn(pty.forward());
+        assert!(child.wait().await.unwrap().success());
+        forwarding.await.unwrap();
+    }
+}
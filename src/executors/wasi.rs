@@ -1,3 +1,4 @@
+use crate::cache::{BlobDigest, MessageDigest};
 use crate::config::OUT_DIR;
 use crate::executors::{ExecutionResult, ExecutionStatus};
 use crate::{config, FileId};
@@ -5,6 +6,8 @@ use anyhow::{Context, Result};
 use cap_std::ambient_authority;
 use cap_std::fs::Dir;
 use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 use wasmtime::component::ResourceTable;
@@ -201,6 +204,86 @@ impl WasiExecutor {
     }
 }
 
+/// Serialized WASM modules to skip compiling them in later runs, see `--prewarm-wasi`
+///
+/// Deserializing runs native code without validation, therefore the cache dir must only be
+/// writable by razel. Each file starts with the digest of the serialized module to reject
+/// truncated or otherwise corrupted files.
+pub struct WasiModuleCache {
+    dir: PathBuf,
+    /// identifies the wasmtime version and compiler settings, part of the file names
+    engine_key: String,
+}
+
+impl WasiModuleCache {
+    pub fn new(dir: PathBuf, engine: &Engine) -> Result<Self> {
+        fs::create_dir_all(&dir).with_context(|| format!("create WASM module cache: {dir:?}"))?;
+        let mut hasher = Sha256Hasher(Default::default());
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        Ok(Self {
+            dir,
+            engine_key: base16ct::lower::encode_string(&sha2::Digest::finalize(hasher.0)),
+        })
+    }
+
+    /// Returns the module and whether it was compiled or loaded from the cache.
+    ///
+    /// Modules not matching their digest or rejected by wasmtime are compiled again.
+    pub fn get_or_create_module(
+        &self,
+        engine: &Engine,
+        file: &Path,
+        module_digest: &str,
+    ) -> Result<(Module, bool)> {
+        let path = self.dir.join(format!(
+            "{}.cwasm",
+            MessageDigest::for_string(&format!("{module_digest}-{}", self.engine_key)).hash
+        ));
+        if let Ok(bytes) = fs::read(&path) {
+            match Self::verified_module_bytes(&bytes) {
+                // SAFETY: the digest matches the bytes written by razel below,
+                // wasmtime checks version and settings
+                Some(serialized) => match unsafe { Module::deserialize(engine, serialized) } {
+                    Ok(module) => return Ok((module, false)),
+                    Err(e) => log::debug!("recompile WASM module {file:?}: {e:?}"),
+                },
+                None => log::debug!("recompile WASM module {file:?}: digest mismatch"),
+            }
+        }
+        let module = WasiExecutor::create_module(engine, file)?;
+        let serialized = module.serialize()?;
+        let mut bytes = BlobDigest::for_bytes(&serialized).hash.into_bytes();
+        bytes.push(b'\n');
+        bytes.extend(serialized);
+        // write via temporary file to not load partial files from concurrent razel processes
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, bytes)
+            .and_then(|()| fs::rename(&tmp, &path))
+            .with_context(|| format!("write serialized WASM module: {path:?}"))?;
+        Ok((module, true))
+    }
+
+    /// Splits off the digest line and returns the serialized module if it matches
+    fn verified_module_bytes(bytes: &[u8]) -> Option<&[u8]> {
+        let pos = bytes.iter().position(|x| *x == b'\n')?;
+        let (digest, serialized) = (&bytes[..pos], &bytes[pos + 1..]);
+        (digest == BlobDigest::for_bytes(serialized).hash.as_bytes()).then_some(serialized)
+    }
+}
+
+/// Feeds [Hash] implementations into sha256 to get a digest which is stable across razel builds
+struct Sha256Hasher(sha2::Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn finish(&self) -> u64 {
+        unreachable!("use the sha256 digest instead")
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(&mut self.0, bytes);
+    }
+}
+
 #[cfg(target_family = "windows")]
 fn wasi_path(path: &str) -> String {
     path.replace("\\", "/")
@@ -242,6 +325,7 @@ mod tests {
     use super::*;
     use crate::new_tmp_dir;
     use crate::tasks::ensure_equal;
+    use itertools::Itertools;
     use std::fs;
 
     static CP_MODULE_PATH: &str = "examples/bin/wasm32-wasi/cp.wasm";
@@ -254,6 +338,49 @@ mod tests {
         WasiExecutor::create_module(&engine, CP_MODULE_PATH).unwrap()
     }
 
+    #[test]
+    fn module_cache() {
+        let dir = new_tmp_dir!();
+        // empty module
+        let file = dir.join_and_write_file("empty.wasm", "\0asm\x01\0\0\0");
+        let engine = WasiExecutor::create_engine().unwrap();
+        let cache = WasiModuleCache::new(dir.join("cache"), &engine).unwrap();
+        let get = |cache: &WasiModuleCache| {
+            cache
+                .get_or_create_module(&engine, &file, "digest")
+                .unwrap()
+                .1
+        };
+        assert!(get(&cache));
+        assert!(!get(&cache));
+        // other wasmtime version or engine settings
+        let other_engine = WasiModuleCache {
+            dir: dir.join("cache"),
+            engine_key: "other".into(),
+        };
+        assert!(get(&other_engine));
+        assert!(!get(&other_engine));
+        // files rejected by wasmtime are replaced
+        let files = fs::read_dir(dir.join("cache"))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .collect_vec();
+        assert_eq!(files.len(), 2);
+        for x in &files {
+            fs::write(x, "invalid").unwrap();
+        }
+        assert!(get(&cache));
+        assert!(!get(&cache));
+        // files not matching their digest are replaced
+        for x in &files {
+            let mut bytes = fs::read(x).unwrap();
+            *bytes.last_mut().unwrap() ^= 1;
+            fs::write(x, bytes).unwrap();
+        }
+        assert!(get(&cache));
+        assert!(!get(&cache));
+    }
+
     #[tokio::test]
     async fn cp_help() {
         let workspace_dir = Path::new(".");
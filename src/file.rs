@@ -2,6 +2,8 @@ use crate::cache::BlobDigest;
 use crate::{config, ArenaId, CommandId};
 use anyhow::{anyhow, Context};
 use itertools::Itertools;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
@@ -118,3 +120,29 @@ impl File {
 }
 
 pub type FileId = ArenaId<File>;
+
+/// Maps paths to files, on Windows paths differing only in case or separators map to the same file
+#[derive(Default)]
+pub struct PathToFileId(HashMap<PathBuf, FileId>);
+
+impl PathToFileId {
+    pub fn get(&self, path: &Path) -> Option<&FileId> {
+        self.0.get(Self::key(path).as_ref())
+    }
+
+    pub fn insert(&mut self, path: PathBuf, id: FileId) -> Option<FileId> {
+        self.0.insert(Self::key(&path).into_owned(), id)
+    }
+
+    /// The file keeps the original path for display and file system operations
+    #[cfg(windows)]
+    fn key(path: &Path) -> Cow<'_, Path> {
+        let normalized = path.to_string_lossy().replace('/', "\\").to_lowercase();
+        Cow::Owned(PathBuf::from(normalized))
+    }
+
+    #[cfg(not(windows))]
+    fn key(path: &Path) -> Cow<'_, Path> {
+        Cow::Borrowed(path)
+    }
+}
@@ -4,6 +4,7 @@ pub use crate::razel::*;
 pub use cli::*;
 pub use command::*;
 pub use file::*;
+pub use output_glob::*;
 pub use parse_batch::*;
 pub use razel_jsonl::*;
 pub use rules::*;
@@ -11,11 +12,13 @@ pub use sandbox::*;
 pub use scheduler::*;
 pub use types::*;
 pub use utils::*;
+pub use version::*;
 
 mod cli;
 mod command;
 pub mod config;
 mod file;
+mod output_glob;
 mod parse_batch;
 mod razel;
 mod razel_jsonl;
@@ -23,6 +26,7 @@ mod rules;
 mod sandbox;
 mod scheduler;
 mod types;
+mod version;
 
 #[allow(clippy::all)]
 pub mod bazel_remote_exec {
@@ -64,12 +68,17 @@ pub mod cache {
     pub use cache::*;
     pub use digest::*;
     pub use local_cache::*;
+    pub use out_dir_index::*;
+    pub use rate_limiter::*;
     pub use remote_cache::*;
 
     #[allow(clippy::module_inception)]
     mod cache;
+    mod cache_archive;
     mod digest;
     mod local_cache;
+    mod out_dir_index;
+    mod rate_limiter;
     mod remote_cache;
 }
 
@@ -78,6 +87,15 @@ pub mod executors {
     pub use execution_result::*;
     pub use executor::*;
     pub use http_remote::*;
+    #[cfg(target_os = "linux")]
+    pub use input_tracer::*;
+    #[cfg(target_family = "unix")]
+    pub use jobserver::*;
+    #[cfg(target_os = "linux")]
+    pub use landlock::*;
+    pub use process_group::*;
+    #[cfg(target_family = "unix")]
+    pub use pty::*;
     pub use task::*;
     pub use wasi::*;
 
@@ -85,6 +103,15 @@ pub mod executors {
     mod execution_result;
     mod executor;
     mod http_remote;
+    #[cfg(target_os = "linux")]
+    mod input_tracer;
+    #[cfg(target_family = "unix")]
+    mod jobserver;
+    #[cfg(target_os = "linux")]
+    mod landlock;
+    mod process_group;
+    #[cfg(target_family = "unix")]
+    mod pty;
     mod task;
     mod wasi;
 }
@@ -93,15 +120,19 @@ pub mod metadata {
     pub use graphs::*;
     pub use log_file::*;
     pub use measurements::*;
+    pub use memory_profile::*;
     pub use profile::*;
     pub use report::*;
+    pub use run_archive::*;
     pub use tags::*;
 
     mod graphs;
     mod log_file;
     mod measurements;
+    mod memory_profile;
     mod profile;
     mod report;
+    mod run_archive;
     mod tags;
 }
 
@@ -109,16 +140,28 @@ pub mod utils {
     pub mod test_utils;
 
     pub use arena::*;
+    pub use copy::*;
+    pub use file_lock::*;
     pub use file_permissions::*;
     pub use hardlink::*;
     pub use helpers::*;
+    pub use http_client::*;
+    pub use logger::*;
+    pub use open_files::*;
+    pub use reporter::*;
     pub use resources::*;
     pub use symlink::*;
 
     mod arena;
+    mod copy;
+    mod file_lock;
     mod file_permissions;
     mod hardlink;
     mod helpers;
+    mod http_client;
+    mod logger;
+    mod open_files;
+    mod reporter;
     #[cfg_attr(target_os = "linux", path = "resources_linux.rs")]
     #[cfg_attr(not(target_os = "linux"), path = "resources_unimplemented.rs")]
     mod resources;
@@ -128,10 +171,16 @@ pub mod utils {
 
 pub mod tasks {
     pub use self::csv::*;
+    pub use archive::*;
+    pub use coverage::*;
+    pub use hash::*;
     pub use http::*;
     pub use tools::*;
 
+    mod archive;
+    mod coverage;
     mod csv;
+    mod hash;
     mod http;
     mod tools;
 }
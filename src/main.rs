@@ -1,25 +1,16 @@
 use anyhow::Context;
-use log::{debug, LevelFilter};
-use simplelog::*;
+use log::debug;
 
-use razel::{parse_cli, Razel};
+use razel::executors::kill_running_process_groups;
+use razel::{init_logger, parse_cli, LogFormat, Razel};
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    TermLogger::init(
-        LevelFilter::Info,
-        ConfigBuilder::new()
-            .add_filter_ignore_str("cranelift_codegen")
-            .add_filter_ignore_str("tracing::span")
-            .add_filter_ignore_str("wasmtime_cranelift")
-            .add_filter_ignore_str("wasmtime_jit")
-            .add_filter_ignore_str("wasmtime_wasi")
-            .set_target_level(LevelFilter::Error)
-            .build(),
-        TerminalMode::Stderr,
-        ColorChoice::Auto,
-    )
-    .unwrap();
+    let args: Vec<String> = std::env::args_os()
+        .map(|x| x.into_string().unwrap())
+        .collect();
+    dotenv_flow::dotenv_flow().context("Failed to read .env file")?;
+    init_logger(LogFormat::from_args(&args)).unwrap();
 
     // exit on panic in any thread
     let default_panic = std::panic::take_hook();
@@ -28,24 +19,30 @@ async fn main() -> Result<(), anyhow::Error> {
         std::process::exit(1);
     }));
 
+    // commands run in their own process group and need to be killed explicitly
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            kill_running_process_groups();
+            #[cfg(target_family = "unix")]
+            razel::executors::restore_terminal();
+            std::process::exit(130);
+        }
+    });
+
     let mut razel = Razel::new();
-    dotenv_flow::dotenv_flow().context("Failed to read .env file")?;
-    let Some(run_args) = parse_cli(
-        std::env::args_os()
-            .map(|x| x.into_string().unwrap())
-            .collect(),
-        &mut razel,
-    )
-    .await?
-    else {
+    let Some(run_args) = parse_cli(args, &mut razel).await? else {
         return Ok(());
     };
     if run_args.info {
-        razel.show_info(run_args.cache_dir)?;
+        razel.show_info(run_args.cache_dir, &run_args.config_sources)?;
         return Ok(());
     }
-    if run_args.no_execution {
-        razel.list_commands();
+    if run_args.no_execution && run_args.print_commands {
+        razel.print_commands();
+    } else if run_args.no_execution && run_args.list_outputs {
+        razel.list_outputs(run_args.format, &mut std::io::stdout().lock())?;
+    } else if run_args.no_execution {
+        razel.list_commands(run_args.format);
     } else {
         let stats = razel
             .run(
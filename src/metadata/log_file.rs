@@ -3,6 +3,7 @@ use crate::metadata::Tag;
 use crate::{CacheHit, Command};
 use anyhow::{Context, Result};
 use itertools::Itertools;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::fmt::Debug;
@@ -32,6 +33,12 @@ pub struct LogFileItem {
     pub output_size: Option<u64>,
     #[serde(default, skip_serializing_if = "Map::is_empty")]
     pub measurements: Map<String, Value>,
+    /// files read by the command which are not declared as inputs, only set with --trace-inputs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub undeclared_inputs: Vec<PathBuf>,
+    /// remote cache hit or why it missed, only set with --explain-remote-miss
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_cache: Option<String>,
 }
 
 impl LogFileItem {
@@ -51,13 +58,68 @@ impl LogFileItem {
 #[derive(Default, Deserialize, Serialize)]
 pub struct LogFile {
     pub items: Vec<LogFileItem>,
+    /// appends each pushed item to the file, see write_incrementally()
+    #[serde(skip)]
+    writer: Option<BufWriter<File>>,
 }
 
 impl LogFile {
+    /// Also reads files written incrementally by an interrupted run, which lack the closing bracket.
+    ///
+    /// Such files contain one item per line, an incomplete last line of a torn write is dropped.
     pub fn from_path<P: AsRef<Path> + Debug>(path: P) -> Result<Self> {
         let contents = fs::read(&path).with_context(|| format!("{path:?}"))?;
-        let items = serde_json::from_slice(&contents)?;
-        Ok(Self { items })
+        let items = if contents.iter().rev().find(|x| !x.is_ascii_whitespace()) == Some(&b']') {
+            serde_json::from_slice(&contents).with_context(|| format!("{path:?}"))?
+        } else {
+            Self::parse_incomplete(&contents).with_context(|| format!("{path:?}"))?
+        };
+        Ok(Self {
+            items,
+            writer: None,
+        })
+    }
+
+    fn parse_incomplete(contents: &[u8]) -> Result<Vec<LogFileItem>> {
+        let mut lines = contents
+            .split(|x| *x == b'\n')
+            .filter(|x| !x.is_empty())
+            .peekable();
+        if !matches!(lines.next(), None | Some(b"[")) {
+            anyhow::bail!("log file does not start with [");
+        }
+        let mut items = vec![];
+        while let Some(line) = lines.next() {
+            let line = line.strip_suffix(b",").unwrap_or(line);
+            match serde_json::from_slice(line) {
+                Ok(item) => items.push(item),
+                Err(_) if lines.peek().is_none() => {
+                    warn!("dropped incomplete last item of log file");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(items)
+    }
+
+    /// Write items already pushed and append each item pushed later to the file immediately.
+    ///
+    /// If razel is killed, the file contains the commands finished until then.
+    pub fn write_incrementally(&mut self, path: &PathBuf) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"[")?;
+        for (i, item) in self.items.iter().enumerate() {
+            Self::append_item(&mut writer, item, i == 0)?;
+        }
+        writer.flush()?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn append_item(writer: &mut impl Write, item: &LogFileItem, is_first: bool) -> Result<()> {
+        writer.write_all(if is_first { b"\n" } else { b",\n" })?;
+        writer.write_all(&serde_json::to_vec(item)?)?;
+        Ok(())
     }
 
     pub fn push(
@@ -66,6 +128,8 @@ impl LogFile {
         execution_result: &ExecutionResult,
         output_size: Option<u64>,
         measurements: Map<String, Value>,
+        undeclared_inputs: Vec<PathBuf>,
+        remote_cache: Option<String>,
     ) {
         let custom_tags = command
             .tags
@@ -85,7 +149,18 @@ impl LogFile {
             total: execution_result.total_duration.map(|x| x.as_secs_f32()),
             output_size: output_size.filter(|&x| x != 0),
             measurements,
+            undeclared_inputs,
+            remote_cache,
         });
+        if let Some(writer) = &mut self.writer {
+            let is_first = self.items.len() == 1;
+            if let Err(e) = Self::append_item(writer, self.items.last().unwrap(), is_first)
+                .and_then(|_| Ok(writer.flush()?))
+            {
+                warn!("failed to append to log file: {e:?}");
+                self.writer = None;
+            }
+        }
     }
 
     pub fn push_not_run(&mut self, command: &Command, status: ExecutionStatus) {
@@ -98,10 +173,12 @@ impl LogFile {
             },
             None,
             Default::default(),
+            vec![],
+            None,
         );
     }
 
-    /// Write a json file with one item per line
+    /// Write a json file with one item per line, replaces an incrementally written file
     pub fn write(&self, path: &PathBuf) -> Result<()> {
         let mut writer = BufWriter::new(File::create(path)?);
         writer.write_all(b"[\n")?;
@@ -6,9 +6,24 @@ use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+/// col for the peak memory usage of a command [byte]
+const PEAK_MEMORY_COL: &str = "peak_memory";
+
+/// cols added for all commands, sizes are in [byte]
+///
+/// The size cols are prefixed to not collide with measurements printed by commands.
+const FIXED_COLS: [&str; 5] = [
+    "command",
+    "status",
+    "razel:stdout_size",
+    "razel:stderr_size",
+    "razel:output_files_size",
+];
+
 /// Parses and collects measurements for all execution results and writes a csv file.
 ///
-/// Cols for command name and execution status are added automatically.
+/// Cols for command name, execution status and the sizes of stdout, stderr and output files are
+/// added automatically, the peak memory usage of the command is added if it was measured.
 pub struct Measurements {
     re: Vec<Regex>,
     /// measurement names with col index
@@ -35,7 +50,11 @@ impl Measurements {
                     r#"<DartMeasurement\s+name="(?P<key>[^"]+)"\s+type="[^"]+">(?P<value>[^<]+)</DartMeasurement>"#,
                 ).unwrap(),
             ],
-            cols: HashMap::from([("command".into(), 0), ("status".into(), 1)]),
+            cols: FIXED_COLS
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (x.to_string(), i))
+                .collect(),
             rows: vec![],
         }
     }
@@ -44,13 +63,27 @@ impl Measurements {
         &mut self,
         command_name: &str,
         execution_result: &ExecutionResult,
+        output_files_size: u64,
     ) -> Map<String, Value> {
-        let (mut row, map) = self.capture(execution_result.stdout.to_str_lossy().as_ref());
-        if !row.is_empty() {
-            row[0] = command_name.to_owned();
-            row[1] = format!("{:?}", execution_result.status);
-            self.rows.push(row);
+        let (mut row, mut map) = self.capture(execution_result.stdout.to_str_lossy().as_ref());
+        if let Some(peak_memory) = execution_result.peak_memory {
+            Self::set(
+                &mut self.cols,
+                &mut row,
+                PEAK_MEMORY_COL,
+                peak_memory.to_string(),
+            );
+            map.insert(PEAK_MEMORY_COL.into(), Value::from(peak_memory));
         }
+        if row.len() < FIXED_COLS.len() {
+            row.resize(FIXED_COLS.len(), Default::default());
+        }
+        row[0] = command_name.to_owned();
+        row[1] = format!("{:?}", execution_result.status);
+        row[2] = execution_result.stdout.len().to_string();
+        row[3] = execution_result.stderr.len().to_string();
+        row[4] = output_files_size.to_string();
+        self.rows.push(row);
         map
     }
 
@@ -59,15 +92,12 @@ impl Measurements {
         let mut map: Map<String, Value> = Default::default();
         for re in &self.re {
             for captures in re.captures_iter(text) {
-                let keys_len = self.cols.len();
-                let col = *self
-                    .cols
-                    .entry(captures["key"].to_string())
-                    .or_insert(keys_len);
-                if vec.len() < col + 1 {
-                    vec.resize(col + 1, Default::default());
-                }
-                vec[col] = captures["value"].to_string();
+                Self::set(
+                    &mut self.cols,
+                    &mut vec,
+                    &captures["key"],
+                    captures["value"].to_string(),
+                );
                 map.insert(
                     captures["key"].to_string(),
                     Value::String(captures["value"].to_string()),
@@ -77,6 +107,15 @@ impl Measurements {
         (vec, map)
     }
 
+    fn set(cols: &mut HashMap<String, usize>, row: &mut Vec<String>, key: &str, value: String) {
+        let keys_len = cols.len();
+        let col = *cols.entry(key.to_string()).or_insert(keys_len);
+        if row.len() < col + 1 {
+            row.resize(col + 1, Default::default());
+        }
+        row[col] = value;
+    }
+
     pub fn write_csv(&self, path: &PathBuf) -> Result<(), anyhow::Error> {
         if self.rows.is_empty() {
             return Ok(());
@@ -107,8 +146,9 @@ impl Default for Measurements {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    static FIXED_COLS: usize = 2;
+    use crate::executors::ExecutionStatus;
+    use crate::new_tmp_dir;
+    use itertools::chain;
 
     #[test]
     fn ctest() {
@@ -117,9 +157,9 @@ mod tests {
             measurements.capture(
                 r#"<CTestMeasurement type="numeric/float" name="score">12.3</CTestMeasurement>"#,
             ).0,
-            vec!["".to_string(), "".to_string(), "12.3".to_string()]
+            chain(vec![""; FIXED_COLS.len()], ["12.3"]).collect_vec()
         );
-        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS));
+        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS.len()));
     }
 
     #[test]
@@ -131,9 +171,9 @@ mod tests {
                     r#"<DartMeasurement type="numeric/float" name="score">12.3</DartMeasurement>"#,
                 )
                 .0,
-            vec!["".to_string(), "".to_string(), "12.3".to_string()]
+            chain(vec![""; FIXED_COLS.len()], ["12.3"]).collect_vec()
         );
-        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS));
+        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS.len()));
     }
 
     #[test]
@@ -150,18 +190,77 @@ mod tests {
                 "#,
                 )
                 .0,
-            vec![
-                "".to_string(),
-                "".to_string(),
-                "12.3".to_string(),
-                "3".to_string(),
-                "blue".to_string(),
-                "grey".to_string()
-            ]
+            chain(vec![""; FIXED_COLS.len()], ["12.3", "3", "blue", "grey"]).collect_vec()
+        );
+        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS.len()));
+        assert_eq!(measurements.cols.get("cost"), Some(&(FIXED_COLS.len() + 1)));
+        assert_eq!(
+            measurements.cols.get("color_fg"),
+            Some(&(FIXED_COLS.len() + 2))
+        );
+        assert_eq!(
+            measurements.cols.get("color bg"),
+            Some(&(FIXED_COLS.len() + 3))
+        );
+    }
+
+    #[test]
+    fn peak_memory() {
+        let mut measurements = Measurements::new();
+        let map = measurements.collect(
+            "command",
+            &ExecutionResult {
+                stdout:
+                    r#"<CTestMeasurement type="numeric/float" name="score">12.3</CTestMeasurement>"#
+                        .into(),
+                peak_memory: Some(1024),
+                ..Default::default()
+            },
+            0,
+        );
+        assert_eq!(map[PEAK_MEMORY_COL], Value::from(1024));
+        assert_eq!(
+            measurements.rows,
+            vec![vec![
+                "command",
+                "NotStarted",
+                "75",
+                "0",
+                "0",
+                "12.3",
+                "1024"
+            ]]
+        );
+    }
+
+    #[test]
+    fn output_sizes() {
+        let dir = new_tmp_dir!();
+        let path = dir.join("measurements.csv");
+        let mut measurements = Measurements::new();
+        measurements.collect(
+            "chatty",
+            &ExecutionResult {
+                status: ExecutionStatus::Success,
+                stdout: [
+                    r#"<CTestMeasurement type="numeric/integer" name="stdout_size">7</CTestMeasurement>"#,
+                    "a",
+                ]
+                .concat()
+                .into(),
+                stderr: "b".repeat(20).into(),
+                ..Default::default()
+            },
+            3,
+        );
+        // row for commands without measurements
+        measurements.collect("quiet", &Default::default(), 0);
+        measurements.write_csv(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "command,status,razel:stdout_size,razel:stderr_size,razel:output_files_size,stdout_size\n\
+            chatty,Success,81,20,3,7\n\
+            quiet,NotStarted,0,0,0,\n"
         );
-        assert_eq!(measurements.cols.get("score"), Some(&FIXED_COLS));
-        assert_eq!(measurements.cols.get("cost"), Some(&(FIXED_COLS + 1)));
-        assert_eq!(measurements.cols.get("color_fg"), Some(&(FIXED_COLS + 2)));
-        assert_eq!(measurements.cols.get("color bg"), Some(&(FIXED_COLS + 3)));
     }
 }
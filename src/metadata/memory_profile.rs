@@ -0,0 +1,72 @@
+use crate::process_rss;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Samples the resident memory of the razel process in a background thread, see --profile-memory
+pub struct MemoryProfiler {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<Vec<MemorySample>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MemoryProfile {
+    /// max of the samples [byte]
+    pub peak_rss: u64,
+    pub samples: Vec<MemorySample>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MemorySample {
+    /// since the profiler was started [s]
+    pub time: f32,
+    /// resident memory [byte]
+    pub rss: u64,
+}
+
+impl MemoryProfiler {
+    /// Returns None if reading the memory of the process is not supported
+    pub fn start(interval: Duration) -> Option<Self> {
+        process_rss()?;
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let start = Instant::now();
+            let mut samples = vec![];
+            loop {
+                if let Some(rss) = process_rss() {
+                    samples.push(MemorySample {
+                        time: start.elapsed().as_secs_f32(),
+                        rss,
+                    });
+                }
+                if stopped.recv_timeout(interval) != Err(mpsc::RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            samples
+        });
+        Some(Self { stop, thread })
+    }
+
+    /// Stops sampling and returns the samples
+    pub fn stop(self) -> MemoryProfile {
+        self.stop.send(()).ok();
+        let samples = self.thread.join().unwrap();
+        MemoryProfile {
+            peak_rss: samples.iter().map(|x| x.rss).max().unwrap_or_default(),
+            samples,
+        }
+    }
+}
+
+impl MemoryProfile {
+    pub fn write_json(&self, path: &PathBuf) -> Result<()> {
+        let vec = serde_json::to_vec(self)?;
+        fs::write(path, vec)?;
+        Ok(())
+    }
+}
@@ -15,7 +15,14 @@ static KEY_OTHER: &str = "[other]";
 
 #[derive(Deserialize, Serialize)]
 pub struct Report {
+    #[serde(flatten)]
     pub stats: HashMap<String, Stats>,
+    /// the run was aborted by `--deadline`, not executed commands are reported as not run
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deadline_exceeded: bool,
+    /// max resident memory of the razel process [byte], only set with `--profile-memory`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss: Option<u64>,
 }
 
 impl Report {
@@ -46,11 +53,15 @@ impl Report {
             grouped.insert(KEY_OTHER.into(), other);
         }
         grouped.insert(KEY_ALL.into(), all);
-        Self { stats: grouped }
+        Self {
+            stats: grouped,
+            deadline_exceeded: false,
+            peak_rss: None,
+        }
     }
 
     pub fn write(&self, path: &PathBuf) -> Result<()> {
-        let vec = serde_json::to_vec_pretty(&self.stats)?;
+        let vec = serde_json::to_vec_pretty(self)?;
         fs::write(path, vec)?;
         Ok(())
     }
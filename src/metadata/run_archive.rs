@@ -0,0 +1,39 @@
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use std::fs;
+use std::path::Path;
+use time::format_description;
+use time::OffsetDateTime;
+
+/// Directory within the metadata dir to keep the metadata of previous runs, see --keep-metadata-runs
+pub static RUNS_DIR: &str = "runs";
+/// Files of the metadata dir copied into the runs dir
+static ARCHIVED_FILES: [&str; 3] = ["log.json", "report.json", "measurements.csv"];
+
+/// Copy metadata files of the current run into a timestamped dir and remove all but the last `keep`
+/// previous runs
+pub fn archive_run(metadata_dir: &Path, keep: usize) -> Result<()> {
+    let runs_dir = metadata_dir.join(RUNS_DIR);
+    // UTC and fixed width to sort dirs by name, without colons for Windows
+    let format = format_description::parse(
+        "[year]-[month]-[day]T[hour]-[minute]-[second].[subsecond digits:6]Z",
+    )?;
+    let run_dir = runs_dir.join(OffsetDateTime::now_utc().format(&format)?);
+    fs::create_dir_all(&run_dir).with_context(|| format!("{run_dir:?}"))?;
+    for file in ARCHIVED_FILES {
+        let src = metadata_dir.join(file);
+        if src.exists() {
+            fs::copy(&src, run_dir.join(file)).with_context(|| format!("{src:?}"))?;
+        }
+    }
+    let runs = fs::read_dir(&runs_dir)?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| x.is_dir())
+        .sorted_unstable()
+        .collect_vec();
+    // the current run is not counted
+    for run in &runs[..runs.len().saturating_sub(keep + 1)] {
+        fs::remove_dir_all(run).with_context(|| format!("{run:?}"))?;
+    }
+    Ok(())
+}
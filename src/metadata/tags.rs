@@ -9,10 +9,76 @@ pub enum Tag {
     Timeout(u16),
     NoCache,
     NoRemoteCache,
+    /// override `--remote-cache-threshold` [kilobyte / s]
+    RemoteCacheThreshold(u32),
     NoSandbox,
+    /// don't remove existing outputs before executing a command, e.g. for incremental tools,
+    /// requires `NoSandbox`
+    PreserveOutputs,
+    /// umask for files created by the command, parsed as octal, only supported on Unix
+    Umask(u32),
+    /// ready commands with higher priority are started first, default is 0
+    Priority(i32),
+    /// capture stderr together with stdout like `2>&1`, only supported on Unix
+    MergeStderr,
+    /// IO scheduling class and level (0 is highest priority, 7 lowest) like `ionice`, only
+    /// supported on Linux
+    IoNice(IoNiceClass, u8),
+    /// with `--phased`, commands of a phase start after all commands of lower phases succeeded
+    Phase(u32),
+    /// mixed into the cache key in addition to `--cache-salt` to not share cache entries
+    CacheSalt(String),
+    /// with `--jobserver`, pass the GNU Make jobserver of razel to the command, only supported on
+    /// Unix
+    Jobserver,
+    /// kill the command this number of seconds after razel started, it's not started afterwards
+    Deadline(u32),
+    /// name and count of a resource the command needs, see `--resource`
+    Resource(String, u32),
     Custom(String),
 }
 
+/// IO scheduling class, see `man ionice`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoNiceClass {
+    /// requires CAP_SYS_ADMIN
+    Realtime,
+    BestEffort,
+    /// only gets disk time when no other process needs it, the level is ignored
+    Idle,
+}
+
+impl IoNiceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IoNiceClass::Realtime => "realtime",
+            IoNiceClass::BestEffort => "best-effort",
+            IoNiceClass::Idle => "idle",
+        }
+    }
+
+    /// Parses `<class>[:<level>]`, the level is required unless the class is idle
+    fn parse_with_level(x: &str) -> Result<(Self, u8), String> {
+        let (class, level) = x.split_once(':').map_or((x, None), |(c, l)| (c, Some(l)));
+        let class = match class {
+            "realtime" => IoNiceClass::Realtime,
+            "best-effort" => IoNiceClass::BestEffort,
+            "idle" => IoNiceClass::Idle,
+            _ => return Err(format!("unknown ionice class: {class}")),
+        };
+        let level = match (class, level) {
+            (IoNiceClass::Idle, None) => 0,
+            (_, Some(x)) => x
+                .parse()
+                .ok()
+                .filter(|x| *x <= 7)
+                .ok_or_else(|| format!("failed to parse ionice level: {x}"))?,
+            (_, None) => return Err(format!("ionice level missing: {x}")),
+        };
+        Ok((class, level))
+    }
+}
+
 impl Serialize for Tag {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -25,7 +91,19 @@ impl Serialize for Tag {
             Tag::Timeout(x) => &format!("razel:timeout:{x}"),
             Tag::NoCache => "razel:no-cache",
             Tag::NoRemoteCache => "razel:no-remote-cache",
+            Tag::RemoteCacheThreshold(x) => &format!("razel:remote-cache-threshold:{x}"),
             Tag::NoSandbox => "razel:no-sandbox",
+            Tag::PreserveOutputs => "razel:preserve-outputs",
+            Tag::Umask(x) => &format!("razel:umask:{x:03o}"),
+            Tag::Priority(x) => &format!("razel:priority:{x}"),
+            Tag::MergeStderr => "razel:merge-stderr",
+            Tag::IoNice(IoNiceClass::Idle, _) => "razel:ionice:idle",
+            Tag::IoNice(c, l) => &format!("razel:ionice:{}:{l}", c.as_str()),
+            Tag::Phase(x) => &format!("razel:phase:{x}"),
+            Tag::CacheSalt(x) => &format!("razel:cache-salt:{x}"),
+            Tag::Jobserver => "razel:jobserver",
+            Tag::Deadline(x) => &format!("razel:deadline:{x}"),
+            Tag::Resource(name, count) => &format!("razel:resource:{name}:{count}"),
             Tag::Custom(x) => x,
         };
         serializer.serialize_str(x)
@@ -55,7 +133,69 @@ impl<'de> Deserialize<'de> for Tag {
                 ("timeout", None) => Err(Error::custom(format!("timeout value missing: {tag}"))),
                 ("no-cache", None) => Ok(Tag::NoCache),
                 ("no-remote-cache", None) => Ok(Tag::NoRemoteCache),
+                ("remote-cache-threshold", Some(x)) => {
+                    let threshold = x.parse().map_err(|x| {
+                        Error::custom(format!("failed to parse remote cache threshold: {x}"))
+                    })?;
+                    Ok(Tag::RemoteCacheThreshold(threshold))
+                }
+                ("remote-cache-threshold", None) => Err(Error::custom(format!(
+                    "remote cache threshold value missing: {tag}"
+                ))),
                 ("no-sandbox", None) => Ok(Tag::NoSandbox),
+                ("preserve-outputs", None) => Ok(Tag::PreserveOutputs),
+                ("umask", Some(x)) => {
+                    let umask = u32::from_str_radix(x, 8)
+                        .ok()
+                        .filter(|x| *x <= 0o777)
+                        .ok_or_else(|| Error::custom(format!("failed to parse umask: {x}")))?;
+                    Ok(Tag::Umask(umask))
+                }
+                ("umask", None) => Err(Error::custom(format!("umask value missing: {tag}"))),
+                ("priority", Some(x)) => {
+                    let priority = x
+                        .parse()
+                        .map_err(|x| Error::custom(format!("failed to parse priority: {x}")))?;
+                    Ok(Tag::Priority(priority))
+                }
+                ("priority", None) => Err(Error::custom(format!("priority value missing: {tag}"))),
+                ("merge-stderr", None) => Ok(Tag::MergeStderr),
+                ("ionice", Some(x)) => {
+                    let (class, level) = IoNiceClass::parse_with_level(x).map_err(Error::custom)?;
+                    Ok(Tag::IoNice(class, level))
+                }
+                ("ionice", None) => Err(Error::custom(format!("ionice class missing: {tag}"))),
+                ("phase", Some(x)) => {
+                    let phase = x
+                        .parse()
+                        .map_err(|x| Error::custom(format!("failed to parse phase: {x}")))?;
+                    Ok(Tag::Phase(phase))
+                }
+                ("phase", None) => Err(Error::custom(format!("phase value missing: {tag}"))),
+                ("cache-salt", Some(x)) if !x.is_empty() => Ok(Tag::CacheSalt(x.into())),
+                ("cache-salt", _) => Err(Error::custom(format!("cache salt missing: {tag}"))),
+                ("jobserver", None) => Ok(Tag::Jobserver),
+                ("deadline", Some(x)) => {
+                    let secs = x
+                        .parse()
+                        .map_err(|x| Error::custom(format!("failed to parse deadline: {x}")))?;
+                    Ok(Tag::Deadline(secs))
+                }
+                ("deadline", None) => Err(Error::custom(format!("deadline value missing: {tag}"))),
+                ("resource", Some(x)) => {
+                    let (name, count) = x.split_once(':').map_or((x, None), |(n, c)| (n, Some(c)));
+                    if name.is_empty() {
+                        return Err(Error::custom(format!("resource name missing: {tag}")));
+                    }
+                    let count = match count {
+                        Some(c) => c.parse().ok().filter(|x| *x > 0).ok_or_else(|| {
+                            Error::custom(format!("failed to parse resource count: {c}"))
+                        })?,
+                        None => 1,
+                    };
+                    Ok(Tag::Resource(name.into(), count))
+                }
+                ("resource", None) => Err(Error::custom(format!("resource name missing: {tag}"))),
                 _ => Err(Error::custom(format!(
                     "unknown tag (razel prefix is reserved): {tag}"
                 ))),
@@ -81,10 +221,92 @@ mod tests {
             Tag::Timeout(13)
         );
         assert!(serde_json::from_str::<Tag>("\"razel:timeout:13m\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:remote-cache-threshold:100\"").unwrap(),
+            Tag::RemoteCacheThreshold(100)
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:remote-cache-threshold\"").is_err());
         assert_eq!(
             serde_json::from_str::<Tag>("\"razel:no-sandbox\"").unwrap(),
             Tag::NoSandbox
         );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:preserve-outputs\"").unwrap(),
+            Tag::PreserveOutputs
+        );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:umask:027\"").unwrap(),
+            Tag::Umask(0o027)
+        );
+        assert_eq!(
+            serde_json::to_string(&Tag::Umask(0o027)).unwrap(),
+            "\"razel:umask:027\""
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:umask:999\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:umask:1777\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:priority:-2\"").unwrap(),
+            Tag::Priority(-2)
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:priority\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:priority:high\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:merge-stderr\"").unwrap(),
+            Tag::MergeStderr
+        );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:ionice:best-effort:7\"").unwrap(),
+            Tag::IoNice(IoNiceClass::BestEffort, 7)
+        );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:ionice:idle\"").unwrap(),
+            Tag::IoNice(IoNiceClass::Idle, 0)
+        );
+        assert_eq!(
+            serde_json::to_string(&Tag::IoNice(IoNiceClass::Realtime, 0)).unwrap(),
+            "\"razel:ionice:realtime:0\""
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:ionice\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:ionice:best-effort\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:ionice:best-effort:8\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:ionice:fast:1\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:phase:2\"").unwrap(),
+            Tag::Phase(2)
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:phase\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:phase:-1\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:cache-salt:gcc:13\"").unwrap(),
+            Tag::CacheSalt("gcc:13".into())
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:cache-salt:\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:jobserver\"").unwrap(),
+            Tag::Jobserver
+        );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:deadline:60\"").unwrap(),
+            Tag::Deadline(60)
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:deadline\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:deadline:1m\"").is_err());
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:resource:gpu:2\"").unwrap(),
+            Tag::Resource("gpu".into(), 2)
+        );
+        assert_eq!(
+            serde_json::from_str::<Tag>("\"razel:resource:gpu\"").unwrap(),
+            Tag::Resource("gpu".into(), 1)
+        );
+        assert_eq!(
+            serde_json::to_string(&Tag::Resource("gpu".into(), 1)).unwrap(),
+            "\"razel:resource:gpu:1\""
+        );
+        assert!(serde_json::from_str::<Tag>("\"razel:resource\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:resource::1\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:resource:gpu:0\"").is_err());
+        assert!(serde_json::from_str::<Tag>("\"razel:resource:gpu:x\"").is_err());
         assert_eq!(
             serde_json::from_str::<Tag>("\"anything\"").unwrap(),
             Tag::Custom("anything".into())
@@ -0,0 +1,133 @@
+use anyhow::{bail, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// Output files matching a pattern which are only known after executing the command.
+///
+/// Supported wildcards are `*` and `?` within a path component and `**` for any number of
+/// directories, e.g. `gen/**/*.rs`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputGlob {
+    pub pattern: String,
+    /// don't fail the command if no file matches the pattern
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_empty: bool,
+}
+
+impl OutputGlob {
+    /// Returns a regex matching the relative paths of files, see path_for_matching()
+    pub fn regex(&self) -> Result<Regex, anyhow::Error> {
+        let components = Self::components(&self.pattern)?;
+        if components.last().is_some_and(|x| x == "**") {
+            bail!("output glob must not end with `**`: {}", self.pattern);
+        }
+        let mut regex = String::from("^");
+        for (i, component) in components.iter().enumerate() {
+            if component == "**" {
+                regex.push_str("(?:[^/]+/)*");
+                continue;
+            }
+            if component.contains("**") {
+                bail!("`**` must be a whole path component: {}", self.pattern);
+            }
+            for c in component.chars() {
+                match c {
+                    '*' => regex.push_str("[^/]*"),
+                    '?' => regex.push_str("[^/]"),
+                    _ => regex.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            if i + 1 < components.len() {
+                regex.push('/');
+            }
+        }
+        regex.push('$');
+        Regex::new(&regex).with_context(|| format!("invalid output glob: {}", self.pattern))
+    }
+
+    /// Returns the directory containing all matching files, i.e. the path up to the first wildcard
+    pub fn base_dir(&self) -> PathBuf {
+        let path = Path::new(&self.pattern);
+        let base_dir = path
+            .components()
+            .take_while(|x| !x.as_os_str().to_string_lossy().contains(['*', '?']))
+            .collect::<PathBuf>();
+        if base_dir == path {
+            // no wildcard
+            return path.parent().map(|x| x.to_path_buf()).unwrap_or_default();
+        }
+        base_dir
+    }
+
+    /// Returns a relative path with `/` as separator to be matched against regex()
+    pub fn path_for_matching(path: &Path) -> String {
+        path.components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn components(pattern: &str) -> Result<Vec<String>, anyhow::Error> {
+        Path::new(pattern)
+            .components()
+            .map(|x| match x {
+                Component::Normal(x) => Ok(x.to_string_lossy().to_string()),
+                _ => bail!("output glob must be a relative path within the workspace: {pattern}"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str) -> OutputGlob {
+        OutputGlob {
+            pattern: pattern.into(),
+            allow_empty: false,
+        }
+    }
+
+    fn is_match(pattern: &str, path: &str) -> bool {
+        glob(pattern)
+            .regex()
+            .unwrap()
+            .is_match(&OutputGlob::path_for_matching(Path::new(path)))
+    }
+
+    #[test]
+    fn regex() {
+        assert!(is_match("gen/*.rs", "gen/a.rs"));
+        assert!(is_match("gen/*.rs", "gen/.rs"));
+        assert!(!is_match("gen/*.rs", "gen/sub/a.rs"));
+        assert!(!is_match("gen/*.rs", "gen/a.rsx"));
+        assert!(!is_match("gen/*.rs", "other/gen/a.rs"));
+        assert!(is_match("gen/?.rs", "gen/a.rs"));
+        assert!(!is_match("gen/?.rs", "gen/ab.rs"));
+        assert!(is_match("gen/**/*.rs", "gen/a.rs"));
+        assert!(is_match("gen/**/*.rs", "gen/x/y/a.rs"));
+        assert!(!is_match("gen/**/*.rs", "gen.rs"));
+        assert!(is_match("a+b/(c).txt", "a+b/(c).txt"));
+        assert!(!is_match("a+b/(c).txt", "aab/c.txt"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(glob("../gen/*.rs").regex().is_err());
+        assert!(glob("/tmp/*.rs").regex().is_err());
+        assert!(glob("gen/**").regex().is_err());
+        assert!(glob("gen/a**.rs").regex().is_err());
+    }
+
+    #[test]
+    fn base_dir() {
+        assert_eq!(glob("gen/*.rs").base_dir(), PathBuf::from("gen"));
+        assert_eq!(glob("a/b/**/*.rs").base_dir(), PathBuf::from("a/b"));
+        assert_eq!(glob("a/b*/c.rs").base_dir(), PathBuf::from("a"));
+        assert_eq!(glob("*.rs").base_dir(), PathBuf::new());
+        assert_eq!(glob("gen/a.rs").base_dir(), PathBuf::from("gen"));
+    }
+}
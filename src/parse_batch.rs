@@ -6,7 +6,7 @@ use anyhow::{bail, Context, Result};
 use itertools::Itertools;
 use log::debug;
 
-use crate::{config, parse_cli_within_file, Razel, Rules};
+use crate::{config, parse_cli_within_file, CustomCommandSpec, Razel, Rules};
 
 pub fn parse_command(razel: &mut Razel, command_line: Vec<String>) -> Result<()> {
     let rules = Rules::new();
@@ -38,8 +38,11 @@ pub fn parse_batch_file(razel: &mut Razel, file_name: &String) -> Result<()> {
             let name = next_name
                 .take()
                 .unwrap_or_else(|| format!("{}:{}", file_name, line_number + 1));
-            let command_line: Vec<String> =
-                line.split_whitespace().map(|x| x.to_string()).collect();
+            let command_line = split_command_line(&line)
+                .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+            if command_line.is_empty() {
+                continue;
+            }
             create_command(razel, &rules, name.clone(), command_line.clone())
                 .with_context(|| command_line.join(" "))
                 .with_context(|| format!("Failed to add command: {name}"))?;
@@ -68,18 +71,121 @@ fn create_command(
         } else {
             (Default::default(), Default::default())
         };
-        razel.push_custom_command(
+        razel.push_custom_command(CustomCommandSpec {
             name,
             executable,
             args,
-            Default::default(),
             inputs,
             outputs,
             stdout,
             stderr,
-            vec![],
-            vec![],
-        )?;
+            ..Default::default()
+        })?;
+    }
+    Ok(())
+}
+
+/// Splits a line of a batch file into words like a POSIX shell.
+///
+/// Supports single and double quotes, backslash escapes, comments and expanding `$VAR`/`${VAR}`
+/// from the environment (unset variables expand to an empty string, values are not split).
+/// Unquoted variables expanding to an empty string are dropped, use `"$VAR"` to keep an empty arg.
+/// Pipes, lists, subshells, command substitution and input redirects are rejected.
+pub fn split_command_line(line: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    // the word is kept as Some if started by quotes, to keep empty args
+    let mut word: Option<String> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '#' if word.is_none() => break,
+            '\\' => {
+                if let Some(x) = chars.next() {
+                    word.get_or_insert_with(String::new).push(x);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(x) => word.push(x),
+                        None => bail!("missing closing single quote"),
+                    }
+                }
+            }
+            '"' => {
+                word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(x @ ('$' | '`' | '"' | '\\')) => word.as_mut().unwrap().push(x),
+                            Some(x) => word.as_mut().unwrap().extend(['\\', x]),
+                            None => bail!("missing closing double quote"),
+                        },
+                        Some('$') => expand_variable(&mut chars, word.as_mut().unwrap())?,
+                        Some('`') => bail!(unsupported("command substitution")),
+                        Some(x) => word.as_mut().unwrap().push(x),
+                        None => bail!("missing closing double quote"),
+                    }
+                }
+            }
+            '$' => {
+                // like in a shell, an unquoted empty expansion does not start an arg
+                let mut value = String::new();
+                expand_variable(&mut chars, &mut value)?;
+                if !value.is_empty() {
+                    word.get_or_insert_with(String::new).push_str(&value);
+                }
+            }
+            '|' => bail!(unsupported("pipes")),
+            ';' | '&' => bail!(unsupported("command lists and background jobs")),
+            '(' | ')' => bail!(unsupported("subshells")),
+            '`' => bail!(unsupported("command substitution")),
+            '<' => bail!(unsupported("input redirects")),
+            x => word.get_or_insert_with(String::new).push(x),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn unsupported(feature: &str) -> String {
+    format!("{feature} are not supported in batch files, use razel.jsonl instead")
+}
+
+/// Appends the value of the variable following a `$`
+fn expand_variable(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    word: &mut String,
+) -> Result<()> {
+    let mut name = String::new();
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(x) if x.is_ascii_alphanumeric() || x == '_' => name.push(x),
+                    Some(_) => bail!("only simple variables are supported: ${{{name}"),
+                    None => bail!("missing closing brace: ${{{name}"),
+                }
+            }
+        }
+        Some('(') => bail!(unsupported("command substitution")),
+        _ => {
+            while let Some(x) = chars.next_if(|x| x.is_ascii_alphanumeric() || *x == '_') {
+                name.push(x);
+            }
+        }
+    }
+    if name.is_empty() {
+        // not a variable
+        word.push('$');
+    } else {
+        word.push_str(&std::env::var(&name).unwrap_or_default());
     }
     Ok(())
 }
@@ -120,3 +226,83 @@ fn parse_redirects(cmd: &mut Vec<String>) -> Result<(Option<String>, Option<Stri
     }
     Ok((stdout, stderr))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        split_command_line(line).unwrap()
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(
+            split(r#"cmd 'a b' "c d" "" 'e"f' "g'h" x"y z"w"#),
+            ["cmd", "a b", "c d", "", "e\"f", "g'h", "xy zw"]
+        );
+        assert_eq!(
+            split(r#"echo "\"\$HOME\\" "\n""#),
+            ["echo", "\"$HOME\\", "\\n"]
+        );
+        assert!(split_command_line("echo 'a").is_err());
+        assert!(split_command_line("echo \"a").is_err());
+    }
+
+    #[test]
+    fn escaped_spaces() {
+        assert_eq!(
+            split(r"cp my\ file.txt out\ dir"),
+            ["cp", "my file.txt", "out dir"]
+        );
+        assert_eq!(split(r"echo \'a\' \#b"), ["echo", "'a'", "#b"]);
+    }
+
+    #[test]
+    fn comments_and_redirects() {
+        assert_eq!(split("  echo a # comment"), ["echo", "a"]);
+        assert_eq!(split("echo a#b"), ["echo", "a#b"]);
+        assert_eq!(
+            split("echo a > out.txt 2> err.txt"),
+            ["echo", "a", ">", "out.txt", "2>", "err.txt"]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        std::env::set_var("RAZEL_TEST_BATCH_VAR", "a b");
+        assert_eq!(
+            split(
+                r#"echo $RAZEL_TEST_BATCH_VAR "${RAZEL_TEST_BATCH_VAR}c" '$RAZEL_TEST_BATCH_VAR'"#
+            ),
+            ["echo", "a b", "a bc", "$RAZEL_TEST_BATCH_VAR"]
+        );
+        assert_eq!(
+            split("echo $RAZEL_TEST_BATCH_UNSET_VAR x $ $1"),
+            ["echo", "x", "$"]
+        );
+        assert_eq!(
+            split(r#"echo "$RAZEL_TEST_BATCH_UNSET_VAR" ''$RAZEL_TEST_BATCH_UNSET_VAR a$1"#),
+            ["echo", "", "", "a"]
+        );
+        assert!(split_command_line("echo ${RAZEL_TEST_BATCH_VAR:-x}").is_err());
+    }
+
+    #[test]
+    fn unsupported_pipe() {
+        let error = split_command_line("cat a.txt | sort").unwrap_err();
+        assert!(error.to_string().contains("pipes"), "{error}");
+        assert!(error.to_string().contains("razel.jsonl"), "{error}");
+        assert_eq!(split("echo 'a | b'"), ["echo", "a | b"]);
+        for line in [
+            "a && b",
+            "a; b",
+            "(a)",
+            "echo $(pwd)",
+            "echo `pwd`",
+            "sort < a.txt",
+        ] {
+            assert!(split_command_line(line).is_err(), "{line}");
+        }
+    }
+}
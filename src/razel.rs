@@ -1,29 +1,41 @@
 use crate::bazel_remote_exec::command::EnvironmentVariable;
-use crate::bazel_remote_exec::{ActionResult, Digest, ExecutedActionMetadata, OutputFile};
-use crate::cache::{BlobDigest, Cache, MessageDigest};
-use crate::config::{select_cache_dir, select_sandbox_dir};
+use crate::bazel_remote_exec::{
+    ActionResult, Digest, ExecutedActionMetadata, NodeProperties, OutputFile,
+};
+use crate::cache::{BlobDigest, Cache, MessageDigest, RemoteCacheDecision, RemoteCacheMiss};
+use crate::config::{select_cache_dir, select_sandbox_dir, LinkType, REMOTE_CACHE_FLUSH_TIMEOUT};
+#[cfg(target_family = "unix")]
+use crate::executors::Jobserver;
 use crate::executors::{
     ExecutionResult, ExecutionStatus, Executor, HttpRemoteExecConfig, HttpRemoteExecDomain,
-    HttpRemoteExecState, WasiExecutor,
+    HttpRemoteExecState, WasiExecutor, WasiModuleCache,
+};
+use crate::metadata::{
+    archive_run, write_graphs_html, LogFile, Measurements, MemoryProfile, MemoryProfiler, Profile,
+    Report, Tag, RUNS_DIR,
 };
-use crate::metadata::{write_graphs_html, LogFile, Measurements, Profile, Report, Tag};
 use crate::tui::TUI;
 use crate::{
-    bazel_remote_exec, config, create_cgroup, force_remove_file, is_file_executable,
-    write_gitignore, Arena, BoxedSandbox, CGroup, Command, CommandBuilder, CommandId, File, FileId,
-    FileType, Scheduler, TmpDirSandbox, WasiSandbox, GITIGNORE_FILENAME,
+    bazel_remote_exec, config, create_cgroup, file_unix_mode, force_remove_file,
+    is_file_executable, raise_open_files_limit, symlink_dir, tasks, write_gitignore, Arena,
+    BoxedSandbox, CGroup, CacheHit, Command, CommandBuilder, CommandId, File, FileId, FileType,
+    HttpClientConfig, ListFormat, OutputGlob, PathToFileId, RazelJsonCommand, Reporter, Scheduler,
+    SelfDigestMode, TmpDirSandbox, TransientSandboxError, WasiSandbox, GITIGNORE_FILENAME,
 };
 use anyhow::{anyhow, bail, Context};
-use itertools::{chain, Itertools};
-use log::{debug, warn};
+use futures_util::{StreamExt, TryStreamExt};
+use itertools::{chain, EitherOrBoth, Itertools};
+use log::{debug, info, warn};
+use regex::RegexSet;
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map, HashMap, HashSet};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use std::{env, fs};
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio::sync::mpsc::{Sender, UnboundedReceiver, UnboundedSender};
+use tokio::task::AbortHandle;
 use url::Url;
 use which::which;
 
@@ -50,6 +62,8 @@ pub struct SchedulerStats {
     pub cache_hits: usize,
     pub preparation_duration: Duration,
     pub execution_duration: Duration,
+    /// the run was aborted because it took longer than `Razel::deadline`
+    pub deadline_exceeded: bool,
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
@@ -67,16 +81,112 @@ impl SchedulerExecStats {
 }
 
 type ExecutionResultChannel = (CommandId, ExecutionResult, Vec<OutputFile>, bool);
+/// Digests of archive entries of a command by index, see Razel::spawn_digest_archive_entries()
+type ArchiveDigestsChannel = (CommandId, Vec<(usize, BlobDigest)>);
+
+/// File within the input root of a command, see Razel::input_root_entries()
+struct InputRootEntry<'a> {
+    name: &'a str,
+    path: &'a Path,
+    /// None for entries of archives
+    file_type: Option<&'a FileType>,
+    digest: Option<&'a BlobDigest>,
+}
 
 pub struct Razel {
     pub read_cache: bool,
+    /// execute non-cached commands twice in separate sandboxes and compare the outputs
+    pub check_determinism: bool,
+    /// don't execute commands, only link outputs of cached commands into out_dir
+    pub materialize_only: bool,
+    /// bandwidth limits for remote cache uploads/downloads [byte / s], 0 means unlimited
+    pub remote_cache_upload_limit: u64,
+    pub remote_cache_download_limit: u64,
+    /// number of remote cache uploads to queue before commands wait for uploading
+    pub max_inflight_uploads: usize,
+    /// don't use remote cache for commands with inputs or outputs larger than this [byte]
+    pub remote_cache_max_size: Option<u64>,
+    /// file containing the bearer token for the remote cache
+    pub remote_cache_auth_token_file: Option<PathBuf>,
+    /// print the reason for each command which was not executed
+    pub explain_skips: bool,
+    /// print the input files of this command after the run, see set_explain_inputs()
+    explain_inputs: Option<CommandId>,
+    /// log why the remote cache was not used for executed commands
+    pub explain_remote_miss: bool,
+    /// append each finished command to log.json immediately instead of writing it at the end
+    pub incremental_log: bool,
+    /// sample the memory of the razel process during the run, only supported on Linux
+    pub profile_memory: bool,
+    /// copy the metadata of each run into razel-metadata/runs and keep this number of previous runs
+    pub keep_metadata_runs: Option<usize>,
+    /// only print failed commands and the final summary
+    pub summary_only: bool,
+    /// interval of status updates, the default depends on writing to a terminal
+    pub stats_interval: Option<Duration>,
+    /// commands without sandbox write outputs to tmp files which are renamed after success
+    pub atomic_outputs: bool,
+    /// trace files read by executed commands and report undeclared inputs, only supported on Linux
+    pub trace_inputs: bool,
+    /// restrict file access of sandboxed commands using Landlock, only supported on Linux
+    pub strict_sandbox: bool,
+    /// kill sandboxed commands whose output files exceed this total size [byte]
+    pub max_command_output_size: Option<u64>,
+    /// query the remote cache for all actions concurrently before scheduling
+    pub seed_from_remote: bool,
+    /// fail instead of disabling the remote cache if it's not available
+    pub require_remote_cache: bool,
+    /// pass the action digest to commands as env var RAZEL_ACTION_DIGEST
+    pub action_digest_env: bool,
+    /// start commands with phase tag only after all commands of lower phases succeeded
+    pub phased: bool,
+    /// abort the run if it takes longer, running commands are killed
+    pub deadline: Option<Duration>,
+    /// write the names of commands in the order they were started
+    pub record_schedule: Option<PathBuf>,
+    /// start ready commands in the order of a schedule written with `record_schedule`
+    pub replay_schedule: Option<PathBuf>,
+    /// mixed into the cache key of all commands to separate cache entries, e.g. of toolchains
+    pub cache_salt: Option<String>,
+    /// how output files are linked from the local cache into out_dir
+    pub link_type: LinkType,
+    /// drop write permissions of output files in out_dir to not modify cache files via links
+    pub link_cas_readonly: bool,
+    /// host a GNU Make jobserver for commands with jobserver tag, only supported on Unix
+    pub jobserver: bool,
+    /// keep compiled WASI modules in the local cache to not compile them again in later runs
+    pub prewarm_wasi: bool,
+    /// raise the open files limit at most to this value instead of the hard limit, only Unix
+    pub max_open_files: Option<u64>,
+    /// attach commands to a pseudo-terminal and don't capture or cache their output, for ad-hoc use
+    pub interactive: bool,
+    /// if set, PATH of commands only contains dirs of declared executables/inputs and this allowlist
+    pub hermetic_path: Option<Vec<String>>,
+    /// rewrite golden files of ensure-golden tasks instead of failing
+    pub update_goldens: bool,
+    /// how the razel executable is included in the cache key of tasks and WASI modules
+    pub self_digest_mode: SelfDigestMode,
+    /// fail if digests of input files differ from this manifest
+    pub input_manifest: Option<PathBuf>,
+    /// write digests of input files to this manifest
+    pub export_input_manifest: Option<PathBuf>,
+    /// add headers included by C/C++ compile commands to their inputs
+    pub discover_includes: bool,
+    /// fail if digests of output files differ from config::LOCK_FILE or are missing in it
+    pub verify_outputs: bool,
+    /// write digests of output files to config::LOCK_FILE
+    pub update_lock: bool,
     worker_threads: usize,
+    /// if set, sandboxed commands fail if they create undeclared files not matching these patterns
+    strict_outputs_ignore: Option<RegexSet>,
     /// absolute directory to resolve relative paths of input/output files
     workspace_dir: PathBuf,
     /// current working directory, read-only, used to execute commands
     current_dir: PathBuf,
     /// directory of output files - relative to current_dir
     out_dir: PathBuf,
+    /// if set, output files are written to this absolute directory and out_dir links to it
+    output_base: Option<PathBuf>,
     cache: Option<Cache>,
     /// directory to use as PWD for executing commands
     ///
@@ -85,28 +195,74 @@ pub struct Razel {
     sandbox_dir: Option<PathBuf>,
     files: Arena<File>,
     /// maps paths relative to current_dir (without out_dir prefix) to <File>s
-    path_to_file_id: HashMap<PathBuf, FileId>,
+    path_to_file_id: PathToFileId,
     which_to_file_id: HashMap<String, FileId>,
+    /// see check_system_executables()
+    checked_system_executables: HashSet<FileId>,
     /// razel executable - used in Action::input_root_digest for versioning tasks
     self_file_id: Option<FileId>,
     commands: Arena<Command>,
+    /// if set, deps on unknown commands are collected to be resolved later, see defer_deps()
+    deferred_deps: Option<Vec<(CommandId, String)>>,
+    /// short names of commands, resolved on lookup by name
+    aliases: HashMap<String, String>,
     excluded_commands_len: usize,
+    /// description of the filter used to exclude commands
+    filter: Option<String>,
     /// single Linux cgroup for all commands to trigger OOM killer
     cgroup: Option<CGroup>,
+    /// used for DownloadFile tasks and http remote exec
+    http_client: reqwest::Client,
     http_remote_exec_state: HttpRemoteExecState,
     waiting: HashSet<CommandId>,
     scheduler: Scheduler,
     succeeded: Vec<CommandId>,
     failed: Vec<CommandId>,
     skipped: Vec<CommandId>,
+    /// maps skipped commands to the failed condition or not materialized command causing the skip
+    skipped_by: HashMap<CommandId, CommandId>,
+    /// only set with `phased`
+    phases: Option<phases::Phases>,
+    /// only set with `jobserver`
+    #[cfg(target_family = "unix")]
+    jobserver_host: Option<Arc<Jobserver>>,
+    schedule: schedule::Schedule,
+    /// commands started again after a target-local system error, each is retried only once
+    retried_after_system_error: HashSet<CommandId>,
+    /// start of the current run, reference for razel:deadline tags
+    run_start: Option<Instant>,
+    /// to kill running commands when the deadline is exceeded
+    running_tasks: HashMap<CommandId, AbortHandle>,
+    deadline_exceeded: bool,
     cache_hits: usize,
     tui: TUI,
     tui_dirty: bool,
+    /// additional reporters, e.g. of library users
+    reporters: Vec<Box<dyn Reporter + Send>>,
     measurements: Measurements,
     profile: Profile,
     log_file: LogFile,
 }
 
+/// Parameters of a custom command for Razel::push_custom_command(), only the name and executable are required
+#[derive(Clone, Debug, Default)]
+pub struct CustomCommandSpec {
+    pub name: String,
+    pub executable: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub inputs: Vec<String>,
+    /// available in the sandbox, but ignored for caching
+    pub config_inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    /// patterns of output files which are only known after execution
+    pub output_globs: Vec<OutputGlob>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub deps: Vec<String>,
+    pub tags: Vec<Tag>,
+}
+
 impl Razel {
     pub fn new() -> Razel {
         let worker_threads = num_cpus::get();
@@ -116,27 +272,85 @@ impl Razel {
         let out_dir = PathBuf::from(config::OUT_DIR);
         Razel {
             read_cache: true,
+            check_determinism: false,
+            materialize_only: false,
+            remote_cache_upload_limit: 0,
+            remote_cache_download_limit: 0,
+            max_inflight_uploads: 1000,
+            remote_cache_max_size: None,
+            remote_cache_auth_token_file: None,
+            explain_skips: false,
+            explain_inputs: None,
+            explain_remote_miss: false,
+            incremental_log: false,
+            profile_memory: false,
+            keep_metadata_runs: None,
+            summary_only: false,
+            stats_interval: None,
+            atomic_outputs: false,
+            trace_inputs: false,
+            interactive: false,
+            strict_sandbox: false,
+            max_command_output_size: None,
+            seed_from_remote: false,
+            require_remote_cache: false,
+            action_digest_env: false,
+            phased: false,
+            deadline: None,
+            record_schedule: None,
+            replay_schedule: None,
+            cache_salt: None,
+            link_type: config::OUT_DIR_LINK_TYPE,
+            link_cas_readonly: false,
+            jobserver: false,
+            prewarm_wasi: false,
+            max_open_files: None,
+            hermetic_path: None,
+            update_goldens: false,
+            self_digest_mode: Default::default(),
+            input_manifest: None,
+            export_input_manifest: None,
+            discover_includes: false,
+            verify_outputs: false,
+            update_lock: false,
             worker_threads,
+            strict_outputs_ignore: None,
             workspace_dir,
             current_dir,
             out_dir,
+            output_base: None,
             cache: None,
             sandbox_dir: None,
             files: Default::default(),
             path_to_file_id: Default::default(),
             which_to_file_id: Default::default(),
+            checked_system_executables: Default::default(),
             self_file_id: None,
             commands: Default::default(),
+            deferred_deps: None,
+            aliases: Default::default(),
             excluded_commands_len: 0,
+            filter: None,
             cgroup: None,
+            http_client: Default::default(),
             http_remote_exec_state: Default::default(),
             waiting: Default::default(),
             scheduler: Scheduler::new(worker_threads),
             succeeded: vec![],
             failed: vec![],
             skipped: vec![],
+            skipped_by: Default::default(),
+            phases: None,
+            #[cfg(target_family = "unix")]
+            jobserver_host: None,
+            schedule: Default::default(),
+            retried_after_system_error: Default::default(),
+            running_tasks: Default::default(),
+            run_start: None,
+            deadline_exceeded: false,
             cache_hits: 0,
             tui: TUI::new(),
+            reporters: vec![],
             tui_dirty: false,
             measurements: Measurements::new(),
             profile: Profile::new(),
@@ -146,7 +360,71 @@ impl Razel {
 
     /// Remove the binary directory
     pub fn clean(&self) {
+        // only removes the link if out_dir links to the output base
         fs::remove_dir_all(&self.out_dir).ok();
+        if let Some(x) = &self.output_base {
+            if x.join(config::OUTPUT_BASE_MARKER).exists() {
+                fs::remove_dir_all(x).ok();
+            } else if x.exists() {
+                warn!("not removing output base {x:?} because it was not created by razel");
+            }
+        }
+    }
+
+    /// Write output files to a directory outside of current_dir, e.g. for out-of-tree builds.
+    ///
+    /// Commands still use paths within out_dir, which is replaced by a link to the output base.
+    /// The output base is removed by [Self::clean] if razel created it, therefore it must not
+    /// contain sources.
+    pub fn set_output_base(&mut self, dir: &Path) -> Result<(), anyhow::Error> {
+        let output_base = self.current_dir.join(dir);
+        let canonical = output_base
+            .canonicalize()
+            .unwrap_or_else(|_| output_base.clone());
+        for source_dir in [&self.current_dir, &self.workspace_dir] {
+            let source_dir = source_dir
+                .canonicalize()
+                .unwrap_or_else(|_| source_dir.clone());
+            if source_dir.starts_with(&canonical) {
+                bail!("output base must not contain the workspace dir {source_dir:?}: {output_base:?}");
+            }
+        }
+        self.output_base = Some(output_base);
+        Ok(())
+    }
+
+    /// Returns the absolute directory containing the output files
+    fn output_directory(&self) -> PathBuf {
+        self.output_base
+            .clone()
+            .unwrap_or_else(|| self.current_dir.join(&self.out_dir))
+    }
+
+    /// Let out_dir link to the output base or remove a link of a previous run
+    fn link_output_base(&self) -> Result<(), anyhow::Error> {
+        let link = self.current_dir.join(&self.out_dir);
+        let existing = fs::symlink_metadata(&link).ok();
+        if let Some(metadata) = &existing {
+            if metadata.is_symlink() {
+                if self.output_base.is_some() && fs::read_link(&link)? == self.output_directory() {
+                    return Ok(());
+                }
+                fs::remove_file(&link).or_else(|_| fs::remove_dir(&link))?;
+            } else if self.output_base.is_some() {
+                bail!("{link:?} exists, remove it to use an output base");
+            }
+        }
+        if let Some(output_base) = &self.output_base {
+            if !output_base.exists() {
+                fs::create_dir_all(output_base)
+                    .with_context(|| format!("Failed to create output base: {output_base:?}"))?;
+                fs::write(output_base.join(config::OUTPUT_BASE_MARKER), "")
+                    .with_context(|| format!("Failed to mark output base: {output_base:?}"))?;
+            }
+            symlink_dir(output_base, &link)
+                .with_context(|| format!("Failed to link {link:?} to {output_base:?}"))?;
+        }
+        Ok(())
     }
 
     /// Set the directory to resolve relative paths of input/output files
@@ -159,27 +437,42 @@ impl Razel {
         Ok(())
     }
 
+    /// Must be called before set_http_remote_exec_config() and pushing commands
+    pub fn set_http_client_config(
+        &mut self,
+        config: &HttpClientConfig,
+    ) -> Result<(), anyhow::Error> {
+        self.http_client = config.build()?;
+        Ok(())
+    }
+
     pub fn set_http_remote_exec_config(&mut self, config: &HttpRemoteExecConfig) {
-        self.http_remote_exec_state = HttpRemoteExecState::new(config);
+        self.http_remote_exec_state = HttpRemoteExecState::new(config, &self.http_client);
     }
 
-    #[allow(clippy::too_many_arguments)]
     pub fn push_custom_command(
         &mut self,
-        name: String,
-        executable: String,
-        args: Vec<String>,
-        env: HashMap<String, String>,
-        inputs: Vec<String>,
-        outputs: Vec<String>,
-        stdout: Option<String>,
-        stderr: Option<String>,
-        deps: Vec<String>,
-        tags: Vec<Tag>,
+        spec: CustomCommandSpec,
     ) -> Result<CommandId, anyhow::Error> {
+        let CustomCommandSpec {
+            name,
+            executable,
+            args,
+            env,
+            inputs,
+            config_inputs,
+            outputs,
+            output_globs,
+            stdout,
+            stderr,
+            deps,
+            tags,
+        } = spec;
         let mut builder = CommandBuilder::new(name, args, tags);
         builder.inputs(&inputs, self)?;
+        builder.config_inputs(&config_inputs, self)?;
         builder.outputs(&outputs, self)?;
+        builder.output_globs(&output_globs, self)?;
         if let Some(x) = stdout {
             builder.stdout(&x, self)?;
         }
@@ -197,14 +490,27 @@ impl Razel {
         self.push(builder)
     }
 
-    pub fn push(&mut self, builder: CommandBuilder) -> Result<CommandId, anyhow::Error> {
+    pub fn push(&mut self, mut builder: CommandBuilder) -> Result<CommandId, anyhow::Error> {
         // TODO check if name is unique
+        if self.aliases.contains_key(builder.name()) {
+            bail!("command name is already used as alias: {}", builder.name());
+        }
+        let deferred_deps = builder.take_deferred_deps();
         let id = self.commands.alloc_with_id(|id| builder.build(id));
+        if let Some(x) = &mut self.deferred_deps {
+            x.extend(deferred_deps.into_iter().map(|name| (id, name)));
+        }
         let command = &mut self.commands[id];
         Self::check_tags(command)?;
-        if !matches!(&command.executor, Executor::CustomCommand(_)) {
+        Self::check_output_globs(command)?;
+        if self.hermetic_path.is_some() {
+            self.set_hermetic_path(id);
+        }
+        let command = &self.commands[id];
+        if !matches!(&command.executor, Executor::CustomCommand(_))
+            && self.self_digest_mode != SelfDigestMode::None
+        {
             // add razel executable to command hash
-            // TODO set digest to razel version once stable
             let self_file_id = self.lazy_self_file_id()?;
             self.commands[id].executables.push(self_file_id);
         }
@@ -218,6 +524,28 @@ impl Razel {
     }
 
     fn check_tags(command: &mut Command) -> Result<(), anyhow::Error> {
+        if command.tags.contains(&Tag::PreserveOutputs)
+            && !(matches!(command.executor, Executor::CustomCommand(_))
+                && command.tags.contains(&Tag::NoSandbox))
+        {
+            // commands see outputs of previous runs, therefore only allowed without sandbox and cache
+            bail!(
+                "Tag {} requires a custom command with {}",
+                serde_json::to_string(&Tag::PreserveOutputs).unwrap(),
+                serde_json::to_string(&Tag::NoSandbox).unwrap()
+            );
+        }
+        let resources = Scheduler::resources_for_command(command);
+        if let Some((name, _)) = resources.iter().duplicates_by(|(name, _)| name).next() {
+            bail!("resource {name} is used more than once, use a single razel:resource tag with the total count");
+        }
+        if !command.archive_entries.is_empty()
+            && (!matches!(command.executor, Executor::CustomCommand(_))
+                || command.tags.contains(&Tag::NoSandbox))
+        {
+            // entries are extracted into the sandbox
+            bail!("archive entries are only supported for commands executed in a sandbox");
+        }
         match &command.executor {
             Executor::CustomCommand(_) => {
                 if command.tags.contains(&Tag::NoSandbox) && !command.tags.contains(&Tag::NoCache) {
@@ -238,6 +566,16 @@ impl Razel {
         Ok(())
     }
 
+    fn check_output_globs(command: &Command) -> Result<(), anyhow::Error> {
+        if !command.output_globs.is_empty()
+            && (!matches!(&command.executor, Executor::CustomCommand(_))
+                || command.tags.contains(&Tag::NoSandbox))
+        {
+            bail!("output globs are only supported for commands executed in a sandbox");
+        }
+        Ok(())
+    }
+
     fn lazy_self_file_id(&mut self) -> Result<FileId, anyhow::Error> {
         if let Some(x) = self.self_file_id {
             Ok(x)
@@ -269,11 +607,58 @@ impl Razel {
         self.commands.get(id)
     }
 
+    /// Returns the command with the name or alias
     pub fn get_command_by_name(&self, command_name: &String) -> Option<&Command> {
-        self.commands.iter().find(|x| &x.name == command_name)
+        let name = self.aliases.get(command_name).unwrap_or(command_name);
+        self.commands.iter().find(|x| &x.name == name)
+    }
+
+    /// Adds a short name for a command, the command may be pushed later
+    pub fn add_alias(&mut self, alias: String, target: String) -> Result<(), anyhow::Error> {
+        if self.commands.iter().any(|x| x.name == alias) {
+            bail!("alias is already used as command name: {alias}");
+        }
+        if let Some(x) = self.aliases.get(&alias) {
+            bail!("alias is already defined for {x}: {alias}");
+        }
+        self.aliases.insert(alias, target);
+        Ok(())
+    }
+
+    pub fn command_names(&self) -> impl Iterator<Item = &String> {
+        self.commands.iter().map(|x| &x.name)
+    }
+
+    /// Accept deps on commands which are not yet known, e.g. to load commands from multiple files.
+    ///
+    /// The deps must be resolved with resolve_deferred_deps() after pushing all commands.
+    pub fn defer_deps(&mut self) {
+        self.deferred_deps.get_or_insert_with(Vec::new);
+    }
+
+    pub fn is_deferring_deps(&self) -> bool {
+        self.deferred_deps.is_some()
+    }
+
+    /// Resolves deps collected since defer_deps() and fails on unknown commands or cycles
+    pub fn resolve_deferred_deps(&mut self) -> Result<(), anyhow::Error> {
+        let Some(deferred_deps) = self.deferred_deps.take() else {
+            return Ok(());
+        };
+        for (id, name) in deferred_deps {
+            let Some(dep) = self.get_command_by_name(&name).map(|x| x.id) else {
+                bail!(
+                    "unknown command for dep of {}: {name}",
+                    self.commands[id].name
+                );
+            };
+            self.commands[id].deps.push(dep);
+        }
+        self.check_for_circular_dependencies()
     }
 
     pub fn add_tag_for_command(&mut self, name: &str, tag: Tag) -> Result<(), anyhow::Error> {
+        let name = self.aliases.get(name).map_or(name, |x| x.as_str());
         match self.commands.iter_mut().find(|x| x.name == name) {
             Some(command) => {
                 command.tags.push(tag);
@@ -284,19 +669,69 @@ impl Razel {
         }
     }
 
-    pub fn list_commands(&mut self) {
+    pub fn list_commands(&mut self, format: ListFormat) {
+        for id in self.commands_in_execution_order() {
+            match format {
+                ListFormat::Text => {
+                    let command = &self.commands[id];
+                    println!("# {}", command.name);
+                    println!(
+                        "{}",
+                        self.tui.format_command_line(
+                            &command
+                                .executor
+                                .command_line_with_redirects(&self.tui.razel_executable)
+                        )
+                    );
+                }
+                ListFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string(&self.resolved_json_command(id)).unwrap()
+                ),
+            }
+        }
+    }
+
+    /// Write the paths of the output files of all not excluded commands, e.g. to stdout
+    pub fn list_outputs(
+        &mut self,
+        format: ListFormat,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), anyhow::Error> {
+        for id in self.commands_in_execution_order() {
+            let outputs = self.output_paths(id);
+            match format {
+                ListFormat::Text => {
+                    for path in outputs {
+                        writeln!(writer, "{}", path.to_str().unwrap())?;
+                    }
+                }
+                ListFormat::Json => writeln!(
+                    writer,
+                    "{}",
+                    serde_json::json!({"name": self.commands[id].name, "outputs": outputs})
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of the output files of a command, including stdout/stderr files
+    fn output_paths(&self, id: CommandId) -> Vec<&PathBuf> {
+        self.commands[id]
+            .outputs
+            .iter()
+            .map(|x| &self.files[*x].path)
+            .collect()
+    }
+
+    /// Returns the ids of all not excluded commands in an order in which they can be executed
+    fn commands_in_execution_order(&mut self) -> Vec<CommandId> {
+        let mut ids = Vec::with_capacity(self.commands.len() - self.excluded_commands_len);
         self.create_dependency_graph();
         while let Some(id) = self.scheduler.pop_ready_and_run() {
+            ids.push(id);
             let command = &mut self.commands[id];
-            println!("# {}", command.name);
-            println!(
-                "{}",
-                self.tui.format_command_line(
-                    &command
-                        .executor
-                        .command_line_with_redirects(&self.tui.razel_executable)
-                )
-            );
             command.schedule_state = ScheduleState::Succeeded;
             self.scheduler
                 .set_finished_and_get_retry_flag(command, false);
@@ -306,26 +741,206 @@ impl Razel {
                 assert!(!rdep.unfinished_deps.is_empty());
                 rdep.unfinished_deps
                     .swap_remove(rdep.unfinished_deps.iter().position(|x| *x == id).unwrap());
-                if rdep.unfinished_deps.is_empty() {
+                if rdep.unfinished_deps.is_empty()
+                    && !self.phases.as_mut().is_some_and(|x| x.block(rdep))
+                {
                     rdep.schedule_state = ScheduleState::Ready;
                     self.waiting.remove(&rdep_id);
                     self.scheduler.push_ready(rdep);
                 }
             }
+            self.on_phase_command_finished(id);
+        }
+        ids
+    }
+
+    /// Returns a command with the executable, args and file paths used for execution
+    fn resolved_json_command(&self, id: CommandId) -> RazelJsonCommand {
+        let command = &self.commands[id];
+        let paths = |ids: &Vec<FileId>| {
+            ids.iter()
+                .map(|x| self.files[*x].path.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let stdout = command.executor.stdout_file();
+        let stderr = command.executor.stderr_file();
+        let mut args = command.executor.args_with_executable();
+        let executable = args.remove(0);
+        RazelJsonCommand {
+            name: command.name.clone(),
+            executable,
+            args,
+            env: command.executor.env().cloned().unwrap_or_default(),
+            args_by_os: Default::default(),
+            env_by_os: Default::default(),
+            env_unset: Default::default(),
+            inputs: command
+                .inputs
+                .iter()
+                .filter(|x| !command.entry_only_archives.contains(x))
+                .map(|x| &self.files[*x].path)
+                .chain(command.archive_entries.iter().map(|x| &x.path))
+                .map(|x| x.to_str().unwrap().to_string())
+                .collect(),
+            config_inputs: paths(&command.config_inputs),
+            outputs: command
+                .outputs
+                .iter()
+                .map(|x| &self.files[*x].path)
+                .filter(|x| Some(*x) != stdout && Some(*x) != stderr)
+                .map(|x| x.to_str().unwrap().to_string())
+                .collect(),
+            output_globs: command.output_globs.clone(),
+            artifacts: paths(&command.artifacts),
+            stdout: stdout.map(|x| x.to_str().unwrap().to_string()),
+            stderr: stderr.map(|x| x.to_str().unwrap().to_string()),
+            deps: command
+                .deps
+                .iter()
+                .map(|x| self.commands[*x].name.clone())
+                .collect(),
+            tags: command.tags.clone(),
+        }
+    }
+
+    pub fn workspace_dir(&self) -> &Path {
+        &self.workspace_dir
+    }
+
+    /// Set the number of commands to run in parallel
+    pub fn set_worker_threads(&mut self, worker_threads: usize) -> Result<(), anyhow::Error> {
+        if worker_threads == 0 {
+            bail!("number of jobs must be greater than 0");
+        }
+        self.worker_threads = worker_threads;
+        self.scheduler = Scheduler::new(worker_threads);
+        Ok(())
+    }
+
+    /// Limit the number of commands tagged with `razel:resource:<name>` running concurrently.
+    ///
+    /// Must be called after set_worker_threads().
+    pub fn set_resource_capacity(
+        &mut self,
+        name: &str,
+        capacity: usize,
+    ) -> Result<(), anyhow::Error> {
+        if capacity == 0 {
+            bail!("capacity of resource {name} must be greater than 0");
+        }
+        self.scheduler.set_resource_capacity(name.into(), capacity);
+        Ok(())
+    }
+
+    /// Fail if commands need resources without capacity or more than the capacity
+    fn check_resources(&self) -> Result<(), anyhow::Error> {
+        for command in self.commands.iter().filter(|x| !x.is_excluded) {
+            for (name, count) in Scheduler::resources_for_command(command) {
+                match self.scheduler.resource_capacity(&name) {
+                    None => bail!(
+                        "resource {name} of command {} is not defined, use --resource {name}=<capacity>",
+                        command.name
+                    ),
+                    Some(capacity) if count > capacity => bail!(
+                        "command {} needs {count} of resource {name}, but its capacity is {capacity}",
+                        command.name
+                    ),
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands executed once despite `check_determinism` because they run without sandbox
+    fn commands_without_determinism_check(&self) -> Vec<CommandId> {
+        self.commands
+            .iter()
+            .filter(|x| !x.is_excluded)
+            .filter(|x| !x.executor.use_sandbox() || x.tags.contains(&Tag::NoSandbox))
+            .map(|x| x.id)
+            .collect()
+    }
+
+    fn warn_about_unchecked_determinism(&self) {
+        let unchecked = self.commands_without_determinism_check();
+        if unchecked.is_empty() {
+            return;
+        }
+        let max_names = 10;
+        let names = unchecked
+            .iter()
+            .take(max_names)
+            .map(|x| self.commands[*x].name.as_str())
+            .join(", ");
+        let more = if unchecked.len() > max_names {
+            ", ..."
+        } else {
+            ""
+        };
+        warn!(
+            "--check-determinism: {} commands without sandbox are not checked: {names}{more}",
+            unchecked.len()
+        );
+    }
+
+    /// Fail sandboxed commands which create files that are neither inputs nor declared outputs.
+    ///
+    /// Paths relative to the sandbox dir matching any of the ignore patterns are allowed.
+    pub fn set_strict_outputs(&mut self, ignore: &[String]) -> Result<(), anyhow::Error> {
+        self.strict_outputs_ignore = Some(RegexSet::new(ignore)?);
+        Ok(())
+    }
+
+    /// Trace files read by commands to report undeclared inputs, the cache is not read to execute all commands
+    pub fn set_trace_inputs(&mut self) {
+        if cfg!(target_os = "linux") {
+            self.trace_inputs = true;
+            self.read_cache = false;
+        } else {
+            warn!("--trace-inputs is only supported on Linux, ignoring it");
+        }
+    }
+
+    /// Restrict sandboxed commands to read declared inputs and write the sandbox dir only
+    pub fn set_strict_sandbox(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(abi_version) = crate::executors::Landlock::abi_version() {
+            debug!("Landlock ABI version: {abi_version}");
+            self.strict_sandbox = true;
+            return;
         }
+        warn!("--strict-sandbox requires Landlock on Linux, ignoring it");
+    }
+
+    /// Register a reporter to be called in addition to the terminal UI
+    pub fn add_reporter(&mut self, reporter: Box<dyn Reporter + Send>) {
+        self.reporters.push(reporter);
     }
 
-    pub fn show_info(&self, cache_dir: Option<PathBuf>) -> Result<(), anyhow::Error> {
-        let output_directory = self.current_dir.join(&self.out_dir);
+    pub fn show_info(
+        &self,
+        cache_dir: Option<PathBuf>,
+        config_sources: &[(String, PathBuf)],
+    ) -> Result<(), anyhow::Error> {
+        let output_directory = self.output_directory();
+        let config_files = config::config_file_paths(&self.workspace_dir);
+        println!(
+            "config precedence: command line, environment, {}",
+            config_files.iter().map(|x| format!("{x:?}")).join(", ")
+        );
+        for (key, path) in config_sources {
+            println!("{:<19}from {:?}", format!("{key}:"), path);
+        }
         println!("workspace dir:     {:?}", self.workspace_dir);
         println!("output directory:  {:?}", output_directory);
-        let cache_dir = match cache_dir {
-            Some(x) => x,
-            _ => select_cache_dir(&self.workspace_dir)?,
-        };
-        println!("cache directory:   {:?}", cache_dir);
+        let (cache_dir, reason) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        println!("cache directory:   {:?} ({reason})", cache_dir);
         println!("sandbox directory: {:?}", select_sandbox_dir(&cache_dir)?);
         println!("worker threads:    {}", self.worker_threads);
+        if let Some(x) = &self.cache_salt {
+            println!("cache salt:        {x:?}");
+        }
         Ok(())
     }
 
@@ -335,34 +950,96 @@ impl Razel {
         remote_cache: Vec<String>,
         remote_cache_threshold: Option<u32>,
     ) -> Result<(), anyhow::Error> {
-        let output_directory = self.current_dir.join(&self.out_dir);
+        self.check_resources()?;
+        if self.phased {
+            self.check_phase_deps()?;
+        }
+        if self.check_determinism {
+            self.warn_about_unchecked_determinism();
+        }
+        let output_directory = self.output_directory();
         debug!("workspace dir:     {:?}", self.workspace_dir);
         debug!("output directory:  {:?}", output_directory);
-        let cache_dir = match cache_dir {
-            Some(x) => x,
-            _ => select_cache_dir(&self.workspace_dir)?,
-        };
-        debug!("cache directory:   {:?}", cache_dir);
+        let (cache_dir, reason) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        debug!("cache directory:   {:?} ({reason})", cache_dir);
+        if output_directory.starts_with(&cache_dir) {
+            bail!("output directory should not be within cache dir: {output_directory:?}");
+        }
+        if self
+            .current_dir
+            .join(&cache_dir)
+            .starts_with(&output_directory)
+        {
+            bail!("output directory must not contain the cache dir: {output_directory:?}");
+        }
+        self.link_output_base()?;
         let sandbox_dir = select_sandbox_dir(&cache_dir)?;
         let mut cache = Cache::new(cache_dir, self.out_dir.clone())?;
         debug!("sandbox directory: {:?}", sandbox_dir);
         debug!("worker threads:    {}", self.worker_threads);
-        cache
+        raise_open_files_limit(self.worker_threads, self.max_open_files)?;
+        cache.set_remote_cache_bandwidth_limits(
+            self.remote_cache_upload_limit,
+            self.remote_cache_download_limit,
+        );
+        cache.set_remote_cache_max_inflight_uploads(self.max_inflight_uploads);
+        if let Some(path) = &self.remote_cache_auth_token_file {
+            let token = fs::read_to_string(path)
+                .with_context(|| format!("failed to read remote cache auth token: {path:?}"))?;
+            cache.set_remote_cache_auth_token(Some(token.trim().into()));
+        }
+        cache.set_out_dir_link_type(self.link_type);
+        cache.set_out_dir_readonly(self.link_cas_readonly);
+        cache.set_remote_cache_max_size(self.remote_cache_max_size);
+        let connected = cache
             .connect_remote_cache(&remote_cache, remote_cache_threshold)
             .await?;
+        if !connected && remote_cache.iter().any(|x| !x.is_empty()) {
+            let urls = remote_cache.join(",");
+            if self.require_remote_cache {
+                bail!("remote cache not available: {urls}");
+            }
+            warn!("remote cache not available, disabled for this run: {urls}");
+        }
         TmpDirSandbox::cleanup(&sandbox_dir);
+        fs::create_dir_all(&sandbox_dir)
+            .with_context(|| format!("Failed to create sandbox directory: {sandbox_dir:?}"))?;
         self.cache = Some(cache);
         self.sandbox_dir = Some(sandbox_dir);
         match create_cgroup() {
             Ok(x) => self.cgroup = x,
             Err(e) => debug!("create_cgroup(): {e}"),
         };
+        if self.jobserver {
+            self.create_jobserver()?;
+        }
+        if self.discover_includes {
+            self.discover_includes().await?;
+        }
         self.create_dependency_graph();
         self.remove_unknown_or_excluded_files_from_out_dir(&self.out_dir)
             .ok();
         self.digest_input_files().await?;
+        self.checked_system_executables.clear();
+        if let Some(x) = &self.input_manifest {
+            self.check_input_manifest(x)?;
+        }
+        if let Some(x) = &self.export_input_manifest {
+            self.write_input_manifest(x)?;
+        }
+        if let Some(x) = self.replay_schedule.clone() {
+            self.read_schedule(&x)?;
+        }
         self.create_output_dirs()?;
-        self.create_wasi_modules()?;
+        if self.incremental_log {
+            let dir = self.out_dir.join("razel-metadata");
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create metadata directory: {dir:?}"))?;
+            self.log_file.write_incrementally(&dir.join("log.json"))?;
+        }
+        if !self.materialize_only {
+            self.create_wasi_modules()?;
+        }
         Ok(())
     }
 
@@ -379,36 +1056,86 @@ impl Razel {
         if self.commands.is_empty() {
             bail!("No commands added");
         }
+        self.run_start = Some(preparation_start);
         self.tui.verbose = verbose;
+        self.tui.summary_only = self.summary_only;
+        self.tui.update_interval = self.stats_interval;
+        let memory_profiler = if self.profile_memory {
+            let x = MemoryProfiler::start(config::MEMORY_PROFILE_INTERVAL);
+            if x.is_none() {
+                warn!("--profile-memory is only supported on Linux, ignoring it");
+            }
+            x
+        } else {
+            None
+        };
         self.prepare_run(cache_dir, remote_cache, remote_cache_threshold)
             .await?;
+        if self.seed_from_remote {
+            self.seed_from_remote().await;
+        }
         let (tx, mut rx) = mpsc::unbounded_channel();
+        let (digests_tx, mut digests_rx) = mpsc::unbounded_channel();
         let mut interval = tokio::time::interval(self.tui.get_update_interval());
         interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         let execution_start = Instant::now();
-        self.start_ready_commands(&tx);
+        let deadline = self.deadline.map(|x| preparation_start + x);
+        let deadline_sleep = async {
+            match deadline {
+                Some(x) => tokio::time::sleep_until(x.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(deadline_sleep);
+        self.start_ready_commands(&tx, &digests_tx);
         let mut start_more_commands = true;
         while self.scheduler.running() != 0 {
             tokio::select! {
                 Some((id, execution_result, output_files, output_files_cached)) = rx.recv() => {
+                    self.running_tasks.remove(&id);
                     self.on_command_finished(id, &execution_result, output_files, output_files_cached);
-                    if execution_result.status == ExecutionStatus::SystemError
+                    if execution_result.is_fatal_system_error()
                         || (!self.failed.is_empty() && !keep_going)
                     {
                         start_more_commands = false;
                     }
                     if start_more_commands {
-                        self.start_ready_commands(&tx);
+                        self.start_ready_commands(&tx, &digests_tx);
+                    }
+                },
+                Some((id, digests)) = digests_rx.recv() => {
+                    // the command might have been killed because the deadline was exceeded
+                    if self.running_tasks.remove(&id).is_some() {
+                        self.set_archive_entry_digests(id, digests);
+                        self.start_next_command(id, tx.clone(), digests_tx.clone());
+                    }
+                },
+                _ = &mut deadline_sleep, if !self.deadline_exceeded => self.on_deadline_exceeded(&mut rx),
+                _ = interval.tick() => {
+                    // jobserver tokens might have been returned in the meantime
+                    if self.is_jobserver_hosted() && start_more_commands && !self.deadline_exceeded {
+                        self.start_ready_commands(&tx, &digests_tx);
                     }
+                    self.update_status();
                 },
-                _ = interval.tick() => self.update_status(),
             }
         }
         self.remove_outputs_of_not_run_actions_from_out_dir();
         TmpDirSandbox::cleanup(self.sandbox_dir.as_ref().unwrap());
+        self.flush_remote_cache().await;
         self.push_logs_for_not_started_commands();
-        self.write_metadata(group_by_tag)
+        let memory_profile = memory_profiler.map(MemoryProfiler::stop);
+        self.write_metadata(group_by_tag, memory_profile.as_ref())
             .context("Failed to write metadata")?;
+        if let Some(x) = &self.record_schedule {
+            self.write_schedule(x)?;
+        }
+        if self.explain_skips {
+            self.print_explain_skips();
+        }
+        if let Some(id) = self.explain_inputs {
+            self.print_explain_inputs(id);
+        }
         let stats = SchedulerStats {
             exec: SchedulerExecStats {
                 succeeded: self.succeeded.len(),
@@ -419,11 +1146,72 @@ impl Razel {
             cache_hits: self.cache_hits,
             preparation_duration: execution_start.duration_since(preparation_start),
             execution_duration: execution_start.elapsed(),
+            deadline_exceeded: self.deadline_exceeded,
         };
-        self.tui.finished(&stats);
+        self.tui.on_run_finished(&stats);
+        for reporter in &mut self.reporters {
+            reporter.on_run_finished(&stats);
+        }
+        let lock_file = self.workspace_dir.join(config::LOCK_FILE);
+        if self.update_lock {
+            self.write_output_lock(&lock_file)?;
+        } else if self.verify_outputs {
+            self.verify_output_lock(&lock_file)?;
+        }
         Ok(stats)
     }
 
+    /// Kills running commands and reports them as timed out, other commands are not started
+    fn on_deadline_exceeded(&mut self, rx: &mut UnboundedReceiver<ExecutionResultChannel>) {
+        self.deadline_exceeded = true;
+        while let Ok((id, execution_result, output_files, output_files_cached)) = rx.try_recv() {
+            self.running_tasks.remove(&id);
+            self.on_command_finished(id, &execution_result, output_files, output_files_cached);
+        }
+        let deadline = self.deadline.unwrap();
+        let running = self.running_tasks.drain().collect_vec();
+        let not_started = self.waiting.len() + self.scheduler.ready();
+        warn!(
+            "deadline of {}s exceeded: killing {} running commands, {not_started} commands not started",
+            deadline.as_secs(),
+            running.len()
+        );
+        for (id, task) in running {
+            // dropping the execution kills the process group of the command
+            task.abort();
+            let result = ExecutionResult {
+                status: ExecutionStatus::Timeout,
+                error: Some(anyhow!(
+                    "killed because deadline of {}s exceeded",
+                    deadline.as_secs()
+                )),
+                ..Default::default()
+            };
+            self.on_command_finished(id, &result, vec![], false);
+        }
+    }
+
+    /// Waits for pending remote cache uploads to not lose them when razel exits
+    async fn flush_remote_cache(&self) {
+        let Some(x) = self
+            .cache
+            .as_ref()
+            .unwrap()
+            .flush_remote_cache(REMOTE_CACHE_FLUSH_TIMEOUT)
+            .await
+        else {
+            return;
+        };
+        if x.dropped != 0 {
+            warn!(
+                "remote cache: flushed {} uploads, dropped {}",
+                x.flushed, x.dropped
+            );
+        } else if x.flushed != 0 {
+            info!("remote cache: flushed {} uploads", x.flushed);
+        }
+    }
+
     pub(crate) fn get_file_path(&self, id: FileId) -> &PathBuf {
         &self.files[id].path
     }
@@ -473,6 +1261,15 @@ impl Razel {
 
     pub fn input_file(&mut self, arg: String) -> Result<&File, anyhow::Error> {
         let rel_path = self.rel_path(&arg)?;
+        if let Some(file) = self.path_to_file_id.get(&rel_path).map(|x| &self.files[*x]) {
+            if file.file_type == FileType::OutputFile && self.current_dir.join(&rel_path).is_file()
+            {
+                bail!(
+                    "File {} cannot be input because it's both a source file and an output file",
+                    arg
+                );
+            }
+        }
         self.input_file_for_rel_path(arg, FileType::DataFile, rel_path)
     }
 
@@ -523,16 +1320,44 @@ impl Razel {
         Ok(&self.files[id])
     }
 
+    /// Maps the pattern like an output file path
+    pub fn output_glob(&self, glob: &OutputGlob) -> Result<OutputGlob, anyhow::Error> {
+        let resolved = OutputGlob {
+            pattern: self
+                .out_dir
+                .join(self.rel_path(&glob.pattern)?)
+                .to_str()
+                .unwrap()
+                .into(),
+            allow_empty: glob.allow_empty,
+        };
+        resolved
+            .regex()
+            .with_context(|| format!("invalid output glob: {}", glob.pattern))?;
+        Ok(resolved)
+    }
+
     pub fn http_remote_exec(&self, url: &Url) -> Option<Arc<HttpRemoteExecDomain>> {
         self.http_remote_exec_state.for_url(url)
     }
 
+    pub fn http_client(&self) -> reqwest::Client {
+        self.http_client.clone()
+    }
+
     pub fn wasi_module(&mut self, arg: String) -> Result<&File, anyhow::Error> {
         let rel_path = self.rel_path(&arg)?;
         self.input_file_for_rel_path(arg, FileType::WasiModule, rel_path)
     }
 
     /// Maps a relative path from workspace dir to cwd, allow absolute path
+    /// Returns true if the arg is a declared file or exists, e.g. a file named like `data.tar#x`
+    pub(crate) fn is_existing_file(&self, arg: &String) -> bool {
+        self.rel_path(arg).is_ok_and(|x| {
+            self.path_to_file_id.get(&x).is_some() || self.current_dir.join(x).exists()
+        })
+    }
+
     fn rel_path(&self, arg: &String) -> Result<PathBuf, anyhow::Error> {
         let path = Path::new(arg);
         if path.is_absolute() {
@@ -558,6 +1383,7 @@ impl Razel {
         self.waiting.reserve(reserve);
         self.succeeded.reserve(reserve);
         let mut rdeps = vec![];
+        self.phases = self.phased.then(|| phases::Phases::new(&self.commands));
         for command in self.commands.iter_mut() {
             assert_eq!(command.schedule_state, ScheduleState::New);
             if command.is_excluded {
@@ -565,7 +1391,11 @@ impl Razel {
                 continue;
             }
             command.unfinished_deps.reserve(command.deps.len());
-            for input_id in chain(command.executables.iter(), command.inputs.iter()) {
+            for input_id in chain!(
+                &command.executables,
+                &command.inputs,
+                &command.config_inputs
+            ) {
                 if let Some(dep) = self.files[*input_id].creating_command {
                     command.unfinished_deps.push(dep);
                     rdeps.push((dep, command.id));
@@ -575,7 +1405,9 @@ impl Razel {
                 command.unfinished_deps.push(*dep);
                 rdeps.push((*dep, command.id));
             }
-            if command.unfinished_deps.is_empty() {
+            if command.unfinished_deps.is_empty()
+                && !self.phases.as_mut().is_some_and(|x| x.block(command))
+            {
                 command.schedule_state = ScheduleState::Ready;
                 self.scheduler.push_ready(command);
             } else {
@@ -586,11 +1418,49 @@ impl Razel {
         for (id, rdep) in rdeps {
             self.commands[id].reverse_deps.push(rdep);
         }
-        self.check_for_circular_dependencies();
     }
 
-    fn check_for_circular_dependencies(&self) {
-        // TODO
+    /// Deps are only resolved to already pushed commands, unless they are deferred
+    fn check_for_circular_dependencies(&self) -> Result<(), anyhow::Error> {
+        enum Visit {
+            InProgress,
+            Done,
+        }
+        let mut visits: HashMap<CommandId, Visit> = HashMap::new();
+        for root in self.commands.iter().map(|x| x.id) {
+            // depth first search with explicit stack of (command, index of next dep)
+            let mut stack = vec![(root, 0)];
+            while let Some((id, i)) = stack.last_mut() {
+                let id = *id;
+                if *i == 0 {
+                    if visits.contains_key(&id) {
+                        stack.pop();
+                        continue;
+                    }
+                    visits.insert(id, Visit::InProgress);
+                }
+                let Some(dep) = self.commands[id].deps.get(*i).cloned() else {
+                    visits.insert(id, Visit::Done);
+                    stack.pop();
+                    continue;
+                };
+                *i += 1;
+                match visits.get(&dep) {
+                    None => stack.push((dep, 0)),
+                    Some(Visit::InProgress) => {
+                        let start = stack.iter().position(|(x, _)| *x == dep).unwrap();
+                        let cycle = stack[start..]
+                            .iter()
+                            .map(|(x, _)| self.commands[*x].name.as_str())
+                            .chain([self.commands[dep].name.as_str()])
+                            .join(" -> ");
+                        bail!("circular dependency: {cycle}");
+                    }
+                    Some(Visit::Done) => {}
+                }
+            }
+        }
+        Ok(())
     }
 
     fn remove_unknown_or_excluded_files_from_out_dir(
@@ -600,6 +1470,10 @@ impl Razel {
         for entry in fs::read_dir(dir)? {
             if let Ok(path) = entry.map(|x| x.path()) {
                 if path.is_dir() {
+                    if path == self.out_dir.join("razel-metadata").join(RUNS_DIR) {
+                        // metadata of previous runs, see --keep-metadata-runs
+                        continue;
+                    }
                     // TODO remove whole dir if not known
                     self.remove_unknown_or_excluded_files_from_out_dir(&path)
                         .ok();
@@ -610,6 +1484,7 @@ impl Razel {
                         .get(path_wo_prefix)
                         .map_or(true, |x| self.files[*x].is_excluded)
                         && path_wo_prefix.to_string_lossy() != GITIGNORE_FILENAME
+                        && path_wo_prefix.to_string_lossy() != config::OUTPUT_BASE_MARKER
                     {
                         fs::remove_file(path).ok();
                     }
@@ -648,12 +1523,31 @@ impl Razel {
             };
             self.spawn_digest_input_file(&mut next_file_id, &mut tx_option);
         }
+        if let Some(id) = self
+            .self_file_id
+            .filter(|_| self.self_digest_mode == SelfDigestMode::Version)
+        {
+            self.files[id].digest = Some(Self::self_version_digest());
+        }
         if missing_files != 0 {
-            bail!("{missing_files} input files not found!");
+            if !self.materialize_only {
+                bail!("{missing_files} input files not found!");
+            }
+            // nothing is executed, commands with missing inputs will be reported as not cached
+            warn!("{missing_files} input files not found!");
         }
         Ok(())
     }
 
+    /// Replaces the digest of the razel executable with --self-digest-mode=version
+    fn self_version_digest() -> BlobDigest {
+        Digest::for_string(&format!(
+            "{} {}",
+            config::EXECUTABLE,
+            env!("CARGO_PKG_VERSION")
+        ))
+    }
+
     fn spawn_digest_input_file(
         &self,
         next_id: &mut FileId,
@@ -692,61 +1586,153 @@ impl Razel {
         Ok(())
     }
 
+    /// Compiles distinct WASI modules on up to `worker_threads` threads, with `prewarm_wasi` via the
+    /// local cache
     fn create_wasi_modules(&mut self) -> Result<(), anyhow::Error> {
-        let mut engine = None;
-        let mut modules: HashMap<FileId, wasmtime::Module> = HashMap::new();
+        let executables: HashMap<FileId, &String> = self
+            .commands
+            .iter()
+            .filter_map(|x| match &x.executor {
+                Executor::Wasi(x) => Some((x.module_file_id.unwrap(), &x.executable)),
+                _ => None,
+            })
+            .collect();
+        if executables.is_empty() {
+            return Ok(());
+        }
+        let engine = WasiExecutor::create_engine()?;
+        let module_cache = self
+            .prewarm_wasi
+            .then(|| WasiModuleCache::new(self.cache.as_ref().unwrap().dir().join("wasi"), &engine))
+            .transpose()?;
+        let threads = self.worker_threads.min(executables.len());
+        let executables = std::sync::Mutex::new(executables.into_iter());
+        let compile = |file_id: FileId, executable: &String| {
+            let module = match module_cache
+                .as_ref()
+                .zip(self.files[file_id].digest.as_ref())
+            {
+                Some((cache, digest)) => {
+                    let (module, compiled) =
+                        cache.get_or_create_module(&engine, executable.as_ref(), &digest.hash)?;
+                    debug!("WASM module {executable} compiled: {compiled}");
+                    module
+                }
+                None => WasiExecutor::create_module(&engine, executable)?,
+            };
+            Ok::<_, anyhow::Error>((file_id, module))
+        };
+        // limit to worker_threads, each thread compiles modules until none are left
+        let modules: HashMap<FileId, wasmtime::Module> = std::thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut modules = vec![];
+                        loop {
+                            let next = executables.lock().unwrap().next();
+                            let Some((file_id, executable)) = next else {
+                                break;
+                            };
+                            modules.push(compile(file_id, executable)?);
+                        }
+                        Ok::<_, anyhow::Error>(modules)
+                    })
+                })
+                .collect_vec();
+            handles
+                .into_iter()
+                .map(|x| x.join().unwrap())
+                .flatten_ok()
+                .collect::<Result<_, _>>()
+        })?;
         for command in self.commands.iter_mut() {
             if let Executor::Wasi(executor) = &mut command.executor {
-                let file_id = executor.module_file_id.unwrap();
-                let module = match modules.entry(file_id) {
-                    hash_map::Entry::Occupied(x) => x.get().clone(),
-                    hash_map::Entry::Vacant(x) => {
-                        if engine.is_none() {
-                            engine = Some(WasiExecutor::create_engine()?);
-                        }
-                        let module = WasiExecutor::create_module(
-                            engine.as_ref().unwrap(),
-                            &executor.executable,
-                        )?;
-                        x.insert(module.clone());
-                        module
-                    }
-                };
-                executor.module = Some(module);
+                executor.module = Some(modules[&executor.module_file_id.unwrap()].clone());
             }
         }
         Ok(())
     }
 
-    fn start_ready_commands(&mut self, tx: &UnboundedSender<ExecutionResultChannel>) {
-        while let Some(id) = self.scheduler.pop_ready_and_run() {
-            self.start_next_command(id, tx.clone());
-            self.tui_dirty = true;
-        }
+    /// Tokens are shared by all commands with jobserver tag, razel does not start own commands in
+    /// the slots of tokens taken by them
+    #[cfg(target_family = "unix")]
+    fn create_jobserver(&mut self) -> Result<(), anyhow::Error> {
+        let tokens = self.worker_threads - 1;
+        debug!("jobserver tokens:  {tokens}");
+        let jobserver = Jobserver::new(tokens).context("failed to create jobserver")?;
+        self.jobserver_host = Some(Arc::new(jobserver));
+        Ok(())
     }
 
-    fn update_status(&mut self) {
-        if !self.tui_dirty {
-            return;
-        }
-        self.tui.status(
-            self.succeeded.len(),
-            self.cache_hits,
-            self.failed.len(),
+    #[cfg(target_family = "windows")]
+    fn create_jobserver(&mut self) -> Result<(), anyhow::Error> {
+        warn!("jobserver is not supported on Windows, ignoring it");
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn is_jobserver_hosted(&self) -> bool {
+        self.jobserver_host.is_some()
+    }
+
+    #[cfg(target_family = "windows")]
+    fn is_jobserver_hosted(&self) -> bool {
+        false
+    }
+
+    fn start_ready_commands(
+        &mut self,
+        tx: &UnboundedSender<ExecutionResultChannel>,
+        digests_tx: &UnboundedSender<ArchiveDigestsChannel>,
+    ) {
+        #[cfg(target_family = "unix")]
+        if let Some(jobserver) = &self.jobserver_host {
+            self.scheduler.set_reserved_slots(jobserver.tokens_in_use());
+        }
+        while let Some(id) = self.pop_ready_command() {
+            for reporter in &mut self.reporters {
+                reporter.on_target_started(&self.commands[id]);
+            }
+            self.start_next_command(id, tx.clone(), digests_tx.clone());
+            self.tui_dirty = true;
+        }
+    }
+
+    fn update_status(&mut self) {
+        if !self.tui_dirty {
+            return;
+        }
+        self.tui.status(
+            self.succeeded.len(),
+            self.cache_hits,
+            self.failed.len(),
             self.scheduler.running(),
             self.waiting.len() + self.scheduler.ready(),
         );
         self.tui_dirty = false;
     }
 
-    fn new_sandbox(&self, command: &Command) -> BoxedSandbox {
+    /// Removes whatever a failed attempt left at the sandbox paths of the command before a retry
+    fn remove_sandbox_leftovers(&self, id: CommandId) {
+        let base_dir = self.sandbox_dir.as_ref().unwrap();
+        for name in [id.to_string(), format!("{id}-check")] {
+            let path = base_dir.join(name);
+            if path.is_dir() {
+                fs::remove_dir_all(&path).ok();
+            } else {
+                fs::remove_file(&path).ok();
+            }
+        }
+    }
+
+    fn new_sandbox(&self, command: &Command, name: &str) -> BoxedSandbox {
         match command.executor {
-            Executor::Wasi(_) => self.new_wasi_sandbox(command),
-            _ => self.new_tmp_dir_sandbox(command),
+            Executor::Wasi(_) => self.new_wasi_sandbox(command, name),
+            _ => self.new_tmp_dir_sandbox(command, name),
         }
     }
 
-    fn new_tmp_dir_sandbox(&self, command: &Command) -> BoxedSandbox {
+    fn new_tmp_dir_sandbox(&self, command: &Command, name: &str) -> BoxedSandbox {
         let command_executables = command.executables.iter().filter(|&&x| {
             if let Some(self_file_id) = self.self_file_id {
                 // razel never calls itself
@@ -755,22 +1741,39 @@ impl Razel {
                 true
             }
         });
-        let inputs = chain(command_executables, command.inputs.iter())
+        let inputs = chain!(command_executables, &command.inputs, &command.config_inputs)
             .map(|x| self.files[*x].path.clone())
             .filter(|x| x.is_relative())
             .collect();
-        Box::new(TmpDirSandbox::new(
-            self.sandbox_dir.as_ref().unwrap(),
-            &command.id.to_string(),
-            inputs,
-        ))
+        let archive_entries = command
+            .archive_entries
+            .iter()
+            .into_group_map_by(|x| x.archive)
+            .into_iter()
+            .map(|(archive, entries)| {
+                (
+                    self.files[archive].path.clone(),
+                    entries
+                        .into_iter()
+                        .map(|x| (x.entry.clone(), x.path.clone()))
+                        .collect(),
+                )
+            })
+            .collect();
+        Box::new(
+            TmpDirSandbox::new(self.sandbox_dir.as_ref().unwrap(), name, inputs)
+                .with_strict_outputs(self.strict_outputs_ignore.clone())
+                .with_output_globs(command.output_globs.clone())
+                .with_archive_entries(archive_entries),
+        )
     }
 
-    fn new_wasi_sandbox(&self, command: &Command) -> BoxedSandbox {
+    fn new_wasi_sandbox(&self, command: &Command, name: &str) -> BoxedSandbox {
         let cache = self.cache.as_ref().unwrap();
         let inputs = command
             .inputs
             .iter()
+            .chain(&command.config_inputs)
             .map(|x| &self.files[*x])
             .filter(|x| x.file_type == FileType::OutputFile)
             .map(|x| {
@@ -783,11 +1786,129 @@ impl Razel {
             .collect();
         Box::new(WasiSandbox::new(
             self.sandbox_dir.as_ref().unwrap(),
-            &command.id.to_string(),
+            name,
             inputs,
         ))
     }
 
+    fn collect_input_file_paths_for_command(&self, command: &Command) -> Vec<PathBuf> {
+        chain!(
+            &command.executables,
+            &command.inputs,
+            &command.config_inputs
+        )
+        .map(|x| self.files[*x].path.clone())
+        .chain(command.archive_entries.iter().map(|x| x.path.clone()))
+        .collect()
+    }
+
+    /// Returns the archive entries of a command without digest: path of the archive and index and
+    /// path of each entry
+    fn pending_archive_entries(
+        &self,
+        id: CommandId,
+        filter: impl Fn(&File) -> bool,
+    ) -> Vec<(PathBuf, Vec<(usize, PathBuf)>)> {
+        self.commands[id]
+            .archive_entries
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.digest.is_none() && filter(&self.files[x.archive]))
+            .into_group_map_by(|(_, x)| x.archive)
+            .into_iter()
+            .map(|(archive, entries)| {
+                (
+                    self.files[archive].path.clone(),
+                    entries
+                        .into_iter()
+                        .map(|(i, x)| (i, x.entry.clone()))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Reads the digests of archive entries, each archive is opened once
+    fn digest_archive_entries(
+        pending: &[(PathBuf, Vec<(usize, PathBuf)>)],
+    ) -> Result<Vec<(usize, BlobDigest)>, anyhow::Error> {
+        let mut digests = vec![];
+        for (archive, entries) in pending {
+            let paths = entries.iter().map(|(_, x)| x.clone()).collect_vec();
+            let entry_digests = tasks::digest_archive_entries(archive, &paths)
+                .with_context(|| format!("failed to read entries of {archive:?}"))?;
+            digests.extend(entries.iter().map(|(i, _)| *i).zip(entry_digests));
+        }
+        Ok(digests)
+    }
+
+    /// Digests are kept for retries of the command
+    fn set_archive_entry_digests(&mut self, id: CommandId, digests: Vec<(usize, BlobDigest)>) {
+        let command = &mut self.commands[id];
+        for (i, digest) in digests {
+            command.archive_entries[i].digest = Some(digest);
+        }
+    }
+
+    /// Reads the digests of entries of input archives, required for action digests without
+    /// executing commands. Entries of archives created by commands are skipped.
+    async fn digest_input_archive_entries(&mut self) -> Result<(), anyhow::Error> {
+        let pending = self
+            .commands
+            .iter()
+            .filter(|x| !x.is_excluded)
+            .map(|x| {
+                let pending = self.pending_archive_entries(x.id, |file| {
+                    file.creating_command.is_none() && file.digest.is_some()
+                });
+                (x.id, pending)
+            })
+            .filter(|(_, x)| !x.is_empty())
+            .collect_vec();
+        for (id, pending) in pending {
+            let result =
+                tokio::task::spawn_blocking(move || Self::digest_archive_entries(&pending)).await?;
+            match result {
+                Ok(digests) => self.set_archive_entry_digests(id, digests),
+                // nothing is executed, the command will be reported as not cached
+                Err(x) if self.materialize_only => warn!("{x:?}"),
+                Err(x) => return Err(x),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the digests of archive entries of a command in a blocking task to not stall the
+    /// scheduler, the command is started again once they are received.
+    fn spawn_digest_archive_entries(
+        &mut self,
+        id: CommandId,
+        pending: Vec<(PathBuf, Vec<(usize, PathBuf)>)>,
+        tx: UnboundedSender<ExecutionResultChannel>,
+        digests_tx: UnboundedSender<ArchiveDigestsChannel>,
+    ) {
+        let task = tokio::task::spawn(async move {
+            let result =
+                tokio::task::spawn_blocking(move || Self::digest_archive_entries(&pending))
+                    .await
+                    .unwrap_or_else(|x| Err(x.into()));
+            match result {
+                Ok(digests) => {
+                    digests_tx.send((id, digests)).ok();
+                }
+                Err(e) => {
+                    let result = ExecutionResult {
+                        status: ExecutionStatus::FailedToStart,
+                        error: Some(e),
+                        ..Default::default()
+                    };
+                    tx.send((id, result, vec![], false)).ok();
+                }
+            }
+        });
+        self.running_tasks.insert(id, task.abort_handle());
+    }
+
     fn collect_output_file_paths_for_command(&self, command: &Command) -> Vec<PathBuf> {
         command
             .outputs
@@ -799,44 +1920,129 @@ impl Razel {
     /// Execute a command in a worker thread with caching.
     ///
     /// If the executed command failed, action_result will be None and the action will not be cached.
-    fn start_next_command(&mut self, id: CommandId, tx: UnboundedSender<ExecutionResultChannel>) {
+    fn start_next_command(
+        &mut self,
+        id: CommandId,
+        tx: UnboundedSender<ExecutionResultChannel>,
+        digests_tx: UnboundedSender<ArchiveDigestsChannel>,
+    ) {
         let total_duration_start = Instant::now();
         let command = &self.commands[id];
         assert_eq!(command.schedule_state, ScheduleState::Ready);
         assert_eq!(command.unfinished_deps.len(), 0);
-        let (bzl_command, bzl_input_root) = self.get_bzl_action_for_command(command);
-        let no_cache_tag = command.tags.contains(&Tag::NoCache);
-        let cache = (!no_cache_tag).then(|| self.cache.as_ref().unwrap().clone());
+        if self.materialize_only
+            && chain(command.executables.iter(), command.inputs.iter())
+                .any(|x| self.files[*x].digest.is_none())
+        {
+            tx.send((id, Self::not_materialized_result(), vec![], false))
+                .ok();
+            return;
+        }
+        let pending = self.pending_archive_entries(id, |_| true);
+        if !pending.is_empty() {
+            self.spawn_digest_archive_entries(id, pending, tx, digests_tx);
+            return;
+        }
+        if !self.materialize_only {
+            if let Err(e) = self.check_system_executables(id) {
+                let result = ExecutionResult {
+                    status: ExecutionStatus::FailedToStart,
+                    error: Some(e),
+                    ..Default::default()
+                };
+                tx.send((id, result, vec![], false)).ok();
+                return;
+            }
+        }
+        let command = &self.commands[id];
+        let deadline = self.deadline_of_command(command);
+        // commands past their deadline are not executed, but outputs might be cached
+        let deadline_exceeded = deadline
+            .filter(|(_, x)| *x <= Instant::now())
+            .map(|(secs, _)| secs);
+        let action_digest = self.action_digest(command);
+        let no_cache_tag = command.tags.contains(&Tag::NoCache) || self.interactive;
+        let cache = (!no_cache_tag).then(|| {
+            let mut cache = self.cache.as_ref().unwrap().clone();
+            if let Some(x) = Self::remote_cache_threshold_tag(command) {
+                cache.set_remote_cache_threshold(Some(x));
+            }
+            cache
+        });
         let read_cache = self.read_cache;
-        let use_remote_cache = cache.is_some() && !command.tags.contains(&Tag::NoRemoteCache);
-        let executor = command.executor.clone();
+        let materialize_only = self.materialize_only;
+        let preserve_outputs = command.tags.contains(&Tag::PreserveOutputs);
+        // tmp files would hide previous outputs from the command
+        let atomic_outputs = self.atomic_outputs && !preserve_outputs;
+        let use_remote_cache = cache.is_some()
+            && !command.tags.contains(&Tag::NoRemoteCache)
+            && !self.is_input_size_above_remote_cache_max_size(command);
+        let mut executor = command.executor.clone();
         let sandbox = (executor.use_sandbox() && !command.tags.contains(&Tag::NoSandbox))
-            .then(|| self.new_sandbox(command));
+            .then(|| self.new_sandbox(command, &command.id.to_string()));
+        if let Executor::CustomCommand(x) = &mut executor {
+            x.trace_inputs = self.trace_inputs;
+            x.interactive = self.interactive;
+            x.deadline = deadline.map(|(_, x)| x);
+            if self.strict_sandbox && sandbox.is_some() {
+                x.strict_sandbox_inputs = Some(self.collect_input_file_paths_for_command(command));
+            }
+            if sandbox.is_some() {
+                x.max_output_size = self.max_command_output_size;
+            }
+            #[cfg(target_family = "unix")]
+            if command.tags.contains(&Tag::Jobserver) {
+                x.jobserver = self.jobserver_host.clone();
+            }
+            if self.action_digest_env {
+                // only set for execution, not part of the digest itself
+                x.env
+                    .insert(config::ACTION_DIGEST_ENV.into(), action_digest.hash.clone());
+            }
+        }
+        let check_sandbox = sandbox
+            .as_ref()
+            .filter(|_| self.check_determinism)
+            .map(|_| self.new_sandbox(command, &format!("{}-check", command.id)));
         let output_paths = self.collect_output_file_paths_for_command(command);
+        let artifacts = self.artifact_paths(id);
         let cgroup = self.cgroup.clone();
         let cwd = self.current_dir.clone();
         let out_dir = self.out_dir.clone();
-        tokio::task::spawn(async move {
+        let task = tokio::task::spawn(async move {
             let use_cache = cache.is_some();
-            let action = bazel_remote_exec::Action {
-                command_digest: Some(Digest::for_message(&bzl_command)),
-                input_root_digest: Some(Digest::for_message(&bzl_input_root)),
-                ..Default::default()
-            };
-            let action_digest = Digest::for_message(&action);
-            let (mut execution_result, output_files) = Self::exec_action(
-                &action_digest,
-                cache,
-                read_cache,
-                use_remote_cache,
-                &executor,
-                &output_paths,
-                sandbox,
-                cgroup,
-                &cwd,
-                &out_dir,
-            )
-            .await
+            let (mut execution_result, output_files) = if materialize_only {
+                Self::materialize_action(&action_digest, cache, use_remote_cache)
+                    .await
+                    .map(|x| x.unwrap_or_else(|| (Self::not_materialized_result(), vec![])))
+            } else if let Some(secs) = deadline_exceeded {
+                Self::deadline_exceeded_action(
+                    &action_digest,
+                    cache,
+                    read_cache,
+                    use_remote_cache,
+                    secs,
+                )
+                .await
+            } else {
+                Self::exec_action(
+                    &action_digest,
+                    cache,
+                    read_cache,
+                    use_remote_cache,
+                    &executor,
+                    &output_paths,
+                    &artifacts,
+                    atomic_outputs,
+                    preserve_outputs,
+                    sandbox,
+                    check_sandbox,
+                    cgroup,
+                    &cwd,
+                    &out_dir,
+                )
+                .await
+            }
             .unwrap_or_else(|e| {
                 (
                     ExecutionResult {
@@ -853,6 +2059,7 @@ impl Razel {
             tx.send((id, execution_result, output_files, output_files_cached))
                 .ok();
         });
+        self.running_tasks.insert(id, task.abort_handle());
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -862,31 +2069,58 @@ impl Razel {
         read_cache: bool,
         use_remote_cache: bool,
         executor: &Executor,
-        output_paths: &Vec<PathBuf>,
+        output_paths: &[PathBuf],
+        artifacts: &[(PathBuf, PathBuf)],
+        atomic_outputs: bool,
+        preserve_outputs: bool,
         sandbox: Option<BoxedSandbox>,
+        check_sandbox: Option<BoxedSandbox>,
         cgroup: Option<CGroup>,
         cwd: &Path,
-        out_dir: &PathBuf,
+        out_dir: &Path,
     ) -> Result<(ExecutionResult, Vec<OutputFile>), anyhow::Error> {
-        let (execution_result, output_files) = if let Some(x) =
-            Self::get_action_from_cache(action_digest, cache.as_mut(), read_cache, use_remote_cache)
-                .await
-        {
+        let cached = Self::get_action_from_cache(
+            action_digest,
+            cache.as_mut(),
+            read_cache,
+            use_remote_cache,
+        )
+        .await;
+        let remote_cache_miss = cached.as_ref().err().cloned().flatten();
+        let (mut execution_result, output_files) = if let Ok(x) = cached {
             x
         } else if let Some(sandbox) = sandbox {
-            Self::exec_action_with_sandbox(
-                action_digest,
-                cache.as_mut(),
-                use_remote_cache,
-                executor,
-                sandbox,
-                output_paths,
-                cgroup,
-                cwd,
-                out_dir,
-            )
-            .await
-            .context("exec_action_with_sandbox()")?
+            if let Some(check_sandbox) = check_sandbox {
+                Self::exec_action_with_determinism_check(
+                    action_digest,
+                    cache.as_mut(),
+                    use_remote_cache,
+                    executor,
+                    [sandbox, check_sandbox],
+                    output_paths,
+                    artifacts,
+                    cgroup,
+                    cwd,
+                    out_dir,
+                )
+                .await
+                .context("exec_action_with_determinism_check()")?
+            } else {
+                Self::exec_action_with_sandbox(
+                    action_digest,
+                    cache.as_mut(),
+                    use_remote_cache,
+                    executor,
+                    sandbox,
+                    output_paths,
+                    artifacts,
+                    cgroup,
+                    cwd,
+                    out_dir,
+                )
+                .await
+                .context("exec_action_with_sandbox()")?
+            }
         } else {
             Self::exec_action_without_sandbox(
                 action_digest,
@@ -894,6 +2128,9 @@ impl Razel {
                 use_remote_cache,
                 executor,
                 output_paths,
+                artifacts,
+                atomic_outputs,
+                preserve_outputs,
                 cgroup,
                 cwd,
                 out_dir,
@@ -901,6 +2138,7 @@ impl Razel {
             .await
             .context("exec_action_without_sandbox()")?
         };
+        execution_result.remote_cache_miss = remote_cache_miss;
         if let Some(cache) = cache.as_ref().filter(|_| execution_result.success()) {
             cache
                 .link_output_files_into_out_dir(&output_files)
@@ -910,37 +2148,99 @@ impl Razel {
         Ok((execution_result, output_files))
     }
 
-    async fn get_action_from_cache(
+    /// Link output files of a cached action into out_dir without executing it
+    async fn materialize_action(
         action_digest: &MessageDigest,
-        cache: Option<&mut Cache>,
-        read_cache: bool,
+        mut cache: Option<Cache>,
         use_remote_cache: bool,
-    ) -> Option<(ExecutionResult, Vec<OutputFile>)> {
-        let cache = cache.filter(|_| read_cache)?;
-        if let Some((action_result, cache_hit)) = cache
-            .get_action_result(action_digest, use_remote_cache)
+    ) -> Result<Option<(ExecutionResult, Vec<OutputFile>)>, anyhow::Error> {
+        let Ok((execution_result, output_files)) =
+            Self::get_action_from_cache(action_digest, cache.as_mut(), true, use_remote_cache)
+                .await
+        else {
+            return Ok(None);
+        };
+        cache
+            .unwrap()
+            .link_output_files_into_out_dir(&output_files)
             .await
-        {
-            let exit_code = Some(action_result.exit_code);
-            let metadata = action_result.execution_metadata.as_ref();
+            .context("link_output_files_into_out_dir()")?;
+        Ok(Some((execution_result, output_files)))
+    }
+
+    /// Link output files of a cached action into out_dir, the action is not executed because the
+    /// deadline of the command is exceeded
+    async fn deadline_exceeded_action(
+        action_digest: &MessageDigest,
+        mut cache: Option<Cache>,
+        read_cache: bool,
+        use_remote_cache: bool,
+        secs: u32,
+    ) -> Result<(ExecutionResult, Vec<OutputFile>), anyhow::Error> {
+        let Ok((execution_result, output_files)) = Self::get_action_from_cache(
+            action_digest,
+            cache.as_mut(),
+            read_cache,
+            use_remote_cache,
+        )
+        .await
+        else {
             let execution_result = ExecutionResult {
-                status: ExecutionStatus::Success,
-                exit_code,
-                signal: None,
-                error: None,
-                cache_hit: Some(cache_hit),
-                stdout: action_result.stdout_raw,
-                stderr: action_result.stderr_raw,
-                exec_duration: metadata
-                    .and_then(|x| x.virtual_execution_duration.as_ref())
-                    .map(|x| Duration::new(x.seconds as u64, x.nanos as u32)),
-                total_duration: None,
+                status: ExecutionStatus::Timeout,
+                error: Some(anyhow!("not started because deadline of {secs}s exceeded")),
+                ..Default::default()
             };
-            return Some((execution_result, action_result.output_files));
+            return Ok((execution_result, vec![]));
+        };
+        cache
+            .unwrap()
+            .link_output_files_into_out_dir(&output_files)
+            .await
+            .context("link_output_files_into_out_dir()")?;
+        Ok((execution_result, output_files))
+    }
+
+    fn not_materialized_result() -> ExecutionResult {
+        ExecutionResult {
+            status: ExecutionStatus::Skipped,
+            error: Some(anyhow!("not materialized: cache miss or missing input")),
+            ..Default::default()
         }
-        None
     }
 
+    /// Returns the cached result, otherwise why the remote cache missed if it was queried
+    async fn get_action_from_cache(
+        action_digest: &MessageDigest,
+        cache: Option<&mut Cache>,
+        read_cache: bool,
+        use_remote_cache: bool,
+    ) -> Result<(ExecutionResult, Vec<OutputFile>), Option<RemoteCacheMiss>> {
+        let cache = cache.filter(|_| read_cache).ok_or(None)?;
+        let (action_result, cache_hit) = cache
+            .get_action_result(action_digest, use_remote_cache)
+            .await?;
+        let exit_code = Some(action_result.exit_code);
+        let metadata = action_result.execution_metadata.as_ref();
+        let execution_result = ExecutionResult {
+            status: ExecutionStatus::Success,
+            exit_code,
+            signal: None,
+            error: None,
+            cache_hit: Some(cache_hit),
+            stdout: action_result.stdout_raw,
+            stderr: action_result.stderr_raw,
+            exec_duration: metadata
+                .and_then(|x| x.virtual_execution_duration.as_ref())
+                .map(|x| Duration::new(x.seconds as u64, x.nanos as u32)),
+            total_duration: None,
+            peak_memory: None,
+            read_files: vec![],
+            remote_cache_miss: None,
+        };
+        Ok((execution_result, action_result.output_files))
+    }
+
+    /// Transient errors are marked to retry the command in a new sandbox, see on_command_finished()
     #[allow(clippy::too_many_arguments)]
     async fn exec_action_with_sandbox(
         action_digest: &MessageDigest,
@@ -948,20 +2248,45 @@ impl Razel {
         use_remote_cache: bool,
         executor: &Executor,
         sandbox: BoxedSandbox,
-        output_paths: &Vec<PathBuf>,
+        output_paths: &[PathBuf],
+        artifacts: &[(PathBuf, PathBuf)],
         cgroup: Option<CGroup>,
         cwd: &Path,
-        out_dir: &PathBuf,
+        out_dir: &Path,
     ) -> Result<(ExecutionResult, Vec<OutputFile>), anyhow::Error> {
         sandbox
             .create(output_paths)
             .await
+            .map_err(TransientSandboxError::mark)
             .context("Sandbox::create()")?;
-        let execution_result = executor
+        let mut execution_result = executor
             .exec(cwd, Some(sandbox.dir().clone()), cgroup)
             .await;
+        let mut output_paths = output_paths.to_vec();
+        if execution_result.success() {
+            match sandbox.output_glob_files(&output_paths).await {
+                Ok(x) => output_paths.extend(x),
+                Err(e) => {
+                    execution_result.status = ExecutionStatus::Failed;
+                    execution_result.error = Some(e);
+                }
+            }
+        }
+        if execution_result.success() {
+            let undeclared = sandbox
+                .undeclared_files(&output_paths)
+                .await
+                .context("Sandbox::undeclared_files()")?;
+            if !undeclared.is_empty() {
+                execution_result.status = ExecutionStatus::Failed;
+                execution_result.error = Some(anyhow!(
+                    "command created undeclared files (--strict-outputs): {}",
+                    undeclared.iter().map(|x| x.to_string_lossy()).join(", ")
+                ));
+            }
+        }
         let output_files = if execution_result.success() {
-            Self::new_output_files_with_digest(Some(sandbox.dir()), out_dir, output_paths).await?
+            Self::new_output_files_with_digest(Some(sandbox.dir()), out_dir, &output_paths).await?
         } else {
             Default::default()
         };
@@ -976,10 +2301,16 @@ impl Razel {
                     use_remote_cache,
                 )
                 .await
+                .map_err(TransientSandboxError::mark)
                 .with_context(|| "cache_action_result()")?;
             } else {
-                sandbox.move_output_files_into_out_dir(output_paths).await?;
+                sandbox
+                    .move_output_files_into_out_dir(&output_paths)
+                    .await
+                    .map_err(TransientSandboxError::mark)?;
             }
+        } else {
+            copy_existing_artifacts(Some(sandbox.dir()), artifacts);
         }
         sandbox
             .destroy()
@@ -988,22 +2319,148 @@ impl Razel {
         Ok((execution_result, output_files))
     }
 
+    /// Executes the action in two sandboxes and only caches the result if the outputs are equal
+    #[allow(clippy::too_many_arguments)]
+    async fn exec_action_with_determinism_check(
+        action_digest: &MessageDigest,
+        cache: Option<&mut Cache>,
+        use_remote_cache: bool,
+        executor: &Executor,
+        sandboxes: [BoxedSandbox; 2],
+        output_paths: &[PathBuf],
+        artifacts: &[(PathBuf, PathBuf)],
+        cgroup: Option<CGroup>,
+        cwd: &Path,
+        out_dir: &Path,
+    ) -> Result<(ExecutionResult, Vec<OutputFile>), anyhow::Error> {
+        let [sandbox, check_sandbox] = sandboxes;
+        let (mut execution_result, output_files) = Self::exec_action_with_sandbox(
+            action_digest,
+            None,
+            false,
+            executor,
+            sandbox,
+            output_paths,
+            artifacts,
+            cgroup.clone(),
+            cwd,
+            out_dir,
+        )
+        .await?;
+        if !execution_result.success() {
+            return Ok((execution_result, output_files));
+        }
+        let (check_result, check_output_files) = Self::exec_action_with_sandbox(
+            action_digest,
+            None,
+            false,
+            executor,
+            check_sandbox,
+            output_paths,
+            artifacts,
+            cgroup,
+            cwd,
+            out_dir,
+        )
+        .await?;
+        if !check_result.success() {
+            return Ok((check_result, check_output_files));
+        }
+        // files matched by output globs might differ as well
+        let differing_paths = output_files
+            .iter()
+            .zip_longest(check_output_files.iter())
+            .filter_map(|x| match x {
+                EitherOrBoth::Both(a, b) if a.path == b.path && a.digest == b.digest => None,
+                EitherOrBoth::Both(a, _) | EitherOrBoth::Left(a) => Some(a.path.as_str()),
+                EitherOrBoth::Right(b) => Some(b.path.as_str()),
+            })
+            .collect_vec();
+        if !differing_paths.is_empty() {
+            execution_result.status = ExecutionStatus::Nondeterministic;
+            execution_result.error = Some(anyhow!(
+                "outputs differ between two executions: {}",
+                differing_paths.join(", ")
+            ));
+            return Ok((execution_result, Default::default()));
+        }
+        if let Some(cache) = cache {
+            // output files have been moved into out_dir
+            Self::cache_action_result(
+                action_digest,
+                &execution_result,
+                output_files.clone(),
+                None,
+                cache,
+                use_remote_cache,
+            )
+            .await
+            .with_context(|| "cache_action_result()")?;
+        }
+        Ok((execution_result, output_files))
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn exec_action_without_sandbox(
         action_digest: &MessageDigest,
         cache: Option<&mut Cache>,
         use_remote_cache: bool,
         executor: &Executor,
-        output_paths: &Vec<PathBuf>,
+        output_paths: &[PathBuf],
+        artifacts: &[(PathBuf, PathBuf)],
+        atomic_outputs: bool,
+        preserve_outputs: bool,
         cgroup: Option<CGroup>,
         cwd: &Path,
-        out_dir: &PathBuf,
+        out_dir: &Path,
     ) -> Result<(ExecutionResult, Vec<OutputFile>), anyhow::Error> {
         // remove expected output files, because symlinks will not be overwritten
-        for x in output_paths {
+        // preserved outputs are only removed if linked to the cache to not modify it
+        for x in output_paths
+            .iter()
+            .filter(|x| !preserve_outputs || x.is_symlink())
+        {
             force_remove_file(x).await?;
         }
-        let execution_result = executor.exec(cwd, None, cgroup).await;
+        let redirected = atomic_outputs
+            .then(|| Self::redirect_output_files_to_tmp(executor, output_paths))
+            .flatten();
+        let execution_result = match &redirected {
+            Some((executor, renames)) => {
+                for (tmp_path, _) in renames {
+                    force_remove_file(tmp_path).await?;
+                }
+                let execution_result = executor.exec(cwd, None, cgroup).await;
+                if !execution_result.success() {
+                    // outputs are only in the tmp files which are removed below
+                    let artifacts = artifacts
+                        .iter()
+                        .map(|(path, dst)| {
+                            let tmp_path = renames.iter().find(|(_, x)| x == path).map(|x| &x.0);
+                            (tmp_path.unwrap_or(path).clone(), dst.clone())
+                        })
+                        .collect_vec();
+                    copy_existing_artifacts(None, &artifacts);
+                }
+                for (tmp_path, path) in renames {
+                    if execution_result.success() {
+                        tokio::fs::rename(tmp_path, path)
+                            .await
+                            .with_context(|| format!("rename {tmp_path:?} -> {path:?}"))?;
+                    } else {
+                        force_remove_file(tmp_path).await.ok();
+                    }
+                }
+                execution_result
+            }
+            None => {
+                let execution_result = executor.exec(cwd, None, cgroup).await;
+                if !execution_result.success() {
+                    copy_existing_artifacts(None, artifacts);
+                }
+                execution_result
+            }
+        };
         let output_files = if execution_result.success() {
             Self::new_output_files_with_digest(None, out_dir, output_paths).await?
         } else {
@@ -1024,19 +2481,53 @@ impl Razel {
         Ok((execution_result, output_files))
     }
 
+    /// Redirects declared outputs passed as args or stdout/stderr files to tmp files in the same dir.
+    ///
+    /// Returns the executor to use and the paths to rename after success, see --atomic-outputs.
+    fn redirect_output_files_to_tmp(
+        executor: &Executor,
+        output_paths: &[PathBuf],
+    ) -> Option<(Executor, Vec<(PathBuf, PathBuf)>)> {
+        let Executor::CustomCommand(executor) = executor else {
+            // tasks write their outputs themselves
+            return None;
+        };
+        let mut executor = executor.clone();
+        let renames = output_paths
+            .iter()
+            .filter_map(|path| {
+                let file_name = path.file_name()?.to_str()?;
+                // keep the extension, some tools select the file format by it
+                let tmp_path = path.with_file_name(format!(".razel-tmp-{file_name}"));
+                executor
+                    .redirect_output_file(path, &tmp_path)
+                    .then_some((tmp_path, path.clone()))
+            })
+            .collect();
+        Some((Executor::CustomCommand(executor), renames))
+    }
+
+    /// Digests output files concurrently, the order of the returned files matches output_paths
     async fn new_output_files_with_digest(
         sandbox_dir: Option<&PathBuf>,
-        out_dir: &PathBuf,
-        output_paths: &Vec<PathBuf>,
+        out_dir: &Path,
+        output_paths: &[PathBuf],
     ) -> Result<Vec<OutputFile>, anyhow::Error> {
-        let mut output_files: Vec<OutputFile> = Vec::with_capacity(output_paths.len());
-        for path in output_paths {
-            let output_file = Self::new_output_file_with_digest(sandbox_dir, out_dir, path)
-                .await
-                .context("Handle expected output file")?;
-            output_files.push(output_file);
-        }
-        Ok(output_files)
+        let concurrent = num_cpus::get();
+        let tasks = output_paths
+            .iter()
+            .map(|x| (sandbox_dir.cloned(), out_dir.to_path_buf(), x.clone()))
+            .collect_vec();
+        futures_util::stream::iter(tasks)
+            .map(|(sandbox_dir, out_dir, path)| {
+                tokio::spawn(async move {
+                    Self::new_output_file_with_digest(sandbox_dir.as_ref(), &out_dir, &path).await
+                })
+            })
+            .buffered(concurrent)
+            .map(|x| x?.context("Handle expected output file"))
+            .try_collect()
+            .await
     }
 
     async fn new_output_file_with_digest(
@@ -1056,6 +2547,9 @@ impl Razel {
         let is_executable = is_file_executable(&file)
             .await
             .with_context(|| format!("is_file_executable(): {src:?}"))?;
+        let unix_mode = file_unix_mode(&file)
+            .await
+            .with_context(|| format!("file_unix_mode(): {src:?}"))?;
         let digest = Digest::for_file(file)
             .await
             .with_context(|| format!("Digest::for_file(): {src:?}"))?;
@@ -1068,7 +2562,10 @@ impl Razel {
             digest: Some(digest),
             is_executable,
             contents: vec![],
-            node_properties: None,
+            node_properties: unix_mode.map(|x| NodeProperties {
+                unix_mode: Some(x),
+                ..Default::default()
+            }),
         })
     }
 
@@ -1081,27 +2578,34 @@ impl Razel {
         use_remote_cache: bool,
     ) -> Result<Vec<OutputFile>, anyhow::Error> {
         assert!(execution_result.success());
-        let mut action_result = ActionResult {
-            output_files,
-            exit_code: execution_result.exit_code.unwrap_or_default(),
-            execution_metadata: Some(ExecutedActionMetadata {
-                virtual_execution_duration: execution_result.exec_duration.map(|x| {
-                    prost_types::Duration {
-                        seconds: x.as_secs() as i64,
+        let action_result = Self::new_action_result(execution_result, output_files);
+        cache
+            .push(action_digest, &action_result, sandbox_dir, use_remote_cache)
+            .await?;
+        Ok(action_result.output_files)
+    }
+
+    fn new_action_result(
+        execution_result: &ExecutionResult,
+        output_files: Vec<OutputFile>,
+    ) -> ActionResult {
+        ActionResult {
+            output_files,
+            exit_code: execution_result.exit_code.unwrap_or_default(),
+            execution_metadata: Some(ExecutedActionMetadata {
+                virtual_execution_duration: execution_result.exec_duration.map(|x| {
+                    prost_types::Duration {
+                        seconds: x.as_secs() as i64,
                         nanos: x.subsec_nanos() as i32,
                     }
                 }),
                 ..Default::default()
             }),
+            // TODO add stdout/stderr files for non-small outputs
+            stdout_raw: execution_result.stdout.clone(),
+            stderr_raw: execution_result.stderr.clone(),
             ..Default::default()
-        };
-        // TODO add stdout/stderr files for non-small outputs
-        action_result.stdout_raw = execution_result.stdout.clone();
-        action_result.stderr_raw = execution_result.stderr.clone();
-        cache
-            .push(action_digest, &action_result, sandbox_dir, use_remote_cache)
-            .await?;
-        Ok(action_result.output_files)
+        }
     }
 
     fn on_command_finished(
@@ -1111,38 +2615,120 @@ impl Razel {
         output_files: Vec<OutputFile>,
         output_files_cached: bool,
     ) {
-        let retry = self.scheduler.set_finished_and_get_retry_flag(
+        let mut retry = self.scheduler.set_finished_and_get_retry_flag(
             &self.commands[id],
             execution_result.out_of_memory_killed(),
         );
+        if !retry
+            && execution_result.is_transient_system_error()
+            && self.retried_after_system_error.insert(id)
+        {
+            warn!(
+                "{}: recreating sandbox to retry after transient error: {:?}",
+                self.commands[id].name,
+                execution_result.error.as_ref().unwrap()
+            );
+            self.remove_sandbox_leftovers(id);
+            self.scheduler.push_ready(&self.commands[id]);
+            retry = true;
+        }
         if retry {
-            self.on_command_retry(id, execution_result);
+            self.report_target_finished(id, execution_result, true);
         } else {
-            let measurements = self
-                .measurements
-                .collect(&self.commands[id].name, execution_result);
-            self.profile.collect(&self.commands[id], execution_result);
-            let output_size = output_files
+            let output_files_size = output_files
                 .iter()
                 .map(|x| x.digest.as_ref().unwrap().size_bytes as u64)
-                .sum::<u64>()
+                .sum::<u64>();
+            let measurements = self.measurements.collect(
+                &self.commands[id].name,
+                execution_result,
+                output_files_size,
+            );
+            self.profile.collect(&self.commands[id], execution_result);
+            let output_size = output_files_size
                 + execution_result.stdout.len() as u64
                 + execution_result.stderr.len() as u64;
+            let undeclared_inputs =
+                self.undeclared_inputs(&self.commands[id], &execution_result.read_files);
+            for path in &undeclared_inputs {
+                warn!("{}: undeclared input {path:?}", self.commands[id].name);
+            }
+            let remote_cache = self.explain_remote_cache(execution_result);
+            if let Some(x) = &remote_cache {
+                info!("{}: remote cache {x}", self.commands[id].name);
+            }
             self.log_file.push(
                 &self.commands[id],
                 execution_result,
                 Some(output_size),
                 measurements,
+                undeclared_inputs,
+                remote_cache,
             );
             if execution_result.success() {
+                self.log_remote_cache_decision(id, execution_result, &output_files, output_size);
                 self.set_output_file_digests(output_files, output_files_cached);
+                self.copy_artifacts(id);
                 self.on_command_succeeded(id, execution_result);
+            } else if self.materialize_only && execution_result.status == ExecutionStatus::Skipped {
+                self.on_command_not_materialized(id);
             } else if self.commands[id].tags.contains(&Tag::Condition) {
-                self.on_condition_failed(id, execution_result);
+                self.on_condition_failed(id);
             } else {
-                self.on_command_failed(id, execution_result);
+                self.on_command_failed(id);
             }
+            self.report_target_finished(id, execution_result, false);
             self.tui_dirty = true;
+            if !self.tui.is_tty() && self.is_progress_step() {
+                self.update_status();
+            }
+        }
+    }
+
+    /// Without terminal, status lines are also printed after each step of finished commands
+    fn is_progress_step(&self) -> bool {
+        let total = self.commands.len() - self.excluded_commands_len;
+        let lines = config::UI_PROGRESS_LINES_NON_TTY;
+        let step = ((total + lines - 1) / lines).max(1);
+        let finished = self.succeeded.len() + self.failed.len();
+        finished % step == 0
+    }
+
+    /// Returns files within the workspace which were read by the command but are not declared.
+    ///
+    /// read_files are relative to the working dir of the command, other paths are absolute.
+    fn undeclared_inputs(&self, command: &Command, read_files: &[PathBuf]) -> Vec<PathBuf> {
+        if read_files.is_empty() {
+            return vec![];
+        }
+        let declared = chain!(
+            &command.executables,
+            &command.inputs,
+            &command.config_inputs,
+            &command.outputs
+        )
+        .map(|x| self.files[*x].path.as_path())
+        .collect::<HashSet<_>>();
+        read_files
+            .iter()
+            .map(|x| x.strip_prefix(&self.current_dir).unwrap_or(x))
+            .filter(|x| x.is_relative() && !declared.contains(x))
+            .filter(|x| self.current_dir.join(x).is_file())
+            .map(|x| x.to_path_buf())
+            .collect()
+    }
+
+    fn report_target_finished(
+        &mut self,
+        id: CommandId,
+        execution_result: &ExecutionResult,
+        will_retry: bool,
+    ) {
+        let command = &self.commands[id];
+        self.tui
+            .on_target_finished(command, execution_result, will_retry);
+        for reporter in &mut self.reporters {
+            reporter.on_target_finished(command, execution_result, will_retry);
         }
     }
 
@@ -1154,7 +2740,11 @@ impl Razel {
         for output_file in output_files {
             assert!(output_file.digest.is_some());
             let path = PathBuf::from(output_file.path);
-            let file = &mut self.files[self.path_to_file_id[&path]];
+            let Some(id) = self.path_to_file_id.get(&path) else {
+                // matched by an output glob, not known as file
+                continue;
+            };
+            let file = &mut self.files[*id];
             assert!(file.digest.is_none());
             file.digest = output_file.digest;
             if output_files_cached {
@@ -1163,6 +2753,113 @@ impl Razel {
         }
     }
 
+    /// Seconds of the razel:deadline tag and the resulting point in time
+    fn deadline_of_command(&self, command: &Command) -> Option<(u32, Instant)> {
+        let secs = command.tags.iter().find_map(|x| {
+            if let Tag::Deadline(x) = x {
+                Some(*x)
+            } else {
+                None
+            }
+        })?;
+        Some((secs, self.run_start? + Duration::from_secs(secs.into())))
+    }
+
+    fn remote_cache_threshold_tag(command: &Command) -> Option<u32> {
+        command.tags.iter().find_map(|x| {
+            if let Tag::RemoteCacheThreshold(x) = x {
+                Some(*x)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Checks if the remote cache should be neither queried nor updated because of large inputs
+    fn is_input_size_above_remote_cache_max_size(&self, command: &Command) -> bool {
+        let Some(max_size) = self.remote_cache_max_size else {
+            return false;
+        };
+        let input_size = self.input_size(command);
+        if input_size <= max_size {
+            return false;
+        }
+        if self.cache.as_ref().is_some_and(|x| x.has_remote_cache()) {
+            info!(
+                "{}: skip remote cache: input size {input_size} B > max size {max_size} B",
+                command.name
+            );
+        }
+        true
+    }
+
+    /// Total size of inputs with known digests [byte]
+    ///
+    /// Executables are not counted, they are usually shared by many commands.
+    fn input_size(&self, command: &Command) -> u64 {
+        command
+            .inputs
+            .iter()
+            .filter_map(|x| self.files[*x].digest.as_ref())
+            .map(|x| x.size_bytes as u64)
+            .sum()
+    }
+
+    /// Explain if the remote cache was used for a command, only with --explain-remote-miss
+    fn explain_remote_cache(&self, execution_result: &ExecutionResult) -> Option<String> {
+        if !self.explain_remote_miss {
+            return None;
+        }
+        match (
+            execution_result.cache_hit,
+            &execution_result.remote_cache_miss,
+        ) {
+            (Some(CacheHit::Remote | CacheHit::Mixed), _) => Some("hit".into()),
+            (_, Some(miss)) => Some(format!("miss: {miss}")),
+            _ => None,
+        }
+    }
+
+    /// Explain if the output files of an executed command are uploaded to the remote cache
+    fn log_remote_cache_decision(
+        &self,
+        id: CommandId,
+        execution_result: &ExecutionResult,
+        output_files: &[OutputFile],
+        output_size: u64,
+    ) {
+        let command = &self.commands[id];
+        let Some(cache) = self.cache.as_ref().filter(|x| x.has_remote_cache()) else {
+            return;
+        };
+        if execution_result.cache_hit.is_some()
+            || command.tags.contains(&Tag::NoCache)
+            || command.tags.contains(&Tag::NoRemoteCache)
+        {
+            return;
+        }
+        if let Some(max_size) = cache.remote_cache_max_size().filter(|x| output_size > *x) {
+            info!(
+                "{}: skip remote cache upload: output size {output_size} B > max size {max_size} B",
+                command.name
+            );
+            return;
+        }
+        let Some(threshold) =
+            Self::remote_cache_threshold_tag(command).or(cache.remote_cache_threshold())
+        else {
+            return;
+        };
+        // same action result as pushed to the cache, see cache_action_result()
+        let action_result = Self::new_action_result(execution_result, output_files.to_vec());
+        let decision = RemoteCacheDecision::for_action_result(Some(threshold), &action_result);
+        if decision.upload() {
+            info!("{}: upload to remote cache: {decision}", command.name);
+        } else {
+            info!("{}: skip remote cache upload: {decision}", command.name);
+        }
+    }
+
     /// Track state and check if reverse dependencies are ready
     fn on_command_succeeded(&mut self, id: CommandId, execution_result: &ExecutionResult) {
         self.succeeded.push(id);
@@ -1171,36 +2868,51 @@ impl Razel {
         }
         let command = &mut self.commands[id];
         command.schedule_state = ScheduleState::Succeeded;
-        self.tui.command_succeeded(command, execution_result);
         for rdep_id in command.reverse_deps.clone() {
             let rdep = &mut self.commands[rdep_id];
             assert!(!rdep.unfinished_deps.is_empty());
             rdep.unfinished_deps
                 .swap_remove(rdep.unfinished_deps.iter().position(|x| *x == id).unwrap());
-            if rdep.unfinished_deps.is_empty() {
+            if rdep.schedule_state == ScheduleState::Skipped {
+                // skipped because of a failed condition or phase
+                continue;
+            }
+            if rdep.unfinished_deps.is_empty()
+                && !self.phases.as_mut().is_some_and(|x| x.block(rdep))
+            {
                 assert_eq!(rdep.schedule_state, ScheduleState::Waiting);
                 rdep.schedule_state = ScheduleState::Ready;
                 self.waiting.remove(&rdep_id);
                 self.scheduler.push_ready(rdep);
             }
         }
+        self.on_phase_command_finished(id);
     }
 
-    fn on_command_retry(&mut self, id: CommandId, execution_result: &ExecutionResult) {
-        let command = &self.commands[id];
-        self.tui.command_retry(command, execution_result);
+    fn on_command_failed(&mut self, id: CommandId) {
+        self.commands[id].schedule_state = ScheduleState::Failed;
+        self.failed.push(id);
+        self.on_phase_command_failed(id);
     }
 
-    fn on_command_failed(&mut self, id: CommandId, execution_result: &ExecutionResult) {
-        self.failed.push(id);
-        let command = &self.commands[id];
-        self.tui.command_failed(command, execution_result);
+    fn on_command_not_materialized(&mut self, id: CommandId) {
+        let command = &mut self.commands[id];
+        warn!("not materialized: {}", command.name);
+        command.schedule_state = ScheduleState::Skipped;
+        self.skipped.push(id);
+        self.skip_reverse_deps(id);
+        self.on_phase_command_finished(id);
     }
 
-    fn on_condition_failed(&mut self, id: CommandId, execution_result: &ExecutionResult) {
-        let command = &self.commands[id];
-        self.tui.command_failed(command, execution_result);
-        let mut ids_to_skip = command.reverse_deps.clone();
+    /// A failed condition does not fail the run, therefore later phases are not skipped
+    fn on_condition_failed(&mut self, id: CommandId) {
+        self.commands[id].schedule_state = ScheduleState::Failed;
+        self.skip_reverse_deps(id);
+        self.on_phase_command_finished(id);
+    }
+
+    fn skip_reverse_deps(&mut self, id: CommandId) {
+        let mut ids_to_skip = self.commands[id].reverse_deps.clone();
         while let Some(id_to_skip) = ids_to_skip.pop() {
             let to_skip = &mut self.commands[id_to_skip];
             if to_skip.schedule_state == ScheduleState::Skipped {
@@ -1213,7 +2925,38 @@ impl Razel {
                 .push_not_run(to_skip, ExecutionStatus::Skipped);
             self.waiting.remove(&id_to_skip);
             self.skipped.push(id_to_skip);
+            self.skipped_by.insert(id_to_skip, id);
             ids_to_skip.extend(to_skip.reverse_deps.iter());
+            self.on_phase_command_finished(id_to_skip);
+        }
+    }
+
+    /// Returns the digest used as cache key, tasks are identified by their args
+    fn action_digest(&self, command: &Command) -> MessageDigest {
+        let (bzl_command, bzl_input_root) = self.get_bzl_action_for_command(command);
+        let action = bazel_remote_exec::Action {
+            command_digest: Some(Digest::for_message(&bzl_command)),
+            input_root_digest: Some(Digest::for_message(&bzl_input_root)),
+            salt: self.cache_salt(command),
+            ..Default::default()
+        };
+        Digest::for_message(&action)
+    }
+
+    /// Combines `cache_salt` and the tag of the command, empty if none is set to keep cache keys
+    fn cache_salt(&self, command: &Command) -> Vec<u8> {
+        let tag = command.tags.iter().find_map(|x| match x {
+            Tag::CacheSalt(x) => Some(x.as_str()),
+            _ => None,
+        });
+        match (self.cache_salt.as_deref(), tag) {
+            (None, None) => vec![],
+            (global, tag) => format!(
+                "{}\0{}",
+                global.unwrap_or_default(),
+                tag.unwrap_or_default()
+            )
+            .into_bytes(),
         }
     }
 
@@ -1238,6 +2981,7 @@ impl Razel {
                 .outputs
                 .iter()
                 .map(|x| self.files[*x].path.to_str().unwrap())
+                .chain(command.output_globs.iter().map(|x| x.pattern.as_str()))
                 .sorted_unstable()
                 .dedup()
                 .map_into()
@@ -1247,18 +2991,18 @@ impl Razel {
         };
         // TODO properly build bazel_remote_exec::Directory tree
         let bzl_input_root = bazel_remote_exec::Directory {
-            files: chain(command.executables.iter(), command.inputs.iter())
+            files: self
+                .input_root_entries(command)
+                .into_iter()
                 .map(|x| {
-                    let file = &self.files[*x];
-                    assert!(file.digest.is_some(), "digest missing for {:?}", file.path);
+                    assert!(x.digest.is_some(), "digest missing for {:?}", x.path);
                     bazel_remote_exec::FileNode {
-                        name: file.path.to_str().unwrap().into(),
-                        digest: file.digest.clone(),
+                        name: x.name.into(),
+                        digest: x.digest.cloned(),
                         is_executable: false, // TODO bazel_remote_exec::FileNode::is_executable
                         node_properties: None,
                     }
                 })
-                .sorted_unstable_by(|a, b| Ord::cmp(&a.name, &b.name))
                 .collect(),
             directories: vec![],
             symlinks: vec![],
@@ -1267,6 +3011,37 @@ impl Razel {
         (bzl_command, bzl_input_root)
     }
 
+    /// Returns the files of the input root of a command sorted by name
+    fn input_root_entries<'a>(&'a self, command: &'a Command) -> Vec<InputRootEntry<'a>> {
+        chain(command.executables.iter(), command.inputs.iter())
+            .filter(|x| !command.entry_only_archives.contains(x))
+            .map(|x| {
+                let file = &self.files[*x];
+                // path of the razel executable differs between installations
+                let name = if file.file_type == FileType::RazelExecutable
+                    && self.self_digest_mode == SelfDigestMode::Version
+                {
+                    config::EXECUTABLE
+                } else {
+                    file.path.to_str().unwrap()
+                };
+                InputRootEntry {
+                    name,
+                    path: &file.path,
+                    file_type: Some(&file.file_type),
+                    digest: file.digest.as_ref(),
+                }
+            })
+            .chain(command.archive_entries.iter().map(|x| InputRootEntry {
+                name: x.path.to_str().unwrap(),
+                path: &x.path,
+                file_type: None,
+                digest: x.digest.as_ref(),
+            }))
+            .sorted_unstable_by(|a, b| Ord::cmp(&a.name, &b.name))
+            .collect()
+    }
+
     fn push_logs_for_not_started_commands(&mut self) {
         assert_eq!(self.scheduler.running(), 0);
         for id in self.waiting.iter().chain(self.scheduler.ready_ids().iter()) {
@@ -1275,7 +3050,11 @@ impl Razel {
         }
     }
 
-    fn write_metadata(&self, group_by_tag: &str) -> Result<(), anyhow::Error> {
+    fn write_metadata(
+        &self,
+        group_by_tag: &str,
+        memory_profile: Option<&MemoryProfile>,
+    ) -> Result<(), anyhow::Error> {
         let dir = self.out_dir.join("razel-metadata");
         fs::create_dir_all(&dir)
             .with_context(|| format!("Failed to create metadata directory: {dir:?}"))?;
@@ -1288,9 +3067,18 @@ impl Razel {
         self.measurements.write_csv(&dir.join("measurements.csv"))?;
         self.profile.write_json(&dir.join("execution_times.json"))?;
         self.log_file.write(&dir.join("log.json"))?;
-        let report = Report::new(group_by_tag, &self.log_file.items);
+        let mut report = Report::new(group_by_tag, &self.log_file.items);
+        report.deadline_exceeded = self.deadline_exceeded;
+        if let Some(x) = memory_profile {
+            x.write_json(&dir.join("memory_profile.json"))?;
+            info!("peak memory of razel: {}MiB", x.peak_rss / 1024 / 1024);
+            report.peak_rss = Some(x.peak_rss);
+        }
         report.print();
         report.write(&dir.join("report.json"))?;
+        if let Some(keep) = self.keep_metadata_runs {
+            archive_run(&dir, keep)?;
+        }
         Ok(())
     }
 }
@@ -1301,16 +3089,128 @@ impl Default for Razel {
     }
 }
 
+mod artifacts;
+mod cache_archive;
+mod clean;
+mod diagnose;
+mod discover_includes;
+mod explain_inputs;
+mod explain_skips;
 mod filter;
+mod fingerprint;
+mod hermetic_path;
+mod implicit_deps;
 mod import;
+mod input_manifest;
+mod output_lock;
+mod phases;
+mod schedule;
+mod seed_from_remote;
+mod shell_script;
 mod system;
+mod system_executables;
+
+use artifacts::copy_existing_artifacts;
+pub use clean::CleanTargets;
 
 #[cfg(test)]
 mod tests {
     use approx::assert_abs_diff_eq;
     use serial_test::serial;
 
-    use crate::{Razel, SchedulerExecStats};
+    use crate::bazel_remote_exec::action_cache_server::ActionCache;
+    use crate::bazel_remote_exec::capabilities_server::Capabilities;
+    use crate::bazel_remote_exec::content_addressable_storage_server::ContentAddressableStorage;
+    use crate::bazel_remote_exec::{
+        batch_read_blobs_response, digest_function, ActionCacheUpdateCapabilities, ActionResult,
+        BatchReadBlobsRequest, BatchReadBlobsResponse, BatchUpdateBlobsRequest,
+        BatchUpdateBlobsResponse, CacheCapabilities, FindMissingBlobsRequest,
+        FindMissingBlobsResponse, GetActionResultRequest, GetCapabilitiesRequest, GetTreeRequest,
+        GetTreeResponse, ServerCapabilities, UpdateActionResultRequest,
+    };
+    use crate::config::LinkType;
+    use crate::executors::{ExecutionResult, ExecutionStatus};
+    use crate::metadata::{LogFile, LogFileItem, MemoryProfile, Report, Tag, RUNS_DIR};
+    use crate::tui::{A_RESET, C_RESET, TUI};
+    use crate::{
+        new_tmp_dir, CacheHit, Command, CustomCommandSpec, OutputGlob, Razel, Reporter,
+        SchedulerExecStats, SchedulerStats, SelfDigestMode, TmpDirSandbox,
+    };
+    use anyhow::Context;
+    use itertools::{chain, Itertools};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tonic::{Request, Response, Status};
+
+    async fn run_with_check_determinism(executable: &str, args: Vec<&str>) -> SchedulerExecStats {
+        let mut razel = Razel::new();
+        razel.read_cache = false;
+        razel.check_determinism = true;
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "check-determinism".into(),
+                executable: executable.into(),
+                args: args.into_iter().map(|x| x.into()).collect(),
+                stdout: Some("check-determinism.txt".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap()
+            .exec
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn check_determinism_fixed_content() {
+        let stats = run_with_check_determinism("cmake", vec!["-E", "echo", "fixed"]).await;
+        assert_eq!(
+            stats,
+            SchedulerExecStats {
+                succeeded: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn check_determinism_timestamp() {
+        let stats = run_with_check_determinism("sh", vec!["-c", "date +%s%N; echo $$"]).await;
+        assert_eq!(
+            stats,
+            SchedulerExecStats {
+                failed: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn check_determinism_skips_commands_without_sandbox() {
+        let mut razel = Razel::new();
+        let mut push = |name: &str, tags| {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "true".into()],
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        push("sandboxed", vec![]);
+        let unchecked = push("no-sandbox", vec![Tag::NoSandbox]);
+        assert_eq!(razel.commands_without_determinism_check(), [unchecked]);
+    }
 
     /// Test that commands are actually run in parallel limited by Scheduler::worker_threads
     #[tokio::test]
@@ -1323,18 +3223,12 @@ mod tests {
         let sleep_duration = 0.5;
         for i in 0..n {
             razel
-                .push_custom_command(
-                    format!("{i}"),
-                    "cmake".into(),
-                    vec!["-E".into(), "sleep".into(), sleep_duration.to_string()],
-                    Default::default(),
-                    vec![],
-                    vec![],
-                    None,
-                    None,
-                    vec![],
-                    vec![],
-                )
+                .push_custom_command(CustomCommandSpec {
+                    name: format!("{i}"),
+                    executable: "cmake".into(),
+                    args: vec!["-E".into(), "sleep".into(), sleep_duration.to_string()],
+                    ..Default::default()
+                })
                 .unwrap();
         }
         let stats = razel
@@ -1354,4 +3248,2450 @@ mod tests {
             epsilon = sleep_duration * 0.5
         );
     }
+
+    fn push_cmake(razel: &mut Razel, name: &str, args: &[&str], inputs: &[&str], output: &str) {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: name.into(),
+                executable: "cmake".into(),
+                args: args.iter().map(|x| x.to_string()).collect(),
+                inputs: inputs.iter().map(|x| x.to_string()).collect(),
+                outputs: vec![output.into()],
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn new_output_files_with_digest() {
+        let dir = new_tmp_dir!();
+        let out_dir = PathBuf::from("razel-out");
+        let paths = (0..8u8)
+            .map(|i| {
+                let path = dir.join(&format!("output-{i}.bin"));
+                std::fs::write(&path, vec![i; 4 * 1024 * 1024]).unwrap();
+                path
+            })
+            .collect_vec();
+        let start = Instant::now();
+        let mut sequential = vec![];
+        for path in &paths {
+            sequential.push(
+                Razel::new_output_file_with_digest(None, &out_dir, path)
+                    .await
+                    .unwrap(),
+            );
+        }
+        let sequential_duration = start.elapsed();
+        let start = Instant::now();
+        let concurrent = Razel::new_output_files_with_digest(None, &out_dir, &paths)
+            .await
+            .unwrap();
+        let concurrent_duration = start.elapsed();
+        println!(
+            "sequential: {sequential_duration:?}, concurrent: {concurrent_duration:?}, speedup: {:.1}",
+            sequential_duration.as_secs_f32() / concurrent_duration.as_secs_f32()
+        );
+        assert_eq!(concurrent, sequential);
+        assert_eq!(
+            concurrent.iter().map(|x| &x.path).collect_vec(),
+            paths.iter().map(|x| x.to_str().unwrap()).collect_vec()
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn mixed_case_paths_are_one_file() {
+        let mut razel = Razel::new();
+        let id = razel.input_file("Src/Foo.c".into()).unwrap().id;
+        for arg in ["src/foo.c", "SRC\\FOO.C", "src\\Foo.c"] {
+            assert_eq!(razel.input_file(arg.into()).unwrap().id, id, "{arg}");
+        }
+        // the first reference is kept for display and file system operations
+        assert_eq!(razel.files[id].arg, "Src/Foo.c");
+        assert!(razel
+            .output_file(&"src/FOO.c".into(), FileType::OutputFile)
+            .is_err());
+    }
+
+    #[test]
+    fn list_commands_json() {
+        let dir = new_tmp_dir!();
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            &[
+                r#"{"name":"a","executable":"cmake","args":["-E","copy","input.txt","a.txt"],"inputs":["input.txt"],"outputs":["a.txt"]}"#,
+                r#"{"name":"b","executable":"cmake","args":["-E","echo","a.txt"],"inputs":["a.txt"],"stdout":"b.txt","deps":["a"],"tags":["razel:quiet"]}"#,
+            ]
+            .join("\n"),
+        );
+        let mut razel = Razel::new();
+        crate::parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+        let commands = razel
+            .commands_in_execution_order()
+            .into_iter()
+            .map(|x| serde_json::to_value(razel.resolved_json_command(x)).unwrap())
+            .collect::<Vec<_>>();
+        let src = dir.dir().to_str().unwrap();
+        let out = Path::new("razel-out").join(src);
+        let out = out.to_str().unwrap();
+        let cmake = which::which("cmake").unwrap();
+        assert_eq!(
+            commands,
+            [
+                serde_json::json!({
+                    "name": "a",
+                    "executable": cmake,
+                    "args": ["-E", "copy", format!("{src}/input.txt"), format!("{out}/a.txt")],
+                    "inputs": [format!("{src}/input.txt")],
+                    "outputs": [format!("{out}/a.txt")],
+                }),
+                serde_json::json!({
+                    "name": "b",
+                    "executable": cmake,
+                    "args": ["-E", "echo", format!("{out}/a.txt")],
+                    "inputs": [format!("{out}/a.txt")],
+                    "outputs": [],
+                    "stdout": format!("{out}/b.txt"),
+                    "deps": ["a"],
+                    "tags": ["razel:quiet"],
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn materialize_only() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let push_commands = |razel: &mut Razel| {
+            push_cmake(razel, "a", &["-E", "touch", "mat-a.txt"], &[], "mat-a.txt");
+            push_cmake(
+                razel,
+                "b",
+                &["-E", "copy", "mat-a.txt", "mat-b.txt"],
+                &["mat-a.txt"],
+                "mat-b.txt",
+            );
+        };
+        // warm cache
+        {
+            let mut razel = Razel::new();
+            razel.clean();
+            push_commands(&mut razel);
+            let stats = razel
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 2);
+        }
+        // materialize into clean razel-out
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.materialize_only = true;
+        push_commands(&mut razel);
+        let input = dir.join_and_write_file("mat-new.txt", "new");
+        push_cmake(
+            &mut razel,
+            "not-cached",
+            &["-E", "copy", input.to_str().unwrap(), "mat-c.txt"],
+            &[input.to_str().unwrap()],
+            "mat-c.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "depends-on-not-cached",
+            &["-E", "copy", "mat-c.txt", "mat-d.txt"],
+            &["mat-c.txt"],
+            "mat-d.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "missing-input",
+            &["-E", "copy", "mat-missing.txt", "mat-e.txt"],
+            &["mat-missing.txt"],
+            "mat-e.txt",
+        );
+        let stats = razel
+            .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats.exec,
+            SchedulerExecStats {
+                succeeded: 2,
+                skipped: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats.cache_hits, 2);
+        assert!(Path::new("razel-out/mat-b.txt").exists());
+        assert!(!Path::new("razel-out/mat-c.txt").exists());
+    }
+
+    #[test]
+    fn list_outputs() {
+        let list = |format: crate::ListFormat| {
+            let mut razel = Razel::new();
+            push_cmake(&mut razel, "a", &["-E", "touch", "a.txt"], &[], "a.txt");
+            push_cmake(
+                &mut razel,
+                "b",
+                &["-E", "copy", "a.txt", "b.txt"],
+                &["a.txt"],
+                "b.txt",
+            );
+            push_cmake(&mut razel, "c", &["-E", "touch", "c.txt"], &[], "c.txt");
+            razel.filter_targets(&["b".into()]).unwrap();
+            let mut output = vec![];
+            razel.list_outputs(format, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            list(crate::ListFormat::Text),
+            "razel-out/a.txt\nrazel-out/b.txt\n"
+        );
+        assert_eq!(
+            list(crate::ListFormat::Json),
+            "{\"name\":\"a\",\"outputs\":[\"razel-out/a.txt\"]}\n\
+            {\"name\":\"b\",\"outputs\":[\"razel-out/b.txt\"]}\n"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingReporter {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Reporter for RecordingReporter {
+        fn on_target_started(&mut self, command: &Command) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("started {}", command.name));
+        }
+
+        fn on_target_finished(
+            &mut self,
+            command: &Command,
+            execution_result: &ExecutionResult,
+            will_retry: bool,
+        ) {
+            self.events.lock().unwrap().push(format!(
+                "finished {} {:?} {will_retry}",
+                command.name, execution_result.status
+            ));
+        }
+
+        fn on_run_finished(&mut self, stats: &SchedulerStats) {
+            self.events.lock().unwrap().push(format!(
+                "run finished {} {}",
+                stats.exec.succeeded, stats.exec.failed
+            ));
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn reporter() {
+        let dir = new_tmp_dir!();
+        let reporter = RecordingReporter::default();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.add_reporter(Box::new(reporter.clone()));
+        push_cmake(
+            &mut razel,
+            "a",
+            &["-E", "touch", "reporter-a.txt"],
+            &[],
+            "reporter-a.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "b",
+            &["-E", "copy", "reporter-a.txt", "reporter-b.txt"],
+            &["reporter-a.txt"],
+            "reporter-b.txt",
+        );
+        razel
+            .run(false, false, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            *reporter.events.lock().unwrap(),
+            [
+                "started a",
+                "finished a Success false",
+                "started b",
+                "finished b Success false",
+                "run finished 2 0",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn self_digest_mode() {
+        let dir = new_tmp_dir!();
+        let other_binary = dir.join_and_write_file("other-razel", "other binary");
+        let push_task = |mode| {
+            let mut razel = Razel::new();
+            razel.clean();
+            razel.self_digest_mode = mode;
+            crate::parse_cli_within_file(
+                &mut razel,
+                ["razel", "task", "write-file", "self-digest.txt", "a"]
+                    .map(String::from)
+                    .to_vec(),
+                "write",
+                vec![],
+            )
+            .unwrap();
+            razel
+        };
+        for (mode, cache_hits) in [
+            (SelfDigestMode::Binary, 0),
+            (SelfDigestMode::Version, 1),
+            (SelfDigestMode::None, 1),
+        ] {
+            let cache_dir = dir.join(&format!("cache-{mode:?}"));
+            let stats = push_task(mode)
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.cache_hits, 0);
+            // same version, but different binary
+            let mut razel = push_task(mode);
+            if let Some(id) = razel.self_file_id {
+                razel.files[id].path = other_binary.clone();
+            }
+            let stats = razel
+                .run(false, true, "", Some(cache_dir), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 1);
+            assert_eq!(stats.cache_hits, cache_hits, "{mode:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn cache_salt() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = |salt: Option<&str>, tags: Vec<Tag>| {
+            let cache_dir = cache_dir.clone();
+            let salt = salt.map(String::from);
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.cache_salt = salt;
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "a".into(),
+                        executable: "sh".into(),
+                        args: vec!["-c".into(), "printf a > \"$0\"".into(), "a.txt".into()],
+                        outputs: vec!["a.txt".into()],
+                        tags,
+                        ..Default::default()
+                    })
+                    .unwrap();
+                let stats = razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap();
+                assert_eq!(stats.exec.succeeded, 1);
+                stats.cache_hits
+            }
+        };
+        let ac_entries = || fs::read_dir(cache_dir.join("ac")).unwrap().count();
+        assert_eq!(run(Some("gcc-12"), vec![]).await, 0);
+        assert_eq!(run(Some("gcc-13"), vec![]).await, 0);
+        assert_eq!(ac_entries(), 2);
+        assert_eq!(run(Some("gcc-12"), vec![]).await, 1);
+        assert_eq!(run(None, vec![]).await, 0);
+        // the tag is combined with the global salt
+        assert_eq!(
+            run(Some("gcc-12"), vec![Tag::CacheSalt("x".into())]).await,
+            0
+        );
+        assert_eq!(run(None, vec![Tag::CacheSalt("gcc-12".into())]).await, 0);
+        assert_eq!(ac_entries(), 5);
+        Razel::new().clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn action_digest_env() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = |action_digest_env: bool| {
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.action_digest_env = action_digest_env;
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "a".into(),
+                        executable: "sh".into(),
+                        args: vec![
+                            "-c".into(),
+                            "printf \"$RAZEL_ACTION_DIGEST\" > \"$0\"".into(),
+                            "a.txt".into(),
+                        ],
+                        outputs: vec!["a.txt".into()],
+                        ..Default::default()
+                    })
+                    .unwrap();
+                let stats = razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap();
+                assert_eq!(stats.exec.succeeded, 1);
+                let digest = razel.action_digest(razel.commands.iter().next().unwrap());
+                let output = fs::read_to_string("razel-out/a.txt").unwrap();
+                (stats.cache_hits, digest.hash, output)
+            }
+        };
+        let (cache_hits, digest, output) = run(true).await;
+        assert_eq!(cache_hits, 0);
+        assert_eq!(output, digest);
+        // the env var is not part of the digest
+        let (cache_hits, digest_without_env, _) = run(false).await;
+        assert_eq!(cache_hits, 1);
+        assert_eq!(digest_without_env, digest);
+        Razel::new().clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn deps_are_order_only() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = |a_content: &'static str| {
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "a".into(),
+                        executable: "sh".into(),
+                        args: vec![
+                            "-c".into(),
+                            "sleep 0.2 && echo \"$1\" > \"$0\"".into(),
+                            "a.txt".into(),
+                            a_content.into(),
+                        ],
+                        outputs: vec!["a.txt".into()],
+                        ..Default::default()
+                    })
+                    .unwrap();
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "b".into(),
+                        executable: "sh".into(),
+                        args: vec!["-c".into(), "echo b > \"$0\"".into(), "b.txt".into()],
+                        outputs: vec!["b.txt".into()],
+                        deps: vec!["a".into()],
+                        ..Default::default()
+                    })
+                    .unwrap();
+                razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap();
+                let b = razel.get_command_by_name(&"b".to_string()).unwrap();
+                let b_digest = razel.action_digest(b);
+                let log = razel
+                    .log_file
+                    .items
+                    .iter()
+                    .map(|x| (x.name.clone(), x.cache))
+                    .collect_vec();
+                (b_digest, log)
+            }
+        };
+        let (b_digest, log) = run("x").await;
+        // b is fast but waits for a
+        assert_eq!(log, [("a".into(), None), ("b".into(), None)]);
+        // changing the output of a does not change the action digest of b
+        let (b_digest_2, log) = run("y").await;
+        assert_eq!(b_digest_2, b_digest);
+        assert_eq!(log[1], ("b".into(), Some(CacheHit::Local)));
+        Razel::new().clean();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    #[serial]
+    async fn output_file_mode_is_restored_from_cache() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = |link_type: LinkType| {
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.link_type = link_type;
+                razel
+                    .push_custom_command(CustomCommandSpec { name: "a".into(), executable: "sh".into(), args: vec![
+                            "-c".into(),
+                            "echo a > \"$0\" && chmod 444 \"$0\" && echo b > \"$1\" && chmod 600 \"$1\"".into(),
+                            "readonly.txt".into(),
+                            "key.txt".into(),
+                        ], outputs: vec!["readonly.txt".into(), "key.txt".into()], ..Default::default() })
+                    .unwrap();
+                let stats = razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap();
+                assert_eq!(stats.exec.succeeded, 1);
+                let modes = ["readonly.txt", "key.txt"].map(|x| {
+                    fs::metadata(razel.out_dir.join(x))
+                        .unwrap()
+                        .permissions()
+                        .mode()
+                        & 0o777
+                });
+                (stats.cache_hits, modes)
+            }
+        };
+        assert_eq!(run(LinkType::Copy).await, (0, [0o444, 0o600]));
+        assert_eq!(run(LinkType::Copy).await, (1, [0o444, 0o600]));
+        // links share the readonly cache file
+        assert_eq!(run(LinkType::Symlink).await, (1, [0o444, 0o400]));
+        assert_eq!(run(LinkType::Hardlink).await, (1, [0o444, 0o400]));
+        Razel::new().clean();
+    }
+
+    #[tokio::test]
+    async fn remote_cache_max_size() {
+        let dir = new_tmp_dir!();
+        let small = dir.join_and_write_file("small.txt", "small");
+        let large = dir.join_and_write_file("large.txt", &"large".repeat(1000));
+        let mut razel = Razel::new();
+        let mut push = |name: &str, input: &PathBuf| {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "cat".into(),
+                    args: vec![input.to_str().unwrap().into()],
+                    inputs: vec![input.to_str().unwrap().into()],
+                    ..Default::default()
+                })
+                .unwrap()
+        };
+        let small_id = push("small", &small);
+        let large_id = push("large", &large);
+        razel.digest_input_files().await.unwrap();
+        // the executable is larger than the limit, but not counted
+        razel.remote_cache_max_size = Some(1000);
+        let small = razel.get_command(small_id).unwrap();
+        let large = razel.get_command(large_id).unwrap();
+        assert!(!razel.is_input_size_above_remote_cache_max_size(small));
+        assert!(razel.is_input_size_above_remote_cache_max_size(large));
+        razel.remote_cache_max_size = None;
+        let large = razel.get_command(large_id).unwrap();
+        assert!(!razel.is_input_size_above_remote_cache_max_size(large));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn output_globs() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = |files: &'static [&'static str], allow_empty: bool| {
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                let mut args = vec!["-E".to_string(), "touch".into(), "razel-out/tmp.txt".into()];
+                args.extend(files.iter().map(|x| format!("razel-out/gen/{x}")));
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "generate".into(),
+                        executable: "cmake".into(),
+                        args,
+                        output_globs: vec![OutputGlob {
+                            pattern: "gen/**/*.rs".into(),
+                            allow_empty,
+                        }],
+                        ..Default::default()
+                    })
+                    .unwrap();
+                razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap()
+            }
+        };
+        let generated = || {
+            fs::read_dir("razel-out/gen")
+                .into_iter()
+                .flatten()
+                .map(|x| x.unwrap().file_name().into_string().unwrap())
+                .sorted()
+                .collect_vec()
+        };
+        let stats = run(&["a.rs", "b.rs"], false).await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 0);
+        assert_eq!(generated(), ["a.rs", "b.rs"]);
+        assert!(!Path::new("razel-out/tmp.txt").exists());
+        // different number of files
+        let stats = run(&["c.rs", "d.rs", "e.rs"], false).await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 0);
+        assert_eq!(generated(), ["c.rs", "d.rs", "e.rs"]);
+        // set of files is restored from cache
+        let stats = run(&["a.rs", "b.rs"], false).await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 1);
+        assert_eq!(generated(), ["a.rs", "b.rs"]);
+        // no matches
+        let stats = run(&["a.txt"], false).await;
+        assert_eq!(stats.exec.failed, 1);
+        let stats = run(&["a.txt"], true).await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert!(generated().is_empty());
+    }
+
+    #[test]
+    fn output_globs_base_dir_arg() {
+        let mut razel = Razel::new();
+        let id = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "generate".into(),
+                executable: "cmake".into(),
+                args: vec!["--out-dir".into(), "gen".into()],
+                output_globs: vec![OutputGlob {
+                    pattern: "gen/*.rs".into(),
+                    allow_empty: false,
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        let command = razel.get_command(id).unwrap();
+        assert_eq!(*command.executor.args(), ["--out-dir", "razel-out/gen"]);
+        assert_eq!(command.output_globs[0].pattern, "razel-out/gen/*.rs");
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn summary_only() {
+        let dir = new_tmp_dir!();
+        let buffer = SharedBuffer::default();
+        let mut razel = Razel::new();
+        razel.tui = TUI::with_output(Box::new(buffer.clone()));
+        razel.summary_only = true;
+        for (name, args, tags) in [
+            (
+                "succeeding",
+                ["-E", "echo", "some-stdout"],
+                vec![Tag::Verbose],
+            ),
+            ("failing", ["-E", "false", ""], vec![]),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "cmake".into(),
+                    args: args
+                        .into_iter()
+                        .filter(|x| !x.is_empty())
+                        .map(|x| x.into())
+                        .collect(),
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(true, true, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.exec.failed, 1);
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("succeeding"));
+        assert!(!output.contains("some-stdout"));
+        assert!(!output.contains("Status"));
+        assert!(output.contains("failing"));
+        assert!(output.contains("Finished after error"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn progress_lines_without_tty() {
+        let dir = new_tmp_dir!();
+        let buffer = SharedBuffer::default();
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        razel.tui = TUI::with_output(Box::new(buffer.clone()));
+        razel.stats_interval = Some(Duration::from_secs(3600));
+        razel.read_cache = false;
+        for i in 0..20 {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: format!("{i}"),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "true".into()],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        razel
+            .run(false, false, "", None, vec![], None)
+            .await
+            .unwrap();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        // no cursor movement
+        assert!(!output.contains("\x1b7") && !output.contains("\x1b8"));
+        let status_lines = output
+            .lines()
+            .filter(|x| x.contains("Status"))
+            .collect_vec();
+        for succeeded in (2..=20).step_by(2) {
+            let progress = format!("{succeeded}{C_RESET}{A_RESET} succeeded");
+            assert!(
+                status_lines.iter().any(|x| x.contains(&progress)),
+                "{succeeded}: {status_lines:?}"
+            );
+        }
+        // one more line from the first tick of the interval
+        assert!(status_lines.len() <= 11, "{status_lines:?}");
+        razel.clean();
+    }
+
+    #[cfg(target_family = "unix")]
+    fn push_write_output(razel: &mut Razel) {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "output".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "printf output > \"$0\"".into(),
+                    "output.txt".into(),
+                ],
+                outputs: vec!["output.txt".into()],
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn output_base() {
+        let dir = new_tmp_dir!();
+        let output_base = fs::canonicalize(dir.dir()).unwrap().join("out");
+        let stale = dir.join_and_write_file("out/stale.txt", "stale");
+        let out_dir = Path::new(crate::config::OUT_DIR);
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.read_cache = false;
+        assert!(razel.set_output_base(Path::new(".")).is_err());
+        assert!(razel.set_output_base(Path::new("..")).is_err());
+        assert!(razel.output_base.is_none());
+        razel.set_output_base(&output_base).unwrap();
+        push_write_output(&mut razel);
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(fs::read_link(out_dir).unwrap(), output_base);
+        assert_eq!(
+            fs::read_to_string(output_base.join("output.txt")).unwrap(),
+            "output"
+        );
+        assert!(!stale.exists());
+        // without output base, the link is replaced by a directory
+        let mut razel = Razel::new();
+        razel.read_cache = false;
+        push_write_output(&mut razel);
+        razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert!(!fs::symlink_metadata(out_dir).unwrap().is_symlink());
+        assert!(out_dir.join("output.txt").exists());
+        assert!(output_base.join("output.txt").exists());
+        razel.clean();
+    }
+
+    #[test]
+    #[serial]
+    fn output_base_clean() {
+        let dir = new_tmp_dir!();
+        let existing = dir.join_and_write_file("existing/file.txt", "x");
+        let created = fs::canonicalize(dir.dir()).unwrap().join("created");
+        for (output_base, removed) in [
+            (existing.parent().unwrap().to_path_buf(), false),
+            (created, true),
+        ] {
+            let mut razel = Razel::new();
+            razel.clean();
+            razel.set_output_base(&output_base).unwrap();
+            razel.link_output_base().unwrap();
+            // only output bases created by razel are removed
+            razel.clean();
+            assert_eq!(output_base.exists(), !removed, "{output_base:?}");
+        }
+        assert!(existing.exists());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn output_base_containing_cache_dir() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.set_output_base(dir.dir()).unwrap();
+        push_write_output(&mut razel);
+        let error = razel
+            .run(false, true, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("cache dir"), "{error}");
+        assert!(!fs::symlink_metadata(crate::config::OUT_DIR).is_ok_and(|x| x.is_symlink()));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn atomic_outputs() {
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.atomic_outputs = true;
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "atomic".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "printf part > \"$0\"; sleep 0.5; printf ial >> \"$0\"".into(),
+                    "atomic.txt".into(),
+                ],
+                outputs: vec!["atomic.txt".into()],
+                tags: vec![Tag::NoSandbox],
+                ..Default::default()
+            })
+            .unwrap();
+        let output = Path::new("razel-out/atomic.txt");
+        let tmp_output = Path::new("razel-out/.razel-tmp-atomic.txt");
+        let done = std::sync::atomic::AtomicBool::new(false);
+        let (stats, tmp_output_seen) = tokio::join!(
+            async {
+                let stats = razel.run(false, true, "", None, vec![], None).await;
+                done.store(true, std::sync::atomic::Ordering::Relaxed);
+                stats
+            },
+            async {
+                let mut tmp_output_seen = false;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    if let Ok(x) = fs::read_to_string(output) {
+                        assert_eq!(x, "partial");
+                    }
+                    tmp_output_seen |= tmp_output.exists();
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                tmp_output_seen
+            }
+        );
+        assert_eq!(stats.unwrap().exec.succeeded, 1);
+        assert!(tmp_output_seen);
+        assert_eq!(fs::read_to_string(output).unwrap(), "partial");
+        assert!(!tmp_output.exists());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn preserve_outputs() {
+        let run = |tags: Vec<Tag>| async {
+            let mut razel = Razel::new();
+            razel.push_custom_command(CustomCommandSpec {
+                name: "append".into(),
+                executable: "sh".into(),
+                args: vec!["-c".into(), "echo x >> \"$0\"".into(), "append.txt".into()],
+                outputs: vec!["append.txt".into()],
+                tags,
+                ..Default::default()
+            })?;
+            let stats = razel.run(false, true, "", None, vec![], None).await?;
+            assert_eq!(stats.exec.succeeded, 1);
+            Ok::<_, anyhow::Error>(fs::read_to_string("razel-out/append.txt")?)
+        };
+        Razel::new().clean();
+        let tags = vec![Tag::NoSandbox, Tag::PreserveOutputs];
+        assert_eq!(run(tags.clone()).await.unwrap(), "x\n");
+        // the previous output is still there and appended to
+        assert_eq!(run(tags.clone()).await.unwrap(), "x\nx\n");
+        // without the tag the output is removed before execution
+        assert_eq!(run(vec![Tag::NoSandbox]).await.unwrap(), "x\n");
+        assert!(run(vec![Tag::PreserveOutputs]).await.is_err());
+        Razel::new().clean();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn keep_metadata_runs() {
+        Razel::new().clean();
+        for _ in 0..4 {
+            let mut razel = Razel::new();
+            razel.keep_metadata_runs = Some(2);
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "true".into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "true".into()],
+                    tags: vec![Tag::NoCache],
+                    ..Default::default()
+                })
+                .unwrap();
+            razel
+                .run(false, true, "", None, vec![], None)
+                .await
+                .unwrap();
+        }
+        let metadata_dir = Razel::new().out_dir.join("razel-metadata");
+        let runs = fs::read_dir(metadata_dir.join(RUNS_DIR))
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .sorted()
+            .collect_vec();
+        // the current and 2 previous runs
+        assert_eq!(runs.len(), 3);
+        for run in &runs {
+            for file in ["log.json", "report.json", "measurements.csv"] {
+                assert!(run.join(file).is_file(), "{run:?} {file}");
+            }
+        }
+        // the newest archived run is the current one
+        assert_eq!(
+            fs::read(runs[2].join("log.json")).unwrap(),
+            fs::read(metadata_dir.join("log.json")).unwrap()
+        );
+        Razel::new().clean();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[serial]
+    async fn profile_memory() {
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.profile_memory = true;
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "sleep".into(),
+                executable: "sleep".into(),
+                args: vec!["0.3".into()],
+                tags: vec![Tag::NoCache],
+                ..Default::default()
+            })
+            .unwrap();
+        razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        let metadata_dir = razel.out_dir.join("razel-metadata");
+        let profile: MemoryProfile =
+            serde_json::from_slice(&fs::read(metadata_dir.join("memory_profile.json")).unwrap())
+                .unwrap();
+        assert!(profile.samples.len() > 1);
+        assert!(profile.peak_rss > 0);
+        assert_eq!(
+            profile.peak_rss,
+            profile.samples.iter().map(|x| x.rss).max().unwrap()
+        );
+        let report: Report =
+            serde_json::from_slice(&fs::read(metadata_dir.join("report.json")).unwrap()).unwrap();
+        assert_eq!(report.peak_rss, Some(profile.peak_rss));
+        Razel::new().clean();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn incremental_log() {
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.incremental_log = true;
+        razel.set_worker_threads(2).unwrap();
+        for (name, secs) in [("fast", "0"), ("slow", "10")] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sleep".into(),
+                    args: vec![secs.into()],
+                    tags: vec![Tag::NoCache],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        // dropping the future like killing razel while the slow command is running
+        let run = razel.run(false, true, "", None, vec![], None);
+        assert!(tokio::time::timeout(Duration::from_secs(2), run)
+            .await
+            .is_err());
+        let log_file =
+            LogFile::from_path(razel.out_dir.join("razel-metadata").join("log.json")).unwrap();
+        assert_eq!(
+            log_file
+                .items
+                .iter()
+                .map(|x| (x.name.as_str(), x.status))
+                .collect_vec(),
+            [("fast", ExecutionStatus::Success)]
+        );
+        // torn writes of razel being killed while appending an item
+        let path = razel.out_dir.join("razel-metadata").join("log.json");
+        let complete = fs::read(&path).unwrap();
+        for torn in [",\n", ",\n{\"name\":\"slow\",\"sta"] {
+            fs::write(&path, [complete.as_slice(), torn.as_bytes()].concat()).unwrap();
+            let log_file = LogFile::from_path(&path).unwrap();
+            assert_eq!(log_file.items.len(), 1, "{torn:?}");
+            assert_eq!(log_file.items[0].name, "fast");
+        }
+        // broken items before the last one are an error
+        fs::write(&path, [complete.as_slice(), b",\n{}\n,{}"].concat()).unwrap();
+        assert!(LogFile::from_path(&path).is_err());
+        Razel::new().clean();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn resource_limits_concurrency() {
+        let dir = new_tmp_dir!();
+        // mkdir fails if the other command holds the lock
+        let lock = std::env::current_dir().unwrap().join(dir.join("lock"));
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.set_worker_threads(2).unwrap();
+        razel.set_resource_capacity("gpu", 1).unwrap();
+        for name in ["a", "b"] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: vec![
+                        "-c".into(),
+                        "mkdir \"$0\" && sleep 0.2 && rmdir \"$0\"".into(),
+                        lock.to_str().unwrap().into(),
+                    ],
+                    tags: vec![Tag::NoCache, Tag::Resource("gpu".into(), 1)],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        assert_eq!(stats.exec.failed, 0);
+        // resources without capacity are an error
+        let mut razel = Razel::new();
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "c".into(),
+                executable: "sh".into(),
+                args: vec!["-c".into(), "true".into()],
+                tags: vec![Tag::Resource("license".into(), 1)],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .is_err());
+        razel.set_resource_capacity("license", 1).unwrap();
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        // multiple tags of the same resource are rejected
+        let spec = |tags| CustomCommandSpec {
+            name: "d".into(),
+            executable: "sh".into(),
+            args: vec!["-c".into(), "true".into()],
+            tags,
+            ..Default::default()
+        };
+        assert!(razel
+            .push_custom_command(spec(vec![
+                Tag::Resource("gpu".into(), 1),
+                Tag::Resource("gpu".into(), 2)
+            ]))
+            .is_err());
+        razel
+            .push_custom_command(spec(vec![Tag::Resource("gpu".into(), 1)]))
+            .unwrap();
+        assert!(razel
+            .add_tag_for_command("d", Tag::Resource("gpu".into(), 2))
+            .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[serial]
+    async fn trace_inputs() {
+        let dir = new_tmp_dir!();
+        let declared = dir.join_and_write_file("declared.txt", "declared");
+        let undeclared = dir.join_and_write_file("undeclared.txt", "undeclared");
+        let undeclared_abs = std::env::current_dir().unwrap().join(&undeclared);
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.set_trace_inputs();
+        for (name, undeclared_arg, tags) in [
+            ("sandbox", &undeclared_abs, vec![]),
+            ("no-sandbox", &undeclared, vec![Tag::NoSandbox]),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "cat".into(),
+                    args: vec![
+                        declared.to_str().unwrap().into(),
+                        undeclared_arg.to_str().unwrap().into(),
+                    ],
+                    inputs: vec![declared.to_str().unwrap().into()],
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        for item in &razel.log_file.items {
+            assert_eq!(
+                item.undeclared_inputs,
+                vec![undeclared.clone()],
+                "{}",
+                item.name
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[serial]
+    async fn strict_sandbox() {
+        if crate::executors::Landlock::abi_version().is_none() {
+            println!("Landlock not supported, skipping test");
+            return;
+        }
+        let dir = new_tmp_dir!();
+        let input = dir.join_and_write_file("input.txt", "input");
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.set_strict_sandbox();
+        for (name, file) in [
+            ("declared", input.as_path()),
+            ("host", Path::new("/etc/hostname")),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "cat".into(),
+                    args: vec![file.to_str().unwrap().into()],
+                    inputs: vec![input.to_str().unwrap().into()],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel.run(true, true, "", None, vec![], None).await.unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.exec.failed, 1);
+        let host = razel.get_command_by_name(&"host".into()).unwrap().id;
+        assert!(razel.failed.contains(&host));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn strict_outputs() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.set_strict_outputs(&["^tmp-".into()]).unwrap();
+        push_cmake(
+            &mut razel,
+            "declared",
+            &["-E", "touch", "strict-a.txt"],
+            &[],
+            "strict-a.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "undeclared",
+            &["-E", "touch", "strict-b.txt", "strict-undeclared.txt"],
+            &[],
+            "strict-b.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "ignored",
+            &["-E", "touch", "strict-c.txt", "tmp-strict.txt"],
+            &[],
+            "strict-c.txt",
+        );
+        let stats = razel
+            .run(true, true, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats.exec,
+            SchedulerExecStats {
+                succeeded: 2,
+                failed: 1,
+                ..Default::default()
+            }
+        );
+        let undeclared = razel.get_command_by_name(&"undeclared".into()).unwrap().id;
+        assert_eq!(razel.failed, vec![undeclared]);
+        assert!(!Path::new("razel-out/strict-b.txt").exists());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn export_and_import_cache() {
+        let dir = new_tmp_dir!();
+        let src_cache_dir = dir.join("src-cache");
+        let dst_cache_dir = dir.join("dst-cache");
+        let archive = dir.join("cache.tar.gz");
+        let push_commands = |razel: &mut Razel| {
+            push_cmake(
+                razel,
+                "a",
+                &["-E", "touch", "export-a.txt"],
+                &[],
+                "export-a.txt",
+            );
+            push_cmake(
+                razel,
+                "b",
+                &["-E", "copy", "export-a.txt", "export-b.txt"],
+                &["export-a.txt"],
+                "export-b.txt",
+            );
+        };
+        // build
+        {
+            let mut razel = Razel::new();
+            razel.clean();
+            push_commands(&mut razel);
+            let stats = razel
+                .run(false, true, "", Some(src_cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 2);
+        }
+        // export, outputs are not materialized
+        {
+            let mut razel = Razel::new();
+            razel.clean();
+            push_commands(&mut razel);
+            razel
+                .export_cache(&archive, Some(src_cache_dir.clone()))
+                .await
+                .unwrap();
+            assert!(!Path::new("razel-out/export-a.txt").exists());
+            assert!(!Path::new("razel-out/export-b.txt").exists());
+        }
+        // import into fresh cache
+        Razel::new()
+            .import_cache(&archive, Some(dst_cache_dir.clone()))
+            .await
+            .unwrap();
+        // cold build
+        let mut razel = Razel::new();
+        razel.clean();
+        push_commands(&mut razel);
+        let stats = razel
+            .run(false, true, "", Some(dst_cache_dir), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        assert_eq!(stats.cache_hits, 2);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn export_cache_archive_entries() {
+        let dir = new_tmp_dir!();
+        let src_cache_dir = dir.join("src-cache");
+        let dst_cache_dir = dir.join("dst-cache");
+        let archive = dir.join("cache.tar.gz");
+        let data = dir.join_and_write_tar("data.tar", &[("f.txt", "f")]);
+        let entry = format!("{}#f.txt", data.to_str().unwrap());
+        let new_razel = || {
+            let mut razel = Razel::new();
+            razel.clean();
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "a".into(),
+                    executable: "cat".into(),
+                    args: vec![entry.clone()],
+                    inputs: vec![entry.clone()],
+                    stdout: Some("export-archive-entries.txt".into()),
+                    ..Default::default()
+                })
+                .unwrap();
+            razel
+        };
+        new_razel()
+            .run(false, true, "", Some(src_cache_dir.clone()), vec![], None)
+            .await
+            .unwrap();
+        new_razel()
+            .export_cache(&archive, Some(src_cache_dir))
+            .await
+            .unwrap();
+        Razel::new()
+            .import_cache(&archive, Some(dst_cache_dir.clone()))
+            .await
+            .unwrap();
+        let mut razel = new_razel();
+        let stats = razel
+            .run(false, true, "", Some(dst_cache_dir), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.cache_hits, 1);
+        razel.clean();
+    }
+
+    async fn task_action_digest(args: &[&str]) -> crate::cache::MessageDigest {
+        let mut razel = Razel::new();
+        // avoid digesting the test executable for each task
+        razel.self_digest_mode = SelfDigestMode::None;
+        let args = ["razel", "task"].iter().chain(args).map(|x| x.to_string());
+        crate::parse_cli_within_file(&mut razel, args.collect(), "task", vec![]).unwrap();
+        razel.digest_input_files().await.unwrap();
+        let command = razel.commands.iter().next().unwrap();
+        razel.action_digest(command)
+    }
+
+    /// Changing a parameter of a task must change the cache key, identical ones must not
+    #[tokio::test]
+    async fn task_params_in_action_digest() {
+        let dir = new_tmp_dir!();
+        let a = dir.join_and_write_file("a.csv", "a,b\n1,2\n");
+        let b = dir.join_and_write_file("b.csv", "a,b\n3,4\n");
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+        let url = "http://localhost:1234/";
+        let other_url = "http://localhost:1234/other";
+        for (task, args, changed) in [
+            (
+                "capture-regex",
+                vec![a, "out.txt", "(a)"],
+                vec![a, "out.txt", "(b)"],
+            ),
+            ("csv-concat", vec![a, b, "out.csv"], vec![b, a, "out.csv"]),
+            (
+                "csv-filter",
+                vec!["-i", a, "-o", "out.csv", "--col", "a"],
+                vec!["-i", a, "-o", "out.csv", "--col", "b"],
+            ),
+            ("write-file", vec!["out.txt", "x"], vec!["out.txt", "y"]),
+            (
+                "download-file",
+                vec!["--url", url, "--output", "out.txt"],
+                vec!["--url", other_url, "--output", "out.txt"],
+            ),
+            (
+                "download-file",
+                vec!["--url", url, "--output", "out.txt"],
+                vec!["--url", url, "--output", "out.txt", "--executable"],
+            ),
+            ("extract-archive", vec![a, "out", "x"], vec![a, "out", "y"]),
+            ("ensure-equal", vec![a, b], vec![a, a]),
+            ("ensure-not-equal", vec![a, b], vec![b, a]),
+            (
+                "ensure-golden",
+                vec![a, b],
+                vec![a, b, "--normalize-line-endings"],
+            ),
+            (
+                "http-remote-exec",
+                vec!["--url", url, "--files", a, "--file-names", "x"],
+                vec!["--url", url, "--files", a, "--file-names", "y"],
+            ),
+            ("hash", vec![a, "out.txt"], vec![a, b, "out.txt"]),
+        ] {
+            let digest = task_action_digest(&[&[task], args.as_slice()].concat()).await;
+            let same = task_action_digest(&[&[task], args.as_slice()].concat()).await;
+            let other = task_action_digest(&[&[task], changed.as_slice()].concat()).await;
+            assert_eq!(digest, same, "{task} {args:?}");
+            assert_ne!(digest, other, "{task} {args:?} -> {changed:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn task_params_cache_hits() {
+        let dir = new_tmp_dir!();
+        let input = dir.join_and_write_file("in.csv", "a,b\n1,2\n");
+        let run = |col: &'static str| {
+            let input = input.to_str().unwrap().to_string();
+            async move {
+                let mut razel = Razel::new();
+                let args = [
+                    "razel",
+                    "task",
+                    "csv-filter",
+                    "-i",
+                    &input,
+                    "-o",
+                    "out.csv",
+                    "--col",
+                    col,
+                ];
+                crate::parse_cli_within_file(
+                    &mut razel,
+                    args.iter().map(|x| x.to_string()).collect(),
+                    "filter",
+                    vec![],
+                )
+                .unwrap();
+                let stats = razel
+                    .run(false, true, "", None, vec![], None)
+                    .await
+                    .unwrap();
+                let output = fs::read_to_string("razel-out/out.csv").unwrap();
+                (stats.cache_hits, output)
+            }
+        };
+        Razel::new().clean();
+        let (_, output) = run("a").await;
+        assert_eq!(output, "a\n1\n");
+        assert_eq!(run("b").await, (0, "b\n2\n".into()));
+        assert_eq!(run("a").await, (1, "a\n1\n".into()));
+    }
+
+    /// Remote cache server which keeps uploads in memory, counts requests and handles uploads slowly
+    #[derive(Clone, Default)]
+    struct SlowRemoteCache {
+        ac_queries: Arc<AtomicUsize>,
+        ac_uploads: Arc<AtomicUsize>,
+        cas_uploads: Arc<AtomicUsize>,
+        ac: Arc<Mutex<HashMap<String, ActionResult>>>,
+        cas: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        /// authorization headers of AC queries
+        authorizations: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SlowRemoteCache {
+        const DELAY: Duration = Duration::from_millis(100);
+
+        async fn serve(&self) -> String {
+            use crate::bazel_remote_exec::action_cache_server::ActionCacheServer;
+            use crate::bazel_remote_exec::capabilities_server::CapabilitiesServer;
+            use crate::bazel_remote_exec::content_addressable_storage_server::ContentAddressableStorageServer;
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("grpc://{}", listener.local_addr().unwrap());
+            let router = tonic::transport::Server::builder()
+                .add_service(CapabilitiesServer::new(self.clone()))
+                .add_service(ActionCacheServer::new(self.clone()))
+                .add_service(ContentAddressableStorageServer::new(self.clone()));
+            tokio::spawn(router.serve_with_incoming(
+                tonic::codegen::tokio_stream::wrappers::TcpListenerStream::new(listener),
+            ));
+            url
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Capabilities for SlowRemoteCache {
+        async fn get_capabilities(
+            &self,
+            _: Request<GetCapabilitiesRequest>,
+        ) -> Result<Response<ServerCapabilities>, Status> {
+            Ok(Response::new(ServerCapabilities {
+                cache_capabilities: Some(CacheCapabilities {
+                    digest_functions: vec![digest_function::Value::Sha256.into()],
+                    action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
+                        update_enabled: true,
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ActionCache for SlowRemoteCache {
+        async fn get_action_result(
+            &self,
+            request: Request<GetActionResultRequest>,
+        ) -> Result<Response<ActionResult>, Status> {
+            self.ac_queries.fetch_add(1, Ordering::Relaxed);
+            if let Some(x) = request.metadata().get("authorization") {
+                self.authorizations
+                    .lock()
+                    .unwrap()
+                    .push(x.to_str().unwrap().into());
+            }
+            let hash = request.into_inner().action_digest.unwrap().hash;
+            match self.ac.lock().unwrap().get(&hash) {
+                Some(x) => Ok(Response::new(x.clone())),
+                None => Err(Status::not_found("")),
+            }
+        }
+
+        async fn update_action_result(
+            &self,
+            request: Request<UpdateActionResultRequest>,
+        ) -> Result<Response<ActionResult>, Status> {
+            tokio::time::sleep(Self::DELAY).await;
+            self.ac_uploads.fetch_add(1, Ordering::Relaxed);
+            let request = request.into_inner();
+            let action_result = request.action_result.unwrap();
+            self.ac
+                .lock()
+                .unwrap()
+                .insert(request.action_digest.unwrap().hash, action_result.clone());
+            Ok(Response::new(action_result))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ContentAddressableStorage for SlowRemoteCache {
+        async fn find_missing_blobs(
+            &self,
+            _: Request<FindMissingBlobsRequest>,
+        ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn batch_update_blobs(
+            &self,
+            request: Request<BatchUpdateBlobsRequest>,
+        ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+            tokio::time::sleep(Self::DELAY).await;
+            let requests = request.into_inner().requests;
+            self.cas_uploads
+                .fetch_add(requests.len(), Ordering::Relaxed);
+            let mut cas = self.cas.lock().unwrap();
+            for x in requests {
+                cas.insert(x.digest.unwrap().hash, x.data);
+            }
+            Ok(Response::new(Default::default()))
+        }
+
+        async fn batch_read_blobs(
+            &self,
+            request: Request<BatchReadBlobsRequest>,
+        ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+            let cas = self.cas.lock().unwrap();
+            let responses = request
+                .into_inner()
+                .digests
+                .into_iter()
+                .map(|digest| {
+                    let data = cas.get(&digest.hash).cloned();
+                    let mut response = batch_read_blobs_response::Response {
+                        status: Some(Default::default()),
+                        ..Default::default()
+                    };
+                    match data {
+                        Some(x) => response.data = x,
+                        None => {
+                            response.status.as_mut().unwrap().code = tonic::Code::NotFound as i32
+                        }
+                    }
+                    response.digest = Some(digest);
+                    response
+                })
+                .collect();
+            Ok(Response::new(BatchReadBlobsResponse { responses }))
+        }
+
+        type GetTreeStream = tonic::codegen::tokio_stream::Empty<Result<GetTreeResponse, Status>>;
+
+        async fn get_tree(
+            &self,
+            _: Request<GetTreeRequest>,
+        ) -> Result<Response<Self::GetTreeStream>, Status> {
+            Err(Status::unimplemented(""))
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remote_cache_max_size_skips_large_commands() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let dir = new_tmp_dir!();
+        let large_input = dir.join_and_write_file("large-input.txt", &"x".repeat(2000));
+        let large_input = large_input.to_str().unwrap().to_string();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.remote_cache_max_size = Some(1000);
+        for (name, script, inputs) in [
+            ("small", "echo small > \"$0\"", vec![]),
+            ("large-input", "cat \"$1\" > \"$0\"", vec![large_input]),
+            ("large-output", "head -c 2000 /dev/zero > \"$0\"", vec![]),
+        ] {
+            let output = format!("max-size-{name}.txt");
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: chain!(["-c".into(), script.into(), output.clone()], inputs.clone())
+                        .collect(),
+                    inputs,
+                    outputs: vec![output],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(false, true, "", Some(dir.join("cache")), vec![url], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 3);
+        // large-input is neither queried nor uploaded, large-output is not uploaded
+        assert_eq!(server.ac_queries.load(Ordering::Relaxed), 2);
+        assert_eq!(server.ac_uploads.load(Ordering::Relaxed), 1);
+        assert_eq!(server.cas_uploads.load(Ordering::Relaxed), 1);
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remote_cache_uploads_flushed() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let cache_dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.read_cache = false;
+        razel.max_inflight_uploads = 1;
+        let n = 3;
+        for i in 0..n {
+            let output = format!("output-{i}.txt");
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: output.clone(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "printf \"$0\" > \"$0\"".into(), output.clone()],
+                    outputs: vec![output],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(
+                false,
+                true,
+                "",
+                Some(cache_dir.dir().clone()),
+                vec![url],
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, n);
+        assert_eq!(server.ac_uploads.load(Ordering::Relaxed), n);
+        assert_eq!(server.cas_uploads.load(Ordering::Relaxed), n);
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remote_cache_auth_token() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.remote_cache_auth_token_file = Some(dir.join_and_write_file("token.txt", "secret\n"));
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "a".into(),
+                executable: "sh".into(),
+                args: vec!["-c".into(), "echo a".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        let stats = razel
+            .run(false, true, "", Some(dir.join("cache")), vec![url], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(
+            *server.authorizations.lock().unwrap(),
+            vec!["Bearer secret".to_string()]
+        );
+        razel.clean();
+    }
+
+    /// 3 outputs of 100 kB with 100 kB/s: 100 kB burst, 200 kB need at least 2 s.
+    /// Only the lower bound is checked because the limiter never waits less.
+    #[tokio::test]
+    #[serial]
+    async fn remote_cache_bandwidth_limits() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let limit = 100_000;
+        let min_duration = Duration::from_millis(1900);
+        let run = |upload_limit: u64, download_limit: u64| {
+            let url = url.clone();
+            async move {
+                let cache_dir = new_tmp_dir!();
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.remote_cache_upload_limit = upload_limit;
+                razel.remote_cache_download_limit = download_limit;
+                for i in 0..3 {
+                    let output = format!("output-{i}.txt");
+                    razel
+                        .push_custom_command(CustomCommandSpec {
+                            name: output.clone(),
+                            executable: "sh".into(),
+                            args: vec![
+                                "-c".into(),
+                                "yes \"$0\" | head -c 100000 > \"$0\"".into(),
+                                output.clone(),
+                            ],
+                            outputs: vec![output],
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+                let start = Instant::now();
+                let stats = razel
+                    .run(
+                        false,
+                        true,
+                        "",
+                        Some(cache_dir.dir().clone()),
+                        vec![url],
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                razel.clean();
+                (stats, start.elapsed())
+            }
+        };
+        let (stats, duration) = run(limit, 0).await;
+        assert_eq!(stats.exec.succeeded, 3);
+        assert_eq!(stats.cache_hits, 0);
+        assert_eq!(server.cas_uploads.load(Ordering::Relaxed), 3);
+        assert!(duration >= min_duration, "{duration:?}");
+        // empty local cache, outputs are downloaded from the remote cache
+        let (stats, duration) = run(0, limit).await;
+        assert_eq!(stats.exec.succeeded, 3);
+        assert_eq!(stats.cache_hits, 3);
+        assert!(duration >= min_duration, "{duration:?}");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remote_cache_threshold_uploads_worthwhile_outputs() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let cache_dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.read_cache = false;
+        // small output of a slow command: 0.01 kB/s
+        // big output of a fast command: 200 kB within much less than 20s
+        for (output, script) in [
+            ("small.txt", "sleep 0.1; printf x > \"$0\""),
+            ("big.txt", "head -c 200000 /dev/zero > \"$0\""),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: output.into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), script.into(), output.into()],
+                    outputs: vec![output.into()],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(
+                false,
+                true,
+                "",
+                Some(cache_dir.dir().clone()),
+                vec![url],
+                Some(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        // action results are always uploaded, output files only if worthwhile
+        assert_eq!(server.ac_uploads.load(Ordering::Relaxed), 2);
+        assert_eq!(server.cas_uploads.load(Ordering::Relaxed), 1);
+        let cas = server.cas.lock().unwrap();
+        assert!(cas.contains_key(&crate::bazel_remote_exec::Digest::for_bytes("x").hash));
+        assert!(
+            !cas.contains_key(&crate::bazel_remote_exec::Digest::for_bytes(vec![0; 200000]).hash)
+        );
+        drop(cas);
+        razel.clean();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[serial]
+    async fn unreachable_remote_cache_is_disabled() {
+        // not routable, connecting would hang without a timeout
+        let remote_cache = vec!["grpc://10.255.255.1:9092".to_string()];
+        for require_remote_cache in [false, true] {
+            let mut razel = Razel::new();
+            razel.require_remote_cache = require_remote_cache;
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "true".into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "true".into()],
+                    tags: vec![Tag::NoCache],
+                    ..Default::default()
+                })
+                .unwrap();
+            let start = Instant::now();
+            let result = razel
+                .run(false, true, "", None, remote_cache.clone(), None)
+                .await;
+            assert!(
+                start.elapsed()
+                    < crate::config::REMOTE_CACHE_CONNECT_TIMEOUT + Duration::from_secs(2)
+            );
+            if require_remote_cache {
+                assert!(result.is_err());
+            } else {
+                assert_eq!(result.unwrap().exec.succeeded, 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn explain_remote_miss() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        // runs a command with an empty local cache, returns the remote cache explanation
+        let run = || {
+            let url = url.clone();
+            async move {
+                let cache_dir = new_tmp_dir!();
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.explain_remote_miss = true;
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "a".into(),
+                        executable: "sh".into(),
+                        args: vec!["-c".into(), "echo a > \"$0\"".into(), "a.txt".into()],
+                        outputs: vec!["a.txt".into()],
+                        ..Default::default()
+                    })
+                    .unwrap();
+                let stats = razel
+                    .run(
+                        false,
+                        true,
+                        "",
+                        Some(cache_dir.dir().clone()),
+                        vec![url],
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(stats.exec.succeeded, 1);
+                let remote_cache = razel.log_file.items[0].remote_cache.clone();
+                razel.clean();
+                remote_cache
+            }
+        };
+        assert_eq!(run().await.unwrap(), "miss: no action result");
+        // AC entry exists, but the output file was evicted from CAS
+        server.cas.lock().unwrap().clear();
+        assert_eq!(
+            run().await.unwrap(),
+            "miss: action result found, output files missing in CAS: [\"a.txt\"]"
+        );
+        // executed again and uploaded
+        assert_eq!(run().await.unwrap(), "hit");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn seed_from_remote() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let n = 3;
+        // runs a chain of commands with an empty local cache, returns cache hits of the commands
+        let run = |seed_from_remote: bool| {
+            let url = url.clone();
+            async move {
+                let cache_dir = new_tmp_dir!();
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.seed_from_remote = seed_from_remote;
+                for i in 0..n {
+                    let output = format!("output-{i}.txt");
+                    let (script, inputs) = if i == 0 {
+                        ("echo 0 > \"$0\"".to_string(), vec![])
+                    } else {
+                        let input = format!("output-{}.txt", i - 1);
+                        (
+                            format!("cat \"$1\" > \"$0\"; echo {i} >> \"$0\""),
+                            vec![input],
+                        )
+                    };
+                    razel
+                        .push_custom_command(CustomCommandSpec {
+                            name: output.clone(),
+                            executable: "sh".into(),
+                            args: chain!(["-c".into(), script, output.clone()], inputs.clone())
+                                .collect(),
+                            inputs,
+                            outputs: vec![output],
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+                let stats = razel
+                    .run(
+                        false,
+                        true,
+                        "",
+                        Some(cache_dir.dir().clone()),
+                        vec![url],
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(stats.exec.succeeded, n);
+                let cache_hits = razel.log_file.items.iter().map(|x| x.cache).collect_vec();
+                razel.clean();
+                cache_hits
+            }
+        };
+        // fill remote cache
+        assert_eq!(run(false).await, [None; 3]);
+        assert_eq!(server.ac_uploads.load(Ordering::Relaxed), n);
+        // without seeding, every command queries the remote cache once it is ready
+        server.ac_queries.store(0, Ordering::Relaxed);
+        assert_eq!(run(false).await, [Some(CacheHit::Remote); 3]);
+        assert_eq!(server.ac_queries.load(Ordering::Relaxed), n);
+        // with seeding, all queries are done before scheduling and commands hit the local cache
+        server.ac_queries.store(0, Ordering::Relaxed);
+        assert_eq!(run(true).await, [Some(CacheHit::Local); 3]);
+        assert_eq!(server.ac_queries.load(Ordering::Relaxed), n);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn seed_from_remote_archive_entries() {
+        let server = SlowRemoteCache::default();
+        let url = server.serve().await;
+        let dir = new_tmp_dir!();
+        let data = dir.join_and_write_tar("data.tar", &[("f.txt", "f")]);
+        let entry = format!("{}#f.txt", data.to_str().unwrap());
+        let run = |seed_from_remote: bool| {
+            let url = url.clone();
+            let entry = entry.clone();
+            async move {
+                let cache_dir = new_tmp_dir!();
+                let mut razel = Razel::new();
+                razel.clean();
+                razel.seed_from_remote = seed_from_remote;
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "a".into(),
+                        executable: "cat".into(),
+                        args: vec![entry.clone()],
+                        inputs: vec![entry],
+                        stdout: Some("seed-archive-entries.txt".into()),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                razel
+                    .run(
+                        false,
+                        true,
+                        "",
+                        Some(cache_dir.dir().clone()),
+                        vec![url],
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                let cache_hits = razel.log_file.items.iter().map(|x| x.cache).collect_vec();
+                razel.clean();
+                cache_hits
+            }
+        };
+        assert_eq!(run(false).await, [None]);
+        assert_eq!(run(true).await, [Some(CacheHit::Local)]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn transient_sandbox_error_is_retried() {
+        let dir = new_tmp_dir!();
+        let cache_dir = fs::canonicalize(dir.dir()).unwrap();
+        let sandbox_dir = crate::config::select_sandbox_dir(&cache_dir).unwrap();
+        let mut razel = Razel::new();
+        razel.clean();
+        // the first command blocks creating the sandbox dir of the second one with a file
+        let blocker = sandbox_dir.join("1");
+        for (name, script, args, inputs) in [
+            (
+                "block",
+                "touch \"$1\" && touch \"$0\"",
+                vec!["block.txt", blocker.to_str().unwrap()],
+                vec![],
+            ),
+            (
+                "target",
+                "touch \"$0\"",
+                vec!["target.txt"],
+                vec!["block.txt"],
+            ),
+            (
+                "after",
+                "touch \"$0\"",
+                vec!["after.txt"],
+                vec!["target.txt"],
+            ),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: chain!(["-c", script], args)
+                        .map(|x| x.to_string())
+                        .collect(),
+                    inputs: inputs.into_iter().map(|x| x.into()).collect(),
+                    outputs: vec![format!("{name}.txt")],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(true, true, "", Some(cache_dir), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 3);
+        assert_eq!(stats.exec.failed, 0);
+        assert_eq!(razel.retried_after_system_error.len(), 1);
+        razel.clean();
+    }
+
+    /// Fails the first create() like a race with a concurrent command
+    struct FlakySandbox {
+        sandbox: TmpDirSandbox,
+        error_kind: std::io::ErrorKind,
+        creates: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::Sandbox for FlakySandbox {
+        fn dir(&self) -> &PathBuf {
+            self.sandbox.dir()
+        }
+
+        async fn create(&self, outputs: &[PathBuf]) -> Result<&PathBuf, anyhow::Error> {
+            if self.creates.fetch_add(1, Ordering::Relaxed) == 0 {
+                return Err(std::io::Error::from(self.error_kind)).context("force_symlink()");
+            }
+            self.sandbox.create(outputs).await
+        }
+
+        async fn move_output_files_into_out_dir(
+            &self,
+            output_paths: &[PathBuf],
+        ) -> Result<(), anyhow::Error> {
+            self.sandbox
+                .move_output_files_into_out_dir(output_paths)
+                .await
+        }
+
+        async fn output_glob_files(
+            &self,
+            outputs: &[PathBuf],
+        ) -> Result<Vec<PathBuf>, anyhow::Error> {
+            self.sandbox.output_glob_files(outputs).await
+        }
+
+        async fn undeclared_files(
+            &self,
+            outputs: &[PathBuf],
+        ) -> Result<Vec<PathBuf>, anyhow::Error> {
+            self.sandbox.undeclared_files(outputs).await
+        }
+
+        async fn destroy(&self) -> Result<(), anyhow::Error> {
+            self.sandbox.destroy().await
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn transient_sandbox_errors_are_marked() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.clean();
+        let id = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "a".into(),
+                executable: "sh".into(),
+                args: vec!["-c".into(), "touch \"$0\"".into(), "a.txt".into()],
+                outputs: vec!["a.txt".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        let command = &razel.commands[id];
+        let output_paths = command
+            .outputs
+            .iter()
+            .map(|x| razel.files[*x].path.clone())
+            .collect_vec();
+        // not used without cache
+        let action_digest = Default::default();
+        let exec = |error_kind| {
+            let creates = Arc::new(AtomicUsize::new(0));
+            let sandbox = Box::new(FlakySandbox {
+                sandbox: TmpDirSandbox::new(dir.dir(), "a", vec![]),
+                error_kind,
+                creates: creates.clone(),
+            });
+            let result = Razel::exec_action_with_sandbox(
+                &action_digest,
+                None,
+                false,
+                &command.executor,
+                sandbox,
+                &output_paths,
+                &[],
+                None,
+                &razel.current_dir,
+                &razel.out_dir,
+            );
+            async move { (result.await.map(|x| x.0), creates.load(Ordering::Relaxed)) }
+        };
+        let (result, creates) = exec(std::io::ErrorKind::AlreadyExists).await;
+        assert!(crate::TransientSandboxError::is(&result.unwrap_err()));
+        assert_eq!(creates, 1);
+        // missing files and other errors are not retried
+        for error_kind in [
+            std::io::ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied,
+        ] {
+            let (result, creates) = exec(error_kind).await;
+            assert!(!crate::TransientSandboxError::is(&result.unwrap_err()));
+            assert_eq!(creates, 1);
+        }
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn deadline_exceeded() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        razel.read_cache = false;
+        razel.deadline = Some(Duration::from_secs(1));
+        for (name, script, inputs, outputs) in [
+            ("fast", "true", vec![], vec![]),
+            ("slow", "sleep 30; touch slow.txt", vec![], vec!["slow.txt"]),
+            ("after", "true", vec!["slow.txt"], vec![]),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), script.into()],
+                    inputs: inputs.into_iter().map(|x| x.into()).collect(),
+                    outputs: outputs.into_iter().map(|x| x.into()).collect(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let start = Instant::now();
+        let stats = razel.run(true, true, "", None, vec![], None).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(stats.deadline_exceeded);
+        assert_eq!(
+            stats.exec,
+            SchedulerExecStats {
+                succeeded: 1,
+                failed: 1,
+                skipped: 0,
+                not_run: 1,
+            }
+        );
+        let metadata_dir = razel.out_dir.join("razel-metadata");
+        let log: Vec<LogFileItem> =
+            serde_json::from_slice(&fs::read(metadata_dir.join("log.json")).unwrap()).unwrap();
+        let status = |name: &str| log.iter().find(|x| x.name == name).unwrap().status;
+        assert_eq!(status("fast"), ExecutionStatus::Success);
+        assert_eq!(status("slow"), ExecutionStatus::Timeout);
+        assert_eq!(status("after"), ExecutionStatus::NotStarted);
+        let report: Report =
+            serde_json::from_slice(&fs::read(metadata_dir.join("report.json")).unwrap()).unwrap();
+        assert!(report.deadline_exceeded);
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn deadline_tag() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        razel.read_cache = false;
+        for (name, script, deps, tags) in [
+            ("first", "sleep 2", vec![], vec![]),
+            ("killed", "sleep 30", vec![], vec![Tag::Deadline(1)]),
+            (
+                "late",
+                "true",
+                vec!["first".to_string()],
+                vec![Tag::Deadline(1)],
+            ),
+            (
+                "in-time",
+                "true",
+                vec!["first".to_string()],
+                vec![Tag::Deadline(60)],
+            ),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), script.into()],
+                    deps,
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let start = Instant::now();
+        let stats = razel.run(true, true, "", None, vec![], None).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!stats.deadline_exceeded);
+        let log: Vec<LogFileItem> = serde_json::from_slice(
+            &fs::read(razel.out_dir.join("razel-metadata").join("log.json")).unwrap(),
+        )
+        .unwrap();
+        let status = |name: &str| log.iter().find(|x| x.name == name).unwrap().status;
+        assert_eq!(status("first"), ExecutionStatus::Success);
+        assert_eq!(status("killed"), ExecutionStatus::Timeout);
+        // scheduled after the deadline because of the dep
+        assert_eq!(status("late"), ExecutionStatus::Timeout);
+        assert_eq!(status("in-time"), ExecutionStatus::Success);
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn deadline_tag_cache_hit() {
+        let dir = new_tmp_dir!();
+        let run = |deadline: u32| {
+            let dir = &dir;
+            async move {
+                let mut razel = Razel::new();
+                razel.set_workspace_dir(dir.dir()).unwrap();
+                for (name, script, deps, stdout, tags) in [
+                    ("first", "sleep 2", vec![], None, vec![Tag::NoCache]),
+                    (
+                        "late",
+                        "echo late",
+                        vec!["first".to_string()],
+                        Some("late.txt".to_string()),
+                        vec![Tag::Deadline(deadline)],
+                    ),
+                ] {
+                    razel
+                        .push_custom_command(CustomCommandSpec {
+                            name: name.into(),
+                            executable: "sh".into(),
+                            args: vec!["-c".into(), script.into()],
+                            deps,
+                            stdout,
+                            tags,
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+                razel
+                    .run(true, true, "", Some(dir.join("cache")), vec![], None)
+                    .await
+                    .unwrap();
+                let log: Vec<LogFileItem> = serde_json::from_slice(
+                    &fs::read(razel.out_dir.join("razel-metadata").join("log.json")).unwrap(),
+                )
+                .unwrap();
+                let late = razel.get_command_by_name(&"late".into()).unwrap();
+                let output = fs::read_to_string(&razel.files[late.outputs[0]].path).ok();
+                razel.clean();
+                let late = log.into_iter().find(|x| x.name == "late").unwrap();
+                (late.status, late.cache, output)
+            }
+        };
+        let output = Some("late\n".to_string());
+        assert_eq!(
+            run(60).await,
+            (ExecutionStatus::Success, None, output.clone())
+        );
+        // scheduled after the deadline, but cached
+        let (status, cache, cached_output) = run(1).await;
+        assert_eq!(status, ExecutionStatus::Success);
+        assert!(cache.is_some());
+        assert_eq!(cached_output, output);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn stdout_file_as_input() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let input = dir.join_and_write_file("input.txt", "a");
+        let run = || async {
+            let mut razel = Razel::new();
+            razel.set_workspace_dir(dir.dir()).unwrap();
+            for (name, input, stdout) in [("a", "input.txt", "a.txt"), ("b", "a.txt", "b.txt")] {
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: name.into(),
+                        executable: "cat".into(),
+                        args: vec![input.into()],
+                        inputs: vec![input.into()],
+                        stdout: Some(stdout.into()),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+            let a = razel.get_command_by_name(&"a".into()).unwrap();
+            let a_txt = a.outputs[0];
+            assert_eq!(razel.files[a_txt].creating_command, Some(a.id));
+            let b = razel.get_command_by_name(&"b".into()).unwrap();
+            assert_eq!(b.inputs, [a_txt]);
+            let b_txt = b.outputs[0];
+            let stats = razel
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 2);
+            let b_txt = fs::read_to_string(&razel.files[b_txt].path).unwrap();
+            razel.clean();
+            (stats.cache_hits, b_txt)
+        };
+        assert_eq!(run().await, (0, "a".to_string()));
+        // stdout file is restored from the cache
+        assert_eq!(run().await, (2, "a".to_string()));
+        fs::write(&input, "changed").unwrap();
+        assert_eq!(run().await, (0, "changed".to_string()));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn archive_entries_as_inputs() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let write_tar = |b: &str, unused: &str| {
+            dir.join_and_write_tar(
+                "data.tar",
+                &[("dir/a.csv", "a"), ("b.csv", b), ("unused.csv", unused)],
+            );
+        };
+        let run = || async {
+            let mut razel = Razel::new();
+            razel.set_workspace_dir(dir.dir()).unwrap();
+            let entries = ["data.tar#dir/a.csv", "data.tar#b.csv"];
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "cat".into(),
+                    executable: "cat".into(),
+                    args: entries.map(String::from).to_vec(),
+                    inputs: entries.map(String::from).to_vec(),
+                    stdout: Some("out.txt".into()),
+                    ..Default::default()
+                })
+                .unwrap();
+            let command = razel.get_command_by_name(&"cat".into()).unwrap();
+            // the archive is the only declared input
+            assert_eq!(command.inputs.len(), 1);
+            assert_eq!(command.archive_entries.len(), 2);
+            let out = command.outputs[0];
+            let stats = razel
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 1);
+            let out = fs::read_to_string(&razel.files[out].path).unwrap();
+            razel.clean();
+            (stats.cache_hits, out)
+        };
+        write_tar("b", "x");
+        assert_eq!(run().await, (0, "ab".to_string()));
+        // only the content of the used entries is part of the cache key
+        write_tar("b", "changed");
+        assert_eq!(run().await, (1, "ab".to_string()));
+        write_tar("changed", "x");
+        assert_eq!(run().await, (0, "achanged".to_string()));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn archive_entries_rejected() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        let push = |razel: &mut Razel, name: &str, input: &str, tags: Vec<Tag>| {
+            razel.push_custom_command(CustomCommandSpec {
+                name: name.into(),
+                executable: "cat".into(),
+                args: vec![input.into()],
+                inputs: vec![input.into()],
+                tags,
+                ..Default::default()
+            })
+        };
+        // entries are extracted into the sandbox
+        let error = push(&mut razel, "a", "data.tar#a.csv", vec![Tag::NoSandbox]).unwrap_err();
+        assert!(error.to_string().contains("only supported"), "{error}");
+        push(&mut razel, "b", "data.tar#b.csv", vec![]).unwrap();
+        let error = razel.add_tag_for_command("b", Tag::NoSandbox).unwrap_err();
+        assert!(error.to_string().contains("only supported"), "{error}");
+        // a file named like an archive entry is ambiguous
+        dir.join_and_write_file("data.tar#c.csv", "c");
+        let error = push(&mut razel, "c", "data.tar#c.csv", vec![]).unwrap_err();
+        assert!(error.to_string().contains("ambiguous"), "{error}");
+    }
 }
@@ -0,0 +1,196 @@
+use super::Razel;
+use crate::CommandId;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory within the out dir to collect the artifacts of all commands, e.g. for CI uploads
+pub static ARTIFACTS_DIR: &str = "artifacts";
+
+impl Razel {
+    /// Mark outputs of a command to be copied to `razel-out/artifacts/<name>/` after success
+    pub fn set_artifacts(&mut self, id: CommandId, artifacts: &[String]) -> Result<()> {
+        for arg in artifacts {
+            let rel_path = self.rel_path(arg)?;
+            match self.path_to_file_id.get(&rel_path) {
+                Some(&file_id) if self.files[file_id].creating_command == Some(id) => {
+                    self.commands[id].artifacts.push(file_id);
+                }
+                _ => bail!(
+                    "artifact {arg} is not an output of {}",
+                    self.commands[id].name
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the artifacts of a succeeded command, executed or restored from cache
+    pub(super) fn copy_artifacts(&self, id: CommandId) {
+        for (src, dst) in self.artifact_paths(id) {
+            copy_artifact(&src, &dst);
+        }
+    }
+
+    /// Output paths and destinations of the artifacts of a command, failed commands copy them
+    /// before their outputs are discarded, see [copy_existing_artifacts]
+    pub(super) fn artifact_paths(&self, id: CommandId) -> Vec<(PathBuf, PathBuf)> {
+        self.commands[id]
+            .artifacts
+            .iter()
+            .map(|x| {
+                let src = &self.files[*x].path;
+                (src.clone(), self.artifact_path(id, src))
+            })
+            .collect()
+    }
+
+    /// Keeps the path of the output within the out dir to avoid collisions
+    fn artifact_path(&self, id: CommandId, output: &Path) -> PathBuf {
+        self.out_dir
+            .join(ARTIFACTS_DIR)
+            .join(artifacts_dir_name(&self.commands[id].name))
+            .join(output.strip_prefix(&self.out_dir).unwrap_or(output))
+    }
+}
+
+/// Copy the artifacts which exist in `dir`, e.g. the sandbox of a failed command
+pub(super) fn copy_existing_artifacts(dir: Option<&Path>, artifacts: &[(PathBuf, PathBuf)]) {
+    for (src, dst) in artifacts {
+        let src = dir.map_or_else(|| src.clone(), |x| x.join(src));
+        if src.is_file() {
+            copy_artifact(&src, dst);
+        }
+    }
+}
+
+fn copy_artifact(src: &Path, dst: &Path) {
+    if let Err(x) = fs::create_dir_all(dst.parent().unwrap())
+        .map_err(anyhow::Error::from)
+        // existing files might be read-only because fs::copy() keeps the permissions
+        .and_then(|_| {
+            fs::remove_file(dst).ok();
+            fs::copy(src, dst).with_context(|| format!("{src:?} -> {dst:?}"))
+        })
+    {
+        warn!("failed to copy artifact: {x:?}");
+    }
+}
+
+/// Escapes the command name to a single path component, `_` starts an escape sequence to keep
+/// names of different commands distinct, e.g. `a/b` -> `a_2fb` and `a_b` -> `a_5fb`
+fn artifacts_dir_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (i, x) in name.bytes().enumerate() {
+        // a leading dot would allow `.` and `..`
+        if x.is_ascii_alphanumeric() || x == b'-' || (x == b'.' && i != 0) {
+            escaped.push(x as char);
+        } else {
+            escaped.push_str(&format!("_{x:02x}"));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Tag;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use itertools::Itertools;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn artifact_is_copied_on_cache_hit() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let run = || {
+            let cache_dir = cache_dir.clone();
+            async move {
+                let mut razel = Razel::new();
+                razel.clean();
+                let id = razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: "//test:report".into(),
+                        executable: "sh".into(),
+                        args: vec!["-c".into(), "echo passed".into()],
+                        stdout: Some("report.txt".into()),
+                        ..Default::default()
+                    })
+                    .unwrap();
+                assert!(razel.set_artifacts(id, &["other.txt".into()]).is_err());
+                razel.set_artifacts(id, &["report.txt".into()]).unwrap();
+                razel
+                    .run(false, true, "", Some(cache_dir), vec![], None)
+                    .await
+                    .unwrap()
+            }
+        };
+        let artifact = Path::new("razel-out/artifacts/_2f_2ftest_3areport/report.txt");
+        let stats = run().await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 0);
+        assert_eq!(fs::read_to_string(artifact).unwrap(), "passed\n");
+        fs::remove_dir_all("razel-out/artifacts").unwrap();
+        let stats = run().await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 1);
+        assert_eq!(fs::read_to_string(artifact).unwrap(), "passed\n");
+        Razel::new().clean();
+    }
+
+    #[test]
+    fn artifacts_dir_names_are_distinct() {
+        let names = ["a/b", "a_b", "a:b", "a_2fb", ".", "..", "a.b", "ä"];
+        let escaped = names.map(artifacts_dir_name);
+        assert_eq!(
+            escaped,
+            ["a_2fb", "a_5fb", "a_3ab", "a_5f2fb", "_2e", "_2e.", "a.b", "_c3_a4"]
+        );
+        assert!(escaped.iter().all_unique());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn artifacts_of_failed_commands() {
+        for (no_sandbox, atomic_outputs) in [(false, false), (true, false), (true, true)] {
+            let mut razel = Razel::new();
+            razel.clean();
+            razel.atomic_outputs = atomic_outputs;
+            let tags = if no_sandbox {
+                vec![Tag::NoSandbox]
+            } else {
+                vec![]
+            };
+            let id = razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "test".into(),
+                    executable: "sh".into(),
+                    args: vec![
+                        "-c".into(),
+                        "echo failed > \"$0\"; exit 1".into(),
+                        "report.txt".into(),
+                    ],
+                    outputs: vec!["report.txt".into(), "missing.txt".into()],
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap();
+            razel
+                .set_artifacts(id, &["report.txt".into(), "missing.txt".into()])
+                .unwrap();
+            let stats = razel.run(true, true, "", None, vec![], None).await.unwrap();
+            assert_eq!(stats.exec.failed, 1);
+            let dir = Path::new("razel-out/artifacts/test");
+            assert_eq!(
+                fs::read_to_string(dir.join("report.txt")).unwrap(),
+                "failed\n",
+                "no_sandbox: {no_sandbox}, atomic_outputs: {atomic_outputs}"
+            );
+            assert!(!dir.join("missing.txt").exists());
+        }
+        Razel::new().clean();
+    }
+}
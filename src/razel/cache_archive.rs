@@ -0,0 +1,51 @@
+use super::Razel;
+use crate::cache::Cache;
+use crate::config::select_cache_dir;
+use crate::FileId;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+impl Razel {
+    /// Export the cached action results and output files of all commands into an archive.
+    ///
+    /// Action digests are resolved with the output digests of cached action results, commands are
+    /// not executed and outputs are not materialized. Commands with missing inputs are skipped.
+    pub async fn export_cache(&mut self, archive: &Path, cache_dir: Option<PathBuf>) -> Result<()> {
+        let (cache_dir, _) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        let mut cache = Cache::new(cache_dir, self.out_dir.clone())?;
+        self.materialize_only = true;
+        self.digest_input_files().await?;
+        self.digest_input_archive_entries().await?;
+        let mut action_digests = vec![];
+        let mut cached_files: Vec<FileId> = vec![];
+        for id in self.commands_in_execution_order() {
+            let command = &self.commands[id];
+            if !self.has_input_digests(command) {
+                continue;
+            }
+            let action_digest = self.action_digest(command);
+            if let Ok((action_result, _)) = cache.get_action_result(&action_digest, false).await {
+                self.set_cached_output_digests(action_result.output_files, &mut cached_files);
+                action_digests.push(action_digest);
+            }
+        }
+        for id in cached_files {
+            self.files[id].digest = None;
+        }
+        let exported = cache.export_archive(&action_digests, archive).await?;
+        println!(
+            "exported {exported} of {} commands to {archive:?}",
+            self.commands.len() - self.excluded_commands_len
+        );
+        Ok(())
+    }
+
+    /// Import action results and output files from an archive created by export_cache()
+    pub async fn import_cache(&self, archive: &Path, cache_dir: Option<PathBuf>) -> Result<()> {
+        let (cache_dir, _) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        let cache = Cache::new(cache_dir, self.out_dir.clone())?;
+        let imported = cache.import_archive(archive).await?;
+        println!("imported {imported} commands from {archive:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use super::Razel;
+use crate::cache::LOCK_FILE_NAME;
+use crate::config::select_cache_dir;
+use crate::FileLock;
+use anyhow::{bail, Context};
+use std::fs;
+use std::path::PathBuf;
+
+/// What to remove with `razel clean`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CleanTargets {
+    /// razel-out and the output base
+    pub out: bool,
+    /// ac, cas, discovered includes, out dir indexes and compiled WASI modules of the local cache
+    pub cache: bool,
+    /// sandbox dirs left by crashed or killed razel processes
+    pub sandbox: bool,
+}
+
+impl Razel {
+    /// Removes razel-out and/or parts of the local cache.
+    ///
+    /// Cleaning the cache or sandbox dirs fails while another razel process uses the cache dir,
+    /// detected by the lock it holds on a file in the cache dir.
+    ///
+    /// The output base is only removed if razel created it, see [Razel::clean], and if it does not
+    /// contain the cache dir.
+    pub fn clean_dirs(
+        &self,
+        targets: CleanTargets,
+        cache_dir: Option<PathBuf>,
+    ) -> Result<(), anyhow::Error> {
+        let (cache_dir, _) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        if let Some(x) = self.output_base.as_ref().filter(|_| targets.out) {
+            if self.current_dir.join(&cache_dir).starts_with(x) {
+                bail!("output base {x:?} contains the cache dir {cache_dir:?}");
+            }
+        }
+        if (targets.cache || targets.sandbox) && cache_dir.exists() {
+            let Some(_lock) = FileLock::try_exclusive(&cache_dir.join(LOCK_FILE_NAME))? else {
+                bail!("cache dir {cache_dir:?} is used by a running razel process");
+            };
+            let mut dirs = vec![];
+            if targets.cache {
+                dirs.extend(
+                    ["ac", "cas", "includes", "out-dir-index", "wasi"].map(|x| cache_dir.join(x)),
+                );
+            }
+            if targets.sandbox {
+                dirs.push(cache_dir.join("sandbox"));
+            }
+            for dir in dirs.iter().filter(|x| x.exists()) {
+                fs::remove_dir_all(dir).with_context(|| format!("failed to remove {dir:?}"))?;
+            }
+        }
+        if targets.out {
+            self.clean();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::LocalCache;
+    use crate::new_tmp_dir;
+    use serial_test::serial;
+    use std::path::Path;
+
+    /// Creates out dir, cache and sandbox dirs, returns the paths of out, cache and sandbox dirs
+    fn create_dirs(razel: &Razel, cache_dir: &Path) -> [PathBuf; 3] {
+        let out = razel.out_dir.clone();
+        let cache = cache_dir.join("cas");
+        let sandbox = cache_dir.join("sandbox").join("host").join("1234567");
+        for dir in [
+            &out,
+            &cache,
+            &cache_dir.join("ac"),
+            &cache_dir.join("includes"),
+            &cache_dir.join("out-dir-index"),
+            &sandbox,
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        [out, cache, sandbox]
+    }
+
+    #[test]
+    #[serial]
+    fn clean_targets() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let razel = Razel::new();
+        for (targets, removed) in [
+            (
+                CleanTargets {
+                    out: true,
+                    ..Default::default()
+                },
+                [true, false, false],
+            ),
+            (
+                CleanTargets {
+                    cache: true,
+                    ..Default::default()
+                },
+                [false, true, false],
+            ),
+            (
+                CleanTargets {
+                    sandbox: true,
+                    ..Default::default()
+                },
+                [false, false, true],
+            ),
+            (
+                CleanTargets {
+                    out: true,
+                    cache: true,
+                    sandbox: true,
+                },
+                [true, true, true],
+            ),
+        ] {
+            let dirs = create_dirs(&razel, &cache_dir);
+            razel.clean_dirs(targets, Some(cache_dir.clone())).unwrap();
+            for (dir, removed) in dirs.iter().zip(removed) {
+                assert_eq!(dir.exists(), !removed, "{targets:?} {dir:?}");
+            }
+            for x in ["ac", "includes", "out-dir-index"] {
+                assert_eq!(cache_dir.join(x).exists(), !targets.cache);
+            }
+        }
+        razel.clean();
+    }
+
+    #[test]
+    #[serial]
+    fn output_base_containing_cache_dir() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.set_output_base(dir.dir()).unwrap();
+        let targets = CleanTargets {
+            out: true,
+            ..Default::default()
+        };
+        assert!(razel.clean_dirs(targets, Some(dir.join("cache"))).is_err());
+        assert!(dir.dir().exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    #[serial]
+    fn cache_in_use() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let razel = Razel::new();
+        let cache = LocalCache::new(cache_dir.clone()).unwrap();
+        let targets = CleanTargets {
+            cache: true,
+            ..Default::default()
+        };
+        assert!(razel.clean_dirs(targets, Some(cache_dir.clone())).is_err());
+        assert!(cache_dir.join("cas").exists());
+        drop(cache);
+        razel.clean_dirs(targets, Some(cache_dir.clone())).unwrap();
+        assert!(!cache_dir.join("cas").exists());
+    }
+}
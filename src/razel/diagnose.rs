@@ -0,0 +1,406 @@
+use super::Razel;
+use crate::cache::Cache;
+use crate::config::{select_cache_dir, select_sandbox_dir};
+use crate::metadata::{LogFile, LogFileItem};
+use crate::{CommandId, FileId, VersionInfo};
+use anyhow::{Context, Result};
+use itertools::{chain, Itertools};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Parts of env var names and args which likely contain secrets, compared case-insensitive to
+/// the parts separated by `_` or `-`, optionally in plural
+static SECRET_ENV_PATTERNS: [&str; 7] = [
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "AUTH",
+    "KEY",
+];
+static REDACTED: &str = "<redacted>";
+
+/// Diagnostics to attach to bug reports, see `razel diagnose`
+#[derive(Serialize)]
+struct Diagnostics {
+    version: VersionInfo,
+    config: Config,
+    commands: Vec<CommandInfo>,
+    critical_path: CriticalPath,
+    /// log.json of the last run, if any
+    last_log: Option<Vec<LogFileItem>>,
+}
+
+/// Resolved config like shown by `razel exec --info`
+#[derive(Serialize)]
+struct Config {
+    workspace_dir: PathBuf,
+    output_directory: PathBuf,
+    cache_dir: PathBuf,
+    cache_dir_reason: &'static str,
+    sandbox_dir: PathBuf,
+    worker_threads: usize,
+    cache_salt: Option<String>,
+    /// args set from config files and the file they were read from
+    config_sources: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Serialize)]
+struct CommandInfo {
+    name: String,
+    /// names of commands creating inputs and explicit deps
+    deps: Vec<String>,
+    /// including the executable, values of secret looking args are redacted
+    args: Vec<String>,
+    /// values of secret looking env vars are redacted
+    env: BTreeMap<String, String>,
+    /// not set if an input is missing or created by a not cached command
+    action_digest: Option<String>,
+    cache: CacheState,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CacheState {
+    Hit,
+    Miss,
+    /// action digest can not be computed without executing commands
+    Unknown,
+}
+
+/// Longest chain of dependent commands using exec durations of the last run
+#[derive(Default, Serialize)]
+struct CriticalPath {
+    /// [s]
+    duration: f32,
+    commands: Vec<String>,
+}
+
+impl Razel {
+    /// Write a json file with config, dependency graph, action digests, cache state, critical
+    /// path and the last log.json for bug reports. No commands are executed.
+    pub async fn diagnose(
+        &mut self,
+        path: &Path,
+        cache_dir: Option<PathBuf>,
+        config_sources: &[(String, PathBuf)],
+    ) -> Result<()> {
+        let last_log = LogFile::from_path(self.out_dir.join("razel-metadata").join("log.json"))
+            .ok()
+            .map(|x| x.items);
+        let (cache_dir, cache_dir_reason) = select_cache_dir(cache_dir, &self.workspace_dir)?;
+        let config = Config {
+            workspace_dir: self.workspace_dir.clone(),
+            output_directory: self.output_directory(),
+            sandbox_dir: select_sandbox_dir(&cache_dir)?,
+            cache_dir: cache_dir.clone(),
+            cache_dir_reason,
+            worker_threads: self.worker_threads,
+            cache_salt: self.cache_salt.clone(),
+            config_sources: config_sources
+                .iter()
+                .map(|(key, path)| (key.clone(), path.clone()))
+                .collect(),
+        };
+        let mut cache = Cache::new(cache_dir, self.out_dir.clone())?;
+        // nothing is executed, commands with missing inputs are reported with unknown cache state
+        self.materialize_only = true;
+        self.digest_input_files().await?;
+        self.digest_input_archive_entries().await?;
+        let ids = self.commands_in_execution_order();
+        let mut cached_files: Vec<FileId> = vec![];
+        let mut commands = Vec::with_capacity(ids.len());
+        for id in &ids {
+            let command = &self.commands[*id];
+            let (action_digest, cache_state) = if self.has_input_digests(command) {
+                let action_digest = self.action_digest(command);
+                match cache.get_action_result(&action_digest, false).await {
+                    Ok((action_result, _)) => {
+                        self.set_cached_output_digests(
+                            action_result.output_files,
+                            &mut cached_files,
+                        );
+                        (Some(action_digest.hash), CacheState::Hit)
+                    }
+                    Err(_) => (Some(action_digest.hash), CacheState::Miss),
+                }
+            } else {
+                (None, CacheState::Unknown)
+            };
+            let command = &self.commands[*id];
+            commands.push(CommandInfo {
+                name: command.name.clone(),
+                deps: self
+                    .deps_of_command(*id)
+                    .into_iter()
+                    .map(|x| self.commands[x].name.clone())
+                    .collect(),
+                args: redact_args(&command.executor.args_with_executable()),
+                env: command
+                    .executor
+                    .env()
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| (key.clone(), redact_env_value(key, value)))
+                    .collect(),
+                action_digest,
+                cache: cache_state,
+            });
+        }
+        for id in cached_files {
+            self.files[id].digest = None;
+        }
+        let critical_path = self.critical_path(&ids, last_log.as_deref().unwrap_or_default());
+        let diagnostics = Diagnostics {
+            version: VersionInfo::new(),
+            config,
+            commands,
+            critical_path,
+            last_log,
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&diagnostics)?)
+            .with_context(|| format!("failed to write {path:?}"))?;
+        println!("wrote diagnostics to {path:?}");
+        Ok(())
+    }
+
+    /// Commands creating inputs of the command and its explicit deps
+    fn deps_of_command(&self, id: CommandId) -> Vec<CommandId> {
+        let command = &self.commands[id];
+        chain!(
+            &command.executables,
+            &command.inputs,
+            &command.config_inputs
+        )
+        .filter_map(|x| self.files[*x].creating_command)
+        .chain(command.deps.iter().copied())
+        .unique()
+        .collect()
+    }
+
+    /// `ids` must be in execution order
+    fn critical_path(&self, ids: &[CommandId], log: &[LogFileItem]) -> CriticalPath {
+        let exec_durations: HashMap<&str, f32> = log
+            .iter()
+            .map(|x| (x.name.as_str(), x.exec.unwrap_or_default()))
+            .collect();
+        // finish time of the longest path to each command and the previous command on it
+        let mut paths: HashMap<CommandId, (f32, Option<CommandId>)> = HashMap::new();
+        for id in ids {
+            let (start, prev) = self
+                .deps_of_command(*id)
+                .into_iter()
+                .filter_map(|x| paths.get(&x).map(|(finish, _)| (*finish, Some(x))))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap_or_default();
+            let duration = exec_durations
+                .get(self.commands[*id].name.as_str())
+                .copied()
+                .unwrap_or_default();
+            paths.insert(*id, (start + duration, prev));
+        }
+        let Some((mut id, (duration, _))) = ids
+            .iter()
+            .map(|x| (*x, paths[x]))
+            .max_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+        else {
+            return Default::default();
+        };
+        let mut commands = vec![self.commands[id].name.clone()];
+        while let Some(prev) = paths[&id].1 {
+            commands.push(self.commands[prev].name.clone());
+            id = prev;
+        }
+        commands.reverse();
+        CriticalPath { duration, commands }
+    }
+}
+
+/// Returns true for e.g. `API_TOKEN` or `--auth-keys`, but not for `KEYBOARD_LAYOUT`
+fn is_secret_name(name: &str) -> bool {
+    name.to_uppercase().split(['_', '-']).any(|part| {
+        SECRET_ENV_PATTERNS
+            .iter()
+            .any(|x| part == *x || part.strip_suffix('S') == Some(x))
+    })
+}
+
+fn redact_env_value(key: &str, value: &str) -> String {
+    if is_secret_name(key) {
+        REDACTED.into()
+    } else {
+        value.into()
+    }
+}
+
+/// Redacts values of `--token=<value>`/`TOKEN=<value>` args and the arg following `--token`
+fn redact_args(args: &[String]) -> Vec<String> {
+    let mut redact_next = false;
+    args.iter()
+        .map(|arg| {
+            if std::mem::take(&mut redact_next) {
+                return REDACTED.into();
+            }
+            match arg.split_once('=') {
+                Some((name, _)) if is_secret_name(name) => format!("{name}={REDACTED}"),
+                Some(_) => arg.clone(),
+                None => {
+                    redact_next = arg.starts_with('-') && is_secret_name(arg);
+                    arg.clone()
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn diagnose() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let new_razel = || {
+            let mut razel = Razel::new();
+            for (name, inputs, outputs) in [
+                ("a", vec![], vec!["a.txt"]),
+                ("b", vec!["a.txt"], vec!["b.txt"]),
+            ] {
+                razel
+                    .push_custom_command(CustomCommandSpec {
+                        name: name.into(),
+                        executable: "sh".into(),
+                        args: vec!["-c".into(), "touch \"$0\"".into(), outputs[0].into()],
+                        env: [
+                            ("API_TOKEN", "secret"),
+                            ("LANG", "C"),
+                            ("KEYBOARD_LAYOUT", "de"),
+                        ]
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .into(),
+                        inputs: inputs.into_iter().map(|x| x.into()).collect(),
+                        outputs: outputs.into_iter().map(|x| x.into()).collect(),
+                        ..Default::default()
+                    })
+                    .unwrap();
+            }
+            razel
+        };
+        let mut razel = new_razel();
+        razel.clean();
+        razel
+            .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+            .await
+            .unwrap();
+        let path = dir.join("diagnose.json");
+        new_razel()
+            .diagnose(&path, Some(cache_dir), &[])
+            .await
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            json.as_object().unwrap().keys().sorted().collect_vec(),
+            ["commands", "config", "critical_path", "last_log", "version"]
+        );
+        let commands = json["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1]["name"], "b");
+        assert_eq!(commands[1]["deps"], serde_json::json!(["a"]));
+        for command in commands {
+            assert_eq!(command["cache"], "hit");
+            assert!(command["action_digest"].is_string());
+            assert_eq!(command["env"]["API_TOKEN"], REDACTED);
+            assert_eq!(command["env"]["LANG"], "C");
+            assert_eq!(command["env"]["KEYBOARD_LAYOUT"], "de");
+            assert_eq!(command["args"][1], "-c");
+        }
+        assert_eq!(
+            json["critical_path"]["commands"],
+            serde_json::json!(["a", "b"])
+        );
+        assert_eq!(json["last_log"].as_array().unwrap().len(), 2);
+        razel.clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn diagnose_archive_entries() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let archive = dir.join_and_write_tar("data.tar", &[("f.txt", "f")]);
+        let entry = format!("{}#f.txt", archive.to_str().unwrap());
+        let new_razel = || {
+            let mut razel = Razel::new();
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: "a".into(),
+                    executable: "cat".into(),
+                    args: vec![entry.clone()],
+                    inputs: vec![entry.clone()],
+                    stdout: Some("a.txt".into()),
+                    ..Default::default()
+                })
+                .unwrap();
+            razel
+        };
+        let mut razel = new_razel();
+        razel.clean();
+        razel
+            .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+            .await
+            .unwrap();
+        let path = dir.join("diagnose.json");
+        new_razel()
+            .diagnose(&path, Some(cache_dir), &[])
+            .await
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["commands"][0]["cache"], "hit");
+        razel.clean();
+    }
+
+    #[test]
+    fn redact() {
+        for (name, is_secret) in [
+            ("API_TOKEN", true),
+            ("aws_secret_access_key", true),
+            ("AUTH", true),
+            ("CREDENTIALS", true),
+            ("--auth-keys", true),
+            ("KEYBOARD_LAYOUT", false),
+            ("MONKEY", false),
+            ("LANG", false),
+        ] {
+            assert_eq!(is_secret_name(name), is_secret, "{name}");
+        }
+        let args = [
+            "tool",
+            "--token=abc",
+            "--password",
+            "abc",
+            "-v",
+            "PATH=/bin",
+            "x",
+        ];
+        assert_eq!(
+            redact_args(&args.map(|x| x.to_string())),
+            [
+                "tool",
+                "--token=<redacted>",
+                "--password",
+                REDACTED,
+                "-v",
+                "PATH=/bin",
+                "x"
+            ]
+        );
+    }
+}
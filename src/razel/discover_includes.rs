@@ -0,0 +1,397 @@
+use super::Razel;
+use crate::bazel_remote_exec::Digest;
+use crate::executors::{CustomCommandExecutor, Executor};
+use crate::{CommandId, FileId};
+use anyhow::{bail, Context};
+use futures_util::StreamExt;
+use itertools::Itertools;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Executables detected as C/C++ compilers, also with a prefix like `arm-none-eabi-`
+const COMPILERS: [&str; 6] = ["cc", "c++", "gcc", "g++", "clang", "clang++"];
+const SOURCE_EXTENSIONS: [&str; 6] = ["c", "cc", "cpp", "cxx", "c++", "C"];
+
+/// Files read by the preprocessor for a compile command, stored in the local cache
+#[derive(Deserialize, Serialize)]
+struct DiscoveredIncludes {
+    /// paths relative to cwd and hashes of the files, the hash is empty for missing files
+    files: Vec<(PathBuf, String)>,
+}
+
+impl Razel {
+    /// Add headers included by C/C++ compile commands to their inputs, requires the cache.
+    ///
+    /// Runs the preprocessor of each compile command with `-MM -MG`, the result is cached until
+    /// one of the listed files changes. Headers which are missing because they are generated by
+    /// other commands are resolved using the include dirs of the command.
+    pub(super) async fn discover_includes(&mut self) -> Result<(), anyhow::Error> {
+        let cache_dir = self.cache.as_ref().unwrap().dir().join("includes");
+        std::fs::create_dir_all(&cache_dir)?;
+        let compile_commands = self
+            .commands
+            .iter()
+            .filter(|x| !x.is_excluded)
+            .filter_map(|x| match &x.executor {
+                Executor::CustomCommand(c) if is_compile_command(c) => Some((x.id, c.clone())),
+                _ => None,
+            })
+            .collect_vec();
+        let cwd = &self.current_dir;
+        let results = futures_util::stream::iter(compile_commands)
+            .map(|(id, executor)| {
+                let cache_dir = &cache_dir;
+                async move {
+                    let result = run_preprocessor(&executor, cwd, cache_dir).await;
+                    (id, executor, result)
+                }
+            })
+            .buffer_unordered(self.worker_threads)
+            .collect::<Vec<_>>()
+            .await;
+        for (id, executor, result) in results {
+            let paths = result.with_context(|| {
+                format!("failed to discover includes of {}", self.commands[id].name)
+            })?;
+            for path in paths {
+                self.add_discovered_include(id, &executor, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_discovered_include(
+        &mut self,
+        id: CommandId,
+        executor: &CustomCommandExecutor,
+        path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let file_id = if let Some(x) = self.output_file_id_for_path(path) {
+            x
+        } else if self.current_dir.join(path).is_file() {
+            let abs = self.current_dir.join(path);
+            self.input_file(abs.to_str().unwrap().into())?.id
+        } else if let Some(x) =
+            include_dirs(executor).find_map(|dir| self.output_file_id_for_path(&dir.join(path)))
+        {
+            x
+        } else {
+            warn!(
+                "{}: included file not found: {path:?}",
+                self.commands[id].name
+            );
+            return Ok(());
+        };
+        let command = &mut self.commands[id];
+        if !command.inputs.contains(&file_id)
+            && !command.executables.contains(&file_id)
+            && !command.outputs.contains(&file_id)
+        {
+            command.inputs.push(file_id);
+        }
+        Ok(())
+    }
+
+    /// Returns the output file for a path relative to cwd, e.g. `razel-out/gen/config.h`
+    fn output_file_id_for_path(&self, path: &Path) -> Option<FileId> {
+        let rel_path = path.strip_prefix(&self.out_dir).ok()?;
+        self.path_to_file_id
+            .get(rel_path)
+            .filter(|x| self.files[**x].creating_command.is_some())
+            .copied()
+    }
+}
+
+fn is_compile_command(executor: &CustomCommandExecutor) -> bool {
+    let Some(name) = Path::new(&executor.executable)
+        .file_stem()
+        .and_then(|x| x.to_str())
+    else {
+        return false;
+    };
+    COMPILERS
+        .iter()
+        .any(|x| name == *x || name.ends_with(&format!("-{x}")))
+        && executor.args.iter().any(|x| x == "-c")
+        && executor.args.iter().any(|x| {
+            Path::new(x)
+                .extension()
+                .is_some_and(|x| SOURCE_EXTENSIONS.iter().any(|e| x == *e))
+        })
+}
+
+/// Args to print the dependencies instead of compiling, missing headers are assumed to be generated
+fn preprocessor_args(args: &[String]) -> Vec<String> {
+    let mut result = Vec::with_capacity(args.len() + 2);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "-MF" | "-MT" | "-MQ" => {
+                iter.next();
+            }
+            "-c" | "-M" | "-MM" | "-MD" | "-MMD" | "-MG" | "-MP" => {}
+            x if x.starts_with("-o") => {}
+            _ => result.push(arg.clone()),
+        }
+    }
+    result.push("-MM".into());
+    result.push("-MG".into());
+    result
+}
+
+/// Returns the dirs of `-I` and `-iquote` args and of the source files
+fn include_dirs(executor: &CustomCommandExecutor) -> impl Iterator<Item = PathBuf> + '_ {
+    let args = &executor.args;
+    args.iter()
+        .enumerate()
+        .filter_map(|(i, x)| match x.as_str() {
+            "-I" | "-iquote" => args.get(i + 1).map(PathBuf::from),
+            x => x.strip_prefix("-I").map(PathBuf::from),
+        })
+        .chain(
+            args.iter()
+                .filter(|x| {
+                    Path::new(x)
+                        .extension()
+                        .is_some_and(|x| SOURCE_EXTENSIONS.iter().any(|e| x == *e))
+                })
+                .filter_map(|x| Path::new(x).parent().map(PathBuf::from)),
+        )
+}
+
+/// Returns the prerequisites of a make rule as written by the preprocessor with `-M`
+fn parse_make_rule(rule: &str) -> Vec<PathBuf> {
+    let rule = rule.replace("\\\r\n", " ").replace("\\\n", " ");
+    let Some((_, prerequisites)) = rule.split_once(": ") else {
+        return vec![];
+    };
+    let mut paths = vec![];
+    let mut path = String::new();
+    let mut chars = prerequisites.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(' ') => path.push(' '),
+                Some(x) => {
+                    path.push('\\');
+                    path.push(x);
+                }
+                None => path.push('\\'),
+            },
+            x if x.is_whitespace() => {
+                if !path.is_empty() {
+                    paths.push(PathBuf::from(std::mem::take(&mut path)));
+                }
+            }
+            x => path.push(x),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(PathBuf::from(path));
+    }
+    paths
+}
+
+/// Returns the files read by the preprocessor, cached until one of them changes
+async fn run_preprocessor(
+    executor: &CustomCommandExecutor,
+    cwd: &Path,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let args = preprocessor_args(&executor.args);
+    let key = Digest::for_string(
+        &[executor.executable.clone()]
+            .iter()
+            .chain(&args)
+            .chain(executor.env.iter().sorted().flat_map(|(k, v)| [k, v]))
+            .join("\0"),
+    );
+    let cache_file = cache_dir.join(key.hash);
+    if let Some(x) = read_cached_includes(&cache_file, cwd).await {
+        return Ok(x);
+    }
+    let output = tokio::process::Command::new(&executor.executable)
+        .env_clear()
+        .envs(&executor.env)
+        .args(&args)
+        .current_dir(cwd)
+        .output()
+        .await
+        .with_context(|| format!("failed to run {:?}", executor.executable))?;
+    if !output.status.success() {
+        bail!(
+            "preprocessor failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let paths = parse_make_rule(&String::from_utf8_lossy(&output.stdout));
+    let mut files = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let hash = Digest::for_path(cwd.join(path))
+            .await
+            .map(|x| x.hash)
+            .unwrap_or_default();
+        files.push((path.clone(), hash));
+    }
+    let json = serde_json::to_string(&DiscoveredIncludes { files })?;
+    tokio::fs::write(&cache_file, json)
+        .await
+        .with_context(|| format!("failed to write {cache_file:?}"))?;
+    Ok(paths)
+}
+
+/// Returns the cached paths if none of the files was changed, created or removed
+async fn read_cached_includes(cache_file: &Path, cwd: &Path) -> Option<Vec<PathBuf>> {
+    let json = tokio::fs::read_to_string(cache_file).await.ok()?;
+    let cached: DiscoveredIncludes = serde_json::from_str(&json).ok()?;
+    for (path, hash) in &cached.files {
+        let current = Digest::for_path(cwd.join(path))
+            .await
+            .map(|x| x.hash)
+            .unwrap_or_default();
+        if current != *hash {
+            return None;
+        }
+    }
+    Some(cached.files.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use serial_test::serial;
+
+    #[test]
+    fn make_rule() {
+        assert_eq!(
+            parse_make_rule("main.o: main.c a.h \\\n  dir/b\\ c.h\n"),
+            vec![
+                PathBuf::from("main.c"),
+                PathBuf::from("a.h"),
+                PathBuf::from("dir/b c.h")
+            ]
+        );
+        assert!(parse_make_rule("").is_empty());
+    }
+
+    #[test]
+    fn args() {
+        let args = [
+            "-c", "main.c", "-o", "main.o", "-MMD", "-MF", "main.d", "-O2",
+        ];
+        assert_eq!(
+            preprocessor_args(&args.map(String::from)),
+            vec!["main.c", "-O2", "-MM", "-MG"]
+        );
+    }
+
+    fn push_compile(razel: &mut Razel, source: &str, args: &[&str]) -> CommandId {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "compile".into(),
+                executable: "cc".into(),
+                args: ["-c", source, "-o", "main.o"]
+                    .iter()
+                    .chain(args)
+                    .map(|x| x.to_string())
+                    .collect(),
+                inputs: vec![source.into()],
+                outputs: vec!["main.o".into()],
+                ..Default::default()
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn header_becomes_input() {
+        if which::which("cc").is_err() {
+            println!("cc not available, skipping test");
+            return;
+        }
+        let dir = new_tmp_dir!();
+        let source =
+            dir.join_and_write_file("main.c", "#include \"a.h\"\nint main() { return A; }\n");
+        let header = dir.join_and_write_file("a.h", "#define A 0\n");
+        let run = || async {
+            let mut razel = Razel::new();
+            razel.discover_includes = true;
+            let id = push_compile(&mut razel, source.to_str().unwrap(), &[]);
+            let stats = razel
+                .run(false, true, "", Some(dir.join("cache")), vec![], None)
+                .await
+                .unwrap();
+            let inputs = razel.commands[id]
+                .inputs
+                .iter()
+                .filter_map(|x| std::fs::canonicalize(&razel.files[*x].path).ok())
+                .collect_vec();
+            (stats, inputs)
+        };
+        // the sandboxed command fails without the header as input
+        let (stats, inputs) = run().await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert!(inputs.contains(&std::fs::canonicalize(&header).unwrap()));
+        let (stats, _) = run().await;
+        assert_eq!(stats.cache_hits, 1);
+        // changed header
+        std::fs::write(&header, "#define A 1\n").unwrap();
+        let (stats, _) = run().await;
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.cache_hits, 0);
+        // the result of the preprocessor is cached
+        let cached = std::fs::read_dir(dir.join("cache/includes"))
+            .unwrap()
+            .count();
+        assert_eq!(cached, 1);
+        Razel::new().clean();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn generated_header() {
+        if which::which("cc").is_err() {
+            println!("cc not available, skipping test");
+            return;
+        }
+        let dir = new_tmp_dir!();
+        let source = dir.join_and_write_file(
+            "main.c",
+            "#include \"config.h\"\nint main() { return CONFIG; }\n",
+        );
+        let mut razel = Razel::new();
+        razel.clean();
+        razel.discover_includes = true;
+        let gen = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "gen".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "echo '#define CONFIG 0' > \"$0\"".into(),
+                    "gen/config.h".into(),
+                ],
+                outputs: vec!["gen/config.h".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        let gen_output = razel.commands[gen].outputs[0];
+        let gen_dir = razel.files[gen_output].path.parent().unwrap().to_path_buf();
+        let compile = push_compile(
+            &mut razel,
+            source.to_str().unwrap(),
+            &["-I", gen_dir.to_str().unwrap()],
+        );
+        let stats = razel
+            .run(false, true, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        assert!(razel.commands[compile].inputs.contains(&gen_output));
+        assert_eq!(razel.commands[compile].unfinished_deps, vec![]);
+        assert_eq!(razel.commands[gen].reverse_deps, vec![compile]);
+        razel.clean();
+    }
+}
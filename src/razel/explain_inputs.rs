@@ -0,0 +1,111 @@
+use super::Razel;
+use crate::{CommandId, FileType};
+use anyhow::{bail, Result};
+
+impl Razel {
+    /// Print the input files of a target after the run, see print_explain_inputs()
+    pub fn set_explain_inputs(&mut self, target: &str) -> Result<()> {
+        let Some(id) = self.command_for_target(target)? else {
+            bail!("unknown target for --explain-inputs: {target}");
+        };
+        self.explain_inputs = Some(id);
+        Ok(())
+    }
+
+    /// Print path, type and digest of each input file in the order of the input root digest
+    pub fn print_explain_inputs(&self, id: CommandId) {
+        println!("Inputs of {}:", self.commands[id].name);
+        for (path, file_type, digest) in self.explain_inputs(id) {
+            println!("  {path} ({file_type}): {digest}");
+        }
+    }
+
+    fn explain_inputs(&self, id: CommandId) -> Vec<(String, &'static str, String)> {
+        self.input_root_entries(&self.commands[id])
+            .into_iter()
+            .map(|x| {
+                let file_type = match x.file_type {
+                    Some(FileType::DataFile) => "data",
+                    Some(FileType::OutputFile) => "output",
+                    Some(
+                        FileType::ExecutableInWorkspace | FileType::ExecutableOutsideWorkspace,
+                    ) => "executable",
+                    Some(FileType::WasiModule) => "wasi",
+                    Some(FileType::SystemExecutable) => "system",
+                    Some(FileType::RazelExecutable) => "razel",
+                    None => "archive entry",
+                };
+                let digest = match x.digest {
+                    Some(d) => format!("{}/{}", d.hash, d.size_bytes),
+                    // the creating command was not executed
+                    None => "unknown".into(),
+                };
+                (x.name.to_string(), file_type, digest)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_remote_exec::Digest;
+    use crate::metadata::Tag;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use itertools::Itertools;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn explain_inputs() {
+        let dir = new_tmp_dir!();
+        let b = dir.join_and_write_file("b.txt", "bb");
+        let a = dir.join_and_write_file("a.txt", "a");
+        let mut razel = Razel::new();
+        razel.clean();
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "concat".into(),
+                executable: "cat".into(),
+                args: vec![b.to_str().unwrap().into(), a.to_str().unwrap().into()],
+                inputs: vec![b.to_str().unwrap().into(), a.to_str().unwrap().into()],
+                stdout: Some("concat.txt".into()),
+                tags: vec![Tag::NoCache],
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(razel.set_explain_inputs("unknown").is_err());
+        razel.set_explain_inputs("razel-out/concat.txt").unwrap();
+        let id = razel.explain_inputs.unwrap();
+        razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        let digest = |content: &str| {
+            let x = Digest::for_bytes(content);
+            format!("{}/{}", x.hash, x.size_bytes)
+        };
+        let cat = which::which("cat").unwrap();
+        let cat_digest = Digest::for_path(&cat).await.unwrap();
+        let expected = [
+            (a.to_str().unwrap().to_string(), "data", digest("a")),
+            (b.to_str().unwrap().to_string(), "data", digest("bb")),
+            (
+                cat.to_str().unwrap().to_string(),
+                "system",
+                format!("{}/{}", cat_digest.hash, cat_digest.size_bytes),
+            ),
+        ]
+        .into_iter()
+        .sorted_unstable_by(|x, y| x.0.cmp(&y.0))
+        .collect_vec();
+        assert_eq!(razel.explain_inputs(id), expected);
+        // same order as the input root used for the cache key
+        let (_, input_root) = razel.get_bzl_action_for_command(&razel.commands[id]);
+        assert_eq!(
+            input_root.files.iter().map(|x| &x.name).collect_vec(),
+            expected.iter().map(|x| &x.0).collect_vec()
+        );
+        Razel::new().clean();
+    }
+}
@@ -0,0 +1,129 @@
+use super::{Razel, ScheduleState};
+use crate::metadata::Tag;
+use crate::CommandId;
+use std::collections::HashSet;
+
+impl Razel {
+    /// Print the reason for each command which was not executed
+    pub fn print_explain_skips(&self) {
+        let skips = self.explain_skips();
+        if skips.is_empty() {
+            return;
+        }
+        println!("Not executed commands:");
+        for (name, reason) in &skips {
+            println!("  {name}: {reason}");
+        }
+    }
+
+    fn explain_skips(&self) -> Vec<(String, String)> {
+        let mut skips = vec![];
+        for command in self.commands.iter() {
+            let reason = match command.schedule_state {
+                ScheduleState::Excluded => match &self.filter {
+                    Some(filter) => format!("excluded by filter: {filter}"),
+                    None => "excluded".into(),
+                },
+                ScheduleState::Skipped => match self.skipped_by.get(&command.id) {
+                    Some(by) if self.commands[*by].tags.contains(&Tag::Condition) => {
+                        format!(
+                            "skipped because condition {} failed",
+                            self.commands[*by].name
+                        )
+                    }
+                    Some(by) => format!(
+                        "skipped because {} was not materialized",
+                        self.commands[*by].name
+                    ),
+                    None => "not materialized".into(),
+                },
+                ScheduleState::Waiting | ScheduleState::Ready => {
+                    match self.find_failed_dep(command.id) {
+                        Some(failed) => format!(
+                            "not started: depends on failed {}",
+                            self.commands[failed].name
+                        ),
+                        None => "not started: execution stopped after a failure".into(),
+                    }
+                }
+                _ => continue,
+            };
+            skips.push((command.name.clone(), reason));
+        }
+        skips
+    }
+
+    /// Returns a failed command the given command transitively depends on
+    fn find_failed_dep(&self, id: CommandId) -> Option<CommandId> {
+        let mut visited = HashSet::new();
+        let mut to_visit = self.commands[id].unfinished_deps.clone();
+        while let Some(dep) = to_visit.pop() {
+            if !visited.insert(dep) {
+                continue;
+            }
+            if self.failed.contains(&dep) {
+                return Some(dep);
+            }
+            to_visit.extend(self.commands[dep].unfinished_deps.iter());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CustomCommandSpec;
+    use serial_test::serial;
+
+    fn push(razel: &mut Razel, name: &str, arg: &str, deps: &[&str], tags: Vec<Tag>) {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: name.into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), arg.into()],
+                deps: deps.iter().map(|x| x.to_string()).collect(),
+                tags,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn explain_skips() {
+        let mut razel = Razel::new();
+        push(&mut razel, "condition", "false", &[], vec![Tag::Condition]);
+        push(
+            &mut razel,
+            "after-condition",
+            "true",
+            &["condition"],
+            vec![],
+        );
+        push(&mut razel, "not-selected", "true", &[], vec![]);
+        push(&mut razel, "failing", "false", &[], vec![]);
+        push(&mut razel, "after-failing", "true", &["failing"], vec![]);
+        let targets = ["condition", "after-condition", "failing", "after-failing"];
+        razel.filter_targets(&targets.map(String::from)).unwrap();
+        razel.run(true, true, "", None, vec![], None).await.unwrap();
+        assert_eq!(
+            razel.explain_skips(),
+            vec![
+                (
+                    "after-condition".into(),
+                    "skipped because condition condition failed".into()
+                ),
+                (
+                    "not-selected".into(),
+                    "excluded by filter: targets condition after-condition failing after-failing"
+                        .into()
+                ),
+                (
+                    "after-failing".into(),
+                    "not started: depends on failed failing".into()
+                ),
+            ]
+        );
+    }
+}
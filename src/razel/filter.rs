@@ -1,18 +1,67 @@
 use super::Razel;
 use crate::config::OUT_DIR;
-use anyhow::Result;
-use itertools::chain;
+use crate::CommandId;
+use anyhow::{bail, Result};
+use itertools::{chain, Itertools};
 use regex::RegexSet;
+use std::path::Path;
 
 impl Razel {
-    pub fn filter_targets(&mut self, targets: &[String]) {
+    /// Select commands by name or by the path of an output file
+    ///
+    /// Paths are accepted relative to cwd or workspace dir, with or without `razel-out/` prefix.
+    pub fn filter_targets(&mut self, targets: &[String]) -> Result<()> {
+        let mut ids = Vec::with_capacity(targets.len());
+        for target in targets {
+            ids.extend(self.command_for_target(target)?);
+        }
         self.exclude_all();
-        self.include_matching(|x| targets.iter().any(|t| t == x));
+        self.filter = Some(format!("targets {}", targets.join(" ")));
+        self.include_commands(ids.into_iter().unique().collect());
+        Ok(())
+    }
+
+    /// Returns the command with the name or output file of a target, fails if it's ambiguous
+    pub fn command_for_target(&self, target: &str) -> Result<Option<CommandId>> {
+        let target_string = target.to_string();
+        let matching = self
+            .get_command_by_name(&target_string)
+            .map(|x| x.id)
+            .into_iter()
+            .chain(
+                Path::new(target)
+                    .strip_prefix(OUT_DIR)
+                    .ok()
+                    .and_then(|x| self.creator_for_file(x)),
+            )
+            .chain(self.creator_for_file(Path::new(target)))
+            .chain(
+                self.rel_path(&target_string)
+                    .ok()
+                    .and_then(|x| self.creator_for_file(&x)),
+            )
+            .unique()
+            .collect_vec();
+        if matching.len() > 1 {
+            bail!(
+                "ambiguous target {target}, matches commands: {}",
+                matching.iter().map(|x| &self.commands[*x].name).join(", ")
+            );
+        }
+        Ok(matching.first().copied())
+    }
+
+    /// Returns the command which creates the file at a path relative to cwd (without out_dir prefix)
+    pub fn creator_for_file(&self, path: &Path) -> Option<CommandId> {
+        self.path_to_file_id
+            .get(path)
+            .and_then(|x| self.files[*x].creating_command)
     }
 
     pub fn filter_targets_regex(&mut self, patterns: &[String]) -> Result<()> {
         self.exclude_all();
         let regex = RegexSet::new(patterns)?;
+        self.filter = Some(format!("--filter-regex {}", patterns.join(" ")));
         self.include_matching(|x| regex.is_match(x));
         Ok(())
     }
@@ -20,6 +69,7 @@ impl Razel {
     pub fn filter_targets_regex_all(&mut self, patterns: &[String]) -> Result<()> {
         self.exclude_all();
         let regex = RegexSet::new(patterns)?;
+        self.filter = Some(format!("--filter-regex-all {}", patterns.join(" ")));
         self.include_matching(|x| regex.matches(x).matched_all());
         Ok(())
     }
@@ -34,19 +84,26 @@ impl Razel {
     }
 
     fn include_matching(&mut self, is_match: impl Fn(&str) -> bool) {
-        let mut matching_len: usize = 0;
-        let mut to_include = vec![];
-        for command in self.commands.iter_mut().filter(|c| {
-            is_match(&c.name)
-                || c.outputs.iter().any(|x| {
-                    let path = &self.files[*x].path;
-                    let path_wo_out_dir = path.strip_prefix(OUT_DIR).unwrap();
-                    is_match(path.to_str().unwrap()) || is_match(path_wo_out_dir.to_str().unwrap())
-                })
-        }) {
-            matching_len += 1;
-            to_include.push(command.id);
-        }
+        let ids = self
+            .commands
+            .iter()
+            .filter(|c| {
+                is_match(&c.name)
+                    || c.outputs.iter().any(|x| {
+                        let path = &self.files[*x].path;
+                        let path_wo_out_dir = path.strip_prefix(OUT_DIR).unwrap();
+                        is_match(path.to_str().unwrap())
+                            || is_match(path_wo_out_dir.to_str().unwrap())
+                    })
+            })
+            .map(|x| x.id)
+            .collect();
+        self.include_commands(ids);
+    }
+
+    /// Include the commands and their dependencies
+    fn include_commands(&mut self, mut to_include: Vec<CommandId>) {
+        let matching_len = to_include.len();
         let mut included: usize = 0;
         while let Some(id) = to_include.pop() {
             let command = &mut self.commands[id];
@@ -54,11 +111,20 @@ impl Razel {
                 continue;
             }
             command.is_excluded = false;
-            for x in chain!(&command.executables, &command.inputs, &command.outputs) {
+            for x in chain!(
+                &command.executables,
+                &command.inputs,
+                &command.config_inputs,
+                &command.outputs
+            ) {
                 self.files[*x].is_excluded = false;
             }
             included += 1;
-            for input_id in chain!(&command.executables, &command.inputs) {
+            for input_id in chain!(
+                &command.executables,
+                &command.inputs,
+                &command.config_inputs
+            ) {
                 if let Some(dep) = self.files[*input_id].creating_command {
                     to_include.push(dep);
                 }
@@ -69,3 +135,76 @@ impl Razel {
         self.excluded_commands_len = self.commands.len() - included;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CustomCommandSpec, SchedulerExecStats};
+    use serial_test::serial;
+
+    fn push_cmake(razel: &mut Razel, name: &str, args: &[&str], inputs: &[&str], output: &str) {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: name.into(),
+                executable: "cmake".into(),
+                args: args.iter().map(|x| x.to_string()).collect(),
+                inputs: inputs.iter().map(|x| x.to_string()).collect(),
+                outputs: vec![output.into()],
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn filter_targets_by_output_path() {
+        let mut razel = Razel::new();
+        razel.read_cache = false;
+        push_cmake(
+            &mut razel,
+            "a",
+            &["-E", "touch", "filter-a.txt"],
+            &[],
+            "filter-a.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "b",
+            &["-E", "copy", "filter-a.txt", "filter-b.txt"],
+            &["filter-a.txt"],
+            "filter-b.txt",
+        );
+        push_cmake(
+            &mut razel,
+            "c",
+            &["-E", "touch", "filter-c.txt"],
+            &[],
+            "filter-c.txt",
+        );
+        razel
+            .filter_targets(&["razel-out/filter-b.txt".into()])
+            .unwrap();
+        let stats = razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats.exec,
+            SchedulerExecStats {
+                succeeded: 2,
+                ..Default::default()
+            }
+        );
+        assert!(razel.get_command_by_name(&"c".into()).unwrap().is_excluded);
+    }
+
+    #[test]
+    fn filter_targets_ambiguous() {
+        let mut razel = Razel::new();
+        push_cmake(&mut razel, "x", &["-E", "touch", "y"], &[], "y");
+        push_cmake(&mut razel, "y", &["-E", "touch", "z"], &[], "z");
+        razel.filter_targets(&["razel-out/y".into()]).unwrap();
+        razel.filter_targets(&["z".into()]).unwrap();
+        assert!(razel.filter_targets(&["y".into()]).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use super::Razel;
+use crate::bazel_remote_exec::Digest;
+use crate::cache::MessageDigest;
+use anyhow::Result;
+use itertools::Itertools;
+
+impl Razel {
+    /// Digest over the action digests of all not excluded commands, see `razel fingerprint`.
+    ///
+    /// No commands are executed: instead of their digest, output files are represented by the
+    /// action digest of the creating command and their path.
+    pub async fn fingerprint(&mut self) -> Result<MessageDigest> {
+        self.digest_input_files().await?;
+        self.digest_input_archive_entries().await?;
+        let mut lines = Vec::with_capacity(self.commands.len() - self.excluded_commands_len);
+        for id in self.commands_in_execution_order() {
+            // entries of archives created by commands are represented by the archive and their path
+            for entry in &mut self.commands[id].archive_entries {
+                if entry.digest.is_none() {
+                    let archive = &self.files[entry.archive];
+                    entry.digest = Some(Digest::for_string(&format!(
+                        "{} {}",
+                        archive.digest.as_ref().unwrap().hash,
+                        entry.entry.to_str().unwrap()
+                    )));
+                }
+            }
+            let command = &self.commands[id];
+            let action_digest = self.action_digest(command);
+            for output_id in command.outputs.clone() {
+                let file = &mut self.files[output_id];
+                file.digest = Some(Digest::for_string(&format!(
+                    "{} {}",
+                    action_digest.hash,
+                    file.path.to_str().unwrap()
+                )));
+            }
+            lines.push(format!(
+                "{} {}\n",
+                self.commands[id].name, action_digest.hash
+            ));
+        }
+        Ok(Digest::for_string(&lines.into_iter().sorted().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use std::path::Path;
+
+    /// Commands a and b, b uses the output of a and another input file
+    async fn fingerprint(a_input: &Path, b_input: &Path) -> String {
+        let mut razel = Razel::new();
+        for (name, inputs, output) in [
+            ("a", vec![a_input.to_str().unwrap()], "a.txt"),
+            ("b", vec!["a.txt", b_input.to_str().unwrap()], "b.txt"),
+        ] {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "cp".into(),
+                    args: vec![inputs[0].into(), output.into()],
+                    inputs: inputs.into_iter().map(|x| x.into()).collect(),
+                    outputs: vec![output.into()],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        razel.fingerprint().await.unwrap().hash
+    }
+
+    #[tokio::test]
+    async fn fingerprint_is_stable_and_depends_on_inputs() {
+        let dir = new_tmp_dir!();
+        let a = dir.join_and_write_file("a.txt", "a");
+        let b = dir.join_and_write_file("b.txt", "b");
+        let original = fingerprint(&a, &b).await;
+        assert_eq!(fingerprint(&a, &b).await, original);
+        // input of the first command changes the fingerprint via the output of the first command
+        std::fs::write(&a, "changed").unwrap();
+        let a_changed = fingerprint(&a, &b).await;
+        assert_ne!(a_changed, original);
+        std::fs::write(&b, "changed").unwrap();
+        let b_changed = fingerprint(&a, &b).await;
+        assert_ne!(b_changed, original);
+        assert_ne!(b_changed, a_changed);
+        // same inputs again
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        assert_eq!(fingerprint(&a, &b).await, original);
+    }
+
+    #[tokio::test]
+    async fn fingerprint_with_archive_entries() {
+        let dir = new_tmp_dir!();
+        let fingerprint = |used: &str, unused: &str| {
+            let archive = dir.join_and_write_tar("data.tar", &[("f.txt", used), ("g.txt", unused)]);
+            let entry = format!("{}#f.txt", archive.to_str().unwrap());
+            async move {
+                let mut razel = Razel::new();
+                for (name, input, output) in [
+                    ("a", entry.as_str(), "a.tar"),
+                    // entry of an archive created by a command
+                    ("b", "a.tar#x.txt", "b.txt"),
+                ] {
+                    razel
+                        .push_custom_command(CustomCommandSpec {
+                            name: name.into(),
+                            executable: "cp".into(),
+                            args: vec![input.into(), output.into()],
+                            inputs: vec![input.into()],
+                            outputs: vec![output.into()],
+                            ..Default::default()
+                        })
+                        .unwrap();
+                }
+                razel.fingerprint().await.unwrap().hash
+            }
+        };
+        let original = fingerprint("f", "g").await;
+        assert_eq!(fingerprint("f", "changed").await, original);
+        assert_ne!(fingerprint("changed", "g").await, original);
+    }
+}
@@ -0,0 +1,92 @@
+use super::Razel;
+use crate::executors::Executor;
+use crate::{CommandId, FileType};
+use itertools::chain;
+use std::path::{Path, PathBuf};
+
+impl Razel {
+    /// Replace PATH of a custom command with the dirs of its declared executables and inputs.
+    ///
+    /// Dirs of system executables are not added because they would expose undeclared host tools.
+    /// Within the sandbox, the dirs only contain declared files.
+    pub(super) fn set_hermetic_path(&mut self, id: CommandId) {
+        let path = self.hermetic_path(id);
+        if let Executor::CustomCommand(x) = &mut self.commands[id].executor {
+            x.env.insert("PATH".into(), path);
+        }
+    }
+
+    fn hermetic_path(&self, id: CommandId) -> String {
+        let command = &self.commands[id];
+        let mut dirs: Vec<PathBuf> = vec![];
+        for file in chain!(&command.executables, &command.inputs).map(|x| &self.files[*x]) {
+            if matches!(
+                file.file_type,
+                FileType::SystemExecutable | FileType::RazelExecutable
+            ) {
+                continue;
+            }
+            let dir = match file.path.parent() {
+                Some(x) if !x.as_os_str().is_empty() => x.to_path_buf(),
+                _ => Path::new(".").to_path_buf(),
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        for dir in self.hermetic_path.iter().flatten() {
+            let dir = PathBuf::from(dir);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        std::env::join_paths(dirs)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use std::collections::HashMap;
+    use std::os::unix::fs::PermissionsExt;
+
+    async fn exec_tool(razel: &mut Razel, inputs: Vec<String>, env_path: &Path) -> bool {
+        let id = razel
+            .push_custom_command(CustomCommandSpec {
+                name: format!("hermetic-path-{}", inputs.len()),
+                executable: "sh".into(),
+                args: vec!["-c".into(), "hermetic-path-tool".into()],
+                env: HashMap::from([("PATH".into(), env_path.to_str().unwrap().into())]),
+                inputs,
+                ..Default::default()
+            })
+            .unwrap();
+        let command = razel.get_command(id).unwrap();
+        command
+            .executor
+            .exec(Path::new("."), None, None)
+            .await
+            .success()
+    }
+
+    #[tokio::test]
+    async fn hermetic_path() {
+        let dir = new_tmp_dir!();
+        let tool = dir.join_and_write_file("tools/hermetic-path-tool", "#!/bin/sh\nexit 0\n");
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let host_path = std::fs::canonicalize(tool.parent().unwrap()).unwrap();
+        // tool on host PATH only
+        let mut razel = Razel::new();
+        assert!(exec_tool(&mut razel, vec![], &host_path).await);
+        razel.hermetic_path = Some(vec![]);
+        assert!(!exec_tool(&mut razel, vec![], &host_path).await);
+        // tool declared as input
+        let input = tool.to_str().unwrap().to_string();
+        assert!(exec_tool(&mut razel, vec![input], &host_path).await);
+    }
+}
@@ -0,0 +1,79 @@
+use super::Razel;
+use log::warn;
+
+impl Razel {
+    /// Log all inputs which are outputs of other commands to allow verifying the dependency edges
+    pub fn warn_implicit_deps(&self) {
+        let deps = self.implicit_deps();
+        for x in &deps {
+            warn!("implicit dependency: {x}");
+        }
+        warn!("{} implicit dependencies", deps.len());
+    }
+
+    fn implicit_deps(&self) -> Vec<String> {
+        let mut deps = vec![];
+        for command in self.commands.iter().filter(|x| !x.is_excluded) {
+            for file in command.inputs.iter().map(|x| &self.files[*x]) {
+                if let Some(creator) = file.creating_command.filter(|x| *x != command.id) {
+                    deps.push(format!(
+                        "{} uses {} created by {}",
+                        command.name, file.arg, self.commands[creator].name
+                    ));
+                }
+            }
+        }
+        deps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+
+    fn push(razel: &mut Razel, name: &str, inputs: &[&str], output: &str) -> anyhow::Result<()> {
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: name.into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "true".into()],
+                inputs: inputs.iter().map(|x| x.to_string()).collect(),
+                outputs: vec![output.into()],
+                ..Default::default()
+            })
+            .map(|_| ())
+    }
+
+    #[test]
+    fn implicit_deps() {
+        let mut razel = Razel::new();
+        push(&mut razel, "a", &["examples/data/a.csv"], "implicit-a.txt").unwrap();
+        push(&mut razel, "b", &["implicit-a.txt"], "implicit-b.txt").unwrap();
+        push(
+            &mut razel,
+            "c",
+            &["implicit-a.txt", "implicit-b.txt"],
+            "implicit-c.txt",
+        )
+        .unwrap();
+        assert_eq!(
+            razel.implicit_deps(),
+            vec![
+                "b uses implicit-a.txt created by a",
+                "c uses implicit-a.txt created by a",
+                "c uses implicit-b.txt created by b",
+            ]
+        );
+    }
+
+    #[test]
+    fn input_is_source_file_and_output() {
+        let dir = new_tmp_dir!();
+        let source = dir.join_and_write_file("source.txt", "");
+        let source = source.to_str().unwrap();
+        let mut razel = Razel::new();
+        push(&mut razel, "a", &[], source).unwrap();
+        assert!(push(&mut razel, "b", &[source], "b.txt").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use super::Razel;
 use crate::executors::Executor;
-use crate::{RazelJson, RazelJsonCommand, RazelJsonTask};
+use crate::{OutputGlob, RazelJson, RazelJsonCommand, RazelJsonTask};
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
@@ -12,23 +12,45 @@ impl Razel {
         for command in self.commands.iter() {
             let json = match &command.executor {
                 Executor::CustomCommand(_) | Executor::Wasi(_) => {
-                    RazelJson::Command(RazelJsonCommand {
+                    RazelJson::Command(Box::new(RazelJsonCommand {
                         name: command.name.clone(),
                         executable: self.files[*command.executables.first().unwrap()]
                             .arg
                             .clone(),
                         args: args_wo_out_dir(&self.out_dir, command.executor.args().iter()),
                         env: command.executor.env().cloned().unwrap_or_default(),
+                        args_by_os: Default::default(),
+                        env_by_os: Default::default(),
+                        env_unset: Default::default(),
                         inputs: command
                             .inputs
                             .iter()
                             .map(|x| self.files[*x].arg.clone())
                             .collect(),
+                        config_inputs: command
+                            .config_inputs
+                            .iter()
+                            .map(|x| self.files[*x].arg.clone())
+                            .collect(),
                         outputs: command
                             .outputs
                             .iter()
                             .map(|x| self.files[*x].arg.clone())
                             .collect(),
+                        output_globs: command
+                            .output_globs
+                            .iter()
+                            .map(|x| OutputGlob {
+                                pattern: args_wo_out_dir(&self.out_dir, [x.pattern.clone()].iter())
+                                    .remove(0),
+                                allow_empty: x.allow_empty,
+                            })
+                            .collect(),
+                        artifacts: command
+                            .artifacts
+                            .iter()
+                            .map(|x| self.files[*x].arg.clone())
+                            .collect(),
                         stdout: command
                             .executor
                             .stdout_file()
@@ -43,7 +65,7 @@ impl Razel {
                             .map(|x| self.commands[*x].name.clone())
                             .collect(),
                         tags: command.tags.clone(),
-                    })
+                    }))
                 }
                 Executor::AsyncTask(_) | Executor::BlockingTask(_) | Executor::HttpRemote(_) => {
                     let mut i = command.executor.args().iter();
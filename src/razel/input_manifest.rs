@@ -0,0 +1,131 @@
+use super::Razel;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct InputManifestEntry {
+    hash: String,
+    size_bytes: i64,
+}
+
+/// Maps paths of input files to their digests
+type InputManifest = BTreeMap<String, InputManifestEntry>;
+
+impl Razel {
+    /// Write the digests of all input files of not excluded commands, requires digest_input_files()
+    pub(super) fn write_input_manifest(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let manifest: InputManifest = self
+            .files
+            .iter()
+            .filter(|x| x.creating_command.is_none() && !x.is_excluded)
+            .filter_map(|x| {
+                let digest = x.digest.as_ref()?;
+                Some((
+                    x.path.to_str().unwrap().to_string(),
+                    InputManifestEntry {
+                        hash: digest.hash.clone(),
+                        size_bytes: digest.size_bytes,
+                    },
+                ))
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&manifest)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("failed to write input manifest {path:?}"))
+    }
+
+    /// Fail if the digest of any input file of not excluded commands differs from the manifest
+    pub(super) fn check_input_manifest(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read input manifest {path:?}"))?;
+        let manifest: InputManifest = serde_json::from_str(&json)
+            .with_context(|| format!("failed to parse input manifest {path:?}"))?;
+        let mut errors = vec![];
+        for file in self
+            .files
+            .iter()
+            .filter(|x| x.creating_command.is_none() && !x.is_excluded)
+        {
+            let file_path = file.path.to_str().unwrap();
+            let Some(expected) = manifest.get(file_path) else {
+                errors.push(format!("{file_path}: not in manifest"));
+                continue;
+            };
+            match &file.digest {
+                Some(x) if x.hash == expected.hash && x.size_bytes == expected.size_bytes => {}
+                Some(x) => errors.push(format!(
+                    "{file_path}: digest {}/{} differs from manifest {}/{}",
+                    x.hash, x.size_bytes, expected.hash, expected.size_bytes
+                )),
+                None => errors.push(format!("{file_path}: file not found")),
+            }
+        }
+        if !errors.is_empty() {
+            bail!(
+                "{} input files do not match input manifest {path:?}:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+
+    fn create_razel(inputs: &[&Path]) -> Razel {
+        let mut razel = Razel::new();
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "command".into(),
+                executable: "cmake".into(),
+                args: vec!["-E".into(), "true".into()],
+                inputs: inputs.iter().map(|x| x.to_str().unwrap().into()).collect(),
+                ..Default::default()
+            })
+            .unwrap();
+        razel
+    }
+
+    #[tokio::test]
+    async fn input_manifest() {
+        let dir = new_tmp_dir!();
+        let a = dir.join_and_write_file("a.txt", "a");
+        let b = dir.join_and_write_file("b.txt", "b");
+        let manifest = dir.join("manifest.json");
+        // export
+        let mut razel = create_razel(&[&a, &b]);
+        razel.digest_input_files().await.unwrap();
+        razel.write_input_manifest(&manifest).unwrap();
+        let exported: InputManifest =
+            serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(
+            exported[a.to_str().unwrap()],
+            InputManifestEntry {
+                hash: "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb".into(),
+                size_bytes: 1,
+            }
+        );
+        assert!(exported.contains_key(b.to_str().unwrap()));
+        // matching inputs
+        let mut razel = create_razel(&[&a, &b]);
+        razel.digest_input_files().await.unwrap();
+        razel.check_input_manifest(&manifest).unwrap();
+        // changed input
+        std::fs::write(&b, "changed").unwrap();
+        let mut razel = create_razel(&[&a, &b]);
+        razel.digest_input_files().await.unwrap();
+        let error = razel.check_input_manifest(&manifest).unwrap_err();
+        println!("{error}");
+        assert!(error.to_string().starts_with("1 input files do not match"));
+        assert!(error
+            .to_string()
+            .contains(&format!("{}: digest", b.to_str().unwrap())));
+        assert!(!error.to_string().contains(a.to_str().unwrap()));
+    }
+}
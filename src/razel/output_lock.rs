@@ -0,0 +1,209 @@
+use super::Razel;
+use crate::File;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct OutputLockEntry {
+    hash: String,
+    size_bytes: i64,
+}
+
+/// Maps paths of output files to their digests
+type OutputLock = BTreeMap<String, OutputLockEntry>;
+
+impl Razel {
+    /// Output files of succeeded or cached commands, requires digests set after execution
+    fn produced_output_files(&self) -> impl Iterator<Item = &File> {
+        self.files
+            .iter()
+            .filter(|x| x.creating_command.is_some() && x.digest.is_some())
+    }
+
+    fn read_output_lock(path: &Path) -> Result<OutputLock, anyhow::Error> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read lock file {path:?}"))?;
+        serde_json::from_str(&json).with_context(|| format!("failed to parse lock file {path:?}"))
+    }
+
+    /// Write the digests of all produced output files.
+    ///
+    /// Entries of output files which were not produced, e.g. of filtered out commands, are kept.
+    pub(super) fn write_output_lock(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut lock = if path.exists() {
+            Self::read_output_lock(path)?
+        } else {
+            Default::default()
+        };
+        let not_produced = self
+            .files
+            .iter()
+            .filter(|x| x.creating_command.is_some() && x.digest.is_none())
+            .map(|x| x.path.to_str().unwrap())
+            .collect::<HashSet<_>>();
+        lock.retain(|x, _| not_produced.contains(x.as_str()));
+        lock.extend(self.produced_output_files().map(|x| {
+            let digest = x.digest.as_ref().unwrap();
+            (
+                x.path.to_str().unwrap().to_string(),
+                OutputLockEntry {
+                    hash: digest.hash.clone(),
+                    size_bytes: digest.size_bytes,
+                },
+            )
+        }));
+        let json = serde_json::to_string_pretty(&lock)?;
+        std::fs::write(path, json + "\n")
+            .with_context(|| format!("failed to write lock file {path:?}"))
+    }
+
+    /// Fail if the digest of any produced output file differs from the lock or is missing in it
+    pub(super) fn verify_output_lock(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let lock = Self::read_output_lock(path)?;
+        let mut errors = vec![];
+        for file in self.produced_output_files() {
+            let file_path = file.path.to_str().unwrap();
+            let digest = file.digest.as_ref().unwrap();
+            match lock.get(file_path) {
+                Some(x) if x.hash == digest.hash && x.size_bytes == digest.size_bytes => {}
+                Some(x) => errors.push(format!(
+                    "{file_path}: digest {}/{} differs from lock {}/{}",
+                    digest.hash, digest.size_bytes, x.hash, x.size_bytes
+                )),
+                None => errors.push(format!("{file_path}: not in lock")),
+            }
+        }
+        if !errors.is_empty() {
+            bail!(
+                "{} output files do not match lock file {path:?}, use --update-lock to accept them:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LOCK_FILE;
+    use crate::{new_tmp_dir, CustomCommandSpec, SchedulerStats};
+    use itertools::Itertools;
+    use serial_test::serial;
+
+    /// Runs commands writing the stdout of a shell script to <name>.txt
+    async fn run(
+        workspace: &Path,
+        scripts: &[(&str, &str)],
+        verify_outputs: bool,
+        update_lock: bool,
+    ) -> Result<SchedulerStats, anyhow::Error> {
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(workspace).unwrap();
+        razel.read_cache = false;
+        razel.verify_outputs = verify_outputs;
+        razel.update_lock = update_lock;
+        for (name, script) in scripts {
+            let output = format!("{name}.txt");
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.to_string(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), format!("{script} > \"$0\""), output.clone()],
+                    outputs: vec![output],
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        razel.run(false, true, "", None, vec![], None).await
+    }
+
+    /// Returns the entry of an output file, paths in the lock are relative to the current dir
+    fn entry<'a>(lock: &'a OutputLock, file_name: &str) -> &'a OutputLockEntry {
+        let (_, entry) = lock
+            .iter()
+            .find(|(path, _)| Path::new(path).file_name().unwrap() == file_name)
+            .unwrap();
+        entry
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn output_lock() {
+        let dir = new_tmp_dir!();
+        let lock_file = dir.join(LOCK_FILE);
+        // missing lock
+        assert!(run(dir.dir(), &[("a", "printf a")], true, false)
+            .await
+            .is_err());
+        // update lock
+        run(
+            dir.dir(),
+            &[("a", "printf a"), ("b", "printf b")],
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+        let lock = Razel::read_output_lock(&lock_file).unwrap();
+        assert_eq!(lock.len(), 2);
+        assert_eq!(
+            *entry(&lock, "a.txt"),
+            OutputLockEntry {
+                hash: "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb".into(),
+                size_bytes: 1,
+            }
+        );
+        // matching build
+        run(
+            dir.dir(),
+            &[("a", "printf a"), ("b", "printf b")],
+            true,
+            false,
+        )
+        .await
+        .unwrap();
+        // mismatching output
+        let error = run(
+            dir.dir(),
+            &[("a", "printf a"), ("b", "printf changed")],
+            true,
+            false,
+        )
+        .await
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.starts_with("1 output files do not match lock file"));
+        assert!(error.contains("/b.txt: digest"));
+        // new output is reported
+        let error = run(
+            dir.dir(),
+            &[("a", "printf a"), ("c", "printf c")],
+            true,
+            false,
+        )
+        .await
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("/c.txt: not in lock"));
+        // updating keeps entries of outputs which were not produced
+        run(
+            dir.dir(),
+            &[("a", "printf changed"), ("b", "false")],
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+        let updated = Razel::read_output_lock(&lock_file).unwrap();
+        assert_eq!(updated.keys().collect_vec(), lock.keys().collect_vec());
+        assert_ne!(entry(&updated, "a.txt"), entry(&lock, "a.txt"));
+        assert_eq!(entry(&updated, "b.txt"), entry(&lock, "b.txt"));
+        Razel::new().clean();
+    }
+}
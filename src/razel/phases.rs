@@ -0,0 +1,305 @@
+use super::Razel;
+use crate::executors::ExecutionStatus;
+use crate::metadata::Tag;
+use crate::{Arena, Command, CommandId, ScheduleState};
+use anyhow::{bail, Result};
+use itertools::{chain, Itertools};
+use std::collections::{BTreeMap, HashMap};
+
+/// Barriers between phases of commands, see `--phased`.
+///
+/// Commands without phase tag are not affected.
+#[derive(Default)]
+pub(super) struct Phases {
+    /// number of not yet finished commands per phase
+    unfinished: BTreeMap<u32, usize>,
+    /// commands without unfinished deps waiting for lower phases to succeed
+    blocked: BTreeMap<u32, Vec<CommandId>>,
+}
+
+impl Phases {
+    pub fn new(commands: &Arena<Command>) -> Self {
+        let mut phases = Self::default();
+        for phase in commands.iter().filter(|x| !x.is_excluded).filter_map(phase) {
+            *phases.unfinished.entry(phase).or_default() += 1;
+        }
+        phases
+    }
+
+    /// Returns true if the command needs to wait for a lower phase, must only be called once
+    /// it has no unfinished deps
+    pub fn block(&mut self, command: &Command) -> bool {
+        let Some(phase) = phase(command) else {
+            return false;
+        };
+        let current = *self.unfinished.keys().next().unwrap();
+        if phase == current {
+            return false;
+        }
+        self.blocked.entry(phase).or_default().push(command.id);
+        true
+    }
+
+    /// Returns the blocked commands of the next phase if the phase of the command is finished.
+    ///
+    /// Must be called once for each command reaching a final state: succeeded, failed or skipped.
+    fn finished(&mut self, command: &Command) -> Vec<CommandId> {
+        let Some(phase) = phase(command) else {
+            return vec![];
+        };
+        let Some(unfinished) = self.unfinished.get_mut(&phase) else {
+            return vec![];
+        };
+        *unfinished -= 1;
+        if *unfinished != 0 {
+            return vec![];
+        }
+        let current = *self.unfinished.keys().next().unwrap();
+        self.unfinished.remove(&phase);
+        if phase != current {
+            // e.g. all its commands were skipped, blocked ones are started with the previous phase
+            return vec![];
+        }
+        self.unfinished
+            .keys()
+            .next()
+            .and_then(|x| self.blocked.remove(x))
+            .unwrap_or_default()
+    }
+}
+
+fn phase(command: &Command) -> Option<u32> {
+    command.tags.iter().find_map(|x| match x {
+        Tag::Phase(x) => Some(*x),
+        _ => None,
+    })
+}
+
+impl Razel {
+    /// Fail if a command depends directly or indirectly on a command of a higher phase, because
+    /// the phase of the command would never finish
+    pub(super) fn check_phase_deps(&self) -> Result<()> {
+        // highest phase of each command and its transitive deps
+        let mut highest: HashMap<CommandId, Option<u32>> = HashMap::new();
+        for root in self.commands.iter().filter(|x| !x.is_excluded) {
+            let mut stack = vec![root.id];
+            while let Some(&id) = stack.last() {
+                if highest.contains_key(&id) {
+                    stack.pop();
+                    continue;
+                }
+                let command = &self.commands[id];
+                let deps = self.phase_deps(command);
+                let unvisited = deps
+                    .iter()
+                    .filter(|x| !highest.contains_key(x))
+                    .collect_vec();
+                if !unvisited.is_empty() {
+                    stack.extend(unvisited);
+                    continue;
+                }
+                stack.pop();
+                let deps_phase = deps.iter().filter_map(|x| highest[x]).max();
+                if let (Some(phase), Some(deps_phase)) = (phase(command), deps_phase) {
+                    if deps_phase > phase {
+                        bail!(
+                            "command {} of phase {phase} depends on a command of phase {deps_phase}",
+                            command.name
+                        );
+                    }
+                }
+                highest.insert(id, phase(command).max(deps_phase));
+            }
+        }
+        Ok(())
+    }
+
+    /// Explicit deps and commands creating inputs
+    fn phase_deps(&self, command: &Command) -> Vec<CommandId> {
+        chain!(
+            &command.executables,
+            &command.inputs,
+            &command.config_inputs
+        )
+        .filter_map(|x| self.files[*x].creating_command)
+        .chain(command.deps.iter().copied())
+        .collect()
+    }
+
+    /// Start commands of the next phase once all commands of the current one finished
+    pub(super) fn on_phase_command_finished(&mut self, id: CommandId) {
+        let Some(phases) = self.phases.as_mut() else {
+            return;
+        };
+        for id in phases.finished(&self.commands[id]) {
+            let command = &mut self.commands[id];
+            if command.schedule_state != ScheduleState::Waiting {
+                // skipped because of a failed command of a lower phase
+                continue;
+            }
+            command.schedule_state = ScheduleState::Ready;
+            self.waiting.remove(&id);
+            self.scheduler.push_ready(command);
+        }
+    }
+
+    /// Skip commands of higher phases because the failed command did not succeed
+    pub(super) fn on_phase_command_failed(&mut self, id: CommandId) {
+        if self.phases.is_none() {
+            return;
+        }
+        if let Some(failed_phase) = phase(&self.commands[id]) {
+            let ids_to_skip = self
+                .commands
+                .iter()
+                .filter(|x| x.schedule_state == ScheduleState::Waiting)
+                .filter(|x| phase(x).is_some_and(|x| x > failed_phase))
+                .map(|x| x.id)
+                .collect::<Vec<_>>();
+            for id_to_skip in ids_to_skip {
+                let to_skip = &mut self.commands[id_to_skip];
+                if to_skip.schedule_state == ScheduleState::Skipped {
+                    // already skipped as reverse dep of another command
+                    continue;
+                }
+                to_skip.schedule_state = ScheduleState::Skipped;
+                self.log_file
+                    .push_not_run(to_skip, ExecutionStatus::Skipped);
+                self.waiting.remove(&id_to_skip);
+                self.skipped.push(id_to_skip);
+                self.skipped_by.insert(id_to_skip, id);
+                self.on_phase_command_finished(id_to_skip);
+                self.skip_reverse_deps(id_to_skip);
+            }
+        }
+        // after skipping to not start blocked commands of the next phase
+        self.on_phase_command_finished(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec, SchedulerStats};
+    use itertools::Itertools;
+    use serial_test::serial;
+    use std::path::Path;
+
+    /// Runs commands appending their name to log.txt, a command named "fail" fails and one named
+    /// "condition" is a failing condition
+    async fn run(
+        workspace: &Path,
+        commands: &[(&str, u32, &[&str])],
+    ) -> (Result<SchedulerStats>, Vec<String>) {
+        let log = std::fs::canonicalize(workspace).unwrap().join("log.txt");
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(workspace).unwrap();
+        razel.read_cache = false;
+        razel.phased = true;
+        for (name, phase, deps) in commands {
+            let mut tags = vec![Tag::Phase(*phase)];
+            let script = match *name {
+                "fail" => "exit 1".to_string(),
+                "condition" => {
+                    tags.push(Tag::Condition);
+                    "exit 1".to_string()
+                }
+                // lower phases take longer to fail the test if later ones start too early
+                _ => format!("sleep 0.{}; echo {name} >> \"$0\"", 4 - phase),
+            };
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.to_string(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), script, log.to_str().unwrap().into()],
+                    deps: deps.iter().map(|x| x.to_string()).collect(),
+                    tags,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel.run(true, true, "", None, vec![], None).await;
+        let lines = std::fs::read_to_string(&log)
+            .unwrap_or_default()
+            .lines()
+            .map(|x| x.to_string())
+            .collect();
+        razel.clean();
+        (stats, lines)
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn phases_are_executed_in_order() {
+        let dir = new_tmp_dir!();
+        let (stats, lines) = run(
+            dir.dir(),
+            &[
+                ("test_a", 3, &[]),
+                ("test_b", 3, &[]),
+                ("compile", 2, &[]),
+                ("generate", 1, &[]),
+            ],
+        )
+        .await;
+        assert_eq!(stats.unwrap().exec.succeeded, 4);
+        assert_eq!(lines[..2], ["generate", "compile"]);
+        assert_eq!(
+            lines[2..].iter().sorted().collect_vec(),
+            ["test_a", "test_b"]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn failed_phase_skips_later_phases() {
+        let dir = new_tmp_dir!();
+        let (stats, lines) = run(
+            dir.dir(),
+            &[
+                ("test", 3, &[]),
+                ("compile", 2, &[]),
+                ("generate", 1, &[]),
+                ("fail", 1, &[]),
+            ],
+        )
+        .await;
+        let stats = stats.unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(stats.exec.failed, 1);
+        assert_eq!(stats.exec.skipped, 2);
+        assert_eq!(lines, ["generate"]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn failed_condition_and_skipped_commands_finish_phase() {
+        let dir = new_tmp_dir!();
+        let (stats, lines) = run(
+            dir.dir(),
+            &[
+                ("condition", 1, &[]),
+                ("skipped", 1, &["condition"]),
+                ("generate", 1, &[]),
+                ("test", 2, &[]),
+            ],
+        )
+        .await;
+        let stats = stats.unwrap();
+        assert_eq!(stats.exec.succeeded, 2);
+        assert_eq!(stats.exec.skipped, 1);
+        assert_eq!(lines, ["generate", "test"]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn dep_on_higher_phase_is_rejected() {
+        let dir = new_tmp_dir!();
+        let (stats, lines) = run(dir.dir(), &[("test", 2, &[]), ("generate", 1, &["test"])]).await;
+        assert_eq!(
+            stats.unwrap_err().to_string(),
+            "command generate of phase 1 depends on a command of phase 2"
+        );
+        assert!(lines.is_empty());
+    }
+}
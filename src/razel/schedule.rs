@@ -0,0 +1,150 @@
+use super::Razel;
+use crate::{CommandId, ScheduleState};
+use anyhow::Context;
+use log::warn;
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// Order in which commands were started, see `--record-schedule` and `--replay-schedule`.
+#[derive(Default)]
+pub(super) struct Schedule {
+    /// started commands, retried commands are contained multiple times
+    recorded: Vec<CommandId>,
+    /// commands still to start in the replayed order, only set with `replay_schedule`
+    replay: Option<VecDeque<CommandId>>,
+}
+
+impl Razel {
+    /// Read the command names of a recorded schedule, unknown commands are ignored
+    pub(super) fn read_schedule(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read schedule {path:?}"))?;
+        let mut replay = VecDeque::new();
+        for name in text.lines().filter(|x| !x.is_empty()) {
+            match self.get_command_by_name(&name.to_string()) {
+                Some(x) => replay.push_back(x.id),
+                None => warn!("command from schedule not found: {name}"),
+            }
+        }
+        self.schedule.replay = Some(replay);
+        Ok(())
+    }
+
+    /// Write the names of the started commands, one per line
+    pub(super) fn write_schedule(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let text: String = self
+            .schedule
+            .recorded
+            .iter()
+            .map(|x| format!("{}\n", self.commands[*x].name))
+            .collect();
+        std::fs::write(path, text).with_context(|| format!("failed to write schedule {path:?}"))
+    }
+
+    /// Returns the next command to start and marks it as running.
+    ///
+    /// When replaying a schedule, ready commands are started in the recorded order. Commands not
+    /// contained in the schedule are selected by the scheduler as usual, but only if no command of
+    /// the schedule is running, e.g. because dependencies changed since recording.
+    pub(super) fn pop_ready_command(&mut self) -> Option<CommandId> {
+        let id = self.pop_replayed_command().or_else(|| {
+            let waits_for_replay = self.schedule.replay.as_ref().is_some_and(|x| !x.is_empty());
+            if waits_for_replay && self.scheduler.running() != 0 {
+                return None;
+            }
+            self.scheduler.pop_ready_and_run()
+        })?;
+        if self.record_schedule.is_some() {
+            self.schedule.recorded.push(id);
+        }
+        Some(id)
+    }
+
+    fn pop_replayed_command(&mut self) -> Option<CommandId> {
+        let replay = self.schedule.replay.as_mut()?;
+        let mut index = 0;
+        while let Some(id) = replay.get(index).cloned() {
+            if self.commands[id].schedule_state == ScheduleState::Waiting {
+                // keep the position of commands with unfinished deps
+                index += 1;
+            } else if self.scheduler.is_ready(id) {
+                // to keep the order, don't start later commands if there are no free resources
+                return self.scheduler.pop_ready_id_and_run(id).then(|| {
+                    replay.remove(index);
+                    id
+                });
+            } else {
+                // already started, finished, skipped or excluded
+                replay.remove(index);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use itertools::Itertools;
+    use serial_test::serial;
+
+    /// Runs independent commands a..f and a command depending on c, returns the started order
+    async fn run(workspace: &Path, record: &Path, replay: Option<&Path>) -> Vec<String> {
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(workspace).unwrap();
+        razel.read_cache = false;
+        razel.record_schedule = Some(record.into());
+        razel.replay_schedule = replay.map(|x| x.into());
+        for name in ["a", "b", "c", "d", "e", "f", "g"] {
+            let deps = if name == "g" {
+                vec!["c".into()]
+            } else {
+                vec![]
+            };
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: name.into(),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "true".into()],
+                    deps,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        razel
+            .run(false, true, "", None, vec![], None)
+            .await
+            .unwrap();
+        razel.clean();
+        std::fs::read_to_string(record)
+            .unwrap()
+            .lines()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn record_and_replay() {
+        let dir = new_tmp_dir!();
+        let recorded = dir.join("recorded.txt");
+        let replayed = dir.join("replayed.txt");
+        let order = run(dir.dir(), &recorded, None).await;
+        assert_eq!(
+            order.iter().sorted().collect_vec(),
+            ["a", "b", "c", "d", "e", "f", "g"]
+        );
+        // a different order than the scheduler would select
+        let order = ["f", "e", "c", "b", "a", "d", "g"];
+        std::fs::write(&recorded, order.join("\n")).unwrap();
+        let replayed_order = run(dir.dir(), &replayed, Some(&recorded)).await;
+        assert_eq!(replayed_order, order);
+        // replaying the replayed order again results in the identical order
+        let replayed_again = dir.join("replayed_again.txt");
+        assert_eq!(
+            run(dir.dir(), &replayed_again, Some(&replayed)).await,
+            replayed_order
+        );
+    }
+}
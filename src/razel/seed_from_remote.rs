@@ -0,0 +1,110 @@
+use super::Razel;
+use crate::bazel_remote_exec::OutputFile;
+use crate::metadata::Tag;
+use crate::{Command, CommandId, FileId};
+use futures_util::StreamExt;
+use itertools::{chain, Itertools};
+use log::{info, warn};
+use std::path::PathBuf;
+
+impl Razel {
+    /// Queries the remote cache for all actions before scheduling to fill the local cache, see
+    /// `--seed-from-remote`.
+    ///
+    /// Runs in waves: output digests of hits allow computing the action digests of their reverse
+    /// deps in the next wave. Those digests are reset afterwards and set again when the commands
+    /// are processed as usual.
+    pub(super) async fn seed_from_remote(&mut self) {
+        let Some(cache) = self.cache.clone().filter(|x| x.has_remote_cache()) else {
+            return;
+        };
+        if !self.read_cache || self.interactive {
+            return;
+        }
+        if let Err(x) = self.digest_input_archive_entries().await {
+            // commands are not seeded, the error is reported when executing them
+            warn!("{x:?}");
+        }
+        let mut pending = self
+            .commands
+            .iter()
+            .filter(|x| !x.is_excluded && Self::is_seedable(x))
+            .map(|x| x.id)
+            .collect_vec();
+        let total = pending.len();
+        let mut seeded_files: Vec<FileId> = vec![];
+        let mut hits = 0;
+        loop {
+            let (ready, waiting): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|x| self.has_input_digests(&self.commands[*x]));
+            pending = waiting;
+            if ready.is_empty() {
+                break;
+            }
+            let queries = ready
+                .into_iter()
+                .filter(|x| !self.is_input_size_above_max(&self.commands[*x]))
+                .map(|id| {
+                    let command = &self.commands[id];
+                    let mut cache = cache.clone();
+                    if let Some(x) = Self::remote_cache_threshold_tag(command) {
+                        cache.set_remote_cache_threshold(Some(x));
+                    }
+                    let action_digest = self.action_digest(command);
+                    async move {
+                        let result = cache.get_action_result(&action_digest, true).await;
+                        (id, result.ok().map(|(x, _)| x.output_files))
+                    }
+                })
+                .collect_vec();
+            let results = futures_util::stream::iter(queries)
+                .buffer_unordered(self.max_inflight_uploads.max(1))
+                .collect::<Vec<(CommandId, _)>>()
+                .await;
+            for output_files in results.into_iter().filter_map(|(_, x)| x) {
+                hits += 1;
+                self.set_cached_output_digests(output_files, &mut seeded_files);
+            }
+        }
+        for id in seeded_files {
+            self.files[id].digest = None;
+        }
+        info!("seeded {hits} of {total} actions from remote cache");
+    }
+
+    /// Sets digests of output files from a cached action result to compute action digests of
+    /// reverse deps without executing, the ids of updated files are appended to `set`
+    pub(super) fn set_cached_output_digests(
+        &mut self,
+        output_files: Vec<OutputFile>,
+        set: &mut Vec<FileId>,
+    ) {
+        for output_file in output_files {
+            let Some(id) = self.path_to_file_id.get(&PathBuf::from(output_file.path)) else {
+                continue;
+            };
+            let file = &mut self.files[*id];
+            if file.digest.is_none() {
+                file.digest = output_file.digest;
+                set.push(*id);
+            }
+        }
+    }
+
+    fn is_seedable(command: &Command) -> bool {
+        !command.tags.contains(&Tag::NoCache) && !command.tags.contains(&Tag::NoRemoteCache)
+    }
+
+    /// Like is_input_size_above_remote_cache_max_size() but without logging
+    fn is_input_size_above_max(&self, command: &Command) -> bool {
+        self.remote_cache_max_size
+            .is_some_and(|x| self.input_size(command) > x)
+    }
+
+    pub(super) fn has_input_digests(&self, command: &Command) -> bool {
+        chain(command.executables.iter(), command.inputs.iter())
+            .all(|x| self.files[*x].digest.is_some())
+            && command.archive_entries.iter().all(|x| x.digest.is_some())
+    }
+}
@@ -0,0 +1,199 @@
+use super::Razel;
+use crate::executors::Executor;
+use crate::CommandId;
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Delimiter of the here-document used to write response files
+const RESPONSE_FILE_EOF: &str = "RAZEL_RESPONSE_FILE_EOF";
+
+impl Razel {
+    /// Print a shell script to run all not excluded commands without sandbox and cache
+    pub fn print_commands(&mut self) {
+        print!("{}", self.shell_script());
+    }
+
+    fn shell_script(&mut self) -> String {
+        let mut script = format!(
+            "#!/bin/sh\n\
+            # generated by {} --print-commands, runs commands without sandbox and cache\n\
+            set -e\n\
+            cd {}\n",
+            crate::config::EXECUTABLE,
+            shell_quote(self.current_dir.to_str().unwrap())
+        );
+        for id in self.commands_in_execution_order() {
+            script += "\n";
+            script += &self.shell_script_for_command(id);
+        }
+        script
+    }
+
+    fn shell_script_for_command(&self, id: CommandId) -> String {
+        let command = &self.commands[id];
+        let mut script = format!("# {}\n", command.name.replace('\n', " "));
+        let dirs = self
+            .output_paths(id)
+            .into_iter()
+            .filter_map(|x| x.parent())
+            .filter(|x| !x.as_os_str().is_empty())
+            .map(|x| shell_quote(x.to_str().unwrap()))
+            .sorted_unstable()
+            .dedup()
+            .collect_vec();
+        if !dirs.is_empty() {
+            writeln!(script, "mkdir -p {}", dirs.join(" ")).unwrap();
+        }
+        let env = command
+            .executor
+            .env()
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                x.iter()
+                    .map(|(k, v)| shell_quote(&format!("{k}={v}")).into_owned())
+                    .sorted_unstable()
+                    .join(" ")
+            })
+            .map(|x| format!("env {x} "))
+            .unwrap_or_default();
+        let (args_with_executable, response_file_args, merge_stderr) = match &command.executor {
+            Executor::CustomCommand(x) => (
+                x.args_with_executable(),
+                x.is_response_file_needed().then(|| x.args.join("\n")),
+                x.merge_stderr,
+            ),
+            _ => {
+                // tasks and WASI modules are run by razel
+                let mut args = command.executor.args_with_executable();
+                args[0] = self.tui.razel_executable.clone();
+                (args, None, false)
+            }
+        };
+        let mut line = match &response_file_args {
+            Some(args) => {
+                writeln!(
+                    script,
+                    "params=$(mktemp)\ncat > \"$params\" <<'{RESPONSE_FILE_EOF}'\n{args}\n{RESPONSE_FILE_EOF}"
+                )
+                .unwrap();
+                format!(
+                    "{env}{} \"{}$params\"",
+                    shell_quote(&args_with_executable[0]),
+                    crate::config::RESPONSE_FILE_PREFIX
+                )
+            }
+            None => {
+                env + &args_with_executable
+                    .iter()
+                    .map(|x| shell_quote(x))
+                    .join(" ")
+            }
+        };
+        if let Some(x) = command.executor.stdout_file() {
+            write!(line, " > {}", shell_quote(x.to_str().unwrap())).unwrap();
+        }
+        if let Some(x) = command.executor.stderr_file() {
+            write!(line, " 2> {}", shell_quote(x.to_str().unwrap())).unwrap();
+        }
+        if merge_stderr {
+            line += " 2>&1";
+        }
+        writeln!(script, "{line}").unwrap();
+        if response_file_args.is_some() {
+            script += "rm \"$params\"\n";
+        }
+        script
+    }
+}
+
+/// Quotes an arg for POSIX shells if needed
+fn shell_quote(x: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+%@".contains(c);
+    if !x.is_empty() && x.chars().all(is_safe) {
+        Cow::Borrowed(x)
+    } else {
+        Cow::Owned(format!("'{}'", x.replace('\'', "'\\''")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use std::collections::HashMap;
+
+    #[test]
+    fn quote() {
+        assert_eq!(shell_quote("razel-out/a.txt"), "razel-out/a.txt");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's $HOME"), "'it'\\''s $HOME'");
+    }
+
+    #[test]
+    fn script_runs_build() {
+        let dir = new_tmp_dir!();
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "a".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "printf \"$GREETING, it's $0\"".into(),
+                    "a.txt".into(),
+                ],
+                env: HashMap::from([("GREETING".into(), "hello world".into())]),
+                stdout: Some("sub dir/a.txt".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        razel
+            .push_custom_command(CustomCommandSpec {
+                name: "b".into(),
+                executable: "sh".into(),
+                args: vec![
+                    "-c".into(),
+                    "cat \"$0\" >&2 && printf ' again' >&2".into(),
+                    "sub dir/a.txt".into(),
+                ],
+                inputs: vec!["sub dir/a.txt".into()],
+                stderr: Some("b.txt".into()),
+                ..Default::default()
+            })
+            .unwrap();
+        let script = razel.shell_script();
+        let script_path = dir.join_and_write_file("build.sh", &script);
+        let status = std::process::Command::new("sh")
+            .arg(std::fs::canonicalize(script_path).unwrap())
+            .current_dir(std::env::temp_dir())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let b_stderr = razel.commands.iter().last().unwrap().executor.stderr_file();
+        assert_eq!(
+            std::fs::read_to_string(razel.current_dir.join(b_stderr.unwrap())).unwrap(),
+            "hello world, it's a.txt again"
+        );
+    }
+
+    #[test]
+    fn script_with_response_file() {
+        let mut razel = Razel::new();
+        let long_arg = "x".repeat(1000);
+        let id = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "long".into(),
+                executable: "echo".into(),
+                args: vec![long_arg.clone(); 10_000],
+                ..Default::default()
+            })
+            .unwrap();
+        let script = razel.shell_script_for_command(id);
+        assert!(script.contains(&format!("<<'{RESPONSE_FILE_EOF}'\n{long_arg}\n")));
+        assert!(script.contains("\"@$params\"\n"));
+        assert!(script.ends_with("rm \"$params\"\n"));
+    }
+}
@@ -6,7 +6,7 @@ use anyhow::{bail, Result};
 impl Razel {
     pub async fn check_remote_cache(&self, urls: Vec<String>) -> Result<()> {
         let mut failed = 0;
-        let cache_dir = select_cache_dir(&self.workspace_dir)?;
+        let (cache_dir, _) = select_cache_dir(None, &self.workspace_dir)?;
         let mut cache = Cache::new(cache_dir, self.out_dir.clone())?;
         for url in urls.iter().filter(|x| !x.is_empty()) {
             match cache.connect_remote_cache(&[url.clone()], None).await {
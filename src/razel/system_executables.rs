@@ -0,0 +1,146 @@
+use super::Razel;
+use crate::bazel_remote_exec::Digest;
+use crate::{CommandId, FileId, FileType};
+use anyhow::{anyhow, Context};
+use log::warn;
+use which::which;
+
+impl Razel {
+    /// Checks that the system executables of a command resolved with `which` are still valid.
+    ///
+    /// Each executable is checked once per run, the result is kept until the next run.
+    pub(super) fn check_system_executables(&mut self, id: CommandId) -> Result<(), anyhow::Error> {
+        let unchecked = self.commands[id]
+            .executables
+            .iter()
+            .copied()
+            .filter(|x| self.files[*x].file_type == FileType::SystemExecutable)
+            .filter(|x| !self.checked_system_executables.contains(x))
+            .collect::<Vec<_>>();
+        for file_id in unchecked {
+            self.check_system_executable(file_id)?;
+            self.checked_system_executables.insert(file_id);
+        }
+        Ok(())
+    }
+
+    /// Commands are executed with the resolved path, which might have been removed or replaced
+    /// since digesting it. A removed executable is resolved again, the digest is updated for
+    /// changed executables. Executables shadowed by another installation in PATH are still used.
+    fn check_system_executable(&mut self, file_id: FileId) -> Result<(), anyhow::Error> {
+        let file = &self.files[file_id];
+        let path = if file.path.is_file() {
+            file.path.clone()
+        } else {
+            let path = which(&file.arg).map_err(|_| {
+                anyhow!(
+                    "executable {:?} was resolved to {:?}, but is not found anymore",
+                    file.arg,
+                    file.path
+                )
+            })?;
+            warn!(
+                "executable {:?} was resolved to {:?}, which does not exist anymore, using {:?}",
+                file.arg, file.path, path
+            );
+            path
+        };
+        let digest = std::fs::File::open(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut x| Digest::for_std_reader(&mut x))
+            .with_context(|| format!("failed to digest executable {path:?}"))?;
+        if path == file.path && file.digest.as_ref().is_some_and(|x| *x != digest) {
+            warn!("executable {path:?} was changed, using its new digest");
+        }
+        let file = &mut self.files[file_id];
+        file.path = path;
+        file.digest = Some(digest);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+    use crate::{new_tmp_dir, CustomCommandSpec};
+    use serial_test::serial;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    const TOOL: &str = "razel-system-executables-test-tool";
+
+    fn write_tool(dir: &Path, version: &str) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(TOOL);
+        std::fs::write(&path, format!("#!/bin/sh\necho {version}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn set_path(dirs: &[&Path], orig_path: &str) {
+        let path = std::env::join_paths(
+            dirs.iter()
+                .map(|x| x.to_path_buf())
+                .chain(std::env::split_paths(orig_path)),
+        )
+        .unwrap();
+        std::env::set_var("PATH", path);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn check_system_executables() {
+        let dir = new_tmp_dir!();
+        let dir_a = std::fs::canonicalize(dir.dir()).unwrap().join("a");
+        let dir_b = std::fs::canonicalize(dir.dir()).unwrap().join("b");
+        let tool_a = write_tool(&dir_a, "1");
+        let orig_path = std::env::var("PATH").unwrap();
+        set_path(&[&dir_a], &orig_path);
+        let mut razel = Razel::new();
+        let id = razel
+            .push_custom_command(CustomCommandSpec {
+                name: "tool".into(),
+                executable: TOOL.into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let file_id = razel.get_command(id).unwrap().executables[0];
+        razel.digest_input_files().await.unwrap();
+        let digest_1 = razel.files[file_id].digest.clone().unwrap();
+        let check = |razel: &mut Razel| {
+            razel.checked_system_executables.clear();
+            razel.check_system_executables(id)
+        };
+        check(&mut razel).unwrap();
+        assert_eq!(razel.files[file_id].digest, Some(digest_1.clone()));
+        // checked only once per run
+        std::fs::remove_file(&tool_a).unwrap();
+        razel.check_system_executables(id).unwrap();
+        // shadowed by another installation: keep using the resolved one
+        write_tool(&dir_a, "1");
+        write_tool(&dir_b, "2");
+        set_path(&[&dir_b, &dir_a], &orig_path);
+        check(&mut razel).unwrap();
+        assert_eq!(razel.files[file_id].path, tool_a);
+        assert_eq!(razel.files[file_id].digest, Some(digest_1.clone()));
+        // changed: digest is updated
+        write_tool(&dir_a, "3");
+        check(&mut razel).unwrap();
+        assert_eq!(razel.files[file_id].path, tool_a);
+        let digest_3 = razel.files[file_id].digest.clone().unwrap();
+        assert_ne!(digest_3, digest_1);
+        // removed: resolved again
+        std::fs::remove_file(&tool_a).unwrap();
+        check(&mut razel).unwrap();
+        assert_eq!(razel.files[file_id].path, dir_b.join(TOOL));
+        assert_ne!(razel.files[file_id].digest, Some(digest_3));
+        // removed everywhere
+        std::fs::remove_file(dir_b.join(TOOL)).unwrap();
+        let error = check(&mut razel).unwrap_err();
+        std::env::set_var("PATH", orig_path);
+        assert!(
+            error.to_string().contains("is not found anymore"),
+            "{error}"
+        );
+    }
+}
@@ -3,18 +3,21 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use log::debug;
 use serde::{Deserialize, Serialize};
 
 use crate::metadata::Tag;
-use crate::{config, parse_cli_within_file, Razel};
+use crate::{config, parse_cli_within_file, CustomCommandSpec, OutputGlob, Razel};
 
 pub fn parse_jsonl_file(razel: &mut Razel, file_name: &String) -> Result<(), anyhow::Error> {
-    razel.set_workspace_dir(Path::new(file_name).parent().unwrap())?;
+    let workspace_dir = Path::new(file_name).parent().unwrap();
+    razel.set_workspace_dir(workspace_dir)?;
     let file = File::open(file_name).with_context(|| file_name.clone())?;
     let file_buffered = BufReader::new(file);
     let mut len: usize = 0;
+    let mut base_env = HashMap::new();
+    let mut templates = HashMap::new();
     for (line_number, line_result) in file_buffered.lines().enumerate() {
         let line = line_result?;
         let line_trimmed = line.trim();
@@ -29,27 +32,68 @@ pub fn parse_jsonl_file(razel: &mut Razel, file_name: &String) -> Result<(), any
                 line_trimmed
             )
         })?;
+        let json = match json {
+            RazelJson::Template(t) => {
+                t.check_placeholders()
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                templates.insert(t.template.clone(), t);
+                continue;
+            }
+            RazelJson::Instantiate(i) => {
+                let command = i
+                    .instantiate(&templates)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                RazelJson::Command(Box::new(command))
+            }
+            x => x,
+        };
         match json {
-            RazelJson::Command(c) => {
-                razel.push_custom_command(
-                    c.name,
-                    c.executable,
-                    c.args,
-                    c.env,
-                    c.inputs,
-                    c.outputs,
-                    c.stdout,
-                    c.stderr,
-                    c.deps,
-                    c.tags,
-                )?;
+            RazelJson::Command(mut c) => {
+                c.resolve_by_os(std::env::consts::OS)
+                    .and_then(|_| c.expand_env_vars(&env_var))
+                    .and_then(|_| c.merge_base_env(&base_env))
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                let (args, args_files) = expand_args_files(workspace_dir, c.args)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                c.inputs.extend(args_files);
+                let id = razel.push_custom_command(CustomCommandSpec {
+                    name: c.name,
+                    executable: c.executable,
+                    args,
+                    env: c.env,
+                    inputs: c.inputs,
+                    config_inputs: c.config_inputs,
+                    outputs: c.outputs,
+                    output_globs: c.output_globs,
+                    stdout: c.stdout,
+                    stderr: c.stderr,
+                    deps: c.deps,
+                    tags: c.tags,
+                })?;
+                razel
+                    .set_artifacts(id, &c.artifacts)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
             }
-            RazelJson::Task(t) => {
+            RazelJson::Task(mut t) => {
+                t.expand_env_vars(&env_var)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
                 let mut args: Vec<String> = vec![config::EXECUTABLE.into(), "task".into(), t.task];
                 args.extend(&mut t.args.iter().map(|x| x.into()));
                 parse_cli_within_file(razel, args.clone(), &t.name, t.tags)
                     .with_context(|| format!("{}\n{}", t.name, args.join(" ")))?
             }
+            RazelJson::Env(mut e) => {
+                e.extend_base_env(&mut base_env, &env_var)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                continue;
+            }
+            RazelJson::Alias(a) => {
+                razel
+                    .add_alias(a.alias, a.target)
+                    .with_context(|| format!("{}:{}", file_name, line_number + 1))?;
+                continue;
+            }
+            RazelJson::Template(_) | RazelJson::Instantiate(_) => unreachable!(),
         }
         len += 1;
     }
@@ -57,11 +101,136 @@ pub fn parse_jsonl_file(razel: &mut Razel, file_name: &String) -> Result<(), any
     Ok(())
 }
 
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Replaces `${VAR}` and `${VAR:-default}` with the value of the environment variable.
+///
+/// `$${` is passed on as `${`. Undefined variables without default are an error.
+fn expand_env_vars(
+    value: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<String, anyhow::Error> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let Some(len) = rest[start + 2..].find('}') else {
+            bail!("missing closing brace: {value}");
+        };
+        let expr = &rest[start + 2..start + 2 + len];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        if name.is_empty() || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_') {
+            bail!("invalid environment variable name {name:?}: {value}");
+        }
+        match lookup(name).or_else(|| default.map(|x| x.to_string())) {
+            Some(x) => expanded.push_str(&x),
+            None => bail!("environment variable {name} is not set and has no default: {value}"),
+        }
+        rest = &rest[start + 2 + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn expand_env_vars_in_vec(
+    field: &str,
+    values: &mut [String],
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<(), anyhow::Error> {
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = expand_env_vars(value, lookup).with_context(|| format!("{field}[{i}]"))?;
+    }
+    Ok(())
+}
+
+/// Replaces args of the form `@path` with the lines of the referenced file.
+///
+/// Each line is used verbatim as a single arg, i.e. spaces do not need to be quoted.
+/// Referenced files are resolved relative to the workspace dir, may contain nested `@path` args
+/// and are returned to be added as inputs. An arg starting with `@@` is passed on as `@...`.
+fn expand_args_files(
+    workspace_dir: &Path,
+    args: Vec<String>,
+) -> Result<(Vec<String>, Vec<String>), anyhow::Error> {
+    let mut expanded = Vec::with_capacity(args.len());
+    let mut files = vec![];
+    expand_args_files_recursive(
+        workspace_dir,
+        Path::new(""),
+        args,
+        &mut expanded,
+        &mut files,
+        &mut vec![],
+    )?;
+    Ok((expanded, files))
+}
+
+/// `base_dir` is the dir of the referencing args file, relative to the workspace dir
+fn expand_args_files_recursive(
+    workspace_dir: &Path,
+    base_dir: &Path,
+    args: Vec<String>,
+    expanded: &mut Vec<String>,
+    files: &mut Vec<String>,
+    stack: &mut Vec<String>,
+) -> Result<(), anyhow::Error> {
+    for arg in args {
+        if arg.starts_with("@@") {
+            expanded.push(arg[1..].to_string());
+            continue;
+        }
+        let Some(path) = arg.strip_prefix('@').filter(|x| !x.is_empty()) else {
+            expanded.push(arg);
+            continue;
+        };
+        let path = base_dir.join(path);
+        let path_str = path.to_str().unwrap().to_string();
+        if stack.contains(&path_str) {
+            bail!("recursive args file: {} -> {path_str}", stack.join(" -> "));
+        }
+        let contents = std::fs::read_to_string(workspace_dir.join(&path))
+            .with_context(|| format!("failed to read args file: {path_str}"))?;
+        if !files.contains(&path_str) {
+            files.push(path_str.clone());
+        }
+        stack.push(path_str);
+        expand_args_files_recursive(
+            workspace_dir,
+            path.parent().unwrap(),
+            contents
+                .lines()
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| x.to_string())
+                .collect(),
+            expanded,
+            files,
+            stack,
+        )?;
+        stack.pop();
+    }
+    Ok(())
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields, untagged)]
 pub enum RazelJson {
-    Command(RazelJsonCommand),
+    Command(Box<RazelJsonCommand>),
     Task(RazelJsonTask),
+    Alias(RazelJsonAlias),
+    Env(RazelJsonEnv),
+    Template(RazelJsonTemplate),
+    Instantiate(RazelJsonInstantiate),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -72,20 +241,128 @@ pub struct RazelJsonCommand {
     pub args: Vec<String>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub env: HashMap<String, String>,
+    /// Args appended to `args` for the host OS, see resolve_by_os()
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub args_by_os: HashMap<String, Vec<String>>,
+    /// Env variables added to `env` for the host OS, see resolve_by_os()
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env_by_os: HashMap<String, HashMap<String, String>>,
+    /// Variables of the base env not to set for this command, see merge_base_env()
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_unset: Vec<String>,
     #[serde(default)]
     pub inputs: Vec<String>,
+    /// Inputs which do not change the outputs and are therefore ignored for caching
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config_inputs: Vec<String>,
     #[serde(default)]
     pub outputs: Vec<String>,
+    /// Patterns of output files which are only known after execution
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_globs: Vec<OutputGlob>,
+    /// Outputs which are also copied to `razel-out/artifacts/<name>/`, see Razel::set_artifacts()
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stdout: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stderr: Option<String>,
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Commands to run before, like order-only prerequisites of Make: their outputs are not
+    /// part of the action digest
+    #[serde(
+        default,
+        alias = "order_only_deps",
+        skip_serializing_if = "Vec::is_empty"
+    )]
     pub deps: Vec<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tags: Vec<Tag>,
 }
 
+impl RazelJsonCommand {
+    /// Keys of `args_by_os` and `env_by_os`, values of `std::env::consts::OS` and the fallback
+    const OS_KEYS: [&'static str; 4] = ["linux", "macos", "windows", "default"];
+
+    /// Merge the `args_by_os`/`env_by_os` entries for the given OS into `args`/`env`.
+    ///
+    /// The entry for the OS is preferred over the `default` one. Selectors without a matching entry
+    /// are an error to not silently run a command with missing args on another platform.
+    pub fn resolve_by_os(&mut self, os: &str) -> Result<(), anyhow::Error> {
+        if let Some(args) = Self::select_by_os("args_by_os", &mut self.args_by_os, os)? {
+            self.args.extend(args);
+        }
+        if let Some(env) = Self::select_by_os("env_by_os", &mut self.env_by_os, os)? {
+            self.env.extend(env);
+        }
+        Ok(())
+    }
+
+    /// Expand environment variables in executable, args, env values and file paths
+    pub fn expand_env_vars(
+        &mut self,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        self.executable = expand_env_vars(&self.executable, lookup).context("executable")?;
+        expand_env_vars_in_vec("args", &mut self.args, lookup)?;
+        for (key, value) in self.env.iter_mut() {
+            *value = expand_env_vars(value, lookup).with_context(|| format!("env.{key}"))?;
+        }
+        expand_env_vars_in_vec("inputs", &mut self.inputs, lookup)?;
+        expand_env_vars_in_vec("config_inputs", &mut self.config_inputs, lookup)?;
+        expand_env_vars_in_vec("outputs", &mut self.outputs, lookup)?;
+        for (i, glob) in self.output_globs.iter_mut().enumerate() {
+            glob.pattern = expand_env_vars(&glob.pattern, lookup)
+                .with_context(|| format!("output_globs[{i}]"))?;
+        }
+        for (field, value) in [("stdout", &mut self.stdout), ("stderr", &mut self.stderr)] {
+            if let Some(x) = value {
+                *x = expand_env_vars(x, lookup).context(field)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the base env from `env` lines under `env`, without the variables in `env_unset`.
+    ///
+    /// Setting a variable to an empty value keeps it set, unlike listing it in `env_unset`.
+    pub fn merge_base_env(&mut self, base: &HashMap<String, String>) -> Result<(), anyhow::Error> {
+        if let Some(x) = self.env_unset.iter().find(|x| self.env.contains_key(*x)) {
+            bail!("env_unset: {x} is also set in env");
+        }
+        let mut env: HashMap<String, String> = base
+            .iter()
+            .filter(|(k, _)| !self.env_unset.contains(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        env.extend(std::mem::take(&mut self.env));
+        self.env = env;
+        self.env_unset.clear();
+        Ok(())
+    }
+
+    fn select_by_os<T>(
+        field: &str,
+        map: &mut HashMap<String, T>,
+        os: &str,
+    ) -> Result<Option<T>, anyhow::Error> {
+        if map.is_empty() {
+            return Ok(None);
+        }
+        if let Some(key) = map.keys().find(|x| !Self::OS_KEYS.contains(&x.as_str())) {
+            bail!(
+                "{field}: unknown OS {key:?}, expected one of {}",
+                Self::OS_KEYS.join(", ")
+            );
+        }
+        let selected = map.remove(os).or_else(|| map.remove("default"));
+        map.clear();
+        match selected {
+            Some(x) => Ok(Some(x)),
+            None => bail!("{field}: no entry for OS {os:?} and no default"),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct RazelJsonTask {
@@ -95,3 +372,526 @@ pub struct RazelJsonTask {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tags: Vec<Tag>,
 }
+
+impl RazelJsonTask {
+    /// Expand environment variables in args
+    pub fn expand_env_vars(
+        &mut self,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        expand_env_vars_in_vec("args", &mut self.args, lookup)
+    }
+}
+
+/// Base env for the commands on the following lines, e.g. `{"env":{"LANG":"C"},"env_inherit":["HOME"]}`.
+///
+/// Later lines add to or override the base env of earlier ones.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RazelJsonEnv {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Variables taken from the env of razel, skipped if not set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_inherit: Vec<String>,
+}
+
+impl RazelJsonEnv {
+    fn extend_base_env(
+        &mut self,
+        base: &mut HashMap<String, String>,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        for (key, value) in self.env.iter_mut() {
+            *value = expand_env_vars(value, lookup).with_context(|| format!("env.{key}"))?;
+        }
+        for name in &self.env_inherit {
+            if self.env.contains_key(name) {
+                bail!("env_inherit: {name} is also set in env");
+            }
+            if let Some(value) = lookup(name) {
+                self.env.insert(name.clone(), value);
+            }
+        }
+        base.extend(std::mem::take(&mut self.env));
+        Ok(())
+    }
+}
+
+/// Short name for a command, e.g. for generated names. Does not create a command.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RazelJsonAlias {
+    pub alias: String,
+    /// name of the command
+    pub target: String,
+}
+
+/// Command with `{{param}}` placeholders in its string values, e.g. to compile each source file
+/// the same way. Does not create a command, see `RazelJsonInstantiate`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RazelJsonTemplate {
+    pub template: String,
+    pub params: Vec<String>,
+    /// fields of a command, placeholders are replaced in all strings including env keys
+    pub command: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RazelJsonTemplate {
+    /// Fails on placeholders which are not declared as params
+    fn check_placeholders(&self) -> Result<(), anyhow::Error> {
+        let mut command = serde_json::Value::Object(self.command.clone());
+        replace_placeholders(&mut command, &mut |name| {
+            if !self.params.iter().any(|x| x == name) {
+                bail!(
+                    "template {}: undefined placeholder {{{{{name}}}}}",
+                    self.template
+                );
+            }
+            Ok(String::new())
+        })
+    }
+}
+
+/// Creates a command from a template defined on a previous line
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RazelJsonInstantiate {
+    /// name of the template
+    pub instantiate: String,
+    /// values of all params of the template
+    pub values: HashMap<String, String>,
+}
+
+impl RazelJsonInstantiate {
+    fn instantiate(
+        &self,
+        templates: &HashMap<String, RazelJsonTemplate>,
+    ) -> Result<RazelJsonCommand, anyhow::Error> {
+        let Some(template) = templates.get(&self.instantiate) else {
+            bail!("unknown template: {}", self.instantiate);
+        };
+        if let Some(x) = template
+            .params
+            .iter()
+            .find(|x| !self.values.contains_key(*x))
+        {
+            bail!("template {}: value missing for {x}", template.template);
+        }
+        if let Some(x) = self.values.keys().find(|x| !template.params.contains(x)) {
+            bail!("template {}: unknown param {x}", template.template);
+        }
+        let mut command = serde_json::Value::Object(template.command.clone());
+        replace_placeholders(&mut command, &mut |name| Ok(self.values[name].clone()))?;
+        serde_json::from_value(command)
+            .with_context(|| format!("template {}: invalid command", template.template))
+    }
+}
+
+/// Replaces `{{name}}` in all strings and object keys of a json value
+fn replace_placeholders(
+    value: &mut serde_json::Value,
+    replace: &mut impl FnMut(&str) -> Result<String, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    match value {
+        serde_json::Value::String(x) => *x = replace_placeholders_in_str(x, replace)?,
+        serde_json::Value::Array(x) => {
+            for x in x.iter_mut() {
+                replace_placeholders(x, replace)?;
+            }
+        }
+        serde_json::Value::Object(x) => {
+            let mut replaced = serde_json::Map::with_capacity(x.len());
+            for (key, mut value) in std::mem::take(x) {
+                replace_placeholders(&mut value, replace)?;
+                replaced.insert(replace_placeholders_in_str(&key, replace)?, value);
+            }
+            *x = replaced;
+        }
+        serde_json::Value::Null | serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn replace_placeholders_in_str(
+    value: &str,
+    replace: &mut impl FnMut(&str) -> Result<String, anyhow::Error>,
+) -> Result<String, anyhow::Error> {
+    let mut replaced = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("{{") {
+        replaced.push_str(&rest[..start]);
+        let Some(len) = rest[start + 2..].find("}}") else {
+            bail!("missing closing braces: {value}");
+        };
+        replaced.push_str(&replace(&rest[start + 2..start + 2 + len])?);
+        rest = &rest[start + 2 + len + 2..];
+    }
+    replaced.push_str(rest);
+    Ok(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use serial_test::serial;
+
+    fn parse_command(line: &str) -> RazelJsonCommand {
+        match serde_json::from_str(line).unwrap() {
+            RazelJson::Command(x) => *x,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn resolve_by_os() {
+        let line = r#"{"name":"a","executable":"tool","args":["-v"],"env":{"A":"a"},
+            "args_by_os":{"linux":["--linux"],"windows":["--windows"],"default":["--other"]},
+            "env_by_os":{"linux":{"B":"linux"},"macos":{"B":"macos"}}}"#;
+        let mut command = parse_command(line);
+        command.resolve_by_os("linux").unwrap();
+        assert_eq!(command.args, ["-v", "--linux"]);
+        assert_eq!(
+            command.env,
+            HashMap::from([("A".into(), "a".into()), ("B".into(), "linux".into())])
+        );
+        assert!(command.args_by_os.is_empty());
+        assert!(command.env_by_os.is_empty());
+        // default args, but env has no entry for freebsd
+        let mut command = parse_command(line);
+        let error = command.resolve_by_os("freebsd").unwrap_err();
+        assert_eq!(command.args, ["-v", "--other"]);
+        assert_eq!(
+            error.to_string(),
+            r#"env_by_os: no entry for OS "freebsd" and no default"#
+        );
+    }
+
+    #[test]
+    fn resolve_by_os_unknown_key() {
+        let mut command = parse_command(
+            r#"{"name":"a","executable":"tool","args":[],"args_by_os":{"linx":["-v"]}}"#,
+        );
+        assert!(command
+            .resolve_by_os("linux")
+            .unwrap_err()
+            .to_string()
+            .starts_with(r#"args_by_os: unknown OS "linx""#));
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "TOOLS" => Some("/opt/tools".into()),
+            "EMPTY" => Some("".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_env_vars_values() {
+        let expand = |x: &str| expand_env_vars(x, &lookup).unwrap();
+        assert_eq!(expand("no vars"), "no vars");
+        assert_eq!(expand("${TOOLS}/bin/tool"), "/opt/tools/bin/tool");
+        assert_eq!(expand("a${EMPTY}b${TOOLS}"), "ab/opt/tools");
+        assert_eq!(expand("${MISSING:-default}/x"), "default/x");
+        assert_eq!(expand("${TOOLS:-default}"), "/opt/tools");
+        assert_eq!(expand("${MISSING:-}"), "");
+        assert_eq!(expand("$${TOOLS} $$x"), "${TOOLS} $$x");
+        assert!(expand_env_vars("${TOOLS", &lookup).is_err());
+        assert!(expand_env_vars("${}", &lookup).is_err());
+    }
+
+    #[test]
+    fn expand_env_vars_in_command() {
+        let mut command = parse_command(
+            r#"{"name":"a","executable":"${TOOLS}/tool","args":["-o","${OUT:-out}.txt"],
+            "env":{"PATH":"${TOOLS}/bin"},"inputs":["${TOOLS}/in.txt"],"outputs":["${OUT:-out}.txt"]}"#,
+        );
+        command.expand_env_vars(&lookup).unwrap();
+        assert_eq!(command.executable, "/opt/tools/tool");
+        assert_eq!(command.args, ["-o", "out.txt"]);
+        assert_eq!(command.env["PATH"], "/opt/tools/bin");
+        assert_eq!(command.inputs, ["/opt/tools/in.txt"]);
+        assert_eq!(command.outputs, ["out.txt"]);
+    }
+
+    #[test]
+    fn expand_env_vars_undefined() {
+        let mut command =
+            parse_command(r#"{"name":"a","executable":"tool","args":["-v","${MISSING}"]}"#);
+        let error = command.expand_env_vars(&lookup).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "args[1]: environment variable MISSING is not set and has no default: ${MISSING}"
+        );
+    }
+
+    #[test]
+    fn base_env() {
+        let dir = new_tmp_dir!();
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"name":"before","executable":"cmake","args":[],"env":{"A":"own"}}
+{"env":{"A":"base","B":"base","C":"base"}}
+{"env":{"C":"${RAZEL_TEST_BASE_ENV:-expanded}"},"env_inherit":["RAZEL_TEST_NOT_SET"]}
+{"name":"merged","executable":"cmake","args":[],"env":{"B":"own"}}
+{"name":"empty","executable":"cmake","args":[],"env":{"A":""},"env_unset":["B"]}"#,
+        );
+        let mut razel = Razel::new();
+        parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+        let env = |name: &str| {
+            razel
+                .get_command_by_name(&name.into())
+                .unwrap()
+                .executor
+                .env()
+                .unwrap()
+                .clone()
+        };
+        let map = |x: &[(&str, &str)]| {
+            x.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        // base env is only used for following lines
+        assert_eq!(env("before"), map(&[("A", "own")]));
+        assert_eq!(
+            env("merged"),
+            map(&[("A", "base"), ("B", "own"), ("C", "expanded")])
+        );
+        // empty value is kept, unset vars are removed
+        assert_eq!(env("empty"), map(&[("A", ""), ("C", "expanded")]));
+    }
+
+    #[test]
+    fn base_env_conflicts() {
+        let mut command = parse_command(
+            r#"{"name":"a","executable":"tool","args":[],"env":{"A":"a"},"env_unset":["A"]}"#,
+        );
+        assert!(command.merge_base_env(&HashMap::new()).is_err());
+        let mut env: RazelJsonEnv =
+            serde_json::from_str(r#"{"env":{"TOOLS":"x"},"env_inherit":["TOOLS"]}"#).unwrap();
+        assert!(env.extend_base_env(&mut HashMap::new(), &lookup).is_err());
+        let mut env: RazelJsonEnv =
+            serde_json::from_str(r#"{"env_inherit":["TOOLS","MISSING"]}"#).unwrap();
+        let mut base = HashMap::new();
+        env.extend_base_env(&mut base, &lookup).unwrap();
+        assert_eq!(base, HashMap::from([("TOOLS".into(), "/opt/tools".into())]));
+    }
+
+    #[test]
+    fn expand_args_files_nested() {
+        let dir = new_tmp_dir!();
+        dir.join_and_write_file("args.txt", "-E\n@nested/args.txt\n\n@@literal\n");
+        // relative to the referencing file
+        dir.join_and_write_file("nested/args.txt", "echo\n@more.txt\n");
+        dir.join_and_write_file("nested/more.txt", "with space\n  \n");
+        let (args, files) = expand_args_files(
+            dir.dir(),
+            vec!["@args.txt".into(), "@@x".into(), "last".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            vec!["-E", "echo", "with space", "@literal", "@x", "last"]
+        );
+        assert_eq!(
+            files,
+            vec!["args.txt", "nested/args.txt", "nested/more.txt"]
+        );
+    }
+
+    #[test]
+    fn expand_args_files_recursive() {
+        let dir = new_tmp_dir!();
+        dir.join_and_write_file("a.txt", "@b.txt");
+        dir.join_and_write_file("b.txt", "@a.txt");
+        assert!(expand_args_files(dir.dir(), vec!["@a.txt".into()]).is_err());
+        assert!(expand_args_files(dir.dir(), vec!["@missing.txt".into()]).is_err());
+    }
+
+    #[test]
+    fn template_instantiated_twice() {
+        let dir = new_tmp_dir!();
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"template":"copy","params":["src","dst"],"command":{"name":"copy-{{dst}}","executable":"cmake","args":["-E","copy","{{src}}","{{dst}}"],"inputs":["{{src}}"],"outputs":["{{dst}}"],"env":{"{{dst}}_SRC":"{{src}}"}}}
+{"instantiate":"copy","values":{"src":"a.c","dst":"a.o"}}
+{"instantiate":"copy","values":{"src":"b.c","dst":"b.o"}}"#,
+        );
+        let mut razel = Razel::new();
+        parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(razel.command_names().count(), 2);
+        for (src, dst) in [("a.c", "a.o"), ("b.c", "b.o")] {
+            let command = razel.get_command_by_name(&format!("copy-{dst}")).unwrap();
+            let args = command.executor.args_with_executable();
+            assert_eq!(args[1..3], ["-E", "copy"]);
+            assert!(args[3].ends_with(src) && args[4].ends_with(dst));
+            assert_eq!(command.inputs.len(), 1);
+            assert_eq!(command.outputs.len(), 1);
+            assert_eq!(
+                command.executor.env().unwrap(),
+                &HashMap::from([(format!("{dst}_SRC"), src.to_string())])
+            );
+        }
+    }
+
+    #[test]
+    fn template_errors() {
+        let dir = new_tmp_dir!();
+        let template = r#"{"template":"t","params":["a"],"command":{"name":"{{a}}","executable":"cmake","args":["{{b}}"]}}"#;
+        let jsonl = dir.join_and_write_file("razel.jsonl", template);
+        let error =
+            parse_jsonl_file(&mut Razel::new(), &jsonl.to_str().unwrap().to_string()).unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "template t: undefined placeholder {{b}}"
+        );
+        let template = r#"{"template":"t","params":["a"],"command":{"name":"{{a}}","executable":"cmake","args":[]}}"#;
+        for (instantiate, expected) in [
+            (
+                r#"{"instantiate":"x","values":{"a":"1"}}"#,
+                "unknown template: x",
+            ),
+            (
+                r#"{"instantiate":"t","values":{}}"#,
+                "template t: value missing for a",
+            ),
+            (
+                r#"{"instantiate":"t","values":{"a":"1","b":"2"}}"#,
+                "template t: unknown param b",
+            ),
+        ] {
+            let jsonl =
+                dir.join_and_write_file("razel.jsonl", &format!("{template}\n{instantiate}"));
+            let error = parse_jsonl_file(&mut Razel::new(), &jsonl.to_str().unwrap().to_string())
+                .unwrap_err();
+            assert_eq!(error.root_cause().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn order_only_deps() {
+        let dir = new_tmp_dir!();
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"name":"a","executable":"sh","args":["-c","true"]}
+{"name":"b","executable":"sh","args":["-c","true"],"order_only_deps":["a"]}"#,
+        );
+        let mut razel = Razel::new();
+        parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+        let a = razel.get_command_by_name(&"a".into()).unwrap().id;
+        assert_eq!(razel.get_command_by_name(&"b".into()).unwrap().deps, [a]);
+    }
+
+    #[test]
+    fn alias() {
+        let dir = new_tmp_dir!();
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"alias":"short","target":"generated/very/long:name"}
+{"name":"generated/very/long:name","executable":"cmake","args":["-E","echo","a"],"stdout":"a.txt"}
+{"name":"b","executable":"cmake","args":["-E","echo","b"],"stdout":"b.txt","deps":["short"]}"#,
+        );
+        let mut razel = Razel::new();
+        parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(razel.command_names().count(), 2);
+        let target = razel.get_command_by_name(&"short".into()).unwrap();
+        assert_eq!(target.name, "generated/very/long:name");
+        let target = target.id;
+        assert_eq!(
+            razel.get_command_by_name(&"b".into()).unwrap().deps,
+            [target]
+        );
+        razel.add_tag_for_command("short", Tag::Quiet).unwrap();
+        assert!(razel
+            .get_command_by_name(&"short".into())
+            .unwrap()
+            .tags
+            .contains(&Tag::Quiet));
+        razel.filter_targets(&["short".into()]).unwrap();
+        assert!(
+            !razel
+                .get_command_by_name(&"short".into())
+                .unwrap()
+                .is_excluded
+        );
+        assert!(razel.get_command_by_name(&"b".into()).unwrap().is_excluded);
+    }
+
+    #[test]
+    fn alias_collides_with_command_name() {
+        let dir = new_tmp_dir!();
+        for lines in [
+            r#"{"name":"a","executable":"cmake","args":[]}
+{"alias":"a","target":"a"}"#,
+            r#"{"alias":"a","target":"b"}
+{"name":"a","executable":"cmake","args":[]}"#,
+            r#"{"alias":"a","target":"b"}
+{"alias":"a","target":"c"}"#,
+        ] {
+            let jsonl = dir.join_and_write_file("razel.jsonl", lines);
+            let mut razel = Razel::new();
+            assert!(parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).is_err());
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn args_file_is_input() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let args_file = dir.join_and_write_file("args.txt", "-E\necho\na");
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"name":"echo","executable":"cmake","args":["@args.txt"],"stdout":"out.txt"}"#,
+        );
+        let run = || async {
+            let mut razel = Razel::new();
+            parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+            let command = razel.get_command_by_name(&"echo".into()).unwrap();
+            assert_eq!(
+                command.executor.args_with_executable()[1..3],
+                ["-E", "echo"]
+            );
+            let stats = razel
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            assert_eq!(stats.exec.succeeded, 1);
+            stats.cache_hits
+        };
+        assert_eq!(run().await, 0);
+        assert_eq!(run().await, 1);
+        std::fs::write(&args_file, "-E\necho\nb").unwrap();
+        assert_eq!(run().await, 0);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn config_input_is_not_cached() {
+        let dir = new_tmp_dir!();
+        let cache_dir = dir.join("cache");
+        let config_file = dir.join_and_write_file("config.txt", "a");
+        let jsonl = dir.join_and_write_file(
+            "razel.jsonl",
+            r#"{"name":"copy","executable":"cmake","args":["-E","copy","config.txt","out.txt"],"config_inputs":["config.txt"],"outputs":["out.txt"]}"#,
+        );
+        let run = || async {
+            let mut razel = Razel::new();
+            parse_jsonl_file(&mut razel, &jsonl.to_str().unwrap().to_string()).unwrap();
+            let stats = razel
+                .run(false, true, "", Some(cache_dir.clone()), vec![], None)
+                .await
+                .unwrap();
+            // copying fails if config.txt is missing in the sandbox
+            assert_eq!(stats.exec.succeeded, 1);
+            stats.cache_hits
+        };
+        assert_eq!(run().await, 0);
+        std::fs::write(&config_file, "b").unwrap();
+        assert_eq!(run().await, 1);
+    }
+}
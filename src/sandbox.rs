@@ -1,11 +1,13 @@
-use crate::config::LinkType;
+use crate::config::{LinkType, RESPONSE_FILE_NAME};
+use crate::OutputGlob;
 use anyhow::bail;
 use anyhow::{Context, Error};
 use async_trait::async_trait;
+use regex::RegexSet;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
-pub type BoxedSandbox = Box<dyn Sandbox + Send>;
+pub type BoxedSandbox = Box<dyn Sandbox + Send + Sync>;
 
 #[async_trait]
 pub trait Sandbox {
@@ -19,15 +21,63 @@ pub trait Sandbox {
         output_paths: &[PathBuf],
     ) -> Result<(), anyhow::Error>;
 
+    /// Returns files created in the sandbox which match the output globs but are not declared outputs.
+    ///
+    /// Fails if a glob without `allow_empty` matches no file.
+    async fn output_glob_files(&self, outputs: &[PathBuf]) -> Result<Vec<PathBuf>, anyhow::Error>;
+
+    /// Returns files created in the sandbox which are neither inputs nor declared outputs.
+    ///
+    /// Only checked if enabled with `--strict-outputs`.
+    async fn undeclared_files(&self, outputs: &[PathBuf]) -> Result<Vec<PathBuf>, anyhow::Error>;
+
     /// Remove tmp dir
     async fn destroy(&self) -> Result<(), anyhow::Error>;
 }
 
+/// Marks errors of creating a sandbox or linking files which might be caused by races with
+/// concurrent commands, e.g. on a shared CAS
+#[derive(Debug)]
+pub struct TransientSandboxError;
+
+impl std::fmt::Display for TransientSandboxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "transient sandbox error")
+    }
+}
+
+impl TransientSandboxError {
+    /// Adds the marker if the error is caused by a file which already exists, e.g. a leftover of
+    /// a previous attempt.
+    ///
+    /// Missing files are not marked, because they are usually caused by missing inputs.
+    pub fn mark(error: Error) -> Error {
+        let is_transient = error
+            .chain()
+            .filter_map(|x| x.downcast_ref::<std::io::Error>())
+            .any(|x| x.kind() == std::io::ErrorKind::AlreadyExists);
+        if is_transient {
+            error.context(Self)
+        } else {
+            error
+        }
+    }
+
+    pub fn is(error: &Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
 /// TODO sandbox does not stop writing to input files
 #[derive(Debug)]
 pub struct TmpDirSandbox {
     dir: PathBuf,
     inputs: Vec<PathBuf>,
+    /// if set, undeclared files not matching these patterns are reported
+    strict_outputs_ignore: Option<RegexSet>,
+    output_globs: Vec<OutputGlob>,
+    /// archives with entries and their paths in the sandbox, extracted after linking inputs
+    archive_entries: Vec<(PathBuf, Vec<(PathBuf, PathBuf)>)>,
 }
 
 impl TmpDirSandbox {
@@ -39,7 +89,58 @@ impl TmpDirSandbox {
         Self {
             dir: base_dir.join(command_id),
             inputs,
+            strict_outputs_ignore: None,
+            output_globs: vec![],
+            archive_entries: vec![],
+        }
+    }
+
+    /// Enable reporting undeclared files, except for paths matching the ignore patterns
+    pub fn with_strict_outputs(mut self, ignore: Option<RegexSet>) -> Self {
+        self.strict_outputs_ignore = ignore;
+        self
+    }
+
+    pub fn with_output_globs(mut self, output_globs: Vec<OutputGlob>) -> Self {
+        self.output_globs = output_globs;
+        self
+    }
+
+    pub fn with_archive_entries(
+        mut self,
+        archive_entries: Vec<(PathBuf, Vec<(PathBuf, PathBuf)>)>,
+    ) -> Self {
+        self.archive_entries = archive_entries;
+        self
+    }
+
+    /// Returns true for linked inputs and extracted archive entries
+    fn is_input(&self, path: &PathBuf) -> bool {
+        self.inputs.contains(path)
+            || self
+                .archive_entries
+                .iter()
+                .any(|(_, entries)| entries.iter().any(|(_, x)| x == path))
+    }
+
+    /// Returns all files below the dir, relative to the sandbox dir
+    async fn files_in_dir(&self, dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut files = vec![];
+        let mut dirs = vec![self.dir.join(dir)];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir)
+                .await
+                .with_context(|| format!("Failed to read sandbox dir: {dir:?}"))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                files.push(path.strip_prefix(&self.dir).unwrap().to_path_buf());
+            }
         }
+        Ok(files)
     }
 }
 
@@ -60,10 +161,28 @@ impl Sandbox for TmpDirSandbox {
             let src = input;
             let dst = self.dir.join(input);
             match crate::config::SANDBOX_LINK_TYPE {
-                LinkType::Hardlink => crate::force_hardlink(src, &dst).await?,
+                LinkType::Hardlink => match crate::force_hardlink(src, &dst).await {
+                    Err(e) if crate::is_cross_device_error(&e) => {
+                        crate::force_copy(src, &dst).await?
+                    }
+                    x => x?,
+                },
                 LinkType::Symlink => crate::force_symlink(src, &dst).await?,
+                LinkType::Copy => crate::force_copy(src, &dst).await?,
             }
         }
+        for (archive, entries) in &self.archive_entries {
+            let archive = archive.clone();
+            let entries = entries
+                .iter()
+                .map(|(entry, path)| (entry.clone(), self.dir.join(path)))
+                .collect::<Vec<_>>();
+            tokio::task::spawn_blocking(move || {
+                crate::tasks::extract_archive_entries(&archive, &entries)
+                    .with_context(|| format!("Failed to extract entries of {archive:?}"))
+            })
+            .await??;
+        }
         for output in outputs {
             let output_abs = self.dir.join(output);
             let dir = output_abs.parent().unwrap();
@@ -71,6 +190,12 @@ impl Sandbox for TmpDirSandbox {
                 .await
                 .with_context(|| format!("Failed to create sandbox output dir: {dir:?}"))?;
         }
+        for glob in &self.output_globs {
+            let dir = self.dir.join(glob.base_dir());
+            fs::create_dir_all(&dir)
+                .await
+                .with_context(|| format!("Failed to create sandbox output dir: {dir:?}"))?;
+        }
         Ok(&self.dir)
     }
 
@@ -80,6 +205,8 @@ impl Sandbox for TmpDirSandbox {
     ) -> Result<(), anyhow::Error> {
         for dst in output_paths {
             let src = self.dir.join(dst);
+            // parent dirs of files matched by output globs might not exist yet
+            fs::create_dir_all(dst.parent().unwrap()).await?;
             tokio::fs::rename(&src, &dst)
                 .await
                 .with_context(|| format!("move_output_files_into_out_dir {src:?} -> {dst:?}"))?;
@@ -87,6 +214,47 @@ impl Sandbox for TmpDirSandbox {
         Ok(())
     }
 
+    async fn output_glob_files(&self, outputs: &[PathBuf]) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut matched = vec![];
+        for glob in &self.output_globs {
+            let regex = glob.regex()?;
+            let mut is_empty = true;
+            for path in self.files_in_dir(&glob.base_dir()).await? {
+                if !regex.is_match(&OutputGlob::path_for_matching(&path)) || self.is_input(&path) {
+                    continue;
+                }
+                is_empty = false;
+                if !outputs.contains(&path) && !matched.contains(&path) {
+                    matched.push(path);
+                }
+            }
+            if is_empty && !glob.allow_empty {
+                bail!("no files match output glob: {}", glob.pattern);
+            }
+        }
+        matched.sort();
+        Ok(matched)
+    }
+
+    async fn undeclared_files(&self, outputs: &[PathBuf]) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let Some(ignore) = &self.strict_outputs_ignore else {
+            return Ok(vec![]);
+        };
+        let mut undeclared = self
+            .files_in_dir(Path::new(""))
+            .await?
+            .into_iter()
+            .filter(|x| {
+                x != Path::new(RESPONSE_FILE_NAME)
+                    && !self.is_input(x)
+                    && !outputs.contains(x)
+                    && !ignore.is_match(&x.to_string_lossy())
+            })
+            .collect::<Vec<_>>();
+        undeclared.sort();
+        Ok(undeclared)
+    }
+
     async fn destroy(&self) -> Result<(), anyhow::Error> {
         fs::remove_dir_all(&self.dir)
             .await
@@ -143,6 +311,16 @@ impl Sandbox for WasiSandbox {
             .await
     }
 
+    /// Output globs are rejected for WASI modules when pushing the command, no files are reported
+    async fn output_glob_files(&self, _outputs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+        Ok(vec![])
+    }
+
+    /// `--strict-outputs` is not supported for WASI modules, no files are reported
+    async fn undeclared_files(&self, _outputs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+        Ok(vec![])
+    }
+
     async fn destroy(&self) -> Result<(), Error> {
         self.tmp_dir_sandbox.destroy().await
     }
@@ -191,4 +369,27 @@ mod tests {
         );
         sandbox.destroy().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn undeclared_files() {
+        let base_dir = new_tmp_dir!();
+        let input = PathBuf::from("README.md");
+        let outputs = vec![PathBuf::from("razel-out/output-file.txt")];
+        let sandbox = TmpDirSandbox::new(base_dir.dir(), "0", vec![input])
+            .with_strict_outputs(Some(RegexSet::new(["^tmp-"]).unwrap()));
+        let sandbox_dir = sandbox.create(&outputs).await.unwrap();
+        assert!(sandbox.undeclared_files(&outputs).await.unwrap().is_empty());
+        for file in [
+            outputs[0].as_path(),
+            Path::new("tmp-file"),
+            Path::new("razel-out/other.txt"),
+        ] {
+            fs::write(sandbox_dir.join(file), OUTPUT_FILE_CONTENT).unwrap();
+        }
+        assert_eq!(
+            sandbox.undeclared_files(&outputs).await.unwrap(),
+            vec![PathBuf::from("razel-out/other.txt")]
+        );
+        sandbox.destroy().await.unwrap();
+    }
 }
@@ -1,4 +1,5 @@
 use crate::executors::{Executor, HttpRemoteExecDomain};
+use crate::metadata::Tag;
 use crate::{Command, CommandId};
 use itertools::Itertools;
 use std::collections::{HashMap, VecDeque};
@@ -10,12 +11,18 @@ struct ReadyItem {
     id: CommandId,
     group: Group,
     slots: usize,
+    priority: i32,
+    /// named resources and counts from `Tag::Resource`
+    resources: Vec<(String, usize)>,
 }
 
 /// Keeps track of ready/running commands and selects next to run depending on resources
 pub struct Scheduler {
     available_slots: usize,
     used_slots: usize,
+    /// slots used outside of the scheduler, e.g. by jobserver tokens taken by commands
+    reserved_slots: usize,
+    /// sorted by descending priority, insertion order within equal priority
     // TODO sort by weight, e.g. recursive number of rdeps
     ready_items: Vec<ReadyItem>,
     ready_for_remote_exec: Vec<(Arc<HttpRemoteExecDomain>, VecDeque<CommandId>)>,
@@ -24,6 +31,10 @@ pub struct Scheduler {
     running_with_remote_exec: usize,
     /// groups commands by estimated resource requirement
     group_to_slots: HashMap<String, usize>,
+    /// capacity of named resources, `Razel::check_resources()` rejects commands using resources
+    /// without capacity
+    resource_capacity: HashMap<String, usize>,
+    resource_used: HashMap<String, usize>,
 }
 
 impl Scheduler {
@@ -31,15 +42,37 @@ impl Scheduler {
         Self {
             available_slots,
             used_slots: 0,
+            reserved_slots: 0,
             ready_items: Default::default(),
             ready_for_remote_exec: Default::default(),
             ready_for_remote_exec_len: 0,
             running_items: Default::default(),
             running_with_remote_exec: 0,
             group_to_slots: Default::default(),
+            resource_capacity: Default::default(),
+            resource_used: Default::default(),
         }
     }
 
+    /// Limit the number of commands using a named resource, see `Tag::Resource`
+    pub fn set_resource_capacity(&mut self, name: String, capacity: usize) {
+        self.resource_capacity.insert(name, capacity);
+    }
+
+    /// Do not start commands in slots used outside of the scheduler, running ones are not affected
+    pub fn set_reserved_slots(&mut self, slots: usize) {
+        self.reserved_slots = slots;
+    }
+
+    fn free_slots(&self) -> usize {
+        self.available_slots
+            .saturating_sub(self.used_slots + self.reserved_slots)
+    }
+
+    pub fn resource_capacity(&self, name: &str) -> Option<usize> {
+        self.resource_capacity.get(name).copied()
+    }
+
     pub fn ready(&self) -> usize {
         self.ready_items.len() + self.ready_for_remote_exec_len
     }
@@ -77,13 +110,22 @@ impl Scheduler {
         }
         let group = Self::group_for_command(command);
         let slots = self.slots_for_group(&group);
-        self.ready_items.push(ReadyItem {
+        self.push_ready_item(ReadyItem {
             id: command.id,
             group,
             slots,
+            priority: Self::priority_for_command(command),
+            resources: Self::resources_for_command(command),
         });
     }
 
+    fn push_ready_item(&mut self, item: ReadyItem) {
+        let index = self
+            .ready_items
+            .partition_point(|x| x.priority >= item.priority);
+        self.ready_items.insert(index, item);
+    }
+
     fn push_ready_for_remote_exec(&mut self, command: &Command) -> bool {
         let Executor::HttpRemote(executor) = &command.executor else {
             return false;
@@ -112,24 +154,74 @@ impl Scheduler {
         if let Some(x) = self.pop_ready_and_run_remote_exec() {
             return Some(x);
         }
-        if self.used_slots >= self.available_slots || self.ready_items.is_empty() {
+        let free_slots = self.free_slots();
+        if free_slots == 0 || self.ready_items.is_empty() {
             return None;
         }
-        let free_slots = self.available_slots - self.used_slots;
         if let Some((index, _)) = self
             .ready_items
             .iter()
-            .find_position(|x| x.slots <= free_slots)
+            .find_position(|x| x.slots <= free_slots && self.are_resources_available(x))
         {
             let item = self.ready_items.remove(index);
-            self.running_items.insert(item.id, item.group);
-            self.used_slots += item.slots;
-            Some(item.id)
+            Some(self.set_running(item))
         } else {
             None
         }
     }
 
+    fn set_running(&mut self, item: ReadyItem) -> CommandId {
+        for (name, count) in &item.resources {
+            *self.resource_used.entry(name.clone()).or_default() += count;
+        }
+        self.running_items.insert(item.id, item.group);
+        self.used_slots += item.slots;
+        item.id
+    }
+
+    fn are_resources_available(&self, item: &ReadyItem) -> bool {
+        item.resources.iter().all(|(name, count)| {
+            let Some(capacity) = self.resource_capacity.get(name) else {
+                return true;
+            };
+            self.resource_used.get(name).unwrap_or(&0) + count <= *capacity
+        })
+    }
+
+    pub fn is_ready(&self, id: CommandId) -> bool {
+        self.ready_items.iter().any(|x| x.id == id)
+            || self
+                .ready_for_remote_exec
+                .iter()
+                .any(|(_, x)| x.contains(&id))
+    }
+
+    /// Like pop_ready_and_run() but for a specific ready command, e.g. to replay a schedule
+    pub fn pop_ready_id_and_run(&mut self, id: CommandId) -> bool {
+        if let Some((domain, commands)) = self
+            .ready_for_remote_exec
+            .iter_mut()
+            .find(|(_, x)| x.contains(&id))
+        {
+            if !domain.try_schedule() {
+                return false;
+            }
+            commands.retain(|x| *x != id);
+            self.ready_for_remote_exec_len -= 1;
+            self.running_with_remote_exec += 1;
+            return true;
+        }
+        let Some((index, item)) = self.ready_items.iter().find_position(|x| x.id == id) else {
+            return false;
+        };
+        if item.slots > self.free_slots() || !self.are_resources_available(item) {
+            return false;
+        }
+        let item = self.ready_items.remove(index);
+        self.set_running(item);
+        true
+    }
+
     fn pop_ready_and_run_remote_exec(&mut self) -> Option<CommandId> {
         if self.ready_for_remote_exec_len == 0 {
             return None;
@@ -151,12 +243,21 @@ impl Scheduler {
         let id = command.id;
         let group = self.running_items.remove(&id).unwrap();
         self.used_slots -= self.slots_for_group(&group);
+        for (name, count) in Self::resources_for_command(command) {
+            *self.resource_used.get_mut(&name).unwrap() -= count;
+        }
         if oom_killed {
             self.scale_up_memory_requirement(&group);
             // stop retry only when command was run exclusively
             if !self.running_items.is_empty() {
                 let slots = self.slots_for_group(&group);
-                self.ready_items.push(ReadyItem { id, group, slots });
+                self.push_ready_item(ReadyItem {
+                    id,
+                    group,
+                    slots,
+                    priority: Self::priority_for_command(command),
+                    resources: Self::resources_for_command(command),
+                });
                 return true;
             }
         }
@@ -211,6 +312,28 @@ impl Scheduler {
             Executor::HttpRemote(_) => String::new(),
         }
     }
+
+    fn priority_for_command(command: &Command) -> i32 {
+        command
+            .tags
+            .iter()
+            .find_map(|x| match x {
+                Tag::Priority(x) => Some(*x),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn resources_for_command(command: &Command) -> Vec<(String, usize)> {
+        command
+            .tags
+            .iter()
+            .filter_map(|x| match x {
+                Tag::Resource(name, count) => Some((name.clone(), *count as usize)),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 impl Drop for Scheduler {
@@ -233,21 +356,36 @@ mod tests {
     use crate::{Arena, ScheduleState};
 
     fn create(available_slots: usize, executables: Vec<&str>) -> (Scheduler, Arena<Command>) {
+        create_with_tags(
+            available_slots,
+            executables.into_iter().map(|x| (x, vec![])).collect(),
+        )
+    }
+
+    fn create_with_tags(
+        available_slots: usize,
+        executables: Vec<(&str, Vec<Tag>)>,
+    ) -> (Scheduler, Arena<Command>) {
         let mut scheduler = Scheduler::new(available_slots);
         let mut commands: Arena<Command> = Default::default();
-        for executable in &executables {
+        for (executable, tags) in &executables {
             let id = commands.alloc_with_id(|id| Command {
                 id,
                 name: format!("cmd_{id}"),
                 executables: vec![],
                 inputs: vec![],
+                config_inputs: vec![],
+                archive_entries: vec![],
+                entry_only_archives: vec![],
                 outputs: vec![],
+                output_globs: vec![],
+                artifacts: vec![],
                 deps: vec![],
                 executor: Executor::CustomCommand(CustomCommandExecutor {
                     executable: executable.to_string(),
                     ..Default::default()
                 }),
-                tags: vec![],
+                tags: tags.clone(),
                 is_excluded: false,
                 unfinished_deps: vec![],
                 reverse_deps: vec![],
@@ -329,4 +467,93 @@ mod tests {
         assert_eq!(s.len(), 0);
         assert_eq!(s.used_slots, 0);
     }
+
+    #[test]
+    fn priority() {
+        let (mut s, commands) = create_with_tags(
+            1,
+            vec![
+                ("exec_0", vec![]),
+                ("exec_1", vec![Tag::Priority(-1)]),
+                ("exec_2", vec![Tag::Priority(2)]),
+                ("exec_3", vec![]),
+                ("exec_4", vec![Tag::Priority(2)]),
+            ],
+        );
+        let mut order = vec![];
+        while let Some(id) = s.pop_ready_and_run() {
+            order.push(commands[id].name.clone());
+            assert_eq!(s.pop_ready_and_run(), None);
+            assert_eq!(
+                s.set_finished_and_get_retry_flag(&commands[id], false),
+                false
+            );
+        }
+        assert_eq!(order, ["cmd_2", "cmd_4", "cmd_0", "cmd_3", "cmd_1"]);
+        assert_eq!(s.len(), 0);
+    }
+
+    #[test]
+    fn resources() {
+        let gpu = || vec![Tag::Resource("gpu".into(), 1)];
+        let (mut s, commands) = create_with_tags(
+            3,
+            vec![
+                ("exec_0", gpu()),
+                ("exec_1", gpu()),
+                ("exec_2", vec![]),
+                ("exec_3", vec![Tag::Resource("license".into(), 5)]),
+            ],
+        );
+        s.set_resource_capacity("gpu".into(), 1);
+        let c0 = s.pop_ready_and_run().unwrap();
+        assert_eq!(commands[c0].name, "cmd_0");
+        // cmd_1 needs the gpu, license is not limited
+        let c2 = s.pop_ready_and_run().unwrap();
+        assert_eq!(commands[c2].name, "cmd_2");
+        let c3 = s.pop_ready_and_run().unwrap();
+        assert_eq!(commands[c3].name, "cmd_3");
+        assert_eq!(
+            s.set_finished_and_get_retry_flag(&commands[c2], false),
+            false
+        );
+        assert_eq!(s.pop_ready_and_run(), None);
+        assert_eq!(
+            s.set_finished_and_get_retry_flag(&commands[c0], false),
+            false
+        );
+        let c1 = s.pop_ready_and_run().unwrap();
+        assert_eq!(commands[c1].name, "cmd_1");
+        assert_eq!(s.resource_used["gpu"], 1);
+        assert_eq!(
+            s.set_finished_and_get_retry_flag(&commands[c1], false),
+            false
+        );
+        assert_eq!(
+            s.set_finished_and_get_retry_flag(&commands[c3], false),
+            false
+        );
+        assert_eq!(s.len(), 0);
+        assert_eq!(s.resource_used["gpu"], 0);
+    }
+
+    #[test]
+    fn reserved_slots() {
+        let (mut s, commands) = create(3, vec!["exec_0", "exec_1", "exec_2"]);
+        s.set_reserved_slots(2);
+        let c0 = s.pop_ready_and_run().unwrap();
+        assert_eq!(s.pop_ready_and_run(), None);
+        s.set_reserved_slots(1);
+        let c1 = s.pop_ready_and_run().unwrap();
+        assert_eq!(s.pop_ready_and_run(), None);
+        s.set_reserved_slots(0);
+        let c2 = s.pop_ready_and_run().unwrap();
+        for id in [c0, c1, c2] {
+            assert_eq!(
+                s.set_finished_and_get_retry_flag(&commands[id], false),
+                false
+            );
+        }
+        assert_eq!(s.len(), 0);
+    }
 }
@@ -0,0 +1,384 @@
+use crate::bazel_remote_exec::Digest;
+use crate::cache::BlobDigest;
+use anyhow::{bail, Context};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn from_path(path: &Path) -> Result<Self, anyhow::Error> {
+        let name = path.to_string_lossy();
+        if name.ends_with(".tar") {
+            Ok(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(Self::TarGz)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Ok(Self::TarXz)
+        } else if name.ends_with(".zip") {
+            Ok(Self::Zip)
+        } else {
+            bail!("unsupported archive format (tar, tar.gz, tar.xz, zip): {path:?}")
+        }
+    }
+}
+
+/// Splits an input file argument like `data.tar#dir/file.csv` into archive and entry path.
+///
+/// Returns None if the part before `#` is not a supported archive.
+pub fn split_archive_entry(arg: &str) -> Option<(&str, &str)> {
+    arg.match_indices('#')
+        .map(|(i, _)| (&arg[..i], &arg[i + 1..]))
+        .find(|(archive, entry)| {
+            !entry.is_empty() && ArchiveFormat::from_path(Path::new(archive)).is_ok()
+        })
+}
+
+/// Extract the given files of an archive into a directory.
+///
+/// Only the declared files are extracted to allow caching them as outputs.
+/// Archives containing absolute paths or `..` are rejected. Symlinks are not supported as output files.
+pub fn extract_archive(
+    archive: PathBuf,
+    output_dir: PathBuf,
+    files: Vec<PathBuf>,
+) -> Result<(), anyhow::Error> {
+    let entries = files
+        .into_iter()
+        .map(|x| {
+            let dst = output_dir.join(normalize_entry_path(&x)?);
+            Ok((x, dst))
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    extract_archive_entries(&archive, &entries)
+}
+
+/// Extract entries of an archive to the given destination paths, the archive is read only once
+pub fn extract_archive_entries(
+    archive: &Path,
+    entries: &[(PathBuf, PathBuf)],
+) -> Result<(), anyhow::Error> {
+    let mut dsts = entries
+        .iter()
+        .map(|(entry, dst)| Ok((normalize_entry_path(entry)?, dst)))
+        .collect::<Result<HashMap<_, _>, anyhow::Error>>()?;
+    for_each_entry(
+        archive,
+        dsts.keys().cloned().collect(),
+        |path, reader, mode| {
+            let dst = dsts.remove(path).unwrap();
+            std::fs::create_dir_all(dst.parent().unwrap())?;
+            let mut out = File::create(dst).with_context(|| format!("{dst:?}"))?;
+            std::io::copy(reader, &mut out)
+                .with_context(|| format!("failed to extract {path:?}"))?;
+            #[cfg(target_family = "unix")]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                out.set_permissions(std::fs::Permissions::from_mode(mode & 0o777))?;
+            }
+            #[cfg(not(target_family = "unix"))]
+            let _ = mode;
+            Ok(())
+        },
+    )
+}
+
+/// Returns the digests of entries of an archive in the given order, the archive is read only once
+pub fn digest_archive_entries(
+    archive: &Path,
+    entries: &[PathBuf],
+) -> Result<Vec<BlobDigest>, anyhow::Error> {
+    let paths = entries
+        .iter()
+        .map(|x| normalize_entry_path(x))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut digests = HashMap::new();
+    for_each_entry(
+        archive,
+        paths.iter().cloned().collect(),
+        |path, reader, _| {
+            digests.insert(path.to_path_buf(), Digest::for_std_reader(reader)?);
+            Ok(())
+        },
+    )?;
+    Ok(paths.iter().map(|x| digests[x].clone()).collect())
+}
+
+/// Calls `f` with path, content and unix mode of each of the given files of an archive.
+///
+/// Fails if a file is missing or not a regular file.
+fn for_each_entry(
+    archive: &Path,
+    mut remaining: HashSet<PathBuf>,
+    mut f: impl FnMut(&Path, &mut dyn Read, Option<u32>) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let file = File::open(archive).with_context(|| format!("{archive:?}"))?;
+    match ArchiveFormat::from_path(archive)? {
+        ArchiveFormat::Tar => for_each_tar_entry(BufReader::new(file), &mut remaining, &mut f)?,
+        ArchiveFormat::TarGz => for_each_tar_entry(
+            flate2::read::GzDecoder::new(BufReader::new(file)),
+            &mut remaining,
+            &mut f,
+        )?,
+        ArchiveFormat::TarXz => {
+            let (reader, decoder) = xz_decoder(file);
+            let result = for_each_tar_entry(reader, &mut remaining, &mut f);
+            // the reader is dropped, therefore the decoder is finished or fails to send
+            match decoder.join().unwrap() {
+                // the tar reader stopped before the end of the stream
+                Err(lzma_rs::error::Error::IoError(x)) if x.kind() == io::ErrorKind::BrokenPipe => {
+                }
+                // a decoding error truncates the tar stream, report it instead
+                x => x.map_err(|x| anyhow::anyhow!("failed to decompress xz: {x}"))?,
+            }
+            result?
+        }
+        ArchiveFormat::Zip => for_each_zip_entry(file, &mut remaining, &mut f)?,
+    }
+    if !remaining.is_empty() {
+        let mut missing = remaining.into_iter().collect::<Vec<_>>();
+        missing.sort();
+        bail!("files not found in archive {archive:?}: {missing:?}");
+    }
+    Ok(())
+}
+
+/// Decompresses an xz file in a thread and streams the result through a bounded channel.
+///
+/// lzma-rs still decodes each xz block into memory, therefore memory is bounded by the block size
+/// of the archive, e.g. by using `xz --block-size`, but not by the archive size.
+fn xz_decoder(file: File) -> (ChannelReader, JoinHandle<lzma_rs::error::Result<()>>) {
+    let (tx, rx) = mpsc::sync_channel(XZ_CHANNEL_CHUNKS);
+    let decoder = std::thread::spawn(move || {
+        lzma_rs::xz_decompress(&mut BufReader::new(file), &mut ChannelWriter(tx))
+    });
+    let reader = ChannelReader {
+        rx,
+        chunk: vec![],
+        pos: 0,
+    };
+    (reader, decoder)
+}
+
+const XZ_CHANNEL_CHUNKS: usize = 4;
+
+struct ChannelWriter(mpsc::SyncSender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(x) => {
+                    self.chunk = x;
+                    self.pos = 0;
+                }
+                // sender dropped: finished or failed, see xz_decoder()
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+fn for_each_tar_entry(
+    reader: impl Read,
+    remaining: &mut HashSet<PathBuf>,
+    f: &mut impl FnMut(&Path, &mut dyn Read, Option<u32>) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize_entry_path(&entry.path()?)?;
+        if !remaining.contains(&path) {
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            bail!("output file must not be a link: {path:?}");
+        } else if !entry_type.is_file() {
+            bail!("output file is not a regular file: {path:?}");
+        }
+        let mode = entry.header().mode().ok();
+        f(&path, &mut entry, mode)?;
+        remaining.remove(&path);
+    }
+    Ok(())
+}
+
+fn for_each_zip_entry(
+    file: File,
+    remaining: &mut HashSet<PathBuf>,
+    f: &mut impl FnMut(&Path, &mut dyn Read, Option<u32>) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let path = normalize_entry_path(Path::new(entry.name()))?;
+        if !remaining.contains(&path) {
+            continue;
+        }
+        if entry.is_symlink() {
+            bail!("output file must not be a link: {path:?}");
+        } else if !entry.is_file() {
+            bail!("output file is not a regular file: {path:?}");
+        }
+        let mode = entry.unix_mode();
+        f(&path, &mut entry, mode)?;
+        remaining.remove(&path);
+    }
+    Ok(())
+}
+
+/// Returns the path of an archive entry without `.` components and rejects path traversal
+pub fn normalize_entry_path(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(x) => normalized.push(x),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                bail!("archive entry with invalid path: {path:?}")
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn write_tar_gz(path: &Path, entries: &[(&str, &[u8])]) {
+        let encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            // set path as raw bytes to allow creating malicious archives
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn tar_gz() {
+        let dir = new_tmp_dir!();
+        let archive = dir.join("archive.tar.gz");
+        write_tar_gz(
+            &archive,
+            &[
+                ("./bin/tool", b"tool"),
+                ("lib/a.txt", b"a"),
+                ("not-declared.txt", b"x"),
+            ],
+        );
+        let output_dir = dir.join("out");
+        extract_archive(
+            archive.clone(),
+            output_dir.clone(),
+            vec!["bin/tool".into(), "lib/a.txt".into()],
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(output_dir.join("bin/tool")).unwrap(), b"tool");
+        assert_eq!(std::fs::read(output_dir.join("lib/a.txt")).unwrap(), b"a");
+        assert!(!output_dir.join("not-declared.txt").exists());
+        assert!(extract_archive(archive, output_dir, vec!["missing.txt".into()]).is_err());
+    }
+
+    #[test]
+    fn tar_xz() {
+        let dir = new_tmp_dir!();
+        let mut builder = tar::Builder::new(vec![]);
+        let big = vec![7; 1_000_000];
+        for (name, data) in [("big.bin", big.as_slice()), ("a.txt", b"a")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        let tar = builder.into_inner().unwrap();
+        let mut xz = vec![];
+        lzma_rs::xz_compress(&mut tar.as_slice(), &mut xz).unwrap();
+        let archive = dir.join("archive.tar.xz");
+        std::fs::write(&archive, &xz).unwrap();
+        let output_dir = dir.join("out");
+        extract_archive(
+            archive.clone(),
+            output_dir.clone(),
+            vec!["big.bin".into(), "a.txt".into()],
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(output_dir.join("big.bin")).unwrap(), big);
+        assert_eq!(std::fs::read(output_dir.join("a.txt")).unwrap(), b"a");
+        // the decoding error is reported instead of the truncated tar stream
+        std::fs::write(&archive, &xz[..xz.len() / 2]).unwrap();
+        let error = extract_archive(archive, output_dir, vec!["a.txt".into()]).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("failed to decompress xz"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn path_traversal() {
+        let dir = new_tmp_dir!();
+        let archive = dir.join("malicious.tar.gz");
+        write_tar_gz(&archive, &[("../evil.txt", b"evil")]);
+        let output_dir = dir.join("out");
+        assert!(extract_archive(archive, output_dir, vec!["evil.txt".into()]).is_err());
+        assert!(!dir.join("evil.txt").exists());
+    }
+
+    #[test]
+    fn zip() {
+        let dir = new_tmp_dir!();
+        let archive = dir.join("archive.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        writer
+            .start_file("data/a.txt", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut writer, b"a").unwrap();
+        writer.finish().unwrap();
+        let output_dir = dir.join("out");
+        extract_archive(archive, output_dir.clone(), vec!["data/a.txt".into()]).unwrap();
+        assert_eq!(std::fs::read(output_dir.join("data/a.txt")).unwrap(), b"a");
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::{anyhow, bail, Context};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Merges LCOV files: hit counts of the same source file are summed up.
+///
+/// `strip_prefixes` are removed from source file paths to merge inputs created in different
+/// source roots, the first matching prefix is used.
+/// Malformed records fail the task unless `skip_malformed` is set.
+pub fn coverage_merge(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    strip_prefixes: Vec<String>,
+    skip_malformed: bool,
+) -> Result<(), anyhow::Error> {
+    let strip_prefixes = strip_prefixes
+        .iter()
+        .map(|x| normalize_source_path(x))
+        .collect::<Vec<_>>();
+    let mut sources: BTreeMap<String, SourceCoverage> = Default::default();
+    for input in &inputs {
+        read_lcov(input, &strip_prefixes, skip_malformed, &mut sources)
+            .with_context(|| format!("{input:?}"))?;
+    }
+    let mut writer = BufWriter::new(File::create(&output)?);
+    for (path, source) in &sources {
+        source.write_lcov(path, &mut writer)?;
+    }
+    writer.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct SourceCoverage {
+    /// line => hit count
+    lines: BTreeMap<u32, u64>,
+    /// name => (line, hit count)
+    functions: BTreeMap<String, (u32, u64)>,
+    /// (line, block, branch) => taken count, None if the block was never executed
+    branches: BTreeMap<(u32, String, String), Option<u64>>,
+}
+
+fn read_lcov(
+    path: &Path,
+    strip_prefixes: &[String],
+    skip_malformed: bool,
+    sources: &mut BTreeMap<String, SourceCoverage>,
+) -> Result<(), anyhow::Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut current: Option<String> = None;
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        let (key, value) = line.split_once(':').unwrap_or((line, ""));
+        let result = match key {
+            "SF" => {
+                let source_path = strip_source_path(&normalize_source_path(value), strip_prefixes);
+                sources.entry(source_path.clone()).or_default();
+                current = Some(source_path);
+                Ok(())
+            }
+            "end_of_record" => {
+                current = None;
+                Ok(())
+            }
+            "DA" | "FN" | "FNDA" | "BRDA" => {
+                match current.as_ref().and_then(|x| sources.get_mut(x)) {
+                    Some(source) => source.parse_record(key, value),
+                    None => Err(anyhow!("record outside of SF section")),
+                }
+            }
+            // summaries are recalculated, other records are not merged
+            _ => Ok(()),
+        };
+        if let Err(x) = result {
+            if skip_malformed {
+                warn!("{path:?}:{}: skipping {line:?}: {x}", line_number + 1);
+            } else {
+                bail!("line {}: {line:?}: {x}", line_number + 1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Allows merging paths with Windows and Unix separators
+fn normalize_source_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
+
+/// Prefixes only match whole path components, e.g. `/ci/job1` does not match `/ci/job10/a.rs`
+fn strip_source_path(path: &str, strip_prefixes: &[String]) -> String {
+    strip_prefixes
+        .iter()
+        .find_map(|x| {
+            let prefix = x.trim_end_matches('/');
+            let rest = path.strip_prefix(prefix)?;
+            (prefix.is_empty() || rest.is_empty() || rest.starts_with('/')).then_some(rest)
+        })
+        .map_or(path, |x| x.trim_start_matches('/'))
+        .to_string()
+}
+
+impl SourceCoverage {
+    fn parse_record(&mut self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        let malformed = || anyhow!("malformed {key} record");
+        match key {
+            // DA:<line>,<count>[,<checksum>]
+            "DA" => {
+                let mut parts = value.split(',');
+                let line = parse_u32(parts.next()).ok_or_else(malformed)?;
+                let count = parse_u64(parts.next()).ok_or_else(malformed)?;
+                let entry = self.lines.entry(line).or_default();
+                *entry = entry.saturating_add(count);
+            }
+            // FN:<line>,<name> or FN:<line>,<end line>,<name>
+            "FN" => {
+                let (line, rest) = value.split_once(',').ok_or_else(malformed)?;
+                let line = parse_u32(Some(line)).ok_or_else(malformed)?;
+                let name = match rest.split_once(',') {
+                    Some((end, name)) if end.parse::<u32>().is_ok() => name,
+                    _ => rest,
+                };
+                if name.is_empty() {
+                    return Err(malformed());
+                }
+                self.functions.entry(name.into()).or_default().0 = line;
+            }
+            // FNDA:<count>,<name>
+            "FNDA" => {
+                let (count, name) = value.split_once(',').ok_or_else(malformed)?;
+                let count = parse_u64(Some(count)).ok_or_else(malformed)?;
+                if name.is_empty() {
+                    return Err(malformed());
+                }
+                let entry = &mut self.functions.entry(name.into()).or_default().1;
+                *entry = entry.saturating_add(count);
+            }
+            // BRDA:<line>,<block>,<branch>,<taken or ->
+            "BRDA" => {
+                let parts = value.splitn(4, ',').collect::<Vec<_>>();
+                let [line, block, branch, taken] = parts[..] else {
+                    return Err(malformed());
+                };
+                let line = parse_u32(Some(line)).ok_or_else(malformed)?;
+                let taken = match taken {
+                    "-" => None,
+                    x => Some(parse_u64(Some(x)).ok_or_else(malformed)?),
+                };
+                let entry = self
+                    .branches
+                    .entry((line, block.into(), branch.into()))
+                    .or_default();
+                *entry = match (*entry, taken) {
+                    (Some(x), Some(y)) => Some(x.saturating_add(y)),
+                    (x, y) => x.or(y),
+                };
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn write_lcov(&self, path: &str, writer: &mut impl Write) -> Result<(), anyhow::Error> {
+        writeln!(writer, "SF:{path}")?;
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(name, (line, _))| (*line, *name));
+        for (name, (line, _)) in &functions {
+            writeln!(writer, "FN:{line},{name}")?;
+        }
+        for (name, (_, count)) in &functions {
+            writeln!(writer, "FNDA:{count},{name}")?;
+        }
+        writeln!(writer, "FNF:{}", functions.len())?;
+        writeln!(
+            writer,
+            "FNH:{}",
+            functions.iter().filter(|(_, (_, x))| *x > 0).count()
+        )?;
+        for ((line, block, branch), taken) in &self.branches {
+            match taken {
+                Some(x) => writeln!(writer, "BRDA:{line},{block},{branch},{x}")?,
+                None => writeln!(writer, "BRDA:{line},{block},{branch},-")?,
+            }
+        }
+        writeln!(writer, "BRF:{}", self.branches.len())?;
+        writeln!(
+            writer,
+            "BRH:{}",
+            self.branches
+                .values()
+                .filter(|x| x.unwrap_or(0) > 0)
+                .count()
+        )?;
+        for (line, count) in &self.lines {
+            writeln!(writer, "DA:{line},{count}")?;
+        }
+        writeln!(writer, "LF:{}", self.lines.len())?;
+        writeln!(
+            writer,
+            "LH:{}",
+            self.lines.values().filter(|x| **x > 0).count()
+        )?;
+        writeln!(writer, "end_of_record")?;
+        Ok(())
+    }
+}
+
+fn parse_u32(x: Option<&str>) -> Option<u32> {
+    x?.trim().parse().ok()
+}
+
+fn parse_u64(x: Option<&str>) -> Option<u64> {
+    x?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+
+    #[test]
+    fn merge_lcov_files() {
+        let dir = new_tmp_dir!();
+        let a = dir.join_and_write_file(
+            "a.lcov",
+            "TN:test_a\n\
+            SF:/ci/job1/src/lib.rs\n\
+            FN:1,lib::add\n\
+            FNDA:1,lib::add\n\
+            BRDA:2,0,0,1\n\
+            BRDA:2,0,1,-\n\
+            DA:1,1\nDA:2,1\nDA:3,0\n\
+            LF:3\nLH:2\n\
+            end_of_record\n",
+        );
+        let b = dir.join_and_write_file(
+            "b.lcov",
+            "TN:test_b\n\
+            SF:C:\\ci\\job2\\src\\lib.rs\n\
+            FN:1,lib::add\n\
+            FNDA:2,lib::add\n\
+            BRDA:2,0,0,-\n\
+            BRDA:2,0,1,-\n\
+            DA:1,2\nDA:3,4\nDA:4,0\n\
+            end_of_record\n\
+            SF:C:\\ci\\job2\\src\\main.rs\n\
+            DA:1,0\n\
+            end_of_record\n",
+        );
+        let output = dir.join("merged.lcov");
+        let strip_prefixes = vec!["/ci/job1".into(), "C:\\ci\\job2".into()];
+        coverage_merge(
+            vec![a.clone(), b.clone()],
+            output.clone(),
+            strip_prefixes.clone(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "SF:src/lib.rs\n\
+            FN:1,lib::add\n\
+            FNDA:3,lib::add\n\
+            FNF:1\nFNH:1\n\
+            BRDA:2,0,0,1\n\
+            BRDA:2,0,1,-\n\
+            BRF:2\nBRH:1\n\
+            DA:1,3\nDA:2,1\nDA:3,4\nDA:4,0\n\
+            LF:4\nLH:3\n\
+            end_of_record\n\
+            SF:src/main.rs\n\
+            FNF:0\nFNH:0\n\
+            BRF:0\nBRH:0\n\
+            DA:1,0\n\
+            LF:1\nLH:0\n\
+            end_of_record\n"
+        );
+        // malformed records
+        let c = dir.join_and_write_file("c.lcov", "SF:/ci/job1/src/lib.rs\nDA:5\nDA:6,1\n");
+        let d = dir.join_and_write_file("d.lcov", "DA:1,1\n");
+        for malformed in [&c, &d] {
+            assert!(coverage_merge(
+                vec![a.clone(), malformed.clone()],
+                output.clone(),
+                strip_prefixes.clone(),
+                false
+            )
+            .is_err());
+        }
+        coverage_merge(vec![a, c, d], output.clone(), strip_prefixes, true).unwrap();
+        let merged = std::fs::read_to_string(&output).unwrap();
+        assert!(merged.contains("DA:6,1\nLF:4\nLH:3\n"));
+    }
+
+    #[test]
+    fn strip_source_path_at_component_boundaries() {
+        let prefixes = ["/ci/job1".to_string(), "/ci/job2/".to_string()];
+        for (path, expected) in [
+            ("/ci/job1/src/a.rs", "src/a.rs"),
+            ("/ci/job10/src/a.rs", "/ci/job10/src/a.rs"),
+            ("/ci/job2/src/a.rs", "src/a.rs"),
+            ("/ci/job2x/src/a.rs", "/ci/job2x/src/a.rs"),
+            ("/other/src/a.rs", "/other/src/a.rs"),
+        ] {
+            assert_eq!(strip_source_path(path, &prefixes), expected, "{path}");
+        }
+    }
+}
@@ -1,13 +1,38 @@
-use anyhow::ensure;
+use anyhow::{anyhow, bail, ensure, Context};
 use csv::{StringRecord, Writer};
+use encoding_rs::Encoding;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn csv_concat(inputs: Vec<PathBuf>, output: PathBuf) -> Result<(), anyhow::Error> {
+/// Parses the label of an input encoding, e.g. `utf-8`, `windows-1252` or `utf-16le`
+pub fn csv_encoding(label: &str) -> Result<&'static Encoding, anyhow::Error> {
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| anyhow!("unknown encoding: {label}"))
+}
+
+/// Reads a csv file and decodes it to UTF-8.
+///
+/// A BOM is stripped and takes precedence over `encoding`, which is used for files without BOM.
+fn csv_reader(
+    path: &Path,
+    encoding: &'static Encoding,
+) -> Result<csv::Reader<io::Cursor<String>>, anyhow::Error> {
+    let bytes = std::fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+    let (text, used_encoding, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        bail!("{path:?} is not valid {}", used_encoding.name());
+    }
+    Ok(csv::Reader::from_reader(io::Cursor::new(text.into_owned())))
+}
+
+pub fn csv_concat(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    encoding: &'static Encoding,
+) -> Result<(), anyhow::Error> {
     let mut writer = csv::Writer::from_path(output)?;
     let mut combined_headers: Option<StringRecord> = None;
     for input in inputs {
-        let mut reader = csv::Reader::from_path(input)?;
+        let mut reader = csv_reader(&input, encoding)?;
         let curr_headers = reader.headers()?;
         if let Some(combined_headers) = &combined_headers {
             ensure!(curr_headers == combined_headers, "headers do not match!");
@@ -24,8 +49,13 @@ pub fn csv_concat(inputs: Vec<PathBuf>, output: PathBuf) -> Result<(), anyhow::E
     Ok(())
 }
 
-pub fn csv_filter(input: PathBuf, output: PathBuf, cols: Vec<String>) -> Result<(), anyhow::Error> {
-    let mut reader = csv::Reader::from_path(input)?;
+pub fn csv_filter(
+    input: PathBuf,
+    output: PathBuf,
+    cols: Vec<String>,
+    encoding: &'static Encoding,
+) -> Result<(), anyhow::Error> {
+    let mut reader = csv_reader(&input, encoding)?;
     let headers = reader.headers()?;
     let indices: Vec<usize> = if !cols.is_empty() {
         headers
@@ -58,3 +88,59 @@ fn write_record_filtered<W: io::Write>(
     writer.write_record(None::<&[u8]>)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+
+    #[test]
+    fn concat_with_bom() {
+        let dir = new_tmp_dir!();
+        let with_bom = dir.join_and_write_file("with_bom.csv", "\u{feff}a,b\n1,2\n");
+        let without_bom = dir.join_and_write_file("without_bom.csv", "a,b\n3,4\n");
+        let output = dir.join("output.csv");
+        csv_concat(
+            vec![with_bom, without_bom],
+            output.clone(),
+            encoding_rs::UTF_8,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"a,b\n1,2\n3,4\n");
+    }
+
+    #[test]
+    fn concat_mixed_encodings() {
+        let dir = new_tmp_dir!();
+        let latin1 = dir.join("latin1.csv");
+        std::fs::write(&latin1, b"name,city\nJ\xfcrgen,K\xf6ln\n").unwrap();
+        // BOM overrides the declared encoding
+        let mut utf16 = vec![0xff, 0xfe];
+        for x in "name,city\nJos\u{e9},M\u{fc}nchen\n".encode_utf16() {
+            utf16.extend(x.to_le_bytes());
+        }
+        let utf16_path = dir.join("utf16.csv");
+        std::fs::write(&utf16_path, utf16).unwrap();
+        let output = dir.join("output.csv");
+        csv_concat(
+            vec![latin1, utf16_path],
+            output.clone(),
+            csv_encoding("latin1").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "name,city\nJ\u{fc}rgen,K\u{f6}ln\nJos\u{e9},M\u{fc}nchen\n"
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let dir = new_tmp_dir!();
+        let input = dir.join("input.csv");
+        std::fs::write(&input, b"a\n\xfc\n").unwrap();
+        let output = dir.join("output.csv");
+        assert!(csv_filter(input, output, vec![], encoding_rs::UTF_8).is_err());
+        assert!(csv_encoding("unknown").is_err());
+    }
+}
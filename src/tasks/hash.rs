@@ -0,0 +1,59 @@
+use crate::bazel_remote_exec::Digest;
+use crate::executors::AsyncTask;
+use async_trait::async_trait;
+use itertools::Itertools;
+use std::path::PathBuf;
+
+/// Writes the SHA-256 digests of the inputs in the format of `sha256sum`, sorted by path
+pub struct HashTask {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+}
+
+#[async_trait]
+impl AsyncTask for HashTask {
+    async fn exec(&self, sandbox_dir: Option<PathBuf>) -> Result<(), anyhow::Error> {
+        let path = |x: &PathBuf| {
+            sandbox_dir
+                .as_ref()
+                .map_or_else(|| x.clone(), |d| d.join(x))
+        };
+        let mut text = String::new();
+        for input in self.inputs.iter().sorted_unstable().dedup() {
+            let digest = Digest::for_path(path(input)).await?;
+            text += &format!("{}  {}\n", digest.hash, input.to_str().unwrap());
+        }
+        tokio::fs::write(path(&self.output), text).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+
+    #[tokio::test]
+    async fn hash() {
+        let dir = new_tmp_dir!();
+        let a = dir.join_and_write_file("a.txt", "a");
+        let b = dir.join_and_write_file("b.txt", "b");
+        let output = dir.join("hashes.sha256");
+        HashTask {
+            inputs: vec![b.clone(), a.clone(), b.clone()],
+            output: output.clone(),
+        }
+        .exec(None)
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output).unwrap(),
+            format!(
+                "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  {}\n\
+                3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d  {}\n",
+                a.to_str().unwrap(),
+                b.to_str().unwrap()
+            )
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::executors::AsyncTask;
 use crate::make_file_executable;
 use async_trait::async_trait;
 use futures_util::StreamExt;
+use reqwest::Client;
 use std::path::PathBuf;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
@@ -10,12 +11,19 @@ pub struct DownloadFileTask {
     pub url: String,
     pub output: PathBuf,
     pub executable: bool,
+    pub client: Client,
 }
 
 #[async_trait]
 impl AsyncTask for DownloadFileTask {
     async fn exec(&self, sandbox_dir: Option<PathBuf>) -> Result<(), anyhow::Error> {
-        let mut stream = reqwest::get(&self.url).await?.bytes_stream();
+        let mut stream = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes_stream();
         let mut file = File::create(
             sandbox_dir
                 .map(|x| x.join(&self.output))
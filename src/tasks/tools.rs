@@ -1,8 +1,8 @@
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Read, Write};
 use std::path::PathBuf;
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Context};
 use regex::Regex;
 
 pub fn capture_regex(input: PathBuf, output: PathBuf, re: String) -> Result<(), anyhow::Error> {
@@ -32,6 +32,44 @@ pub fn write_file(file_name: PathBuf, lines: Vec<String>) -> Result<(), anyhow::
     Ok(())
 }
 
+/// Concatenate files without reading them into memory.
+///
+/// The separator is written between files, the prefix/suffix before the first/after the last one.
+/// With `ensure_newline`, a newline is appended to inputs not ending with one.
+pub fn concat(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    separator: &str,
+    prefix: &str,
+    suffix: &str,
+    ensure_newline: bool,
+) -> Result<(), anyhow::Error> {
+    let mut writer = BufWriter::new(File::create(&output)?);
+    writer.write_all(prefix.as_bytes())?;
+    let mut buffer = vec![0; 64 * 1024];
+    for (i, input) in inputs.iter().enumerate() {
+        if i != 0 {
+            writer.write_all(separator.as_bytes())?;
+        }
+        let mut reader = File::open(input).with_context(|| format!("{input:?}"))?;
+        let mut last_byte = None;
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..count])?;
+            last_byte = Some(buffer[count - 1]);
+        }
+        if ensure_newline && last_byte.is_some_and(|x| x != b'\n') {
+            writer.write_all(b"\n")?;
+        }
+    }
+    writer.write_all(suffix.as_bytes())?;
+    writer.into_inner()?.sync_all()?;
+    Ok(())
+}
+
 pub fn ensure_equal(file1: PathBuf, file2: PathBuf) -> Result<(), anyhow::Error> {
     let file1_bytes = std::fs::read(&file1)?;
     let file2_bytes = std::fs::read(&file2)?;
@@ -49,3 +87,120 @@ pub fn ensure_not_equal(file1: PathBuf, file2: PathBuf) -> Result<(), anyhow::Er
     }
     Ok(())
 }
+
+/// Compare a file with its expected content, rewrite the golden file if `update` is set
+pub fn ensure_golden(
+    actual: PathBuf,
+    golden: PathBuf,
+    normalize_line_endings: bool,
+    update: bool,
+) -> Result<(), anyhow::Error> {
+    let actual_bytes = std::fs::read(&actual)?;
+    let golden_bytes = std::fs::read(&golden)?;
+    let (actual_text, golden_text) = if normalize_line_endings {
+        (
+            String::from_utf8_lossy(&actual_bytes).replace("\r\n", "\n"),
+            String::from_utf8_lossy(&golden_bytes).replace("\r\n", "\n"),
+        )
+    } else {
+        (
+            String::from_utf8_lossy(&actual_bytes).into(),
+            String::from_utf8_lossy(&golden_bytes).into(),
+        )
+    };
+    if actual_text == golden_text {
+        return Ok(());
+    }
+    if update {
+        std::fs::write(&golden, &actual_bytes)?;
+        return Ok(());
+    }
+    bail!(
+        "File {:?} differs from golden file {:?}:\n{}",
+        actual,
+        golden,
+        diff_lines(&golden_text, &actual_text)
+    );
+}
+
+/// Returns the differing lines prefixed with `-` for expected and `+` for actual
+fn diff_lines(expected: &str, actual: &str) -> String {
+    const MAX_LINES: usize = 10;
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+    let mut diff = vec![];
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e == a {
+            continue;
+        }
+        if diff.len() >= MAX_LINES {
+            diff.push("...".to_string());
+            break;
+        }
+        diff.push(format!("@@ line {} @@", i + 1));
+        if let Some(e) = e {
+            diff.push(format!("-{e}"));
+        }
+        if let Some(a) = a {
+            diff.push(format!("+{a}"));
+        }
+    }
+    if diff.is_empty() {
+        diff.push("(difference in line endings or trailing newline)".into());
+    }
+    diff.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+
+    #[test]
+    fn concat_with_separator() {
+        let dir = new_tmp_dir!();
+        let inputs = vec![
+            dir.join_and_write_file("a.txt", "a\n"),
+            dir.join_and_write_file("b.txt", "b"),
+            dir.join_and_write_file("c.txt", "c\r\n"),
+        ];
+        let output = dir.join("out.txt");
+        concat(inputs.clone(), output.clone(), "--\n", "", "", false).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"a\n--\nb--\nc\r\n");
+        concat(inputs.clone(), output.clone(), "--\n", "<", ">", true).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"<a\n--\nb\n--\nc\r\n>");
+        let empty = dir.join_and_write_file("empty.txt", "");
+        concat(vec![empty.clone(), empty], output.clone(), "", "", "", true).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"");
+    }
+
+    #[test]
+    fn ensure_golden_matching() {
+        let dir = new_tmp_dir!();
+        let actual = dir.join_and_write_file("actual.txt", "a\r\nb\r\n");
+        let golden = dir.join_and_write_file("golden.txt", "a\nb\n");
+        assert!(ensure_golden(actual.clone(), golden.clone(), false, false).is_err());
+        ensure_golden(actual, golden, true, false).unwrap();
+    }
+
+    #[test]
+    fn ensure_golden_mismatching() {
+        let dir = new_tmp_dir!();
+        let actual = dir.join_and_write_file("actual.txt", "a\nb\nc\n");
+        let golden = dir.join_and_write_file("golden.txt", "a\nx\nc\n");
+        let err = ensure_golden(actual, golden.clone(), false, false).unwrap_err();
+        assert!(err.to_string().ends_with("@@ line 2 @@\n-x\n+b"), "{err}");
+        assert_eq!(std::fs::read_to_string(golden).unwrap(), "a\nx\nc\n");
+    }
+
+    #[test]
+    fn ensure_golden_update() {
+        let dir = new_tmp_dir!();
+        let actual = dir.join_and_write_file("actual.txt", "new\n");
+        let golden = dir.join_and_write_file("golden.txt", "old\n");
+        ensure_golden(actual.clone(), golden.clone(), false, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&golden).unwrap(), "new\n");
+        ensure_golden(actual, golden, false, false).unwrap();
+    }
+}
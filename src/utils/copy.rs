@@ -0,0 +1,45 @@
+use crate::force_remove_file;
+use anyhow::{bail, Context};
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Force copying a file: overwrite existing file and create parent directories
+pub async fn force_copy(src: &PathBuf, dst: &PathBuf) -> Result<(), anyhow::Error> {
+    {
+        if src == dst {
+            bail!("copy dst must not equal src");
+        }
+        force_remove_file(&dst).await?; // to not write through an existing link
+        let parent = dst.parent().unwrap();
+        fs::create_dir_all(&parent)
+            .await
+            .with_context(|| format!("fs::create_dir_all() {parent:?}"))?;
+        fs::copy(&src, dst)
+            .await
+            .with_context(|| format!("fs::copy() {src:?} -> {dst:?}"))
+    }
+    .with_context(|| format!("force_copy() {src:?} -> {dst:?}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{force_symlink, new_tmp_dir};
+    use std::fs;
+
+    #[tokio::test]
+    async fn replaces_link() {
+        let src_dir = new_tmp_dir!();
+        let first_src = src_dir.join_and_write_file("first-src-file", "first");
+        let other_src = src_dir.join_and_write_file("other-src-file", "other");
+        let dst_dir = new_tmp_dir!();
+        let dst = dst_dir.join("dst-dir").join("dst-file");
+        force_symlink(&first_src, &dst).await.unwrap();
+        force_copy(&other_src, &dst).await.unwrap();
+        assert!(!fs::symlink_metadata(&dst).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "other");
+        // the previous link target is not modified
+        assert_eq!(fs::read_to_string(&first_src).unwrap(), "first");
+    }
+}
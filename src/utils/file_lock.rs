@@ -0,0 +1,54 @@
+use anyhow::Context;
+use std::fs::File;
+use std::path::Path;
+
+/// Advisory lock on a file, released when dropped or the process exits
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Takes a shared lock, waits while an exclusive lock is held
+    pub fn shared(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::create(path).with_context(|| format!("create lock file {path:?}"))?;
+        lock(&file, false).with_context(|| format!("lock {path:?}"))?;
+        Ok(Self { _file: file })
+    }
+
+    /// Takes an exclusive lock, returns None if another lock is held
+    pub fn try_exclusive(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let file = File::create(path).with_context(|| format!("create lock file {path:?}"))?;
+        Ok(lock(&file, true)
+            .with_context(|| format!("lock {path:?}"))?
+            .then_some(Self { _file: file }))
+    }
+}
+
+/// Returns false if a non-blocking exclusive lock is not available
+#[cfg(target_family = "unix")]
+fn lock(file: &File, exclusive: bool) -> Result<bool, std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+    let operation = if exclusive {
+        libc::LOCK_EX | libc::LOCK_NB
+    } else {
+        libc::LOCK_SH
+    };
+    loop {
+        // SAFETY: the fd is valid for the lifetime of file
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(true);
+        }
+        let error = std::io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EWOULDBLOCK) => return Ok(false),
+            _ => return Err(error),
+        }
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn lock(_file: &File, _exclusive: bool) -> Result<bool, std::io::Error> {
+    Ok(true)
+}
@@ -30,6 +30,35 @@ pub async fn make_file_executable(_file: &File) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Permission bits of a file, only available on Unix
+#[cfg(target_family = "unix")]
+pub async fn file_unix_mode(file: &File) -> Result<Option<u32>, anyhow::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = file.metadata().await?.permissions();
+    Ok(Some(permissions.mode() & 0o777))
+}
+
+#[cfg(not(target_family = "unix"))]
+pub async fn file_unix_mode(_file: &File) -> Result<Option<u32>, anyhow::Error> {
+    Ok(None)
+}
+
+#[cfg(target_family = "unix")]
+pub async fn set_file_unix_mode(path: &Path, mode: u32) -> Result<(), anyhow::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777)).await?;
+    Ok(())
+}
+
+/// Only the readonly flag is mapped to non-Unix systems
+#[cfg(not(target_family = "unix"))]
+pub async fn set_file_unix_mode(path: &Path, mode: u32) -> Result<(), anyhow::Error> {
+    let mut permissions = tokio::fs::metadata(path).await?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    tokio::fs::set_permissions(path, permissions).await?;
+    Ok(())
+}
+
 pub async fn set_file_readonly(path: &Path) -> Result<(), anyhow::Error> {
     let mut perms = tokio::fs::metadata(path).await?.permissions();
     perms.set_readonly(true);
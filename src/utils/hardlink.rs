@@ -30,6 +30,17 @@ pub async fn force_hardlink(src: &PathBuf, dst: &PathBuf) -> Result<(), anyhow::
     Ok(())
 }
 
+/// Returns true if force_hardlink() failed because src and dst are on different devices
+pub fn is_cross_device_error(e: &anyhow::Error) -> bool {
+    #[cfg(target_family = "unix")]
+    const CROSS_DEVICE_ERROR: i32 = libc::EXDEV;
+    #[cfg(target_family = "windows")]
+    const CROSS_DEVICE_ERROR: i32 = 17; // ERROR_NOT_SAME_DEVICE
+    e.chain()
+        .filter_map(|x| x.downcast_ref::<std::io::Error>())
+        .any(|x| x.raw_os_error() == Some(CROSS_DEVICE_ERROR))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,4 +71,24 @@ mod tests {
         assert_eq!(fs::read_to_string(&other_src).unwrap(), OTHER_CONTENT);
         assert_eq!(fs::read_to_string(&dst).unwrap(), OTHER_CONTENT);
     }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn cross_device_error() {
+        use std::os::unix::fs::MetadataExt;
+        let src_dir = new_tmp_dir!();
+        let src = src_dir.join_and_write_file("src-file", FIRST_CONTENT);
+        let other_device = PathBuf::from("/dev/shm");
+        match fs::metadata(&other_device) {
+            Ok(x) if x.dev() != fs::metadata(&src).unwrap().dev() => {}
+            _ => {
+                println!("no other device available, skipping test");
+                return;
+            }
+        }
+        let dst = other_device.join(format!("razel-test-{}", std::process::id()));
+        let error = force_hardlink(&src, &dst).await.unwrap_err();
+        assert!(is_cross_device_error(&error));
+        assert!(!dst.exists());
+    }
 }
@@ -0,0 +1,175 @@
+use anyhow::{bail, Context};
+use reqwest::{Certificate, Client, NoProxy, Proxy};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Settings of the http client used by DownloadFile tasks and http remote exec
+#[derive(Clone, Debug, Default)]
+pub struct HttpClientConfig {
+    /// proxy for http requests
+    pub http_proxy: Option<String>,
+    /// proxy for https requests
+    pub https_proxy: Option<String>,
+    /// comma separated list of hosts, domains and IP ranges to not use the proxy for
+    pub no_proxy: Option<String>,
+    pub connect_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    /// PEM files with additional root certificates
+    pub ca_certs: Vec<PathBuf>,
+}
+
+impl HttpClientConfig {
+    /// Fills proxy settings which are not set from HTTP_PROXY, HTTPS_PROXY and NO_PROXY
+    pub fn with_env_proxy(mut self) -> Self {
+        self.http_proxy = self.http_proxy.or_else(|| env_var("HTTP_PROXY"));
+        self.https_proxy = self.https_proxy.or_else(|| env_var("HTTPS_PROXY"));
+        self.no_proxy = self.no_proxy.or_else(|| env_var("NO_PROXY"));
+        self
+    }
+
+    pub fn build(&self) -> Result<Client, anyhow::Error> {
+        // proxies from the environment are only used if passed explicitly
+        let mut builder = Client::builder().no_proxy();
+        if let Some(url) = &self.http_proxy {
+            let proxy = Proxy::http(url).with_context(|| format!("invalid http proxy: {url}"))?;
+            builder = builder.proxy(proxy.no_proxy(self.no_proxy()));
+        }
+        if let Some(url) = &self.https_proxy {
+            let proxy = Proxy::https(url).with_context(|| format!("invalid https proxy: {url}"))?;
+            builder = builder.proxy(proxy.no_proxy(self.no_proxy()));
+        }
+        if let Some(x) = self.connect_timeout {
+            builder = builder.connect_timeout(x);
+        }
+        if let Some(x) = self.read_timeout {
+            builder = builder.read_timeout(x);
+        }
+        for path in &self.ca_certs {
+            let pem = std::fs::read(path).with_context(|| format!("{path:?}"))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("invalid CA certificate: {path:?}"))?;
+            if certs.is_empty() {
+                bail!("no CA certificate found in {path:?}");
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        Ok(builder.build()?)
+    }
+
+    fn no_proxy(&self) -> Option<NoProxy> {
+        self.no_proxy.as_deref().and_then(NoProxy::from_string)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .or_else(|_| std::env::var(name.to_lowercase()))
+        .ok()
+        .filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Accepts a single request, responds with its request line and returns it
+    async fn serve_once(listener: TcpListener) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut buf = vec![0; 4096];
+        while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+            let len = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..len]);
+        }
+        let request_line = String::from_utf8_lossy(&request)
+            .lines()
+            .next()
+            .unwrap()
+            .to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{request_line}",
+            request_line.len()
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        request_line
+    }
+
+    #[tokio::test]
+    async fn proxy_is_used() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = HttpClientConfig {
+            http_proxy: Some(format!("http://{}", proxy.local_addr().unwrap())),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let server = tokio::spawn(serve_once(proxy));
+        let text = client
+            .get("http://razel.invalid/file.txt")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let request_line = server.await.unwrap();
+        assert_eq!(request_line, "GET http://razel.invalid/file.txt HTTP/1.1");
+        assert_eq!(text, request_line);
+    }
+
+    #[tokio::test]
+    async fn no_proxy_bypasses_proxy() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("http://{}", proxy.local_addr().unwrap());
+        drop(proxy);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+        let client = HttpClientConfig {
+            http_proxy: Some(proxy_url),
+            no_proxy: Some("localhost,127.0.0.1".into()),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let server = tokio::spawn(serve_once(listener));
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(server.await.unwrap(), "GET /file.txt HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let client = HttpClientConfig {
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        let server = tokio::spawn(async move {
+            // accept but never respond
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+        let error = client.get(&url).send().await.unwrap_err();
+        assert!(error.is_timeout(), "{error:?}");
+        server.abort();
+    }
+
+    #[test]
+    fn invalid_ca_cert() {
+        let dir = crate::new_tmp_dir!();
+        let path = dir.join_and_write_file("ca.pem", "no certificate");
+        let config = HttpClientConfig {
+            ca_certs: vec![path],
+            ..Default::default()
+        };
+        assert!(config.build().is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use simplelog::{ColorChoice, ConfigBuilder, TermLogger, TerminalMode};
+use std::io::{stderr, Write};
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Log targets of dependencies which are too verbose
+static IGNORED_TARGETS: [&str; 5] = [
+    "cranelift_codegen",
+    "tracing::span",
+    "wasmtime_cranelift",
+    "wasmtime_jit",
+    "wasmtime_wasi",
+];
+
+/// Format of razel's own log messages - not related to stdout/stderr of commands
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// One json object per line: timestamp, level, target, message
+    Json,
+}
+
+impl LogFormat {
+    /// Returns the format set with `--log-format` or the env var `RAZEL_LOG_FORMAT`.
+    ///
+    /// The raw args are scanned because the logger is initialized before parsing the CLI.
+    pub fn from_args(args: &[String]) -> Self {
+        let mut value = std::env::var("RAZEL_LOG_FORMAT").ok();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                break;
+            } else if arg == "--log-format" {
+                value = iter.next().cloned();
+            } else if let Some(x) = arg.strip_prefix("--log-format=") {
+                value = Some(x.to_string());
+            }
+        }
+        value
+            .and_then(|x| Self::from_str(&x, true).ok())
+            .unwrap_or_default()
+    }
+}
+
+pub fn init_logger(format: LogFormat) -> Result<(), log::SetLoggerError> {
+    let level = LevelFilter::Info;
+    match format {
+        LogFormat::Text => {
+            let mut builder = ConfigBuilder::new();
+            for target in IGNORED_TARGETS {
+                builder.add_filter_ignore_str(target);
+            }
+            TermLogger::init(
+                level,
+                builder.set_target_level(LevelFilter::Error).build(),
+                TerminalMode::Stderr,
+                ColorChoice::Auto,
+            )
+        }
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger::new(level, Box::new(stderr()))))?;
+            log::set_max_level(level);
+            Ok(())
+        }
+    }
+}
+
+/// Writes log records as json lines, e.g. for running razel with systemd or CI log ingestion
+pub struct JsonLogger {
+    level: LevelFilter,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+#[derive(Serialize)]
+struct JsonLogItem<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            level,
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            && !IGNORED_TARGETS
+                .iter()
+                .any(|x| metadata.target().starts_with(x))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let item = JsonLogItem {
+            timestamp: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        let Ok(mut line) = serde_json::to_vec(&item) else {
+            return;
+        };
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&line).ok();
+    }
+
+    fn flush(&self) {
+        self.writer.lock().unwrap().flush().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log(logger: &JsonLogger, level: Level, target: &str, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{message}"))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let buffer = SharedBuffer::default();
+        let logger = JsonLogger::new(LevelFilter::Info, Box::new(buffer.clone()));
+        log(
+            &logger,
+            Level::Info,
+            "razel::razel",
+            "connected to \"cache\"",
+        );
+        log(&logger, Level::Warn, "razel::cache", "second line");
+        log(&logger, Level::Debug, "razel::razel", "filtered by level");
+        log(&logger, Level::Warn, "wasmtime_jit", "filtered by target");
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = text
+            .lines()
+            .map(|x| serde_json::from_str::<Value>(x).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "razel::razel");
+        assert_eq!(lines[0]["message"], "connected to \"cache\"");
+        let timestamp = lines[0]["timestamp"].as_str().unwrap();
+        assert!(
+            timestamp.contains('T') && timestamp.ends_with('Z'),
+            "{timestamp}"
+        );
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["message"], "second line");
+    }
+
+    #[test]
+    fn from_args() {
+        let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            LogFormat::from_args(&args(&["razel", "exec"])),
+            LogFormat::Text
+        );
+        assert_eq!(
+            LogFormat::from_args(&args(&["razel", "exec", "--log-format", "json"])),
+            LogFormat::Json
+        );
+        assert_eq!(
+            LogFormat::from_args(&args(&["razel", "--log-format=json", "exec"])),
+            LogFormat::Json
+        );
+        assert_eq!(
+            LogFormat::from_args(&args(&["razel", "command", "--", "x", "--log-format=json"])),
+            LogFormat::Text
+        );
+    }
+}
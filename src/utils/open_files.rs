@@ -0,0 +1,232 @@
+/// File descriptors used by razel itself, e.g. for the cache, remote connections and logs
+const OPEN_FILES_RAZEL: u64 = 64;
+/// File descriptors per running command, e.g. pipes for stdin/stdout/stderr and output files
+const OPEN_FILES_PER_COMMAND: u64 = 8;
+/// Used instead of an infinite hard limit, which setrlimit() rejects as soft limit on macOS.
+/// Same as OPEN_MAX of macOS.
+const OPEN_FILES_FINITE_MAX: u64 = 10240;
+
+/// Number of open files needed to run `jobs` commands in parallel
+pub fn required_open_files(jobs: usize) -> u64 {
+    OPEN_FILES_RAZEL + jobs as u64 * OPEN_FILES_PER_COMMAND
+}
+
+/// Raises the soft limit of open files to the hard limit, or to `max` if lower, if it is too
+/// low to run `jobs` commands in parallel. If that is rejected, e.g. by macOS for values above
+/// `kern.maxfilesperproc`, the required limit is tried.
+///
+/// Fails if the resulting limit is still too low.
+/// Returns the previous and the new limit if it was raised.
+#[cfg(target_family = "unix")]
+pub fn raise_open_files_limit(
+    jobs: usize,
+    max: Option<u64>,
+) -> Result<Option<(u64, u64)>, anyhow::Error> {
+    let (soft, hard) = get_open_files_limit()?;
+    let required = required_open_files(jobs);
+    if soft >= required {
+        return Ok(None);
+    }
+    let hard_max = finite_hard_limit(hard);
+    let target = max.map_or(hard_max, |x| x.min(hard_max));
+    let mut raised = None;
+    // retry with the required limit, e.g. if the kernel rejects the hard limit
+    let mut targets = vec![target];
+    if required < target {
+        targets.push(required);
+    }
+    for target in targets.into_iter().filter(|x| *x > soft) {
+        match set_open_files_limit(target, hard) {
+            Ok(()) => {
+                log::info!("raised open files limit from {soft} to {target} for {jobs} jobs");
+                raised = Some((soft, target));
+                break;
+            }
+            Err(e) => log::debug!("failed to raise open files limit from {soft} to {target}: {e}"),
+        }
+    }
+    let limit = raised.map_or(soft, |(_, x)| x);
+    if limit < required {
+        anyhow::bail!(
+            "open files limit {limit} is too low for {jobs} jobs which need about {required}: \
+            raise it with `ulimit -n` or reduce --jobs"
+        );
+    }
+    Ok(raised)
+}
+
+#[cfg(not(target_family = "unix"))]
+pub fn raise_open_files_limit(
+    _jobs: usize,
+    _max: Option<u64>,
+) -> Result<Option<(u64, u64)>, anyhow::Error> {
+    Ok(None)
+}
+
+/// Maximum soft limit for the given hard limit
+#[cfg(target_family = "unix")]
+fn finite_hard_limit(hard: u64) -> u64 {
+    // rlim_t is not u64 on all platforms
+    #[allow(clippy::unnecessary_cast)]
+    let hard = if hard == libc::RLIM_INFINITY as u64 {
+        OPEN_FILES_FINITE_MAX
+    } else {
+        hard
+    };
+    max_open_files_per_process().map_or(hard, |x| x.min(hard))
+}
+
+/// Open files limit of the kernel per process, setting a higher soft limit fails
+#[cfg(target_os = "macos")]
+fn max_open_files_per_process() -> Option<u64> {
+    let mut value: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    // SAFETY: the name is nul-terminated, value and size are valid for the duration of the call
+    let result = unsafe {
+        libc::sysctlbyname(
+            b"kern.maxfilesperproc\0".as_ptr() as *const libc::c_char,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (result == 0 && value > 0).then_some(value as u64)
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn max_open_files_per_process() -> Option<u64> {
+    None
+}
+
+/// Soft and hard limit of open files
+#[cfg(target_family = "unix")]
+pub fn get_open_files_limit() -> Result<(u64, u64), std::io::Error> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: the pointer is valid for the duration of the call
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // rlim_t is not u64 on all platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+#[cfg(target_family = "unix")]
+fn set_open_files_limit(soft: u64, hard: u64) -> Result<(), std::io::Error> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    // SAFETY: the pointer is valid for the duration of the call
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use super::*;
+    use crate::new_tmp_dir;
+    use crate::{CustomCommandSpec, JsonLogger, Razel};
+    use serial_test::serial;
+    use std::fs;
+
+    const CHILD_ENV: &str = "RAZEL_TEST_OPEN_FILES_CHILD";
+
+    /// The limit is per process, lowering it must not affect concurrently running tests
+    #[test]
+    fn many_jobs_capturing_output() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "utils::open_files::tests::many_jobs_capturing_output_in_child",
+                "--nocapture",
+            ])
+            .env(CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+
+    #[tokio::test]
+    async fn many_jobs_capturing_output_in_child() {
+        if std::env::var_os(CHILD_ENV).is_none() {
+            return;
+        }
+        let jobs = 100;
+        let (_, hard) = get_open_files_limit().unwrap();
+        let required = required_open_files(jobs);
+        if hard < required {
+            return;
+        }
+        // just too low for the jobs
+        set_open_files_limit(required - 1, hard).unwrap();
+        let dir = new_tmp_dir!();
+        let log_file = dir.join("log.jsonl");
+        log::set_boxed_logger(Box::new(JsonLogger::new(
+            log::LevelFilter::Info,
+            Box::new(fs::File::create(&log_file).unwrap()),
+        )))
+        .unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+        let mut razel = Razel::new();
+        razel.set_workspace_dir(dir.dir()).unwrap();
+        razel.read_cache = false;
+        razel.set_worker_threads(jobs).unwrap();
+        for i in 0..jobs * 2 {
+            razel
+                .push_custom_command(CustomCommandSpec {
+                    name: format!("{i}"),
+                    executable: "sh".into(),
+                    args: vec!["-c".into(), "echo out; echo err >&2; sleep 0.1".into()],
+                    stdout: Some(format!("{i}.out")),
+                    stderr: Some(format!("{i}.err")),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let stats = razel
+            .run(false, true, "", Some(dir.join("cache")), vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(stats.exec.succeeded, jobs * 2);
+        assert_eq!(get_open_files_limit().unwrap().0, hard);
+        let log = fs::read_to_string(&log_file).unwrap();
+        assert!(log.contains(&format!(
+            "raised open files limit from {} to {hard} for {jobs} jobs",
+            required - 1
+        )));
+        razel.clean();
+    }
+
+    #[test]
+    fn infinite_hard_limit() {
+        #[allow(clippy::unnecessary_cast)]
+        let infinity = libc::RLIM_INFINITY as u64;
+        assert_eq!(finite_hard_limit(infinity), OPEN_FILES_FINITE_MAX);
+        assert_eq!(finite_hard_limit(4096), 4096);
+    }
+
+    #[test]
+    #[serial]
+    fn limit_too_low() {
+        let (original_soft, hard) = get_open_files_limit().unwrap();
+        // enough for a single job, not raised
+        assert_eq!(raise_open_files_limit(1, None).unwrap(), None);
+        assert_eq!(get_open_files_limit().unwrap().0, original_soft);
+        let error = raise_open_files_limit(usize::MAX / 16, Some(original_soft)).unwrap_err();
+        assert!(error.to_string().starts_with("open files limit"));
+        set_open_files_limit(original_soft, hard).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+use crate::executors::ExecutionResult;
+use crate::{Command, SchedulerStats};
+
+/// Callbacks about the progress of a run, e.g. to integrate razel into dashboards or test frameworks.
+///
+/// Register implementations with `Razel::add_reporter()`. The terminal UI is one implementation.
+pub trait Reporter {
+    /// A command was started, called again if it is retried
+    fn on_target_started(&mut self, _command: &Command) {}
+
+    /// A command finished. `will_retry` is set if it failed but will be started again, e.g. after OOM kill.
+    ///
+    /// Not called for commands which are skipped because a dependency failed.
+    fn on_target_finished(
+        &mut self,
+        _command: &Command,
+        _execution_result: &ExecutionResult,
+        _will_retry: bool,
+    ) {
+    }
+
+    /// All commands finished or the run was stopped after errors
+    fn on_run_finished(&mut self, _stats: &SchedulerStats) {}
+}
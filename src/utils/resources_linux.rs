@@ -28,6 +28,13 @@ pub fn create_cgroup() -> Result<Option<CGroup>, anyhow::Error> {
     Ok(Some(cgroup))
 }
 
+/// Returns the resident memory of the razel process from /proc/self/statm [byte]
+pub fn process_rss() -> Option<u64> {
+    let statm = read_to_string("/proc/self/statm").ok()?;
+    let pages = statm.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(pages * procfs::page_size())
+}
+
 /// Reproduces what the K8s kubelet does to calculate memory.available relative to root cgroup.
 ///
 /// see https://kubernetes.io/docs/concepts/scheduling-eviction/node-pressure-eviction/
@@ -63,6 +70,26 @@ impl CGroup {
         self.write(controller, "tasks", pid)
     }
 
+    /// Creates a sub group, e.g. to measure the memory usage of a single command
+    pub fn create_child(&self, controller: &str, name: &str) -> Result<CGroup, anyhow::Error> {
+        let child = CGroup::new(format!("{}/{name}", self.group));
+        child.create(controller)?;
+        Ok(child)
+    }
+
+    /// Removes the group, fails if it still contains tasks
+    pub fn remove(&self, controller: &str) -> Result<(), anyhow::Error> {
+        let path = self.path(controller, "x");
+        let dir = path.parent().unwrap();
+        fs::remove_dir(dir).with_context(|| format!("Failed to remove dir {dir:?}"))?;
+        Ok(())
+    }
+
+    /// Returns the max memory usage of all tasks of the group [byte]
+    pub fn peak_memory(&self) -> Result<u64, anyhow::Error> {
+        self.read::<u64>("memory", "memory.max_usage_in_bytes")
+    }
+
     pub fn read<T>(&self, controller: &str, file: &str) -> Result<T, anyhow::Error>
     where
         T: FromStr,
@@ -123,6 +150,11 @@ mod tests {
     use super::*;
     use serial_test::serial;
 
+    #[test]
+    fn process_rss_is_set() {
+        assert!(process_rss().unwrap() > 0);
+    }
+
     #[test]
     #[ignore]
     fn available_memory() {
@@ -3,6 +3,11 @@ pub fn create_cgroup() -> Result<Option<CGroup>, anyhow::Error> {
     Ok(None)
 }
 
+/// Only supported on Linux, `--profile-memory` is ignored with a warning on other platforms
+pub fn process_rss() -> Option<u64> {
+    None
+}
+
 #[derive(Clone)]
 pub struct CGroup {}
 
@@ -10,4 +15,16 @@ impl CGroup {
     pub fn add_task(&self, _controller: &str, _pid: u32) -> Result<(), anyhow::Error> {
         unreachable!()
     }
+
+    pub fn create_child(&self, _controller: &str, _name: &str) -> Result<CGroup, anyhow::Error> {
+        unreachable!()
+    }
+
+    pub fn remove(&self, _controller: &str) -> Result<(), anyhow::Error> {
+        unreachable!()
+    }
+
+    pub fn peak_memory(&self) -> Result<u64, anyhow::Error> {
+        unreachable!()
+    }
 }
@@ -30,6 +30,16 @@ pub async fn force_symlink(src: &PathBuf, dst: &PathBuf) -> Result<(), anyhow::E
     Ok(())
 }
 
+#[cfg(target_family = "windows")]
+pub fn symlink_dir(src: &PathBuf, dst: &PathBuf) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(src, dst)
+}
+
+#[cfg(target_family = "unix")]
+pub fn symlink_dir(src: &PathBuf, dst: &PathBuf) -> io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+
 #[cfg(target_family = "windows")]
 fn symlink_file(src: &PathBuf, dst: &PathBuf) -> io::Result<()> {
     std::os::windows::fs::symlink_file(src, dst)
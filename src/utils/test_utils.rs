@@ -34,6 +34,19 @@ macro_rules! new_tmp_dir {
     }};
 }
 
+/// Returns the peak resident memory of the test process [kB].
+///
+/// It includes tests running in parallel, therefore tests using it should be serial.
+#[cfg(target_os = "linux")]
+pub fn peak_memory_kb() -> u64 {
+    procfs::process::Process::myself()
+        .unwrap()
+        .status()
+        .unwrap()
+        .vmhwm
+        .unwrap()
+}
+
 /// Temp directory/file tool for tests
 pub struct TempDir {
     dir: PathBuf,
@@ -68,6 +81,22 @@ impl TempDir {
         fs::write(&child, contents).unwrap();
         child
     }
+
+    /// Write a tar archive with the given entries within the temp dir and return its path
+    pub fn join_and_write_tar(&self, path: &str, entries: &[(&str, &str)]) -> PathBuf {
+        let child = self.join_and_create_parent(path);
+        let mut builder = tar::Builder::new(fs::File::create(&child).unwrap());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, name, data.as_bytes())
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        child
+    }
 }
 
 impl Drop for TempDir {
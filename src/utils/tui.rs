@@ -1,6 +1,6 @@
-use crate::executors::ExecutionResult;
+use crate::executors::{ExecutionResult, ExecutionStatus};
 use crate::metadata::Tag;
-use crate::{config, Command, SchedulerStats};
+use crate::{config, Command, Reporter, SchedulerStats};
 use bstr::ByteSlice;
 use crossterm::cursor::{RestorePosition, SavePosition};
 use crossterm::style::{Attribute, Color, SetForegroundColor};
@@ -21,8 +21,13 @@ pub static C_RESET: SetForegroundColor = SetForegroundColor(Color::Reset);
 pub struct TUI {
     pub razel_executable: String,
     pub verbose: bool,
+    /// only print failed commands and the final summary, e.g. for CI logs
+    pub summary_only: bool,
+    /// interval of status updates, the default depends on writing to a terminal
+    pub update_interval: Option<std::time::Duration>,
     status_printed: bool,
     is_tty: bool,
+    out: Box<dyn Write + Send>,
 }
 
 impl TUI {
@@ -33,12 +38,32 @@ impl TUI {
         Self {
             razel_executable,
             verbose: false,
+            summary_only: false,
+            update_interval: None,
             status_printed: false,
             is_tty: stdout().is_tty(),
+            out: Box::new(stdout()),
         }
     }
 
+    /// Write to the given output instead of stdout, which is handled as non-TTY
+    pub fn with_output(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            is_tty: false,
+            out,
+            ..Self::new()
+        }
+    }
+
+    /// Status is updated in place on a terminal, otherwise status lines are appended
+    pub fn is_tty(&self) -> bool {
+        self.is_tty
+    }
+
     pub fn get_update_interval(&self) -> std::time::Duration {
+        if let Some(x) = self.update_interval {
+            return x;
+        }
         let secs = if self.is_tty {
             config::UI_UPDATE_INTERVAL_TTY
         } else {
@@ -48,7 +73,8 @@ impl TUI {
     }
 
     pub fn command_succeeded(&mut self, command: &Command, execution_result: &ExecutionResult) {
-        if (!self.verbose && !command.tags.contains(&Tag::Verbose))
+        if self.summary_only
+            || (!self.verbose && !command.tags.contains(&Tag::Verbose))
             || command.tags.contains(&Tag::Quiet)
         {
             return;
@@ -59,7 +85,7 @@ impl TUI {
             return;
         }
         self.clear_status();
-        Self::field(
+        self.field(
             format!("{:?} ", execution_result.status).as_str(),
             Color::Green,
             if let Some(duration) = execution_result.exec_duration {
@@ -72,12 +98,12 @@ impl TUI {
             },
         );
         let print_stream_name = !stdout.is_empty() && !stderr.is_empty();
-        Self::field(
+        self.field(
             if print_stream_name { "stdout:\n" } else { "" },
             Color::Blue,
             &stdout,
         );
-        Self::field(
+        self.field(
             if print_stream_name { "stderr:\n" } else { "" },
             Color::Blue,
             &stderr,
@@ -110,28 +136,28 @@ impl TUI {
             Color::Red
         };
         self.clear_status();
-        println!();
-        Self::line();
-        Self::field(
+        writeln!(self.out).unwrap();
+        self.line();
+        self.field(
             format!("{:<11}", format!("{:?} ", execution_result.status)).as_str(),
             color,
             command.name.as_str(),
         );
         if let Some(x) = &execution_result.error {
             if will_retry {
-                Self::field_with_hint(
+                self.field_with_hint(
                     "error:     ",
                     color,
                     format!("{x:?}").as_str(),
                     "(will retry)",
                 );
             } else {
-                Self::field("error:     ", color, format!("{x:?}").as_str());
+                self.field("error:     ", color, format!("{x:?}").as_str());
             }
         } else if let Some(x) = execution_result.exit_code {
-            Self::field("exit code: ", color, x.to_string().as_str());
+            self.field("exit code: ", color, x.to_string().as_str());
         }
-        Self::field(
+        self.field(
             "command:   ",
             Color::Blue,
             self.format_command_line(
@@ -142,7 +168,7 @@ impl TUI {
             .as_str(),
         );
         if let Some(env) = command.executor.env() {
-            Self::field(
+            self.field(
                 "env:       ",
                 Color::Blue,
                 env.iter()
@@ -152,18 +178,18 @@ impl TUI {
                     .as_str(),
             );
         }
-        Self::field(
+        self.field(
             "stderr:\n",
             Color::Blue,
             execution_result.stderr.to_str_lossy(),
         );
-        Self::field(
+        self.field(
             "stdout:\n",
             Color::Blue,
             execution_result.stdout.to_str_lossy(),
         );
-        Self::line();
-        println!();
+        self.line();
+        writeln!(self.out).unwrap();
     }
 
     pub fn status(
@@ -174,14 +200,17 @@ impl TUI {
         running: usize,
         remaining: usize,
     ) {
+        if self.summary_only {
+            return;
+        }
         if self.is_tty {
             if self.status_printed {
-                print!("{RestorePosition}");
+                write!(self.out, "{RestorePosition}").unwrap();
             } else {
-                print!("{SavePosition}");
+                write!(self.out, "{SavePosition}").unwrap();
             }
         }
-        print!(
+        write!(self.out,
             "{A_BOLD}{C_BLUE}Status{C_RESET}{A_RESET}: {A_BOLD}{}{}{C_RESET}{A_RESET} succeeded ({} cached), {}{}{}{C_RESET}{A_RESET} failed, {} running, {} remaining",
             if succeeded > 0 {
                 C_GREEN
@@ -195,29 +224,31 @@ impl TUI {
             failed,
             running,
             remaining,
-        );
+        ).unwrap();
         if !self.is_tty {
-            println!();
+            writeln!(self.out).unwrap();
         }
-        stdout().flush().unwrap();
+        self.out.flush().unwrap();
         self.status_printed = true;
     }
 
     pub fn finished(&mut self, stats: &SchedulerStats) {
         self.clear_status();
-        println!(
+        writeln!(self.out,
             "{A_BOLD}{}{} {}{C_RESET}{A_RESET}: {A_BOLD}{}{}{C_RESET}{A_RESET} succeeded ({} cached), {A_BOLD}{}{}{C_RESET}{A_RESET} failed, {A_BOLD}{}{A_RESET} skipped, {A_BOLD}{}{}{C_RESET}{A_RESET} not run.",
             if stats.exec.finished_successfully() {
                 C_GREEN
             } else {
                 C_RED
             },
-            if stats.exec.not_run == 0 {
+            if stats.exec.not_run == 0 && !stats.deadline_exceeded {
                 "Finished"
             } else {
                 "Stopped"
             },
-            if stats.exec.finished_successfully() {
+            if stats.deadline_exceeded {
+                "at deadline"
+            } else if stats.exec.finished_successfully() {
                 "successfully"
             } else if stats.exec.failed == 1 {
                 "after error"
@@ -244,7 +275,7 @@ impl TUI {
                 C_RESET
             },
             stats.exec.not_run,
-        );
+        ).unwrap();
     }
 
     pub fn format_command_line(&self, args_with_executable: &[String]) -> String {
@@ -274,36 +305,67 @@ impl TUI {
 
     fn clear_status(&mut self) {
         if self.is_tty && self.status_printed {
-            print!("{}{:>90}{}", RestorePosition, " ", RestorePosition);
+            write!(
+                self.out,
+                "{}{:>90}{}",
+                RestorePosition, " ", RestorePosition
+            )
+            .unwrap();
             self.status_printed = false;
         }
     }
 
-    fn field<S: AsRef<str>>(name: &str, color: Color, value: S) {
+    fn field<S: AsRef<str>>(&mut self, name: &str, color: Color, value: S) {
         if value.as_ref().is_empty() {
             return;
         }
         let c = SetForegroundColor(color);
-        println!(
+        writeln!(
+            self.out,
             "{A_BOLD}{c}{name}{C_RESET}{A_RESET}{}",
             value.as_ref().trim()
-        );
+        )
+        .unwrap();
     }
 
-    fn field_with_hint<S: AsRef<str>>(name: &str, color: Color, value: S, hint: &str) {
+    fn field_with_hint<S: AsRef<str>>(&mut self, name: &str, color: Color, value: S, hint: &str) {
         if value.as_ref().is_empty() {
             return;
         }
         let c = SetForegroundColor(color);
-        println!(
+        writeln!(
+            self.out,
             "{A_BOLD}{c}{name}{C_RESET}{A_RESET}{}{A_BOLD}{c} {hint}{C_RESET}{A_RESET}",
             value.as_ref().trim()
-        );
+        )
+        .unwrap();
     }
 
-    fn line() {
+    fn line(&mut self) {
         let columns = terminal::size().map_or(90, |x| x.0 as usize);
-        println!("{C_RED}{}{C_RESET}", "-".repeat(columns));
+        writeln!(self.out, "{C_RED}{}{C_RESET}", "-".repeat(columns)).unwrap();
+    }
+}
+
+impl Reporter for TUI {
+    fn on_target_finished(
+        &mut self,
+        command: &Command,
+        execution_result: &ExecutionResult,
+        will_retry: bool,
+    ) {
+        if will_retry {
+            self.command_retry(command, execution_result);
+        } else if execution_result.success() {
+            self.command_succeeded(command, execution_result);
+        } else if execution_result.status != ExecutionStatus::Skipped {
+            // not materialized commands are skipped and logged by Razel
+            self.command_failed(command, execution_result);
+        }
+    }
+
+    fn on_run_finished(&mut self, stats: &SchedulerStats) {
+        self.finished(stats);
     }
 }
 
@@ -0,0 +1,62 @@
+use crate::bazel_remote_exec::digest_function;
+use crate::config;
+use itertools::Itertools;
+use serde::Serialize;
+use std::fmt;
+
+/// Build-time metadata of the razel executable, e.g. to correlate cache divergence with binaries
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    /// commit of the razel repo, `unknown` if not built from a git checkout
+    pub git_commit: &'static str,
+    pub target: &'static str,
+    /// enabled cargo features
+    pub features: Vec<&'static str>,
+    /// digest function used for the cache
+    pub digest_function: &'static str,
+}
+
+impl VersionInfo {
+    pub fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("RAZEL_GIT_COMMIT"),
+            target: env!("RAZEL_TARGET"),
+            features: env!("RAZEL_FEATURES")
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .sorted_unstable()
+                .collect(),
+            digest_function: digest_function::Value::Sha256.as_str_name(),
+        }
+    }
+}
+
+impl Default for VersionInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    /// Same as `razel --version`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", config::EXECUTABLE, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let json = serde_json::to_value(VersionInfo::new()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["features"].is_array());
+        assert_eq!(json["digest_function"], "SHA256");
+        assert!(!json["git_commit"].as_str().unwrap().is_empty());
+        assert!(!json["target"].as_str().unwrap().is_empty());
+    }
+}